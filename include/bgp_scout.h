@@ -0,0 +1,58 @@
+/*
+ * C API of bgp-scout, provided by the bgp_scout shared library (libbgp_scout.so).
+ */
+#ifndef BGP_SCOUT_H
+#define BGP_SCOUT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Only return IPv4 prefixes */
+#define BGS_IPV4_ONLY 1u
+/* Only return IPv6 prefixes */
+#define BGS_IPV6_ONLY 2u
+/* Return the announced prefixes as they are instead of aggregating them */
+#define BGS_NO_AGGREGATE 4u
+
+/* The call succeeded */
+#define BGS_OK 0
+/* The lookup failed, as the error message of the result tells */
+#define BGS_ERROR 1
+/* The library panicked, which was caught instead of unwinding into the caller */
+#define BGS_PANIC 2
+
+typedef struct bgs_result {
+    /* NUL-terminated prefixes in CIDR notation, sorted by address */
+    char **prefixes;
+    /* Number of prefixes */
+    size_t len;
+    /* NUL-terminated error message, or NULL when the lookup succeeded */
+    char *error;
+    /* BGS_OK, BGS_ERROR or BGS_PANIC */
+    int32_t code;
+} bgs_result;
+
+/*
+ * Finds the prefixes announced by the given origin ASNs in an uncompressed MRT file, aggregated
+ * unless BGS_NO_AGGREGATE is set in flags. Always returns a result, which holds an error message
+ * and code when the lookup failed and must be released with bgs_free_result. Panics are caught
+ * and reported as BGS_PANIC.
+ */
+bgs_result *bgs_find_netblocks(const char *mrt_file, const uint32_t *asns, size_t asns_len,
+                               uint32_t flags);
+
+/*
+ * Releases a result returned by bgs_find_netblocks. Does nothing when given NULL. Returns BGS_OK,
+ * or BGS_PANIC when releasing it panicked.
+ */
+int32_t bgs_free_result(bgs_result *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BGP_SCOUT_H */
@@ -0,0 +1,65 @@
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Aggregates prefixes as they arrive, merging each into the result right away so memory grows
+/// with the size of the aggregated result rather than with the number of prefixes fed in.
+///
+/// The set never holds a prefix covering another, and never holds both halves of a supernet, so
+/// it always equals what `IpNet::aggregate` would return for everything inserted so far.
+#[derive(Debug, Default)]
+pub struct StreamingAggregator {
+    prefixes: BTreeSet<IpNet>,
+    inserted: u64,
+}
+
+impl StreamingAggregator {
+    pub fn insert(&mut self, prefix: IpNet) {
+        self.inserted += 1;
+        let mut prefix = prefix.trunc();
+        loop {
+            // A covering prefix sorts before the prefixes it covers, and as no other prefix can
+            // sort between them, only the nearest one before needs checking
+            if let Some(before) = self.prefixes.range(..=prefix).next_back() {
+                if before.contains(&prefix) {
+                    return;
+                }
+            }
+            let covered: Vec<IpNet> = self
+                .prefixes
+                .range(prefix..)
+                .take_while(|after| prefix.contains(*after))
+                .copied()
+                .collect();
+            for after in covered {
+                self.prefixes.remove(&after);
+            }
+
+            // Merge with the other half of the supernet, then retry one level up
+            let Some(supernet) = prefix.supernet() else {
+                break;
+            };
+            let sibling = supernet
+                .subnets(prefix.prefix_len())
+                .ok()
+                .and_then(|mut halves| halves.find(|half| *half != prefix));
+            match sibling {
+                Some(sibling) if self.prefixes.remove(&sibling) => prefix = supernet,
+                _ => break,
+            }
+        }
+        self.prefixes.insert(prefix);
+    }
+
+    /// Number of prefixes fed in, before aggregation
+    pub const fn inserted(&self) -> u64 {
+        self.inserted
+    }
+
+    /// Returns the aggregated prefixes, sorted by address.
+    pub fn into_prefixes(self) -> Vec<IpNet> {
+        self.prefixes.into_iter().collect()
+    }
+}
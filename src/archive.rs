@@ -0,0 +1,139 @@
+use reqwest::Url;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::net::ToSocketAddrs;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use suppaftp::types::FileType;
+use suppaftp::FtpStream;
+
+use crate::{cache, download};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Returns whether the URL is served by an FTP or rsync archive rather than over HTTP.
+pub fn is_archive_url(url: &str) -> bool {
+    url.starts_with("ftp://") || url.starts_with("rsync://")
+}
+
+/// Size and modification time of a file on an FTP or rsync server, standing in for the HTTP
+/// validators those protocols lack. Servers not reporting the time leave it `None`, so the file
+/// is always downloaded again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub size: u64,
+    pub modified: Option<String>,
+}
+
+/// Connects and logs in to the FTP server of the URL, anonymously unless it has a user name,
+/// returning the connection and the path of the file.
+fn ftp_connect(url: &str, timeout: Duration) -> Result<(FtpStream, String), Box<dyn Error>> {
+    let parsed = Url::parse(url)?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("No host in {url}"))?;
+    let address = (host, parsed.port().unwrap_or(21))
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("Could not resolve {host}"))?;
+    let mut ftp = FtpStream::connect_timeout(address, timeout)
+        .map_err(|e| format!("Could not connect to {host}: {e}"))?;
+    ftp.get_ref().set_read_timeout(Some(timeout))?;
+    let (user, password) = match parsed.username() {
+        "" => ("anonymous", "anonymous@"),
+        user => (user, parsed.password().unwrap_or_default()),
+    };
+    ftp.login(user, password)
+        .map_err(|e| format!("Could not log in to {host}: {e}"))?;
+    ftp.transfer_type(FileType::Binary)?;
+    Ok((ftp, parsed.path().to_string()))
+}
+
+/// Parses the line rsync --list-only prints for a file, such as
+/// `-rw-r--r--    123,456,789 2024/01/01 08:00:00 bview.gz`.
+fn parse_rsync_listing(line: &str) -> Option<Listing> {
+    let mut fields = line.split_whitespace();
+    let _permissions = fields.next()?;
+    let size = fields.next()?.replace(',', "").parse().ok()?;
+    let date = fields.next()?;
+    let time = fields.next()?;
+    Some(Listing {
+        size,
+        modified: Some(format!("{date} {time}")),
+    })
+}
+
+/// Runs rsync with the timeout, returning its output or an error with what it printed.
+fn rsync(args: &[&str], timeout: Duration) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("rsync")
+        .arg(format!("--timeout={}", timeout.as_secs().max(1)))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Could not run rsync: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "rsync failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Lists the size and modification time of the file an ftp:// or rsync:// URL points at.
+pub fn stat(url: &str, timeout: Duration) -> Result<Listing, Box<dyn Error>> {
+    if url.starts_with("rsync://") {
+        let listed = rsync(&["--list-only", url], timeout)?;
+        return listed
+            .lines()
+            .find_map(parse_rsync_listing)
+            .ok_or_else(|| format!("No file listed at {url}").into());
+    }
+    let (mut ftp, path) = ftp_connect(url, timeout)?;
+    let size = ftp
+        .size(&path)
+        .map_err(|e| format!("Could not get the size of {url}: {e}"))?;
+    let modified = match ftp.mdtm(&path) {
+        Ok(modified) => Some(modified.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        Err(e) => {
+            debug!("No modification time for {url}: {e}");
+            None
+        }
+    };
+    let _ = ftp.quit();
+    Ok(Listing {
+        size: u64::try_from(size)?,
+        modified,
+    })
+}
+
+/// Retrieves the file an ftp:// or rsync:// URL points at, passing its content to `store`.
+/// rsync only writes to files, so its copy goes to a temporary file next to the cached objects.
+pub fn retrieve<T>(
+    url: &str,
+    timeout: Duration,
+    store: impl FnOnce(&mut dyn Read) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    if url.starts_with("rsync://") {
+        let objects_dir = cache::object_path("");
+        fs::create_dir_all(&objects_dir)?;
+        let temp_file_name = download::temp_path(&objects_dir.join("rsync").display().to_string());
+        let stored = rsync(&["--quiet", url, &temp_file_name], timeout).and_then(|_| {
+            let mut reader = BufReader::new(File::open(&temp_file_name)?);
+            store(&mut reader)
+        });
+        let _ = fs::remove_file(&temp_file_name);
+        return stored;
+    }
+    let (mut ftp, path) = ftp_connect(url, timeout)?;
+    let mut stream = ftp
+        .retr_as_stream(&path)
+        .map_err(|e| format!("Could not retrieve {url}: {e}"))?;
+    let stored = store(&mut stream)?;
+    ftp.finalize_retr_stream(stream)?;
+    let _ = ftp.quit();
+    Ok(stored)
+}
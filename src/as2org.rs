@@ -0,0 +1,114 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A record of the JSON lines as2org format, which lists organizations and ASNs as separate
+/// objects linked by organization id
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonRecord {
+    #[serde(rename = "type")]
+    record_type: String,
+    organization_id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    asn: Option<String>,
+}
+
+/// Organization names and the ASNs registered to each organization id
+#[derive(Debug, Default)]
+struct As2Org {
+    org_names: HashMap<String, String>,
+    org_asns: HashMap<String, Vec<u32>>,
+}
+
+impl As2Org {
+    fn add_asn(&mut self, org_id: &str, asn: &str) {
+        match u32::from_str(asn) {
+            Ok(asn) => self
+                .org_asns
+                .entry(org_id.to_string())
+                .or_default()
+                .push(asn),
+            Err(_) => trace!("Skipping invalid as2org ASN {asn}"),
+        }
+    }
+
+    /// Parses either the JSON lines format or the older pipe-separated format, whose sections
+    /// are introduced by `# format:` comments.
+    fn parse(reader: &mut dyn BufRead) -> Result<Self, Box<dyn Error>> {
+        let mut as2org = Self::default();
+        let mut in_asn_section = false;
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('{') {
+                let record: JsonRecord = serde_json::from_str(&line)?;
+                match (record.record_type.as_str(), &record.asn) {
+                    ("Organization", _) => {
+                        as2org.org_names.insert(record.organization_id, record.name);
+                    }
+                    ("ASN", Some(asn)) => as2org.add_asn(&record.organization_id, asn),
+                    _ => {}
+                }
+            } else if let Some(format) = line.strip_prefix("# format:") {
+                in_asn_section = format.starts_with("aut|");
+            } else if !line.starts_with('#') {
+                let fields: Vec<&str> = line.split('|').collect();
+                match (in_asn_section, fields.as_slice()) {
+                    // aut|changed|aut_name|org_id|opaque_id|source
+                    (true, [asn, _, _, org_id, ..]) => as2org.add_asn(org_id, asn),
+                    // org_id|changed|org_name|country|source
+                    (false, [org_id, _, org_name, ..]) => {
+                        as2org
+                            .org_names
+                            .insert((*org_id).to_string(), (*org_name).to_string());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        debug!(
+            "Loaded {} organizations from as2org",
+            as2org.org_names.len()
+        );
+        Ok(as2org)
+    }
+}
+
+/// Finds the ASNs of every organization in CAIDA's as2org dataset whose name contains the
+/// search term, ignoring case. The dataset is read from a local file or a cached download, and
+/// gzipped files are decompressed while reading.
+pub fn search_org_asns(
+    location: &str,
+    name: &str,
+    verify_cache_interval: Duration,
+) -> Result<BTreeMap<u32, String>, Box<dyn Error>> {
+    let path = if location.contains("://") {
+        source::fetch_file(location, verify_cache_interval)?
+    } else {
+        location.to_string()
+    };
+    debug!("Loading as2org dataset from {path}");
+    let as2org = As2Org::parse(&mut BufReader::new(oneio::get_reader(&path)?))?;
+
+    let needle = name.to_lowercase();
+    let mut asns = BTreeMap::new();
+    for (org_id, org_name) in &as2org.org_names {
+        if !org_name.to_lowercase().contains(&needle) {
+            continue;
+        }
+        info!("Organization {name} matched as2org organization {org_name}");
+        for asn in as2org.org_asns.get(org_id).into_iter().flatten() {
+            asns.insert(*asn, org_name.clone());
+        }
+    }
+    Ok(asns)
+}
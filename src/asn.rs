@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// ASN ranges that never appear as the origin of a public route, as (first, last, description)
+const SPECIAL_ASNS: [(u32, u32, &str); 9] = [
+    (0, 0, "reserved (RFC 7607)"),
+    (23456, 23456, "AS_TRANS (RFC 6793)"),
+    (64496, 64511, "reserved for documentation (RFC 5398)"),
+    (64512, 65534, "reserved for private use (RFC 6996)"),
+    (65535, 65535, "reserved (RFC 7300)"),
+    (65536, 65551, "reserved for documentation (RFC 5398)"),
+    (65552, 131_071, "reserved by IANA"),
+    (
+        4_200_000_000,
+        4_294_967_294,
+        "reserved for private use (RFC 6996)",
+    ),
+    (4_294_967_295, 4_294_967_295, "reserved (RFC 7300)"),
+];
+
+/// Parses an ASN in asplain (`13335`) or asdot (`3.14`) notation, optionally prefixed with `AS`
+/// as copied from whois output.
+pub fn parse_asn(value: &str) -> Result<u32, String> {
+    let trimmed = value.trim();
+    let digits = match trimmed.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("AS") => &trimmed[2..],
+        _ => trimmed,
+    };
+    let parsed = match digits.split_once('.') {
+        // asdot splits 4-byte ASNs into the high and low 16 bits
+        Some((high, low)) => match (u16::from_str(high), u16::from_str(low)) {
+            (Ok(high), Ok(low)) => Ok((u32::from(high) << 16) | u32::from(low)),
+            _ => Err(()),
+        },
+        None => u32::from_str(digits).map_err(|_| ()),
+    };
+    parsed.map_err(|()| format!("{value} is not an ASN, expected e.g. 13335, AS13335 or 3.14"))
+}
+
+/// Reads one ASN per line from a file, or from stdin when the file name is `-`. Blank lines and
+/// `#` comments are ignored, and so are invalid lines unless `strict` is set.
+pub fn read_asn_file(file_name: &str, strict: bool) -> Result<Vec<u32>, Box<dyn Error>> {
+    let reader: Box<dyn BufRead> = if file_name == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(file_name)?))
+    };
+
+    let mut asns = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let value = line.split('#').next().unwrap_or_default().trim();
+        if value.is_empty() {
+            continue;
+        }
+        match parse_asn(value) {
+            Ok(asn) => asns.push(asn),
+            Err(e) if strict => return Err(format!("{file_name}:{}: {e}", number + 1).into()),
+            Err(e) => warn!("Skipping invalid ASN on {file_name}:{}: {e}", number + 1),
+        }
+    }
+    debug!("Read {} ASNs from {file_name}", asns.len());
+    Ok(asns)
+}
+
+/// Describes why an ASN cannot originate public routes, or returns `None` for ordinary ASNs.
+pub fn special_purpose(asn: u32) -> Option<&'static str> {
+    SPECIAL_ASNS
+        .iter()
+        .find(|(first, last, _)| (*first..=*last).contains(&asn))
+        .map(|(_, _, description)| *description)
+}
+
+/// Warns about reserved, private and documentation ASNs, which almost always indicate a typo and
+/// match no public routes. With `strict` they are rejected instead.
+pub fn check_asns<'asn>(
+    asns: impl IntoIterator<Item = &'asn u32>,
+    strict: bool,
+) -> Result<(), Box<dyn Error>> {
+    for &asn in asns {
+        let Some(description) = special_purpose(asn) else {
+            continue;
+        };
+        if strict {
+            return Err(format!("AS{asn} is {description}").into());
+        }
+        warn!("AS{asn} is {description} and is unlikely to originate any public routes");
+    }
+    Ok(())
+}
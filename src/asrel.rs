@@ -0,0 +1,214 @@
+use bgpkit_parser::BgpkitParser;
+use chrono::{Datelike, Months, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{self, BufRead, BufReader};
+use std::time::Duration;
+
+use crate::render::{self, ReportFormat};
+use crate::{mrt, source};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const CAIDA_AS_REL_URL: &str = "https://publicdata.caida.org/datasets/as-relationships/serial-1";
+
+/// Relationship of a neighbor as seen from the AS it is adjacent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relationship {
+    Provider,
+    Peer,
+    Customer,
+    /// Not present in the relationship dataset
+    Unknown,
+}
+
+impl Relationship {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Provider => "provider",
+            Self::Peer => "peer",
+            Self::Customer => "customer",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Pairwise AS relationships from CAIDA's as-rel dataset
+#[derive(Debug, Default)]
+pub struct AsRelationships {
+    /// Keyed by (AS, neighbor) with the role of the neighbor
+    relationships: HashMap<(u32, u32), Relationship>,
+}
+
+impl AsRelationships {
+    /// Parses the serial-1 or serial-2 format, `<provider>|<customer>|-1` or `<peer>|<peer>|0`,
+    /// ignoring any further fields and `#` comments.
+    pub fn parse(reader: &mut dyn BufRead) -> Result<Self, Box<dyn Error>> {
+        let mut relationships = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('|');
+            let (Some(first), Some(second), Some(kind)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                warn!("Skipping malformed AS relationship line {line}");
+                continue;
+            };
+            let (Ok(first), Ok(second)) = (first.parse::<u32>(), second.parse::<u32>()) else {
+                warn!("Skipping malformed AS relationship line {line}");
+                continue;
+            };
+            match kind.trim() {
+                "-1" => {
+                    relationships.insert((first, second), Relationship::Customer);
+                    relationships.insert((second, first), Relationship::Provider);
+                }
+                "0" => {
+                    relationships.insert((first, second), Relationship::Peer);
+                    relationships.insert((second, first), Relationship::Peer);
+                }
+                _ => warn!("Skipping unknown AS relationship type in {line}"),
+            }
+        }
+        debug!("Loaded {} AS relationships", relationships.len() / 2);
+        Ok(Self { relationships })
+    }
+
+    /// Classifies the neighbor of an AS, e.g. `Provider` when the neighbor is its upstream.
+    pub fn classify(&self, asn: u32, neighbor: u32) -> Relationship {
+        self.relationships
+            .get(&(asn, neighbor))
+            .copied()
+            .unwrap_or(Relationship::Unknown)
+    }
+}
+
+/// Returns the URL of the most recent monthly serial-1 dataset CAIDA has most likely published,
+/// which is the one for the previous month.
+pub fn default_url() -> String {
+    let now = Utc::now();
+    let month = now.checked_sub_months(Months::new(1)).unwrap_or(now);
+    format!(
+        "{CAIDA_AS_REL_URL}/{:04}{:02}01.as-rel.txt.bz2",
+        month.year(),
+        month.month()
+    )
+}
+
+/// Loads AS relationships from a local file or from a URL, caching downloads. Files ending in
+/// `.bz2` or `.gz` are decompressed while reading.
+pub fn load(
+    location: Option<&str>,
+    verify_cache_interval: Duration,
+) -> Result<AsRelationships, Box<dyn Error>> {
+    let location = location.map_or_else(default_url, str::to_string);
+    let path = if location.contains("://") {
+        source::fetch_file(&location, verify_cache_interval)?
+    } else {
+        location
+    };
+    debug!("Loading AS relationships from {path}");
+    let mut reader = BufReader::new(oneio::get_reader(&path)?);
+    AsRelationships::parse(&mut reader)
+}
+
+/// An AS seen adjacent to the target AS in announced paths
+#[derive(Debug, Serialize)]
+pub struct Neighbor {
+    pub asn: u32,
+    pub relationship: Relationship,
+    /// Paths where the neighbor sits between the target and the collector
+    pub paths_upstream: u64,
+    /// Paths where the neighbor sits between the target and the origin
+    pub paths_downstream: u64,
+}
+
+/// Scans the AS paths of an MRT file for the ASes adjacent to the target, classifying each
+/// using the relationship dataset.
+pub fn neighbors(
+    file_name: &str,
+    asn: u32,
+    relationships: &AsRelationships,
+) -> Result<Vec<Neighbor>, Box<dyn Error>> {
+    let parser = BgpkitParser::from_reader(mrt::open(file_name)?).add_filter("type", "announce")?;
+
+    debug!("Scanning {file_name} for neighbors of AS{asn}");
+    // Counts of (upstream, downstream) paths per neighbor
+    let mut counts: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+    for elem in parser.into_elem_iter() {
+        let Some(path) = elem
+            .as_path
+            .as_ref()
+            .and_then(|path| path.to_u32_vec_opt(true))
+        else {
+            continue;
+        };
+        let Some(position) = path.iter().position(|&hop| hop == asn) else {
+            continue;
+        };
+        // Paths run from the collector peer towards the origin
+        if let Some(&upstream) = position.checked_sub(1).and_then(|index| path.get(index)) {
+            counts.entry(upstream).or_default().0 += 1;
+        }
+        if let Some(&downstream) = path.get(position + 1) {
+            counts.entry(downstream).or_default().1 += 1;
+        }
+    }
+
+    let mut neighbors: Vec<Neighbor> = counts
+        .into_iter()
+        .map(|(neighbor, (paths_upstream, paths_downstream))| Neighbor {
+            asn: neighbor,
+            relationship: relationships.classify(asn, neighbor),
+            paths_upstream,
+            paths_downstream,
+        })
+        .collect();
+    neighbors.sort_by_key(|neighbor| (neighbor.relationship, neighbor.asn));
+    debug!("Found {} neighbors of AS{asn}", neighbors.len());
+    Ok(neighbors)
+}
+
+pub fn render_neighbors(
+    neighbors: &[Neighbor],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), neighbors)?,
+        ReportFormat::Text => {
+            for neighbor in neighbors {
+                println!(
+                    "AS{} {} upstream={} downstream={}",
+                    neighbor.asn,
+                    neighbor.relationship.as_str(),
+                    neighbor.paths_upstream,
+                    neighbor.paths_downstream
+                );
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = neighbors
+                .iter()
+                .map(|neighbor| {
+                    vec![
+                        format!("AS{}", neighbor.asn),
+                        neighbor.relationship.as_str().to_string(),
+                        neighbor.paths_upstream.to_string(),
+                        neighbor.paths_downstream.to_string(),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &["ASN", "RELATIONSHIP", "UPSTREAM PATHS", "DOWNSTREAM PATHS"],
+                &rows,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use bgpkit_parser::models::BgpElem;
+use bgpkit_parser::BgpkitParser;
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::IpAddr;
+
+use crate::mrt;
+use crate::scan::PeerFilter;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Path attribute included with each prefix by --with-attributes
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    AsPath,
+    NextHop,
+    Med,
+    Communities,
+}
+
+impl Field {
+    pub const ALL: [Self; 4] = [Self::AsPath, Self::NextHop, Self::Med, Self::Communities];
+
+    /// Name of the JSON key and CSV column
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::AsPath => "as_path",
+            Self::NextHop => "next_hop",
+            Self::Med => "med",
+            Self::Communities => "communities",
+        }
+    }
+}
+
+/// Attributes of the route chosen to represent an announced prefix
+#[derive(Debug, Clone)]
+struct Route {
+    as_path: String,
+    /// Number of ASNs in the path, counting each set as one
+    path_len: usize,
+    next_hop: Option<IpAddr>,
+    med: Option<u32>,
+    communities: Vec<String>,
+}
+
+impl Route {
+    fn from_elem(elem: &BgpElem) -> Self {
+        Self {
+            as_path: elem
+                .as_path
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            path_len: elem.as_path.as_ref().map_or(0, |path| path.route_len()),
+            next_hop: elem.next_hop,
+            med: elem.med,
+            communities: elem
+                .communities
+                .iter()
+                .flatten()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// Representative route attributes of the announced prefixes, and the fields to output
+#[derive(Debug)]
+pub struct PrefixAttributes {
+    fields: Vec<Field>,
+    routes: HashMap<IpNet, Route>,
+}
+
+impl PrefixAttributes {
+    /// Returns the route of the prefix, or of the most specific announced prefix covering it for
+    /// results split from an announcement. Aggregates of several announcements have none.
+    fn route(&self, prefix: &IpNet) -> Option<&Route> {
+        (0..=prefix.prefix_len()).rev().find_map(|len| {
+            IpNet::new(prefix.addr(), len)
+                .ok()
+                .and_then(|covering| self.routes.get(&covering.trunc()))
+        })
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Adds the selected attributes of the prefix to a JSON object, as null when it has none.
+    pub fn insert_json(&self, object: &mut Map<String, Value>, prefix: &IpNet) {
+        let route = self.route(prefix);
+        for field in &self.fields {
+            let value = route.map_or(Value::Null, |route| match field {
+                Field::AsPath => Value::from(route.as_path.clone()),
+                Field::NextHop => route
+                    .next_hop
+                    .map_or(Value::Null, |next_hop| Value::from(next_hop.to_string())),
+                Field::Med => route.med.map_or(Value::Null, Value::from),
+                Field::Communities => Value::from(route.communities.clone()),
+            });
+            object.insert(field.name().to_string(), value);
+        }
+    }
+
+    /// Returns the selected attributes of the prefix as CSV cells, empty when it has none. Lists
+    /// are separated by spaces.
+    pub fn csv_cells(&self, prefix: &IpNet) -> Vec<String> {
+        let route = self.route(prefix);
+        self.fields
+            .iter()
+            .map(|field| {
+                route.map_or_else(String::new, |route| match field {
+                    Field::AsPath => route.as_path.clone(),
+                    Field::NextHop => route
+                        .next_hop
+                        .map(|next_hop| next_hop.to_string())
+                        .unwrap_or_default(),
+                    Field::Med => route.med.map(|med| med.to_string()).unwrap_or_default(),
+                    Field::Communities => route.communities.join(" "),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reads the routes of the announced prefixes from the MRT file, keeping the one with the
+/// shortest AS path per prefix, the route a typical best path selection would prefer, among the
+/// routes of the selected peers.
+pub fn collect(
+    mrt_file: &str,
+    prefixes: &HashSet<IpNet>,
+    peers: &PeerFilter,
+    fields: Vec<Field>,
+) -> Result<PrefixAttributes, Box<dyn Error>> {
+    let _span = info_span!("attributes", prefixes = prefixes.len()).entered();
+    let parser = BgpkitParser::from_reader(mrt::open(mrt_file)?).add_filter("type", "announce")?;
+    let mut routes: HashMap<IpNet, Route> = HashMap::new();
+    for elem in parser.into_elem_iter() {
+        let prefix = elem.prefix.prefix;
+        if !prefixes.contains(&prefix) || !peers.matches(&elem) {
+            continue;
+        }
+        let path_len = elem.as_path.as_ref().map_or(0, |path| path.route_len());
+        let shorter = routes
+            .get(&prefix)
+            .is_none_or(|route| path_len < route.path_len);
+        if shorter {
+            routes.insert(prefix, Route::from_elem(&elem));
+        }
+    }
+    debug!(
+        "Found attributes of {} of {} announced prefixes",
+        routes.len(),
+        prefixes.len()
+    );
+    Ok(PrefixAttributes { fields, routes })
+}
@@ -0,0 +1,198 @@
+use clap::Parser;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use crate::output::Output;
+use crate::{asn, index, source, table, yaml};
+use crate::{AsnArgs, Backend, LoadedMrt, MrtSource, NetblockArgs};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// A query of a batch file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchEntry {
+    /// Name used in logs and errors [default: the position of the query in the file]
+    name: Option<String>,
+    /// Arguments of find-netblocks, e.g. `["13335", "-4", "--format", "nginx"]`, without the
+    /// MRT source options, which are given to batch once for all queries
+    #[serde(deserialize_with = "deserialize_args")]
+    args: Vec<String>,
+    /// File the results are written to instead of stdout
+    output: Option<String>,
+}
+
+/// Reads the arguments of a query, keeping as written the numbers YAML types as integers, such
+/// as `13335` and `-4`.
+fn deserialize_args<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Vec::<Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(arg),
+            Value::Number(arg) => Ok(arg.to_string()),
+            arg => Err(D::Error::custom(format!(
+                "invalid argument {arg}, expected a string or a number"
+            ))),
+        })
+        .collect()
+}
+
+/// The find-netblocks arguments of a batch query
+#[derive(Parser, Debug)]
+#[command(name = "query", no_binary_name = true)]
+struct BatchQuery {
+    #[clap(flatten)]
+    asns: AsnArgs,
+
+    #[clap(flatten)]
+    args: NetblockArgs,
+}
+
+/// A batch query with its parsed arguments
+#[derive(Debug)]
+struct Query {
+    name: String,
+    args: BatchQuery,
+    output: Option<String>,
+}
+
+/// Reads and parses the queries of a batch file, a YAML list of mappings with the `name`,
+/// `args` and `output` of each query.
+fn read_queries(file_name: &str) -> Result<Vec<Query>, Box<dyn Error>> {
+    let contents = fs::read_to_string(file_name).map_err(|e| format!("{file_name}: {e}"))?;
+    let document = yaml::parse(&contents).map_err(|e| format!("{file_name}: {e}"))?;
+    let entries: Vec<BatchEntry> =
+        serde_json::from_value(document).map_err(|e| format!("{file_name}: {e}"))?;
+    if entries.is_empty() {
+        return Err(format!("{file_name} has no queries").into());
+    }
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            let name = entry.name.unwrap_or_else(|| format!("#{}", position + 1));
+            let args = BatchQuery::try_parse_from(&entry.args)
+                .map_err(|e| format!("Query {name}: {}", e.render().to_string().trim_end()))?;
+            let source = &args.args.source;
+            if source.mrt_file.is_some() || source.rrc.is_some() || source.url.is_some() {
+                return Err(format!(
+                    "Query {name}: give the MRT source to batch, it is shared by all queries"
+                )
+                .into());
+            }
+            if args.args.watch.is_some() || args.args.refresh_cron.is_some() {
+                return Err(format!(
+                    "Query {name}: --watch and --refresh-cron are not supported in batches"
+                )
+                .into());
+            }
+            Ok(Query {
+                name,
+                args,
+                output: entry.output,
+            })
+        })
+        .collect()
+}
+
+fn run_query(
+    query: &Query,
+    loaded: Option<&LoadedMrt>,
+    strict: bool,
+) -> Result<(), Box<dyn Error>> {
+    let args = &query.args;
+    let verify_cache_interval = Duration::from_secs(args.args.source.verify_cache_seconds);
+    let origin_asns = crate::resolve_asns(&args.asns, verify_cache_interval, strict)?;
+    asn::check_asns(&origin_asns, strict)?;
+    let Some(output) = &query.output else {
+        return crate::find_netblocks(&origin_asns, &args.args, loaded, &mut io::stdout());
+    };
+    debug!("Writing results to {output}");
+    let mut writer = Output::create(output)?;
+    crate::find_netblocks(&origin_asns, &args.args, loaded, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Answers every query of a batch file from a single download and parse of the MRT source.
+///
+/// Each query takes the arguments of find-netblocks and writes its results to its own output
+/// file, or to stdout. A failing query does not stop the others; the batch fails once all have
+/// run.
+pub fn run(file_name: &str, source: &MrtSource, strict: bool) -> Result<(), Box<dyn Error>> {
+    let mut queries = read_queries(file_name)?;
+
+    let loaded = if queries
+        .iter()
+        .any(|query| query.args.args.backend == Backend::Mrt)
+    {
+        let mrt_file = source::resolve_mrt(source)?;
+        let table = if source.no_index {
+            table::scan_origins(&mrt_file, false, false)?
+        } else {
+            index::load_or_build(&mrt_file)?
+        };
+        info!(
+            "Loaded {} prefixes from {mrt_file} for {} queries",
+            table.len(),
+            queries.len()
+        );
+        Some(LoadedMrt { mrt_file, table })
+    } else {
+        None
+    };
+
+    let total = queries.len();
+    let mut failed = 0;
+    for query in &mut queries {
+        query.args.args.source = source.clone();
+        let _span = info_span!("query", name = query.name.as_str()).entered();
+        if let Err(e) = run_query(query, loaded.as_ref(), strict) {
+            error!("Query {} failed: {e}", query.name);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{failed} of {total} queries failed").into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_yaml_queries() -> Result<(), Box<dyn Error>> {
+        let queries = read_queries(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/batch.yaml"))?;
+        let summary: Vec<(&str, &[u32], Option<&str>)> = queries
+            .iter()
+            .map(|query| {
+                (
+                    query.name.as_str(),
+                    query.args.asns.origin_asns.as_slice(),
+                    query.output.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "cloudflare",
+                    [13335, 209242].as_slice(),
+                    Some("cloudflare.conf")
+                ),
+                ("google", [15169].as_slice(), Some("google.json")),
+                ("#3", [16509].as_slice(), None),
+            ]
+        );
+        assert_eq!(queries[2].args.asns.org.as_deref(), Some("Amazon"));
+        Ok(())
+    }
+}
@@ -0,0 +1,616 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::render::{self, ReportFormat};
+use crate::source::CACHE_DIR;
+use crate::{download, index, source};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Directory holding downloaded content named by its SHA-256, and files derived from it
+const OBJECTS_DIR: &str = "objects";
+
+/// Directory holding one manifest per downloaded URL
+const MANIFESTS_DIR: &str = "urls";
+
+/// Directory of cached query results
+const RESULTS_DIR: &str = "results";
+
+/// Files younger than this are never collected, as a download may have stored its object but
+/// not yet written the manifest referencing it
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Metadata of a downloaded URL, pointing at the object holding its content. Times are unix
+/// seconds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub url: String,
+    /// File name of the object holding the content: its SHA-256 followed by the extension of
+    /// the URL
+    pub object: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Seconds after verification the server allows reusing the content without asking again,
+    /// from its Cache-Control or Expires header
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// When the content was downloaded
+    pub fetched_at: i64,
+    /// When the server last confirmed the content is current
+    pub verified_at: i64,
+    /// When the content was last used
+    pub used_at: i64,
+}
+
+impl Manifest {
+    /// Returns the path of the object holding the content.
+    pub fn object_path(&self) -> PathBuf {
+        object_path(&self.object)
+    }
+}
+
+pub fn object_path(object: &str) -> PathBuf {
+    Path::new(CACHE_DIR).join(OBJECTS_DIR).join(object)
+}
+
+pub fn manifest_path(url: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    Path::new(CACHE_DIR)
+        .join(MANIFESTS_DIR)
+        .join(format!("{:x}.json", hasher.finish()))
+}
+
+pub fn results_dir() -> PathBuf {
+    Path::new(CACHE_DIR).join(RESULTS_DIR)
+}
+
+/// Reads the manifest of a URL, returning `None` when there is none or its object is missing.
+pub fn read_manifest(path: &Path) -> Option<Manifest> {
+    let file = File::open(path).ok()?;
+    let manifest: Manifest = match serde_json::from_reader(BufReader::new(file)) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!("Ignoring unreadable cache manifest {}: {e}", path.display());
+            return None;
+        }
+    };
+    // With --cache-mode mrt-only only the decompressed copy of a gzip object is kept. The object
+    // is looked up next to the manifest, in the same cache.
+    let cache_dir = path
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""));
+    let object_path = cache_dir.join(OBJECTS_DIR).join(&manifest.object);
+    if !object_path.exists() && !Path::new(&format!("{}.mrt", object_path.display())).exists() {
+        debug!("Object of cache manifest {} is missing", path.display());
+        return None;
+    }
+    Some(manifest)
+}
+
+pub fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = download::temp_path(&path.display().to_string());
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer_pretty(&mut writer, manifest)?;
+    writer.flush()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Moves a downloaded file into the object store under its content hash. Content already in
+/// the store is kept and the new copy discarded, so URLs serving the same file share one object.
+pub fn store_object(temp_path: &str, object: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = object_path(object);
+    if path.exists() {
+        debug!(
+            "Content of {temp_path} is already cached as {}",
+            path.display()
+        );
+        fs::remove_file(temp_path)?;
+    } else {
+        fs::rename(temp_path, &path)?;
+    }
+    Ok(path)
+}
+
+/// Forgets the cached copy of a URL, removing its manifest and the object it pointed at.
+pub fn evict(url: &str, object_path: &Path) -> io::Result<()> {
+    for path in [manifest_path(url).as_path(), object_path] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => debug!("Evicted {}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+pub fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// Outcome of warming the cache with one MRT source
+#[derive(Debug, Serialize)]
+pub struct Warmed {
+    pub url: String,
+    /// Whether the download was already cached and current
+    pub cached: bool,
+    /// Number of prefixes in the index of the MRT file
+    pub prefixes: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Downloads and indexes MRT files on `threads` threads, so later queries of them find both
+/// cached. A source failing does not stop the others, its error is in its outcome.
+pub fn warm(urls: &[String], threads: usize, verify_cache_interval: Duration) -> Vec<Warmed> {
+    let warm_one = |url: &String| {
+        let _span = info_span!("warm", url).entered();
+        let warmed =
+            download::cached(url, Some(verify_cache_interval), None).and_then(|(_, cached)| {
+                let mrt_file = source::fetch_mrt(url, verify_cache_interval)?;
+                Ok((cached, index::load_or_build(&mrt_file)?.len()))
+            });
+        match warmed {
+            Ok((cached, prefixes)) => {
+                info!("Warmed {url} with {prefixes} prefixes");
+                Warmed {
+                    url: url.clone(),
+                    cached,
+                    prefixes: Some(prefixes),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                warn!("Could not warm {url}: {e}");
+                Warmed {
+                    url: url.clone(),
+                    cached: false,
+                    prefixes: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        }
+    };
+    let next = Mutex::new(urls.iter().enumerate());
+    let mut warmed: Vec<(usize, Warmed)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.clamp(1, urls.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut warmed = Vec::new();
+                    loop {
+                        let Some((index, url)) = next.lock().ok().and_then(|mut next| next.next())
+                        else {
+                            return warmed;
+                        };
+                        warmed.push((index, warm_one(url)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect()
+    });
+    warmed.sort_by_key(|(index, _)| *index);
+    warmed.into_iter().map(|(_, warmed)| warmed).collect()
+}
+
+pub fn render_warm(warmed: &[Warmed], format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    let status = |warmed: &Warmed| match (&warmed.error, warmed.cached) {
+        (Some(e), _) => format!("failed: {e}"),
+        (None, true) => "cached".to_string(),
+        (None, false) => "downloaded".to_string(),
+    };
+    let prefixes = |warmed: &Warmed| {
+        warmed
+            .prefixes
+            .map(|prefixes| prefixes.to_string())
+            .unwrap_or_default()
+    };
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), warmed)?,
+        ReportFormat::Text => {
+            for warmed in warmed {
+                println!("{} {} {}", warmed.url, status(warmed), prefixes(warmed));
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = warmed
+                .iter()
+                .map(|warmed| vec![warmed.url.clone(), status(warmed), prefixes(warmed)])
+                .collect();
+            render::write_table(&mut io::stdout(), &["URL", "STATUS", "PREFIXES"], &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// Files removed, or that would be removed, by a garbage collection
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub removed: Vec<String>,
+    pub bytes: u64,
+}
+
+impl GcReport {
+    fn remove(&mut self, path: &Path, dry_run: bool) -> io::Result<()> {
+        let size = fs::metadata(path).map(|metadata| metadata.len())?;
+        if !dry_run {
+            fs::remove_file(path)?;
+        }
+        debug!("Removed {}", path.display());
+        self.removed.push(path.display().to_string());
+        self.bytes += size;
+        Ok(())
+    }
+}
+
+fn age(path: &Path) -> Duration {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .unwrap_or_default()
+}
+
+fn list_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Returns the entry a lock file locks, or `None` for other files.
+fn locked_entry(path: &Path) -> Option<PathBuf> {
+    let name = file_name(path);
+    let entry = name.strip_suffix(".lock")?;
+    Some(path.with_file_name(entry))
+}
+
+/// Removes what the cache no longer needs: manifests of URLs unused for `max_age` together with
+/// objects no manifest references and the files derived from them, expired query results,
+/// abandoned temporary files, entries of the old per-URL layout and the lock files of entries
+/// that are gone. Entries another process holds locked are skipped, so content in use is never
+/// removed.
+pub fn gc(max_age: Duration, dry_run: bool) -> Result<GcReport, Box<dyn Error>> {
+    gc_in(Path::new(CACHE_DIR), max_age, dry_run)
+}
+
+/// Runs [`gc`] on the cache in `cache_dir`.
+fn gc_in(cache_dir: &Path, max_age: Duration, dry_run: bool) -> Result<GcReport, Box<dyn Error>> {
+    let mut report = GcReport::default();
+    let cutoff = now() - i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX);
+
+    let mut referenced = HashSet::new();
+    for path in list_files(&cache_dir.join(MANIFESTS_DIR))? {
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        match read_manifest(&path) {
+            Some(manifest) if manifest.used_at >= cutoff => {
+                let hash = manifest.object.split('.').next().unwrap_or_default();
+                referenced.insert(hash.to_string());
+            }
+            Some(manifest) => {
+                let Some(_lock) = download::try_lock_entry(&path)? else {
+                    debug!("Not expiring {}, it is in use", manifest.url);
+                    continue;
+                };
+                info!(
+                    "Expiring {}, unused since {}",
+                    manifest.url, manifest.used_at
+                );
+                report.remove(&path, dry_run)?;
+            }
+            None if age(&path) >= GC_GRACE_PERIOD => {
+                if let Some(_lock) = download::try_lock_entry(&path)? {
+                    report.remove(&path, dry_run)?;
+                }
+            }
+            None => {}
+        }
+    }
+
+    // Derived files such as decompressed copies and indexes are named after their object, and
+    // are locked while being written
+    let mut unreferenced: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut locks: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for path in list_files(&cache_dir.join(OBJECTS_DIR))? {
+        let file_name = file_name(&path);
+        let hash = file_name.split('.').next().unwrap_or_default().to_string();
+        if referenced.contains(&hash) {
+            continue;
+        }
+        match locked_entry(&path) {
+            Some(entry) => locks.entry(hash).or_default().push(entry),
+            None => unreferenced.entry(hash).or_default().push(path),
+        }
+    }
+    for (hash, files) in unreferenced {
+        let entries = locks.get(&hash).map_or(&[][..], Vec::as_slice);
+        let mut held = Vec::new();
+        for entry in entries {
+            match download::try_lock_entry(entry)? {
+                Some(lock) => held.push(lock),
+                None => break,
+            }
+        }
+        if held.len() < entries.len() {
+            debug!("Not collecting object {hash}, it is in use");
+            continue;
+        }
+        for path in files {
+            if age(&path) >= GC_GRACE_PERIOD {
+                report.remove(&path, dry_run)?;
+            }
+        }
+    }
+
+    for path in list_files(&cache_dir.join(RESULTS_DIR))? {
+        if age(&path) >= max_age.max(GC_GRACE_PERIOD) {
+            report.remove(&path, dry_run)?;
+        }
+    }
+
+    for path in list_files(cache_dir)? {
+        let file_name = file_name(&path);
+        // The old layout named entries by a hash of their URL, e.g. 5478569a1a2aa4c4-bview.gz
+        let legacy = file_name.split_once('-').is_some_and(|(hash, _)| {
+            !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        if legacy && age(&path) >= GC_GRACE_PERIOD {
+            report.remove(&path, dry_run)?;
+        }
+    }
+
+    for dir in [OBJECTS_DIR, MANIFESTS_DIR, RESULTS_DIR] {
+        for path in list_files(&cache_dir.join(dir))? {
+            if path.extension().is_some_and(|extension| extension == "tmp")
+                && age(&path) >= GC_GRACE_PERIOD
+            {
+                report.remove(&path, dry_run)?;
+            }
+        }
+    }
+
+    // A lock file is only removed while holding it, so no other process holds it or waits for it
+    for dir in [OBJECTS_DIR, MANIFESTS_DIR] {
+        for path in list_files(&cache_dir.join(dir))? {
+            let Some(entry) = locked_entry(&path) else {
+                continue;
+            };
+            let removed = report.removed.contains(&entry.display().to_string());
+            if entry.exists() && !removed {
+                continue;
+            }
+            if let Some(_lock) = download::try_lock_entry(&entry)? {
+                report.remove(&path, dry_run)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Removes the least recently used downloads, with the objects and derived files only they
+/// reference, until at least `bytes` are freed. Entries another download or decompression holds
+/// locked are skipped, so content in use is never removed.
+pub fn prune(bytes: u64) -> Result<GcReport, Box<dyn Error>> {
+    let mut report = GcReport::default();
+    let mut manifests: Vec<(PathBuf, Manifest)> =
+        list_files(&Path::new(CACHE_DIR).join(MANIFESTS_DIR))?
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .filter_map(|path| read_manifest(&path).map(|manifest| (path, manifest)))
+            .collect();
+    manifests.sort_by_key(|(_, manifest)| manifest.used_at);
+    let hash = |manifest: &Manifest| {
+        manifest
+            .object
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut references: HashMap<String, usize> = HashMap::new();
+    for (_, manifest) in &manifests {
+        *references.entry(hash(manifest)).or_default() += 1;
+    }
+    let objects = list_files(&Path::new(CACHE_DIR).join(OBJECTS_DIR))?;
+
+    for (path, manifest) in &manifests {
+        if report.bytes >= bytes {
+            break;
+        }
+        let Some(_lock) = download::try_lock_entry(path)? else {
+            debug!("Not pruning {}, it is in use", manifest.url);
+            continue;
+        };
+        info!(
+            "Pruning {}, unused since {}",
+            manifest.url, manifest.used_at
+        );
+        report.remove(path, false)?;
+        let hash = hash(manifest);
+        let remaining = references.entry(hash.clone()).or_default();
+        *remaining = remaining.saturating_sub(1);
+        if *remaining > 0 {
+            continue;
+        }
+        // Decompressed copies are locked while being written
+        let derived_lock = object_path(&format!("{}.mrt", manifest.object));
+        let Some(_derived_lock) = download::try_lock_entry(&derived_lock)? else {
+            continue;
+        };
+        for object in &objects {
+            let file_name = file_name(object);
+            if file_name.split('.').next() == Some(hash.as_str())
+                && !file_name.ends_with(".lock")
+                && object.exists()
+            {
+                report.remove(object, false)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+pub fn render_gc(
+    report: &GcReport,
+    dry_run: bool,
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), report)?,
+        ReportFormat::Text => {
+            for path in &report.removed {
+                println!("{path}");
+            }
+            info!(
+                "{verb} {} files, {} bytes",
+                report.removed.len(),
+                report.bytes
+            );
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = report
+                .removed
+                .iter()
+                .map(|path| vec![path.clone()])
+                .collect();
+            render::write_table(&mut io::stdout(), &["PATH"], &rows)?;
+            info!(
+                "{verb} {} files, {} bytes",
+                report.removed.len(),
+                report.bytes
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::time::SystemTime;
+
+    /// Creates a test cache directory holding the objects, manifests and results directories.
+    fn cache_dir(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("bgp-scout-gc-{name}-{}", process::id()));
+        for subdir in [OBJECTS_DIR, MANIFESTS_DIR, RESULTS_DIR] {
+            fs::create_dir_all(dir.join(subdir))?;
+        }
+        Ok(dir)
+    }
+
+    /// Writes a file last modified `age` ago.
+    fn write_file(path: &Path, age: Duration) -> Result<(), Box<dyn Error>> {
+        fs::write(path, b"content")?;
+        File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(SystemTime::now() - age)?;
+        Ok(())
+    }
+
+    #[test]
+    fn gc_keeps_files_in_grace_period() -> Result<(), Box<dyn Error>> {
+        let dir = cache_dir("grace")?;
+        let old = GC_GRACE_PERIOD * 2;
+        let old_object = dir.join(OBJECTS_DIR).join("1234.gz");
+        let new_object = dir.join(OBJECTS_DIR).join("5678.gz");
+        let new_temp = dir.join(MANIFESTS_DIR).join("5678.json.tmp");
+        write_file(&old_object, old)?;
+        write_file(&new_object, Duration::ZERO)?;
+        write_file(&new_temp, Duration::ZERO)?;
+
+        let dry_run = gc_in(&dir, Duration::ZERO, true)?;
+        let report = gc_in(&dir, Duration::ZERO, false)?;
+        let remaining = [&old_object, &new_object, &new_temp].map(|path| path.exists());
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(dry_run.removed, report.removed);
+        assert_eq!(report.removed, [old_object.display().to_string()]);
+        assert_eq!(report.bytes, 7);
+        assert_eq!(remaining, [false, true, true]);
+        Ok(())
+    }
+
+    #[test]
+    fn gc_skips_locked_objects() -> Result<(), Box<dyn Error>> {
+        let dir = cache_dir("locked")?;
+        let object = dir.join(OBJECTS_DIR).join("1234.gz");
+        let derived = dir.join(OBJECTS_DIR).join("1234.gz.mrt");
+        write_file(&object, GC_GRACE_PERIOD * 2)?;
+        write_file(&derived, GC_GRACE_PERIOD * 2)?;
+
+        // Another process decompressing the object holds the lock of its decompressed copy
+        let lock = download::try_lock_entry(&derived)?;
+        let locked = gc_in(&dir, Duration::ZERO, false)?;
+        drop(lock);
+        let unlocked = gc_in(&dir, Duration::ZERO, false)?;
+        let remaining = list_files(&dir.join(OBJECTS_DIR))?;
+        fs::remove_dir_all(&dir)?;
+        assert!(locked.removed.is_empty());
+        assert_eq!(unlocked.removed.len(), 3);
+        assert!(remaining.is_empty(), "{remaining:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn gc_removes_stale_locks() -> Result<(), Box<dyn Error>> {
+        let dir = cache_dir("stale")?;
+        let manifest = dir.join(MANIFESTS_DIR).join("1234.json");
+        let manifest_lock = dir.join(MANIFESTS_DIR).join("1234.json.lock");
+        let stale_lock = dir.join(MANIFESTS_DIR).join("5678.json.lock");
+        let held_lock = dir.join(MANIFESTS_DIR).join("9abc.json.lock");
+        // The lock file of an existing entry, one without an entry, and one held by a download
+        // that has not written its entry yet
+        write_file(&manifest, Duration::ZERO)?;
+        write_file(&manifest_lock, Duration::ZERO)?;
+        write_file(&stale_lock, Duration::ZERO)?;
+        let lock = download::try_lock_entry(&dir.join(MANIFESTS_DIR).join("9abc.json"))?;
+
+        let report = gc_in(&dir, Duration::ZERO, false)?;
+        let remaining =
+            [&manifest, &manifest_lock, &stale_lock, &held_lock].map(|path| path.exists());
+        drop(lock);
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(report.removed, [stale_lock.display().to_string()]);
+        assert_eq!(remaining, [true, true, false, true]);
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::blocking::Client;
+use reqwest::header::LAST_MODIFIED;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::render::{self, ReportFormat};
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const RRC_INFO_URL: &str = "https://stat.ripe.net/data/rrc-info/data.json";
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Geographically spread collectors probed by `--rrc auto`
+const PROBED_RRCS: [u8; 10] = [0, 1, 6, 11, 14, 15, 19, 23, 24, 26];
+
+/// RIS dumps a RIB every eight hours, so older latest dumps indicate a stalled collector
+const MAX_RIB_AGE: TimeDelta = TimeDelta::hours(16);
+
+const NEAREST_RRC_CACHE_FILE: &str = "nearest-rrc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Project {
+    Ris,
+    Routeviews,
+}
+
+/// A route collector, with the live details filled in when requested
+#[derive(Debug, Serialize)]
+pub struct Collector {
+    pub name: String,
+    pub project: Project,
+    pub location: &'static str,
+    /// Value to pass to `--rrc` for RIPE RIS collectors
+    pub rrc: Option<u8>,
+    pub active: bool,
+    pub peers: Option<usize>,
+    pub latest_file: Option<String>,
+}
+
+/// RIPE RIS collectors as (number, location, active)
+const RIS_COLLECTORS: [(u8, &str, bool); 26] = [
+    (0, "Amsterdam, NL (multihop)", true),
+    (1, "London, GB (LINX, LONAP)", true),
+    (2, "Paris, FR (SFINX)", false),
+    (3, "Amsterdam, NL (AMS-IX, NL-IX)", true),
+    (4, "Geneva, CH (CIXP)", true),
+    (5, "Vienna, AT (VIX)", true),
+    (6, "Otemachi, JP (DIX-IE, JPIX)", true),
+    (7, "Stockholm, SE (Netnod)", true),
+    (8, "San Jose, US (MAE-West)", false),
+    (9, "Zurich, CH (TIX)", false),
+    (10, "Milan, IT (MIX)", true),
+    (11, "New York, US (NYIIX)", true),
+    (12, "Frankfurt, DE (DE-CIX)", true),
+    (13, "Moscow, RU (MSK-IX)", true),
+    (14, "Palo Alto, US (PAIX)", true),
+    (15, "Sao Paulo, BR (PTTMetro-SP)", true),
+    (16, "Miami, US (Equinix MI1)", true),
+    (18, "Barcelona, ES (CATNIX)", true),
+    (19, "Johannesburg, ZA (NAP Africa JB)", true),
+    (20, "Zurich, CH (SwissIX)", true),
+    (21, "Paris, FR (France-IX)", true),
+    (22, "Bucharest, RO (InterLAN)", true),
+    (23, "Singapore, SG (Equinix SG)", true),
+    (24, "Montevideo, UY (LACNIC region multihop)", true),
+    (25, "Amsterdam, NL (multihop)", true),
+    (26, "Dubai, AE (UAE-IX)", true),
+];
+
+/// RouteViews collectors as (name, location)
+const ROUTEVIEWS_COLLECTORS: [(&str, &str); 16] = [
+    ("route-views2", "Eugene, US (multihop)"),
+    ("route-views3", "Eugene, US (multihop)"),
+    ("route-views4", "Eugene, US (multihop)"),
+    ("route-views6", "Eugene, US (IPv6 multihop)"),
+    ("route-views.amsix", "Amsterdam, NL (AMS-IX)"),
+    ("route-views.chicago", "Chicago, US (Equinix CH1)"),
+    ("route-views.eqix", "Ashburn, US (Equinix DC)"),
+    ("route-views.isc", "Palo Alto, US (PAIX)"),
+    ("route-views.kixp", "Nairobi, KE (KIXP)"),
+    ("route-views.linx", "London, GB (LINX)"),
+    ("route-views.napafrica", "Johannesburg, ZA (NAP Africa)"),
+    ("route-views.nwax", "Portland, US (NWAX)"),
+    ("route-views.saopaulo", "Sao Paulo, BR (PTTMetro-SP)"),
+    ("route-views.sg", "Singapore, SG (Equinix SG)"),
+    ("route-views.sydney", "Sydney, AU (Equinix SY)"),
+    ("route-views.wide", "Tokyo, JP (DIX-IE)"),
+];
+
+#[derive(Debug, Deserialize)]
+struct RrcInfoResponse {
+    data: RrcInfoData,
+}
+
+#[derive(Debug, Deserialize)]
+struct RrcInfoData {
+    rrcs: Vec<RrcInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RrcInfo {
+    id: u8,
+    #[serde(default)]
+    peers: Vec<serde_json::Value>,
+}
+
+/// Returns the embedded collector catalogue, optionally fetching RIS peer counts from RIPEstat
+/// and the publication time of the latest RIS RIB dumps.
+pub fn catalogue(
+    live: bool,
+    verify_cache_interval: Duration,
+) -> Result<Vec<Collector>, Box<dyn Error>> {
+    let mut collectors: Vec<Collector> = RIS_COLLECTORS
+        .iter()
+        .map(|&(rrc, location, active)| Collector {
+            name: format!("rrc{rrc:02}"),
+            project: Project::Ris,
+            location,
+            rrc: Some(rrc),
+            active,
+            peers: None,
+            latest_file: None,
+        })
+        .chain(
+            ROUTEVIEWS_COLLECTORS
+                .iter()
+                .map(|&(name, location)| Collector {
+                    name: name.to_string(),
+                    project: Project::Routeviews,
+                    location,
+                    rrc: None,
+                    active: true,
+                    peers: None,
+                    latest_file: None,
+                }),
+        )
+        .collect();
+
+    if live {
+        let path = source::fetch_file(RRC_INFO_URL, verify_cache_interval)?;
+        let response: RrcInfoResponse = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let peer_counts: HashMap<u8, usize> = response
+            .data
+            .rrcs
+            .iter()
+            .map(|rrc| (rrc.id, rrc.peers.len()))
+            .collect();
+
+        let client = Client::builder().timeout(NETWORK_TIMEOUT).build()?;
+        for collector in &mut collectors {
+            let Some(rrc) = collector.rrc.filter(|_| collector.active) else {
+                continue;
+            };
+            collector.peers = peer_counts.get(&rrc).copied();
+            let url = source::ripe_bview_url(rrc);
+            match client.head(&url).send() {
+                Ok(response) => {
+                    collector.latest_file = response
+                        .headers()
+                        .get(LAST_MODIFIED)
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                }
+                Err(e) => warn!("Could not check latest RIB dump of {}: {e}", collector.name),
+            }
+        }
+    }
+    Ok(collectors)
+}
+
+/// Returns whether the RIPE RIS collector still publishes RIB dumps.
+pub fn is_active_rrc(rrc: u8) -> bool {
+    RIS_COLLECTORS
+        .iter()
+        .any(|&(number, _, active)| number == rrc && active)
+}
+
+/// Picks the collector with the lowest HTTP latency among those publishing fresh RIB dumps. The
+/// choice is cached for the cache verification interval.
+pub fn nearest_rrc(verify_cache_interval: Duration) -> Result<u8, Box<dyn Error>> {
+    let cache_file = Path::new(source::CACHE_DIR).join(NEAREST_RRC_CACHE_FILE);
+    let cached_age = fs::metadata(&cache_file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+    if cached_age.is_some_and(|age| age < verify_cache_interval) {
+        if let Ok(rrc) = fs::read_to_string(&cache_file)?.trim().parse::<u8>() {
+            debug!("Using cached nearest collector rrc{rrc:02}");
+            return Ok(rrc);
+        }
+    }
+
+    let client = Client::builder().timeout(NETWORK_TIMEOUT).build()?;
+    let mut best: Option<(Duration, u8)> = None;
+    for rrc in PROBED_RRCS {
+        let url = source::ripe_bview_url(rrc);
+        let started = Instant::now();
+        let response = match client.head(&url).send() {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                debug!("Skipping rrc{rrc:02}, {url} returned {}", response.status());
+                continue;
+            }
+            Err(e) => {
+                debug!("Skipping rrc{rrc:02}: {e}");
+                continue;
+            }
+        };
+        let latency = started.elapsed();
+        let fresh = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .is_some_and(|modified| Utc::now() - modified.with_timezone(&Utc) < MAX_RIB_AGE);
+        debug!(
+            "Probed rrc{rrc:02}: {} ms, fresh: {fresh}",
+            latency.as_millis()
+        );
+        if fresh && best.is_none_or(|(best_latency, _)| latency < best_latency) {
+            best = Some((latency, rrc));
+        }
+    }
+
+    let (_, rrc) = best.ok_or("No RIPE RIS collector with a fresh RIB dump could be reached")?;
+    info!("Selected nearest collector rrc{rrc:02}");
+    fs::create_dir_all(source::CACHE_DIR)?;
+    fs::write(&cache_file, format!("{rrc}\n"))?;
+    Ok(rrc)
+}
+
+pub fn render_catalogue(
+    collectors: &[Collector],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    if format == ReportFormat::Json {
+        serde_json::to_writer(io::stdout(), collectors)?;
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = collectors
+        .iter()
+        .map(|collector| {
+            vec![
+                collector.name.clone(),
+                match collector.project {
+                    Project::Ris => "RIS".to_string(),
+                    Project::Routeviews => "RouteViews".to_string(),
+                },
+                collector
+                    .rrc
+                    .map_or_else(|| "-".to_string(), |rrc| rrc.to_string()),
+                collector.location.to_string(),
+                if collector.active { "yes" } else { "no" }.to_string(),
+                collector
+                    .peers
+                    .map_or_else(|| "-".to_string(), |peers| peers.to_string()),
+                collector
+                    .latest_file
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    render::write_table(
+        &mut io::stdout(),
+        &[
+            "NAME",
+            "PROJECT",
+            "RRC",
+            "LOCATION",
+            "ACTIVE",
+            "PEERS",
+            "LATEST RIB",
+        ],
+        &rows,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, Datelike, DurationRound, TimeDelta, Timelike, Utc};
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// How far ahead a run is searched for, covering schedules such as February 29th on a Monday
+const SEARCH_DAYS: i64 = 366 * 28;
+
+/// A cron schedule of five fields, minute, hour, day of month, month and day of week, such as
+/// `0 */4 * * *`, evaluated in UTC. Each field takes `*`, values, ranges, steps and lists of
+/// them. As in cron, a run matching either the day of month or the day of week is due when both
+/// are restricted.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether both day fields are restricted, making either of them match
+    any_day: bool,
+}
+
+/// Parses a field into a bit set of the values it matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0_u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("Invalid step in {part}"))?,
+            ),
+            None => (part, 1),
+        };
+        let parse = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("{value} is not between {min} and {max}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            // A single value with a step runs to the end of the field, as in cron
+            None if part.contains('/') => (parse(range)?, max),
+            None => {
+                let value = parse(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            return Err(format!("Invalid range {range}"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields.as_slice() else {
+            return Err(format!(
+                "Invalid cron schedule {expression}, expected 5 fields such as \"0 */4 * * *\""
+            ));
+        };
+        let field_error = |name: &str, e: String| format!("Invalid {name} in {expression}: {e}");
+        let mut weekday_bits =
+            parse_field(weekdays, 0, 7).map_err(|e| field_error("day of week", e))?;
+        // Both 0 and 7 are Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_field(minutes, 0, 59).map_err(|e| field_error("minute", e))?,
+            hours: parse_field(hours, 0, 23).map_err(|e| field_error("hour", e))?,
+            days: parse_field(days, 1, 31).map_err(|e| field_error("day of month", e))?,
+            months: parse_field(months, 1, 12).map_err(|e| field_error("month", e))?,
+            weekdays: weekday_bits,
+            any_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+const fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl Schedule {
+    fn day_matches(&self, time: DateTime<Utc>) -> bool {
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        has(self.months, time.month())
+            && if self.any_day {
+                day || weekday
+            } else {
+                day && weekday
+            }
+    }
+
+    /// Returns the first run strictly after the given time.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = time.duration_trunc(TimeDelta::minutes(1)).ok()? + TimeDelta::minutes(1);
+        let end = time + TimeDelta::days(SEARCH_DAYS);
+        while next < end {
+            if !self.day_matches(next) {
+                next = next.duration_trunc(TimeDelta::days(1)).ok()? + TimeDelta::days(1);
+            } else if !has(self.hours, next.hour()) {
+                next = next.duration_trunc(TimeDelta::hours(1)).ok()? + TimeDelta::hours(1);
+            } else if !has(self.minutes, next.minute()) {
+                next += TimeDelta::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    /// Sleeps until the next run, returning its time.
+    pub fn wait(&self) -> Result<DateTime<Utc>, String> {
+        let next = self
+            .next_after(Utc::now())
+            .ok_or_else(|| format!("Cron schedule {self} never runs"))?;
+        debug!("Next scheduled run at {next}");
+        if let Ok(delay) = (next - Utc::now()).to_std() {
+            thread::sleep(delay);
+        }
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    /// Returns the next run of a schedule after a time, both as `YYYY-MM-DD HH:MM` in UTC.
+    fn next_run(schedule: &str, after: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let schedule: Schedule = schedule.parse()?;
+        let after = format!("{after}:00Z").replacen(' ', "T", 1).parse()?;
+        Ok(schedule
+            .next_after(after)
+            .map(|next| next.format("%Y-%m-%d %H:%M").to_string()))
+    }
+
+    #[test]
+    fn steps_and_lists() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            next_run("0 */4 * * *", "2024-01-01 05:30")?.as_deref(),
+            Some("2024-01-01 08:00")
+        );
+        assert_eq!(
+            next_run("15,45 9-17 * * *", "2024-01-01 17:50")?.as_deref(),
+            Some("2024-01-02 09:15")
+        );
+        assert_eq!(
+            next_run("10/20 * * * *", "2024-01-01 00:31")?.as_deref(),
+            Some("2024-01-01 00:50")
+        );
+        assert_eq!(
+            next_run("@monthly", "2024-01-31 12:00")?.as_deref(),
+            Some("2024-02-01 00:00")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn runs_strictly_after() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            next_run("0 8 * * *", "2024-01-01 08:00")?.as_deref(),
+            Some("2024-01-02 08:00")
+        );
+        let schedule: Schedule = "0 8 * * *".parse()?;
+        let after = "2024-01-01T07:59:30Z".parse()?;
+        assert_eq!(
+            schedule.next_after(after).map(|next| next.to_rfc3339()),
+            Some("2024-01-01T08:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            next_run("59 23 31 12 *", "2024-12-31 23:59")?.as_deref(),
+            Some("2025-12-31 23:59")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn day_fields() -> Result<(), Box<dyn Error>> {
+        // Restricting both day fields runs on either, September 6th 2024 being a Friday
+        assert_eq!(
+            next_run("0 0 13 * 5", "2024-09-01 00:00")?.as_deref(),
+            Some("2024-09-06 00:00")
+        );
+        assert_eq!(
+            next_run("0 0 13 * 5", "2024-09-10 00:00")?.as_deref(),
+            Some("2024-09-13 00:00")
+        );
+        // Only the day of week, with 7 as Sunday
+        assert_eq!(
+            next_run("0 12 * * 7", "2024-09-02 00:00")?.as_deref(),
+            Some("2024-09-08 12:00")
+        );
+        assert_eq!(
+            next_run("30 2 29 2 *", "2025-03-01 00:00")?.as_deref(),
+            Some("2028-02-29 02:30")
+        );
+        assert_eq!(next_run("0 0 31 2 *", "2024-01-01 00:00")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        for schedule in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(schedule.parse::<Schedule>().is_err(), "{schedule}");
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use ipnet::IpNet;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ipmap::IpMapping;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const CYMRU_WHOIS: &str = "whois.cymru.com:43";
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Team Cymru asks bulk users to keep each session to a reasonable number of queries
+const IPS_PER_SESSION: usize = 10_000;
+
+/// Maps IP addresses to their announced prefix and origin ASN using the Team Cymru bulk whois
+/// interface, so no local RIB is required.
+pub fn map_ips(ips: &[IpAddr]) -> Result<Vec<IpMapping>, Box<dyn Error>> {
+    let mut mappings = Vec::with_capacity(ips.len());
+    for chunk in ips.chunks(IPS_PER_SESSION) {
+        mappings.extend(query(chunk)?);
+    }
+    Ok(mappings)
+}
+
+fn query(ips: &[IpAddr]) -> Result<Vec<IpMapping>, Box<dyn Error>> {
+    debug!("Querying {CYMRU_WHOIS} for {} addresses", ips.len());
+    let mut stream = TcpStream::connect(CYMRU_WHOIS)?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+
+    let mut request = String::from("begin\nverbose\n");
+    for ip in ips {
+        request.push_str(&ip.to_string());
+        request.push('\n');
+    }
+    request.push_str("end\n");
+    stream.write_all(request.as_bytes())?;
+
+    let mut mappings = Vec::with_capacity(ips.len());
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        match parse_line(&line) {
+            Some(mapping) => mappings.push(mapping),
+            None => trace!("Skipping Cymru response line {line}"),
+        }
+    }
+    Ok(mappings)
+}
+
+/// Parses a verbose bulk response line of the form
+/// `AS | IP | BGP Prefix | CC | Registry | Allocated | AS Name`.
+fn parse_line(line: &str) -> Option<IpMapping> {
+    let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+    if fields.len() < 7 {
+        return None;
+    }
+    let ip = IpAddr::from_str(fields[1]).ok()?;
+    let origin_asns = fields[0]
+        .split_whitespace()
+        .filter_map(|asn| asn.parse().ok())
+        .collect();
+
+    Some(IpMapping {
+        ip,
+        prefix: IpNet::from_str(fields[2]).ok(),
+        origin_asns,
+        as_name: Some(fields[6].to_string()).filter(|name| !name.is_empty() && name != "NA"),
+        country: Some(fields[3].to_string()).filter(|cc| !cc.is_empty()),
+        status: None,
+        registration: None,
+    })
+}
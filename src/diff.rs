@@ -0,0 +1,158 @@
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+
+use crate::firewall::{self, ApplyTarget, SetTarget};
+use crate::render::{self, Format};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Deployed list the results are compared against
+#[derive(Debug, Clone)]
+pub enum Existing {
+    /// nftables set, with the family and table holding it
+    Nft {
+        table: String,
+        set: String,
+    },
+    Ipset(String),
+    /// File with one prefix or address per line
+    File(String),
+}
+
+/// Parses `nft:<table>/<set>`, `ipset:<set>`, `file:<path>` or a bare file path. The nftables
+/// table may start with its family, e.g. `nft:inet filter/blocklist`.
+pub fn parse_existing(value: &str) -> Result<Existing, String> {
+    if let Some(rest) = value.strip_prefix("nft:") {
+        return match rest.rsplit_once('/') {
+            Some((table, set)) if !table.trim().is_empty() && !set.is_empty() => {
+                Ok(Existing::Nft {
+                    table: table.trim().to_string(),
+                    set: set.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "Invalid nftables set {rest}, expected nft:<table>/<set>"
+            )),
+        };
+    }
+    if let Some(set) = value.strip_prefix("ipset:") {
+        if set.is_empty() {
+            return Err("Missing ipset name, expected ipset:<set>".to_string());
+        }
+        return Ok(Existing::Ipset(set.to_string()));
+    }
+    let path = value.strip_prefix("file:").unwrap_or(value);
+    if path.is_empty() {
+        return Err("Missing file name".to_string());
+    }
+    Ok(Existing::File(path.to_string()))
+}
+
+/// Changes that turn the deployed list into the results
+#[derive(Debug, Serialize)]
+pub struct ListDiff {
+    pub added: Vec<IpNet>,
+    pub removed: Vec<IpNet>,
+    pub unchanged: usize,
+}
+
+/// Reads a list of prefixes, skipping blank lines and `#` comments. Addresses without a length
+/// are read as host prefixes, and anything after the first word of a line is ignored.
+fn read_file(file_name: &str) -> Result<BTreeSet<IpNet>, Box<dyn Error>> {
+    let contents = fs::read_to_string(file_name).map_err(|e| format!("{file_name}: {e}"))?;
+    let mut prefixes = BTreeSet::new();
+    for (number, line) in contents.lines().enumerate() {
+        let Some(word) = line.split_whitespace().next() else {
+            continue;
+        };
+        if word.starts_with('#') {
+            continue;
+        }
+        let prefix = firewall::parse_member(word)
+            .ok_or_else(|| format!("{file_name}:{}: invalid prefix {word}", number + 1))?;
+        prefixes.insert(prefix);
+    }
+    Ok(prefixes)
+}
+
+/// Compares the results against a deployed list. nftables sets and ipsets only hold one address
+/// family, so results of the other family are left out of the comparison.
+pub fn diff(existing: &Existing, prefixes: &[IpNet]) -> Result<ListDiff, Box<dyn Error>> {
+    let (current, family_v6) = match existing {
+        Existing::Nft { table, set } => {
+            let contents = firewall::read_set(&SetTarget {
+                kind: ApplyTarget::Nft,
+                name: set,
+                nft_table: table,
+            })?;
+            (contents.prefixes, Some(contents.is_v6))
+        }
+        Existing::Ipset(set) => {
+            let contents = firewall::read_set(&SetTarget {
+                kind: ApplyTarget::Ipset,
+                name: set,
+                nft_table: "",
+            })?;
+            (contents.prefixes, Some(contents.is_v6))
+        }
+        Existing::File(file_name) => (read_file(file_name)?, None),
+    };
+    let wanted: BTreeSet<IpNet> = prefixes
+        .iter()
+        .filter(|prefix| family_v6.is_none_or(|is_v6| matches!(prefix, IpNet::V6(_)) == is_v6))
+        .map(IpNet::trunc)
+        .collect();
+    let skipped = prefixes.len() - wanted.len();
+    if skipped > 0 {
+        warn!("Leaving out {skipped} prefixes not matching the address family of the set");
+    }
+    Ok(ListDiff {
+        added: wanted.difference(&current).copied().collect(),
+        removed: current.difference(&wanted).copied().collect(),
+        unchanged: wanted.intersection(&current).count(),
+    })
+}
+
+/// Writes the changes as JSON, a table, or otherwise as `+ prefix` and `- prefix` lines.
+pub fn render_diff(
+    output: &mut dyn Write,
+    diff: &ListDiff,
+    format: Format,
+    ranges: bool,
+) -> Result<(), Box<dyn Error>> {
+    let changes = diff
+        .removed
+        .iter()
+        .map(|prefix| ("removed", "-", prefix))
+        .chain(diff.added.iter().map(|prefix| ("added", "+", prefix)));
+    match format {
+        Format::Json => {
+            serde_json::to_writer(&mut *output, diff)?;
+            writeln!(output)?;
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = changes
+                .map(|(change, _, prefix)| {
+                    vec![change.to_string(), render::format_prefix(prefix, ranges)]
+                })
+                .collect();
+            render::write_table(output, &["CHANGE", "PREFIX"], &rows)?;
+        }
+        _ => {
+            for (_, sign, prefix) in changes {
+                writeln!(output, "{sign} {}", render::format_prefix(prefix, ranges))?;
+            }
+        }
+    }
+    info!(
+        "{} prefixes added, {} removed, {} unchanged",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.unchanged
+    );
+    Ok(())
+}
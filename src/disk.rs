@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cache;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Estimated size of a decompressed MRT file relative to its gzip download, on the high side of
+/// what RIB dumps and update files compress by
+pub const DECOMPRESSION_RATIO: u64 = 5;
+
+/// Whether --prune-cache was given, letting the preflight checks evict old downloads
+static PRUNE_CACHE: AtomicBool = AtomicBool::new(false);
+
+/// Lets the preflight checks remove the least recently used downloads when short of space.
+pub fn prune_cache_when_full() {
+    PRUNE_CACHE.store(true, Ordering::Relaxed);
+}
+
+/// Returns the bytes available to unprivileged users on the filesystem holding the path, or
+/// `None` when it cannot be told.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+// The field types of statvfs differ between platforms
+#[allow(clippy::useless_conversion)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is NUL-terminated and stat points at memory the size of a statvfs, which
+    // statvfs only reads the path from and writes the statistics to.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs returned 0, so it filled in stat.
+    let stat = unsafe { stat.assume_init() };
+    u64::from(stat.f_bavail).checked_mul(u64::from(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn megabytes(bytes: u64) -> u64 {
+    bytes.div_ceil(1 << 20)
+}
+
+/// Checks that the filesystem of `dir` has room for `needed` more bytes of `what`, failing with
+/// how much is missing instead of running out of space mid-write. With --prune-cache the least
+/// recently used downloads are removed to make room first.
+pub fn ensure_space(dir: &Path, needed: u64, what: &str) -> Result<(), Box<dyn Error>> {
+    let Some(available) = available_space(dir) else {
+        debug!("Cannot tell the free space of {}", dir.display());
+        return Ok(());
+    };
+    if available >= needed {
+        return Ok(());
+    }
+    if PRUNE_CACHE.load(Ordering::Relaxed) {
+        let report = cache::prune(needed - available)?;
+        info!(
+            "Removed {} cached files, {} MB, to make room for {what}",
+            report.removed.len(),
+            megabytes(report.bytes)
+        );
+        if available_space(dir).is_none_or(|available| available >= needed) {
+            return Ok(());
+        }
+    }
+    let hint = if PRUNE_CACHE.load(Ordering::Relaxed) {
+        "Free up space"
+    } else {
+        "Free up space, run cache gc, or pass --prune-cache to remove old downloads"
+    };
+    Err(format!(
+        "Not enough disk space for {what}: it needs about {} MB in {} but only {} MB are free. \
+         {hint}",
+        megabytes(needed),
+        dir.display(),
+        megabytes(available_space(dir).unwrap_or(available))
+    )
+    .into())
+}
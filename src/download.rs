@@ -1,298 +1,798 @@
-use chrono::{DateTime, Utc};
-use filetime::FileTime;
-use reqwest::blocking::Client;
-use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH};
-use reqwest::StatusCode;
+use chrono::DateTime;
+use clap::ValueEnum;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AGE, CACHE_CONTROL, CONTENT_LENGTH, DATE, ETAG, EXPIRES,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Certificate, Identity};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::env;
 use std::error::Error;
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 
-use crate::gzip;
+use crate::{archive, cache, disk, gzip, index, netrc, storage, telemetry};
 #[allow(unused_imports)]
-use log::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn};
 
-/// Downloads a file from the given URL and caches it.
+/// Number of times a download is attempted before giving up on truncated or corrupt content
+const DOWNLOAD_ATTEMPTS: u32 = 2;
+
+/// Which copies of a gzipped download the cache keeps
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Keep the download and its decompressed copy
+    #[default]
+    Both,
+    /// Keep only the download, decompressing it while reading
+    GzOnly,
+    /// Keep only the decompressed copy
+    MrtOnly,
+}
+
+/// The --cache-mode of this run
+static CACHE_MODE: OnceLock<CacheMode> = OnceLock::new();
+
+/// Sets which copies of gzipped downloads the cache keeps from now on.
+pub fn set_cache_mode(mode: CacheMode) {
+    let _ = CACHE_MODE.set(mode);
+}
+
+fn cache_mode() -> CacheMode {
+    CACHE_MODE.get().copied().unwrap_or_default()
+}
+
+/// Removes a cached copy the cache mode does not keep, with its index.
+fn remove_copy(file_name: &str) -> io::Result<()> {
+    for path in [file_name.to_string(), index::index_path(file_name)] {
+        match fs::remove_file(&path) {
+            Ok(()) => debug!("Removed {path}"),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Takes an exclusive advisory lock on a cache entry, waiting while another process holds it.
+/// The lock is released when the returned file is dropped.
+///
+/// Lock files are kept next to the entry, since removing them would let a waiting process lock a
+/// file that is no longer the one others open.
+fn lock_entry(path: &Path) -> Result<File, Box<dyn Error>> {
+    let (lock_file, lock_file_name) = open_lock_file(path)?;
+    match lock_file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            info!("Waiting for another process updating {}", path.display());
+            lock_file.lock()?;
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(format!("Failed to lock {lock_file_name}: {e}").into())
+        }
+    }
+    Ok(lock_file)
+}
+
+/// Takes the lock of a cache entry like [`lock_entry`] when no other process holds it, returning
+/// `None` when one does.
+pub fn try_lock_entry(path: &Path) -> Result<Option<File>, Box<dyn Error>> {
+    let (lock_file, lock_file_name) = open_lock_file(path)?;
+    match lock_file.try_lock() {
+        Ok(()) => Ok(Some(lock_file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(format!("Failed to lock {lock_file_name}: {e}").into()),
+    }
+}
+
+fn open_lock_file(path: &Path) -> io::Result<(File, String)> {
+    let lock_file_name = format!("{}.lock", path.display());
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_file_name)?;
+    Ok((lock_file, lock_file_name))
+}
+
+/// Settings of the client downloads and uploads are sent with
+#[derive(Debug, Default)]
+pub struct ClientOptions {
+    /// Headers sent with downloads from the hosts of the URLs the user gave, each as `Name: value`
+    pub headers: Vec<String>,
+    /// User-Agent sent instead of bgp-scout and its version
+    pub user_agent: Option<String>,
+    /// User name of HTTP basic auth, optionally followed by a colon and the password
+    pub http_user: Option<String>,
+    /// Token of HTTP bearer auth
+    pub bearer_token: Option<String>,
+    /// PEM file of the client certificate chain presented to servers asking for one
+    pub tls_cert: Option<String>,
+    /// PEM file of the PKCS#8 key of the client certificate, the certificate file if not given
+    pub tls_key: Option<String>,
+    /// PEM file of CA certificates trusted besides, or with `no_system_roots` instead of, those
+    /// of the system
+    pub tls_ca: Option<String>,
+    /// Trust only the CA certificates of `tls_ca`
+    pub no_system_roots: bool,
+}
+
+/// Credentials sent with downloads from the hosts of the URLs the user gave
+#[derive(Debug, Clone)]
+enum Credentials {
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+/// Client of this run, built from the command line options
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Credentials of this run, from the command line options or environment
+static CREDENTIALS: OnceLock<Credentials> = OnceLock::new();
+
+/// Headers of this run from the command line options, which often carry access tokens too
+static HEADERS: OnceLock<HeaderMap> = OnceLock::new();
+
+/// Hosts of the URLs the user gave, the only ones sent the credentials and headers
+static AUTHORIZED_HOSTS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Resolves the credentials of the options, falling back to BGP_SCOUT_HTTP_USER,
+/// BGP_SCOUT_HTTP_PASSWORD and BGP_SCOUT_BEARER_TOKEN.
+fn credentials(options: &ClientOptions) -> Result<Option<Credentials>, Box<dyn Error>> {
+    let (http_user, bearer_token) = match (&options.http_user, &options.bearer_token) {
+        (None, None) => (
+            env::var("BGP_SCOUT_HTTP_USER").ok(),
+            env::var("BGP_SCOUT_BEARER_TOKEN").ok(),
+        ),
+        (http_user, bearer_token) => (http_user.clone(), bearer_token.clone()),
+    };
+    match (http_user, bearer_token) {
+        (Some(_), Some(_)) => {
+            Err("Give either HTTP basic auth credentials or a bearer token, not both".into())
+        }
+        (Some(http_user), None) => Ok(Some(match http_user.split_once(':') {
+            Some((user, password)) => Credentials::Basic {
+                user: user.to_string(),
+                password: Some(password.to_string()),
+            },
+            None => Credentials::Basic {
+                user: http_user,
+                password: env::var("BGP_SCOUT_HTTP_PASSWORD").ok(),
+            },
+        })),
+        (None, Some(token)) => Ok(Some(Credentials::Bearer(token))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Returns the blocks of a PEM file with the label, such as the certificates of a file also
+/// holding their private key.
+fn pem_blocks(pem: &str, label: &str) -> String {
+    let (begin, end) = (
+        format!("-----BEGIN {label}-----"),
+        format!("-----END {label}-----"),
+    );
+    let mut blocks = String::new();
+    let mut in_block = false;
+    for line in pem.lines() {
+        in_block |= line.starts_with(&begin);
+        if in_block {
+            blocks.push_str(line);
+            blocks.push('\n');
+        }
+        in_block &= !line.starts_with(&end);
+    }
+    blocks
+}
+
+fn parse_headers(options: &ClientOptions) -> Result<HeaderMap, Box<dyn Error>> {
+    let mut headers = HeaderMap::new();
+    for header in &options.headers {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid header {header:?}, expected 'Name: value'"))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|e| format!("Invalid header name {:?}: {e}", name.trim()))?;
+        let mut value = HeaderValue::from_str(value.trim())
+            .map_err(|e| format!("Invalid value of header {name}: {e}"))?;
+        // Headers often carry access tokens, keep them out of debug output
+        value.set_sensitive(true);
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn build_client(options: &ClientOptions) -> Result<Client, Box<dyn Error>> {
+    let user_agent = options
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| format!("bgp-scout/{}", env!("CARGO_PKG_VERSION")));
+    let mut builder = Client::builder().user_agent(user_agent);
+    if let Some(cert) = &options.tls_cert {
+        let cert_pem = fs::read_to_string(cert).map_err(|e| format!("{cert}: {e}"))?;
+        // Without --tls-key the key is kept in the certificate file, next to the certificates
+        let key = options.tls_key.as_ref().unwrap_or(cert);
+        let key_pem = fs::read_to_string(key).map_err(|e| format!("{key}: {e}"))?;
+        let identity = Identity::from_pkcs8_pem(
+            pem_blocks(&cert_pem, "CERTIFICATE").as_bytes(),
+            pem_blocks(&key_pem, "PRIVATE KEY").as_bytes(),
+        )
+        .map_err(|e| {
+            format!(
+                "Invalid client certificate {cert} or key {key}, expected PEM certificates and \
+                 a PKCS#8 key: {e}"
+            )
+        })?;
+        builder = builder.identity(identity);
+    }
+    if let Some(ca) = &options.tls_ca {
+        let certificates =
+            Certificate::from_pem_bundle(&fs::read(ca).map_err(|e| format!("{ca}: {e}"))?)
+                .map_err(|e| format!("Invalid CA certificates in {ca}: {e}"))?;
+        if certificates.is_empty() {
+            return Err(format!("No CA certificates in {ca}").into());
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder
+        .tls_built_in_root_certs(!options.no_system_roots)
+        .build()?)
+}
+
+/// Sets up the client downloads and uploads are sent with from now on.
+pub fn configure_client(options: &ClientOptions) -> Result<(), Box<dyn Error>> {
+    let _ = CLIENT.set(build_client(options)?);
+    let _ = HEADERS.set(parse_headers(options)?);
+    if let Some(credentials) = credentials(options)? {
+        let _ = CREDENTIALS.set(credentials);
+    }
+    Ok(())
+}
+
+/// Sends the credentials of the command line or environment with downloads from the host of the
+/// URL, which the user gave.
+pub fn authorize_url(url: &str) {
+    if let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+    {
+        if let Ok(mut hosts) = AUTHORIZED_HOSTS.lock() {
+            hosts.insert(host);
+        }
+    }
+}
+
+/// Adds the credentials of the host to an http(s) request: those of the command line or
+/// environment for hosts of URLs the user gave, otherwise those of netrc. Credentials in the URL
+/// itself are left for reqwest to send. The headers of the command line are only sent to the
+/// hosts of URLs the user gave as well, never to third-party APIs.
+fn authorize(request: RequestBuilder, url: &str) -> RequestBuilder {
+    let Ok(parsed) = Url::parse(url) else {
+        return request;
+    };
+    let Some(host) = parsed.host_str() else {
+        return request;
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return request;
+    }
+    let authorized = AUTHORIZED_HOSTS
+        .lock()
+        .is_ok_and(|hosts| hosts.contains(host));
+    let request = match HEADERS.get().filter(|_| authorized) {
+        Some(headers) => request.headers(headers.clone()),
+        None => request,
+    };
+    if !parsed.username().is_empty() {
+        return request;
+    }
+    match CREDENTIALS.get().filter(|_| authorized) {
+        Some(Credentials::Basic { user, password }) => request.basic_auth(user, password.as_ref()),
+        Some(Credentials::Bearer(token)) => request.bearer_auth(token),
+        None => match netrc::lookup(host) {
+            Some((login, password)) => request.basic_auth(login, Some(password)),
+            None => request,
+        },
+    }
+}
+
+/// Returns the client downloads and uploads are sent with.
+pub fn client() -> Result<Client, Box<dyn Error>> {
+    match CLIENT.get() {
+        Some(client) => Ok(client.clone()),
+        None => build_client(&ClientOptions::default()),
+    }
+}
+
+/// Returns a temporary file name next to the output file, unique to this process so concurrent
+/// writers never share one.
+pub fn temp_path(output_file_name: &str) -> String {
+    format!("{output_file_name}.{}.tmp", process::id())
+}
+
+/// Passes writes through while computing the SHA-256 and size of everything written
+#[derive(Debug)]
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the extension of the file a URL points at, such as `.bz2`, which is kept on its
+/// object so compressed content is still recognised by its name.
+fn url_extension(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    match file_name.rsplit_once('.') {
+        Some((_, extension))
+            if !extension.is_empty()
+                && extension.len() <= 8
+                && extension.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            format!(".{extension}")
+        }
+        _ => String::new(),
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Returns how many seconds the response may be reused without revalidation, per its
+/// Cache-Control max-age, less its Age, or else its Expires relative to its Date. no-cache and
+/// no-store allow none, and `None` means the server did not say.
+fn max_age(headers: &HeaderMap) -> Option<u64> {
+    let age = header_string(headers, AGE)
+        .and_then(|age| age.trim().parse::<u64>().ok())
+        .unwrap_or_default();
+    if let Some(cache_control) = header_string(headers, CACHE_CONTROL) {
+        let mut max_age = None;
+        for directive in cache_control.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            if directive == "no-cache" || directive == "no-store" {
+                return Some(0);
+            }
+            if let Some(seconds) = directive.strip_prefix("max-age=") {
+                max_age = seconds.trim_matches('"').parse::<u64>().ok();
+            }
+        }
+        if let Some(max_age) = max_age {
+            return Some(max_age.saturating_sub(age));
+        }
+    }
+    // Invalid dates such as "0" mean already expired
+    let expires = header_string(headers, EXPIRES)?;
+    let expires = DateTime::parse_from_rfc2822(&expires).map_or(0, |date| date.timestamp());
+    let date = header_string(headers, DATE)
+        .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+        .map_or_else(cache::now, |date| date.timestamp());
+    Some(u64::try_from(expires - date).unwrap_or_default())
+}
+
+/// Returns whether a full response is the content already cached, for servers answering
+/// conditional requests in full: it has the same strong ETag, or the same Last-Modified date and
+/// length.
+fn unchanged(manifest: &cache::Manifest, headers: &HeaderMap) -> bool {
+    let etag = header_string(headers, ETAG);
+    if let (Some(etag), Some(cached)) = (&etag, &manifest.etag) {
+        return !etag.starts_with("W/") && etag == cached;
+    }
+    let length = header_string(headers, CONTENT_LENGTH).and_then(|length| length.parse().ok());
+    manifest.last_modified.is_some()
+        && header_string(headers, LAST_MODIFIED) == manifest.last_modified
+        && length == Some(manifest.size)
+}
+
+/// Records that the server confirmed the cached content is current, with any validators and
+/// freshness it sent along.
+fn revalidated(
+    mut manifest: cache::Manifest,
+    headers: &HeaderMap,
+    manifest_path: &Path,
+) -> Result<(PathBuf, bool), Box<dyn Error>> {
+    manifest.etag = header_string(headers, ETAG).or(manifest.etag);
+    manifest.last_modified = header_string(headers, LAST_MODIFIED).or(manifest.last_modified);
+    manifest.max_age = max_age(headers).or(manifest.max_age);
+    manifest.verified_at = cache::now();
+    manifest.used_at = manifest.verified_at;
+    cache::write_manifest(manifest_path, &manifest)?;
+    Ok((manifest.object_path(), true))
+}
+
+/// Checks there is room in the object store for a download of the size the server announced.
+fn ensure_download_space(url: &str, size: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let Some(size) = size else {
+        return Ok(());
+    };
+    let objects_dir = cache::object_path("");
+    fs::create_dir_all(&objects_dir)?;
+    disk::ensure_space(&objects_dir, size, &format!("downloading {url}"))
+}
+
+/// Writes downloaded content into the object store, returning the path and size of its object.
+/// Gzip content is checked by [`cached_gzip`] as it decompresses it, which evicts and downloads
+/// again truncated objects.
+fn download_object(reader: &mut dyn Read, url: &str) -> Result<(PathBuf, u64), Box<dyn Error>> {
+    let objects_dir = cache::object_path("");
+    fs::create_dir_all(&objects_dir)?;
+    // The content hash is only known once downloaded, so write under a temporary name
+    let temp_file_name = temp_path(&objects_dir.join("download").display().to_string());
+    let mut writer = HashingWriter {
+        inner: BufWriter::new(File::create(&temp_file_name)?),
+        hasher: Sha256::new(),
+        size: 0,
+    };
+    debug!("Writing response to {temp_file_name}");
+    if let Err(e) = io::copy(reader, &mut writer)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| Ok(writer.flush()?))
+    {
+        drop(writer);
+        let _ = fs::remove_file(&temp_file_name); // Attempt to delete the partial file if write fails
+        return Err(format!("Failed to write content to file: {e}").into());
+    }
+    let extension = url_extension(url);
+    let object = format!("{:x}{extension}", writer.hasher.finalize());
+    let size = writer.size;
+    drop(writer.inner);
+    telemetry::add_downloaded_bytes(size);
+    Ok((cache::store_object(&temp_file_name, &object)?, size))
+}
+
+/// Downloads a file from the given URL and caches it in the content-addressed object store,
+/// recording the URL, validators and timestamps in its manifest. Concurrent callers caching the
+/// same URL wait for each other, and objects only appear once fully downloaded.
 ///
 /// # Arguments
 ///
 /// * `url` - A string slice that holds the URL of the file to download.
-/// * `output_file_name` - A string slice that holds the path to the output file.
-/// * `verify_etag_interval` - The duration for which the cache is valid.
+/// * `verify_cache_interval` - The duration for which the cache is valid before it is verified
+///   with the server again.
 /// * `network_timeout` - An optional duration for the download timeout.
 ///
 /// # Returns
 ///
-/// * `Result<bool, Box<dyn Error>>` - Returns `Ok(true)` if the file was cached, `Ok(false)` if
-///   the file was downloaded, or an `Err` with a boxed error if it failed.
+/// * `Result<(PathBuf, bool), Box<dyn Error>>` - Returns the path of the cached object, with
+///   `true` if it was already cached or `false` if it was downloaded, or an `Err` with a boxed
+///   error if it failed.
 ///
 /// # Examples
 ///
 /// ```
 /// use std::time::Duration;
-/// let result = download::cached("https://example.com/asset.gz", "/home/user/asset.gz", Duration::from_secs(86400), None);
+/// let result = download::cached("https://example.com/asset.gz", Some(Duration::from_secs(86400)), None);
 /// assert!(result.is_ok());
 /// ```
 pub fn cached(
     url: &str,
-    output_file_name: &Path,
     verify_cache_interval: Option<Duration>,
     network_timeout: Option<Duration>,
-) -> Result<bool, Box<dyn Error>> {
+) -> Result<(PathBuf, bool), Box<dyn Error>> {
     const DEFAULT_TIMEOUT: Duration = Duration::from_secs(86400);
-    let verify_etag_duration = verify_cache_interval.unwrap_or(DEFAULT_TIMEOUT);
-    let etag_file_name_str = format!("{}.etag", output_file_name.display());
-    let etag_file_name = Path::new(&etag_file_name_str);
-    let mut headers = HeaderMap::new();
+    let _span = info_span!("download", url).entered();
+    let verify_duration = verify_cache_interval.unwrap_or(DEFAULT_TIMEOUT);
+    let manifest_path = cache::manifest_path(url);
+    if let Some(parent) = manifest_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _lock = lock_entry(&manifest_path)?;
 
-    if evaluate_etag(
-        output_file_name,
-        etag_file_name,
-        verify_etag_duration,
-        &mut headers,
-    )? {
-        // If it's not time to verify etag again, exit early
-        return Ok(true);
+    let mut headers = HeaderMap::new();
+    let mut manifest = cache::read_manifest(&manifest_path);
+    if let Some(manifest) = &mut manifest {
+        let verified_elapsed = cache::now() - manifest.verified_at;
+        debug!(
+            "Cached {url} was verified {verified_elapsed} seconds ago as {}",
+            manifest.object
+        );
+        // The server may ask for revalidation sooner, never later than the verify interval
+        let fresh_for = manifest
+            .max_age
+            .map_or(verify_duration.as_secs(), |max_age| {
+                max_age.min(verify_duration.as_secs())
+            });
+        if verified_elapsed < i64::try_from(fresh_for).unwrap_or(i64::MAX) {
+            debug!("Cache new enough (fresh for {fresh_for} seconds) to skip checking server");
+            manifest.used_at = cache::now();
+            cache::write_manifest(&manifest_path, manifest)?;
+            return Ok((manifest.object_path(), true));
+        }
+        // Send every validator the server reported, as some mirrors only implement one
+        for (name, validator) in [
+            (IF_NONE_MATCH, &manifest.etag),
+            (IF_MODIFIED_SINCE, &manifest.last_modified),
+        ] {
+            let Some(validator) = validator else {
+                continue;
+            };
+            match HeaderValue::from_str(validator) {
+                Ok(value) => {
+                    debug!("Adding {name} header with value {validator}");
+                    headers.insert(name, value);
+                }
+                Err(_) => warn!("Validator {validator} is not a valid {name} value"),
+            }
+        }
+    } else {
+        debug!("No cached copy of {url}");
+    }
+    if archive::is_archive_url(url) {
+        return cached_archive(
+            url,
+            &manifest_path,
+            manifest,
+            network_timeout.unwrap_or(DEFAULT_TIMEOUT),
+        );
     }
 
-    let client = Client::new();
-    let mut response = client
-        .get(url)
+    let client = client()?;
+    let mut response = authorize(storage::request(&client, Method::GET, url, None)?, url)
         .headers(headers)
         .timeout(network_timeout.unwrap_or(DEFAULT_TIMEOUT))
         .send()
         .map_err(|e| format!("Failed to send request: {e}"))?;
 
-    match response.status() {
-        StatusCode::NOT_MODIFIED => {
+    match (response.status(), manifest) {
+        (StatusCode::NOT_MODIFIED, Some(manifest)) => {
             debug!("HTTP request returned StatusCode::NOT_MODIFIED");
-            if etag_file_name.exists() {
-                debug!("Update mtime for etag file {}", etag_file_name_str);
-                // Touch the ETag file to update its modified date
-                let file = OpenOptions::new().write(true).open(etag_file_name)?;
-                file.set_len(file.metadata()?.len())?;
-            } else {
-                // If the server provides an etag and the etag file does not exist, save the etag
-                if let Some(etag) = response.headers().get(ETAG) {
-                    let etag_str = etag
-                        .to_str()
-                        .expect("Failed to convert etag header to string");
-                    debug!(
-                        "Creating missing etag file {} with value {}",
-                        etag_file_name_str, etag_str
-                    );
-                    let mut file = OpenOptions::new()
-                        .create(true)
-                        .truncate(true)
-                        .write(true)
-                        .open(etag_file_name)?;
-                    writeln!(file, "{etag_str}")?;
-                } else {
-                    debug!("Etag file does not exist and server did not return an etag in Not Modified response");
+            revalidated(manifest, response.headers(), &manifest_path)
+        }
+        (StatusCode::OK, Some(manifest)) if unchanged(&manifest, response.headers()) => {
+            debug!("Server ignored the validators but reports the cached content unchanged");
+            revalidated(manifest, response.headers(), &manifest_path)
+        }
+        (StatusCode::OK, _) => {
+            debug!("HTTP request returned StatusCode::OK");
+            ensure_download_space(url, response.content_length())?;
+            // Truncated or corrupt downloads are fetched again rather than cached
+            let mut attempt = 1;
+            let object = loop {
+                match download_object(&mut response, url) {
+                    Ok(object) => break object,
+                    Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                        warn!("Downloading {url} again: {e}");
+                        attempt += 1;
+                        response =
+                            authorize(storage::request(&client, Method::GET, url, None)?, url)
+                                .timeout(network_timeout.unwrap_or(DEFAULT_TIMEOUT))
+                                .send()
+                                .map_err(|e| format!("Failed to send request: {e}"))?
+                                .error_for_status()?;
+                    }
+                    Err(e) => return Err(e),
                 }
-            }
+            };
+            let (object_path, size) = object;
 
-            Ok(true)
+            let now = cache::now();
+            let manifest = cache::Manifest {
+                url: url.to_string(),
+                object: object_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size,
+                etag: header_string(response.headers(), ETAG),
+                last_modified: header_string(response.headers(), LAST_MODIFIED),
+                max_age: max_age(response.headers()),
+                fetched_at: now,
+                verified_at: now,
+                used_at: now,
+            };
+            debug!(
+                "Cached {url} as {} ({size} bytes, etag {:?})",
+                manifest.object, manifest.etag
+            );
+            cache::write_manifest(&manifest_path, &manifest)?;
+            Ok((object_path, false))
         }
-        StatusCode::OK => {
-            debug!("HTTP request returned StatusCode::OK");
-            let file = File::create(output_file_name)?;
-            let mut writer = BufWriter::new(file);
-            debug!("Writing response to {}", output_file_name.display());
-            if let Err(e) = response.copy_to(&mut writer) {
-                let _ = fs::remove_file(etag_file_name);
-                let _ = fs::remove_file(output_file_name); // Attempt to delete the output file if write fails
-                return Err(format!("Failed to write content to file: {e}").into());
-            }
+        (status, _) => {
+            let _ = fs::remove_file(&manifest_path); // Forget the cached copy on any other failure
+            Err(format!("Failed to download file: HTTP {status}").into())
+        }
+    }
+}
 
-            // If the server provides a Last-Modified header, set the mtime of the output file to match
-            if let Some(last_modified_value) =
-                response.headers().get(reqwest::header::LAST_MODIFIED)
-            {
-                let last_modified_str = last_modified_value.to_str()?;
-                let last_modified =
-                    DateTime::parse_from_rfc2822(last_modified_str)?.with_timezone(&Utc);
-
-                let modified_time = FileTime::from_unix_time(
-                    last_modified.timestamp(),
-                    last_modified.timestamp_subsec_nanos(),
-                );
-
-                // Set the modified time of the output file
-                filetime::set_file_mtime(output_file_name, modified_time)?;
-                debug!(
-                    "Set mtime {} to match server Last-Modified: {}",
-                    output_file_name.display(),
-                    last_modified_str
-                );
-            } else {
-                debug!("No Last-Modified header found.");
-                // TODO: What should we set the file time to that ensures optimal behavior?
+/// Caches a file of an FTP or rsync archive like [`cached`] does over HTTP. Those protocols have
+/// no validators, so the cached copy is current while the size and modification time the server
+/// lists are those it had when downloaded.
+fn cached_archive(
+    url: &str,
+    manifest_path: &Path,
+    manifest: Option<cache::Manifest>,
+    timeout: Duration,
+) -> Result<(PathBuf, bool), Box<dyn Error>> {
+    let listing = archive::stat(url, timeout)?;
+    if let Some(mut manifest) = manifest {
+        if listing.modified.is_some()
+            && manifest.size == listing.size
+            && manifest.last_modified == listing.modified
+        {
+            debug!("{url} is unchanged since {:?}", listing.modified);
+            manifest.verified_at = cache::now();
+            manifest.used_at = manifest.verified_at;
+            cache::write_manifest(manifest_path, &manifest)?;
+            return Ok((manifest.object_path(), true));
+        }
+    }
+
+    ensure_download_space(url, Some(listing.size))?;
+    let mut attempt = 1;
+    let (object_path, size) = loop {
+        match archive::retrieve(url, timeout, |reader| download_object(reader, url)) {
+            Ok(object) => break object,
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                warn!("Downloading {url} again: {e}");
+                attempt += 1;
             }
+            Err(e) => return Err(e),
+        }
+    };
+    let now = cache::now();
+    let manifest = cache::Manifest {
+        url: url.to_string(),
+        object: object_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        size,
+        etag: None,
+        last_modified: listing.modified,
+        max_age: None,
+        fetched_at: now,
+        verified_at: now,
+        used_at: now,
+    };
+    debug!("Cached {url} as {} ({size} bytes)", manifest.object);
+    cache::write_manifest(manifest_path, &manifest)?;
+    Ok((object_path, false))
+}
 
-            // If the server provides an etag, save the etag in an etag+touch file
-            if let Some(etag) = response.headers().get(ETAG) {
-                debug!("Writing etag to file {}", etag_file_name.display());
-                if let Err(e) = fs::write(etag_file_name, etag.to_str()?) {
-                    let _ = fs::remove_file(etag_file_name);
-                    return Err(format!(
-                        "Failed to write etag to file {}: {}",
-                        etag_file_name.display(),
-                        e
-                    )
-                    .into());
-                }
+/// Downloads a gzipped file into the cache, returning the path of its decompressed copy, which
+/// is kept next to the object and shared by every URL serving the same content.
+///
+/// With --cache-mode gz-only the path of the download itself is returned instead, and readers
+/// decompress it on the fly; a copy left decompressed by mrt-only is compressed again. With
+/// mrt-only the download is removed once decompressed.
+///
+/// A cached file that fails to decompress, for example after being truncated on disk, is evicted
+/// and downloaded again.
+pub fn cached_gzip(url: &str, verify_etag_interval: Duration) -> Result<String, Box<dyn Error>> {
+    let mode = cache_mode();
+    let mut attempt = 1;
+    loop {
+        let (object_path, cache_result) = cached(url, Some(verify_etag_interval), None)?;
+        let output_file = format!("{}.mrt", object_path.display());
+        let object_file = object_path.display().to_string();
+
+        // Held while checking and decompressing, so only one process decompresses a new download
+        let _lock = lock_entry(Path::new(&output_file))?;
+        if cache_result {
+            debug!("Using cached gzipped file {object_file}");
+        } else {
+            debug!("Downloaded gzipped file {object_file}");
+        }
+        if mode == CacheMode::GzOnly {
+            // Downloads are not decompressed in this mode, so new ones are test-decoded instead
+            let verified = if cache_result || !object_path.exists() {
+                Ok(())
             } else {
-                debug!("Server did not return an etag");
+                gzip::verify(&object_file)
+            };
+            match verified {
+                Ok(()) => {}
+                Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                    warn!("Downloaded {object_file} is truncated or corrupt, downloading it again: {e}");
+                    cache::evict(url, &object_path)?;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(format!("Downloaded {object_file} is corrupt: {e}").into()),
             }
-
-            Ok(false)
+            if !object_path.exists() {
+                debug!("Compressing {output_file} again");
+                disk::ensure_space(
+                    &cache::object_path(""),
+                    fs::metadata(&output_file)?.len(),
+                    &format!("compressing {output_file}"),
+                )?;
+                gzip::compress(&output_file, &object_file)?;
+            }
+            remove_copy(&output_file)?;
+            debug!("Output file {object_file}");
+            return Ok(object_file);
         }
-        _ => {
-            let _ = fs::remove_file(output_file_name); // Delete the output file on any other failure
-            let _ = fs::remove_file(etag_file_name);
-            Err(format!("Failed to download file: HTTP {}", response.status()).into())
+        if fs::metadata(&output_file).is_ok() {
+            if mode == CacheMode::MrtOnly {
+                remove_copy(&object_file)?;
+            }
+            debug!("Output file {output_file}");
+            return Ok(output_file);
         }
-    }
-}
 
-fn evaluate_etag(
-    output_file_name: &Path,
-    etag_file_name: &Path,
-    verify_etag_duration: Duration,
-    headers: &mut HeaderMap,
-) -> Result<bool, Box<dyn Error>> {
-    let mut delete_etag_file = false;
-    let output_file_metadata_result = fs::metadata(output_file_name);
-
-    // Does the output file already exist?
-    if output_file_metadata_result.is_ok() {
-        debug!("Output file {} exists", output_file_name.display());
-        // Does the etag file exist?
-        if let Ok(etag_metadata) = fs::metadata(etag_file_name) {
-            debug!("Etag file {} exists", etag_file_name.display());
-            // Can we get the mtime of the etag file?
-            if let Ok(etag_modified) = etag_metadata.modified() {
-                // How long has it been since we've verified the etag with the server?
-                let etag_modified_elapsed = etag_modified.elapsed()?;
-                debug!(
-                    "Etag file mtime elapsed is {} seconds",
-                    etag_modified_elapsed.as_secs()
-                );
-                if etag_modified_elapsed > verify_etag_duration {
-                    // We're going to verify etag with the server, get the etag value from the etag file
-                    debug!(
-                        "Etag mtime is older than {} seconds, need to recheck with If-None-Match",
-                        verify_etag_duration.as_secs()
-                    );
-                    if let Ok(etag_file_str) = fs::read_to_string(etag_file_name) {
-                        if let Some(etag) = etag_file_str.lines().next().map(str::trim) {
-                            if let Ok(etag_header_value) = HeaderValue::from_str(etag) {
-                                debug!("Adding If-None-Match header with etag value {}", etag);
-                                headers.insert(IF_NONE_MATCH, etag_header_value);
-                            } else {
-                                warn!("Etag value {} is not a valid header value", etag);
-                                delete_etag_file = true;
-                            }
-                        } else {
-                            warn!("Can't get first line of etag file [{}]", etag_file_str);
-
-                            delete_etag_file = true;
-                        }
-                    } else {
-                        // Handle can't read etag value?
-                        warn!(
-                            "Failed to read etag value from {}",
-                            etag_file_name.display()
-                        );
-
-                        delete_etag_file = true;
-                    }
-                } else {
-                    // We have an etag file with recent enough mtime
-                    // We aren't going to check with the server
-                    debug!("Etag file mtime new enough (verify interval {} seconds) to skip checking server", verify_etag_duration.as_secs());
-                    return Ok(true);
+        debug!("Decompressing gzipped file {object_file}");
+        let compressed_size = fs::metadata(&object_file)?.len();
+        disk::ensure_space(
+            &cache::object_path(""),
+            compressed_size.saturating_mul(disk::DECOMPRESSION_RATIO),
+            &format!("decompressing {object_file}"),
+        )?;
+        match gzip::decompress(&object_file, &output_file) {
+            Ok(()) => {
+                if mode == CacheMode::MrtOnly {
+                    remove_copy(&object_file)?;
                 }
-            } else {
-                // Handle can't get modified time of etag from metadata?
-                delete_etag_file = true;
+                debug!("Output file {output_file}");
+                return Ok(output_file);
             }
-        } else {
-            // Handle etag file doesn't exist
-            debug!("Etag file {} does not exist", etag_file_name.display());
-
-            // If we have an output file but no etag, attempt to use If-Modified-Since
-            let output_file_metadata =
-                output_file_metadata_result.expect("Failed to get metadata for output file?");
-
-            if let Ok(output_file_modified) = output_file_metadata.modified() {
-                // Convert SystemTime to DateTime<Utc>
-                let datetime: DateTime<Utc> = output_file_modified.into();
-
-                // Format the DateTime<Utc> to an RFC 2822 formatted string
-                let output_file_modified_str = datetime.to_rfc2822();
-
-                debug!(
-                    "Adding header If-Modified-Since with value {}",
-                    output_file_modified_str
-                );
-                headers.insert(
-                    IF_MODIFIED_SINCE,
-                    HeaderValue::from_str(output_file_modified_str.as_str())?,
-                );
-            } else {
-                warn!(
-                    "Unable to get modified time from output file metadata {:?}",
-                    output_file_metadata
-                );
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                warn!("Cached {object_file} is truncated or corrupt, downloading it again: {e}");
+                cache::evict(url, &object_path)?;
+                attempt += 1;
             }
+            Err(e) => return Err(format!("Failed to decompress {object_file}: {e}").into()),
         }
-    } else {
-        // Handle output file doesn't exist
-        debug!("Output file {} does not exist", output_file_name.display());
-        delete_etag_file = true;
     }
+}
 
-    if delete_etag_file {
-        debug!("Deleting etag file {}", etag_file_name.display());
-        let _ = fs::remove_file(etag_file_name);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(false)
-}
+    #[test]
+    fn headers_only_sent_to_authorized_hosts() -> Result<(), Box<dyn Error>> {
+        configure_client(&ClientOptions {
+            headers: vec!["X-Token: secret".to_string()],
+            ..ClientOptions::default()
+        })?;
+        authorize_url("https://mirror.example.net/bview.gz");
+        let client = client()?;
 
-pub fn cached_gzip(
-    url: &str,
-    output_file_gzip: &str,
-    output_file: &str,
-    verify_etag_interval: Duration,
-) -> Result<String, Box<dyn Error>> {
-    let cache_result = cached(
-        url,
-        Path::new(output_file_gzip),
-        Some(verify_etag_interval),
-        None,
-    )?;
-
-    let mut need_decompress_gzip = false;
-    if cache_result {
-        debug!("Using cached gzipped file {}", output_file_gzip);
-        if fs::metadata(output_file).is_err() {
-            debug!("Output file {} does not exist", output_file);
-            need_decompress_gzip = true;
+        let mirror_url = "https://mirror.example.net/other/bview.gz";
+        let mirror = authorize(client.get(mirror_url), mirror_url).build()?;
+        assert_eq!(
+            mirror.headers().get("x-token").map(HeaderValue::as_bytes),
+            Some(&b"secret"[..])
+        );
+
+        for url in [
+            "https://www.peeringdb.com/api/net?asn=13335",
+            "https://example.net/bview.gz",
+            "http://mirror.example.net.evil.example/bview.gz",
+        ] {
+            let request = authorize(client.get(url), url).build()?;
+            assert!(request.headers().get("x-token").is_none(), "{url}");
         }
-    } else {
-        debug!("Downloaded gzipped file {}", output_file_gzip);
-        need_decompress_gzip = true;
+        Ok(())
     }
-    if need_decompress_gzip {
-        debug!("Decompressing gzipped file {}", output_file_gzip);
-        gzip::decompress(output_file_gzip, output_file)?;
-    }
-
-    debug!("Output file {}", output_file);
-    Ok(output_file.to_string())
 }
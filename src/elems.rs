@@ -0,0 +1,543 @@
+use bgpkit_parser::models::{BgpElem, ElemType, MrtMessage};
+use bgpkit_parser::{parse_mrt_record, BgpkitParser, Elementor};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use ipnet::IpNet;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{Cursor, Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::scan::PeerFilter;
+use crate::{ipmap, mrt};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One pipe-separated line per element, as printed by bgpdump -m
+    #[default]
+    Bgpdump,
+    /// Newline-delimited JSON objects
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConvertFormat {
+    /// CSV with a header line, lists separated by spaces
+    Csv,
+    /// Newline-delimited JSON objects
+    Jsonl,
+}
+
+impl ConvertFormat {
+    /// Picks the format from the extension of the output file.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" | "json" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Column of converted elements
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Timestamp,
+    Type,
+    PeerIp,
+    PeerAsn,
+    Prefix,
+    NextHop,
+    AsPath,
+    OriginAsns,
+    Origin,
+    LocalPref,
+    Med,
+    Communities,
+    Atomic,
+    Aggregator,
+}
+
+impl Column {
+    pub const ALL: [Self; 14] = [
+        Self::Timestamp,
+        Self::Type,
+        Self::PeerIp,
+        Self::PeerAsn,
+        Self::Prefix,
+        Self::NextHop,
+        Self::AsPath,
+        Self::OriginAsns,
+        Self::Origin,
+        Self::LocalPref,
+        Self::Med,
+        Self::Communities,
+        Self::Atomic,
+        Self::Aggregator,
+    ];
+
+    /// Name of the JSON key and CSV column
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Timestamp => "timestamp",
+            Self::Type => "type",
+            Self::PeerIp => "peer_ip",
+            Self::PeerAsn => "peer_asn",
+            Self::Prefix => "prefix",
+            Self::NextHop => "next_hop",
+            Self::AsPath => "as_path",
+            Self::OriginAsns => "origin_asns",
+            Self::Origin => "origin",
+            Self::LocalPref => "local_pref",
+            Self::Med => "med",
+            Self::Communities => "communities",
+            Self::Atomic => "atomic",
+            Self::Aggregator => "aggregator",
+        }
+    }
+
+    /// Value of the column for an element, null when the element lacks the attribute.
+    fn value(self, elem: &BgpElem) -> Value {
+        match self {
+            // Whole seconds unless the record has a microsecond timestamp
+            Self::Timestamp if elem.timestamp.fract() == 0.0 => Value::from(elem.timestamp as i64),
+            Self::Timestamp => Value::from(elem.timestamp),
+            Self::Type => Value::from(match elem.elem_type {
+                ElemType::ANNOUNCE => "announce",
+                ElemType::WITHDRAW => "withdraw",
+            }),
+            Self::PeerIp => Value::from(elem.peer_ip.to_string()),
+            Self::PeerAsn => Value::from(elem.peer_asn.to_u32()),
+            Self::Prefix => Value::from(elem.prefix.prefix.to_string()),
+            Self::NextHop => elem
+                .next_hop
+                .map_or(Value::Null, |next_hop| Value::from(next_hop.to_string())),
+            Self::AsPath => elem
+                .as_path
+                .as_ref()
+                .map_or(Value::Null, |path| Value::from(path.to_string())),
+            Self::OriginAsns => elem.origin_asns.as_ref().map_or(Value::Null, |asns| {
+                asns.iter().map(|asn| asn.to_u32()).collect()
+            }),
+            Self::Origin => elem
+                .origin
+                .map_or(Value::Null, |origin| Value::from(origin.to_string())),
+            Self::LocalPref => elem.local_pref.map_or(Value::Null, Value::from),
+            Self::Med => elem.med.map_or(Value::Null, Value::from),
+            Self::Communities => elem
+                .communities
+                .as_ref()
+                .map_or(Value::Null, |communities| {
+                    communities.iter().map(ToString::to_string).collect()
+                }),
+            Self::Atomic => Value::from(elem.atomic),
+            Self::Aggregator => match (elem.aggr_asn, elem.aggr_ip) {
+                (Some(asn), Some(ip)) => Value::from(format!("{} {ip}", asn.to_u32())),
+                _ => Value::Null,
+            },
+        }
+    }
+}
+
+/// Kind of BGP element
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElemKind {
+    Announce,
+    Withdraw,
+}
+
+/// Conditions an element must meet to be kept. Empty conditions keep every element.
+#[derive(Debug, Default)]
+pub struct ElemFilter {
+    /// Origin ASNs of which any must originate the route
+    pub origin_asns: HashSet<u32>,
+    /// Prefixes of which any must equal the element prefix, or cover or be covered by it as
+    /// selected below
+    pub prefixes: Vec<IpNet>,
+    pub more_specifics: bool,
+    pub less_specifics: bool,
+    pub peers: PeerFilter,
+    /// Regular expression the AS path, written as space-separated ASNs, must match
+    pub as_path: Option<Regex>,
+    pub kind: Option<ElemKind>,
+    /// Start of the time range, inclusive
+    pub start: Option<DateTime<Utc>>,
+    /// End of the time range, exclusive
+    pub end: Option<DateTime<Utc>>,
+    pub ipv4_only: bool,
+    pub ipv6_only: bool,
+}
+
+impl ElemFilter {
+    fn matches_prefix(&self, prefix: &IpNet) -> bool {
+        self.prefixes.is_empty()
+            || self.prefixes.iter().any(|selected| {
+                selected == prefix
+                    || (self.more_specifics && selected.contains(prefix))
+                    || (self.less_specifics && prefix.contains(selected))
+            })
+    }
+
+    /// Whether the element meets every condition.
+    pub fn matches(&self, elem: &BgpElem) -> bool {
+        let prefix = &elem.prefix.prefix;
+        let timestamp = elem.timestamp;
+        (!self.ipv4_only || matches!(prefix, IpNet::V4(_)))
+            && (!self.ipv6_only || matches!(prefix, IpNet::V6(_)))
+            && self.kind.is_none_or(|kind| match kind {
+                ElemKind::Announce => elem.elem_type == ElemType::ANNOUNCE,
+                ElemKind::Withdraw => elem.elem_type == ElemType::WITHDRAW,
+            })
+            && self
+                .start
+                .is_none_or(|start| timestamp >= start.timestamp() as f64)
+            && self
+                .end
+                .is_none_or(|end| timestamp < end.timestamp() as f64)
+            && self.peers.matches(elem)
+            && self.matches_prefix(prefix)
+            && (self.origin_asns.is_empty()
+                || elem
+                    .origin_asns
+                    .iter()
+                    .flatten()
+                    .any(|asn| self.origin_asns.contains(&asn.to_u32())))
+            && self.as_path.as_ref().is_none_or(|regex| {
+                elem.as_path
+                    .as_ref()
+                    .is_some_and(|path| regex.is_match(&path.to_string()))
+            })
+    }
+}
+
+/// Formats an element as bgpdump -m does, with the type of the MRT record it came from.
+fn bgpdump_line(record_type: &str, elem: &BgpElem) -> String {
+    let mut line = format!(
+        "{record_type}|{}|{}|{}|{}|{}",
+        elem.timestamp as i64,
+        match (record_type, elem.elem_type) {
+            (_, ElemType::WITHDRAW) => "W",
+            ("BGP4MP", ElemType::ANNOUNCE) => "A",
+            // Table dump entries are routes in the RIB rather than announcements
+            _ => "B",
+        },
+        elem.peer_ip,
+        elem.peer_asn.to_u32(),
+        elem.prefix.prefix
+    );
+    if elem.elem_type == ElemType::WITHDRAW {
+        return line;
+    }
+    let communities: Vec<String> = elem
+        .communities
+        .iter()
+        .flatten()
+        .map(ToString::to_string)
+        .collect();
+    let _ = write!(
+        line,
+        "|{}|{}|{}|{}|{}|{}|{}|",
+        elem.as_path
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        elem.origin
+            .map(|origin| origin.to_string())
+            .unwrap_or_default(),
+        elem.next_hop
+            .map(|next_hop| next_hop.to_string())
+            .unwrap_or_default(),
+        elem.local_pref.unwrap_or_default(),
+        elem.med.unwrap_or_default(),
+        communities.join(" "),
+        if elem.atomic { "AG" } else { "NAG" },
+    );
+    if let (Some(asn), Some(ip)) = (elem.aggr_asn, elem.aggr_ip) {
+        let _ = write!(line, "{} {ip}", asn.to_u32());
+    }
+    line.push('|');
+    line
+}
+
+/// Writes every element of the MRT file that matches the filter, returning how many were
+/// written. Gzipped files are decompressed while reading.
+pub fn dump(
+    file_name: &str,
+    filter: &ElemFilter,
+    format: DumpFormat,
+    writer: &mut dyn Write,
+) -> Result<u64, Box<dyn Error>> {
+    let _span = info_span!("dump", file = file_name).entered();
+    let parser = BgpkitParser::from_reader(mrt::open(file_name)?);
+    let mut elementor = Elementor::new();
+    let mut written = 0;
+    for record in parser.into_record_iter() {
+        let record_type = match record.message {
+            MrtMessage::TableDumpMessage(_) => "TABLE_DUMP",
+            MrtMessage::TableDumpV2Message(_) => "TABLE_DUMP2",
+            MrtMessage::Bgp4Mp(_) => "BGP4MP",
+        };
+        for elem in elementor.record_to_elems(record) {
+            if !filter.matches(&elem) {
+                continue;
+            }
+            match format {
+                DumpFormat::Bgpdump => writeln!(writer, "{}", bgpdump_line(record_type, &elem))?,
+                DumpFormat::Json => {
+                    serde_json::to_writer(&mut *writer, &elem)?;
+                    writeln!(writer)?;
+                }
+            }
+            written += 1;
+        }
+    }
+    debug!("Dumped {written} elements of {file_name}");
+    Ok(written)
+}
+
+/// Records handed to a conversion thread at a time
+const CHUNK_RECORDS: usize = 1024;
+
+/// Consecutive records of an MRT file, with the peer index table their RIB entries refer to
+#[derive(Debug)]
+struct Chunk {
+    index: u64,
+    peer_table: Option<Arc<Vec<u8>>>,
+    records: Vec<Vec<u8>>,
+}
+
+/// Converted elements of a chunk, and how many there are
+#[derive(Debug)]
+struct Converted {
+    index: u64,
+    text: String,
+    elems: u64,
+}
+
+/// Frames the records of the MRT file into chunks, keeping the latest peer index table aside so
+/// that every thread can decode the RIB entries of any chunk.
+fn read_chunks(mut reader: Box<dyn Read + Send>, chunks: &SyncSender<Chunk>) -> Result<(), String> {
+    let mut peer_table = None;
+    let mut records = Vec::with_capacity(CHUNK_RECORDS);
+    let mut index = 0;
+    let mut send = |records: Vec<Vec<u8>>, peer_table: &Option<Arc<Vec<u8>>>| {
+        let chunk = Chunk {
+            index,
+            peer_table: peer_table.clone(),
+            records,
+        };
+        index += 1;
+        // The conversion threads only stop early when writing failed, which is reported instead
+        chunks.send(chunk).is_ok()
+    };
+    while let Some(record) = mrt::read_record(&mut reader)? {
+        if mrt::is_peer_table(&record) {
+            if !records.is_empty() && !send(std::mem::take(&mut records), &peer_table) {
+                return Ok(());
+            }
+            peer_table = Some(Arc::new(record));
+        } else {
+            records.push(record);
+            if records.len() == CHUNK_RECORDS && !send(std::mem::take(&mut records), &peer_table) {
+                return Ok(());
+            }
+        }
+    }
+    if !records.is_empty() {
+        send(records, &peer_table);
+    }
+    Ok(())
+}
+
+/// Decodes and converts chunks until there are none left.
+fn convert_chunks(
+    chunks: &Mutex<Receiver<Chunk>>,
+    converted: &SyncSender<Converted>,
+    filter: &ElemFilter,
+    format: ConvertFormat,
+    columns: &[Column],
+) {
+    let mut elementor = Elementor::new();
+    let mut peer_table: Option<Arc<Vec<u8>>> = None;
+    loop {
+        let chunk = match chunks.lock() {
+            Ok(chunks) => chunks.recv(),
+            Err(_) => return,
+        };
+        let Ok(chunk) = chunk else {
+            return;
+        };
+        if let Some(table) = &chunk.peer_table {
+            if !peer_table
+                .as_ref()
+                .is_some_and(|current| Arc::ptr_eq(current, table))
+            {
+                match parse_mrt_record(&mut Cursor::new(table.as_slice())) {
+                    Ok(record) => {
+                        elementor.record_to_elems(record);
+                    }
+                    Err(e) => warn!("Undecodable peer index table: {}", e.error),
+                }
+                peer_table = Some(Arc::clone(table));
+            }
+        }
+
+        let mut text = String::new();
+        let mut elems = 0;
+        for record in &chunk.records {
+            let record = match parse_mrt_record(&mut Cursor::new(record.as_slice())) {
+                Ok(record) => record,
+                Err(e) => {
+                    debug!("Undecodable record: {}", e.error);
+                    continue;
+                }
+            };
+            for elem in elementor.record_to_elems(record) {
+                if !filter.matches(&elem) {
+                    continue;
+                }
+                match format {
+                    ConvertFormat::Csv => {
+                        let cells: Vec<String> = columns
+                            .iter()
+                            .map(|column| csv_cell(&column.value(&elem)))
+                            .collect();
+                        text.push_str(&cells.join(","));
+                    }
+                    ConvertFormat::Jsonl => {
+                        let object: Map<String, Value> = columns
+                            .iter()
+                            .map(|column| (column.name().to_string(), column.value(&elem)))
+                            .collect();
+                        text.push_str(&Value::Object(object).to_string());
+                    }
+                }
+                text.push('\n');
+                elems += 1;
+            }
+        }
+        let converted_chunk = Converted {
+            index: chunk.index,
+            text,
+            elems,
+        };
+        if converted.send(converted_chunk).is_err() {
+            return;
+        }
+    }
+}
+
+/// Formats a column value as a CSV cell, with lists separated by spaces and nulls left empty.
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => ipmap::csv_field(text),
+        Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(csv_cell).collect();
+            ipmap::csv_field(&values.join(" "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Converts every element of the MRT file that matches the filter to CSV or JSON lines with the
+/// given columns, returning how many were written. Records are decoded by `threads` threads in
+/// chunks and written in the order of the file.
+pub fn convert(
+    file_name: &str,
+    filter: &ElemFilter,
+    format: ConvertFormat,
+    columns: &[Column],
+    threads: usize,
+    writer: &mut dyn Write,
+) -> Result<u64, Box<dyn Error>> {
+    let _span = info_span!("convert", file = file_name, threads).entered();
+    let reader = mrt::open(file_name)?;
+    if format == ConvertFormat::Csv {
+        let header: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+        writeln!(writer, "{}", header.join(","))?;
+    }
+
+    thread::scope(|scope| {
+        let (chunk_sender, chunk_receiver) = mpsc::sync_channel(threads * 2);
+        let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+        let (converted_sender, converted_receiver) = mpsc::sync_channel(threads * 2);
+        for _ in 0..threads {
+            let chunks = Arc::clone(&chunk_receiver);
+            let converted = converted_sender.clone();
+            scope.spawn(move || convert_chunks(&chunks, &converted, filter, format, columns));
+        }
+        // Once the conversion threads are done, nothing is left to receive chunks and reading stops
+        drop(chunk_receiver);
+        drop(converted_sender);
+        let reading = scope.spawn(move || read_chunks(reader, &chunk_sender));
+
+        // Chunks finish out of order, so later ones wait until the ones before are written
+        let mut pending: BTreeMap<u64, Converted> = BTreeMap::new();
+        let mut next = 0;
+        let mut written = 0;
+        for converted in converted_receiver {
+            pending.insert(converted.index, converted);
+            while let Some(converted) = pending.remove(&next) {
+                writer.write_all(converted.text.as_bytes())?;
+                written += converted.elems;
+                next += 1;
+            }
+        }
+        match reading.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Stopped reading {file_name} after {written} elements: {e}"),
+            Err(_) => return Err("Reading the MRT file failed".into()),
+        }
+        debug!("Converted {written} elements of {file_name}");
+        Ok(written)
+    })
+}
+
+/// Copies the records of the MRT file with any element matching the filter, byte for byte, along
+/// with the peer index tables their RIB entries refer to, so the result is a valid MRT file of
+/// the same type. Returns the number of records read and written.
+pub fn extract(
+    file_name: &str,
+    filter: &ElemFilter,
+    writer: &mut dyn Write,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    let _span = info_span!("extract", file = file_name).entered();
+    let mut reader = mrt::open(file_name)?;
+    let mut elementor = Elementor::new();
+    let mut read = 0;
+    let mut written = 0;
+    loop {
+        let raw = match mrt::read_record(&mut reader) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Stopped reading {file_name} after {read} records: {e}");
+                break;
+            }
+        };
+        read += 1;
+        let record = match parse_mrt_record(&mut Cursor::new(raw.as_slice())) {
+            Ok(record) => record,
+            Err(e) => {
+                debug!("Undecodable record in {file_name}: {}", e.error);
+                continue;
+            }
+        };
+        let elems = elementor.record_to_elems(record);
+        if mrt::is_peer_table(&raw) || elems.iter().any(|elem| filter.matches(elem)) {
+            writer.write_all(&raw)?;
+            written += 1;
+        }
+    }
+    debug!("Extracted {written} of {read} records of {file_name}");
+    Ok((read, written))
+}
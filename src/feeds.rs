@@ -0,0 +1,131 @@
+use bgpkit_parser::BgpkitParser;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::IpAddr;
+
+use crate::{download, index, mrt};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Number of routes a collector peer sent per address family
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerFeed {
+    pub asn: u32,
+    pub ip: IpAddr,
+    pub ipv4_routes: u64,
+    pub ipv6_routes: u64,
+}
+
+/// Feed sizes kept next to an MRT file, with the size and modification time of the file they
+/// were counted from
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedFeeds {
+    stamp: (u64, u64, u32),
+    peers: Vec<PeerFeed>,
+}
+
+fn feeds_path(mrt_file: &str) -> String {
+    format!("{mrt_file}.feeds.json")
+}
+
+/// Counts the routes each collector peer sent, per address family.
+fn count_feeds(mrt_file: &str) -> Result<Vec<PeerFeed>, Box<dyn Error>> {
+    let _span = info_span!("count_feeds").entered();
+    let parser = BgpkitParser::from_reader(mrt::open(mrt_file)?).add_filter("type", "announce")?;
+    let mut feeds: HashMap<IpAddr, PeerFeed> = HashMap::new();
+    for elem in parser.into_elem_iter() {
+        let feed = feeds.entry(elem.peer_ip).or_insert_with(|| PeerFeed {
+            asn: elem.peer_asn.to_u32(),
+            ip: elem.peer_ip,
+            ipv4_routes: 0,
+            ipv6_routes: 0,
+        });
+        match elem.prefix.prefix {
+            IpNet::V4(_) => feed.ipv4_routes += 1,
+            IpNet::V6(_) => feed.ipv6_routes += 1,
+        }
+    }
+    let mut feeds: Vec<PeerFeed> = feeds.into_values().collect();
+    feeds.sort_by_key(|feed| feed.ip);
+    Ok(feeds)
+}
+
+/// Returns the routes each collector peer sent, counted once per MRT file and kept next to it
+/// like its index. Failing to keep them only means counting again next time.
+pub fn peer_feeds(mrt_file: &str) -> Result<Vec<PeerFeed>, Box<dyn Error>> {
+    let path = feeds_path(mrt_file);
+    let stamp = index::source_stamp(mrt_file)?;
+    let cached = File::open(&path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|file| {
+            Ok(serde_json::from_reader::<_, CachedFeeds>(BufReader::new(
+                file,
+            ))?)
+        });
+    match cached {
+        Ok(cached) if cached.stamp == stamp => {
+            debug!(
+                "Loaded the feeds of {} peers from {path}",
+                cached.peers.len()
+            );
+            return Ok(cached.peers);
+        }
+        Ok(_) => debug!("Ignoring stale feeds {path}"),
+        Err(e) => debug!("No usable feeds at {path}: {e}"),
+    }
+
+    let cached = CachedFeeds {
+        stamp,
+        peers: count_feeds(mrt_file)?,
+    };
+    let temp_path = download::temp_path(&path);
+    let written = File::create(&temp_path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &cached)?;
+            writer.flush()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&temp_path, &path)?));
+    if let Err(e) = written {
+        warn!("Could not keep the peer feeds of {mrt_file} in {path}: {e}");
+    }
+    Ok(cached.peers)
+}
+
+/// Selects the peers sending (near) full tables: those whose routes of an address family number
+/// at least `threshold` percent of the largest feed of that family. A peer can have a full IPv4
+/// table and only a partial IPv6 one, so the selection is per family, as (peer, is IPv6) pairs.
+pub fn full_feeds(feeds: &[PeerFeed], threshold: u8) -> Vec<(&PeerFeed, bool)> {
+    let mut full = Vec::new();
+    for is_v6 in [false, true] {
+        let routes = |feed: &PeerFeed| {
+            if is_v6 {
+                feed.ipv6_routes
+            } else {
+                feed.ipv4_routes
+            }
+        };
+        let largest = feeds.iter().map(routes).max().unwrap_or_default();
+        if largest == 0 {
+            continue;
+        }
+        let selected: Vec<&PeerFeed> = feeds
+            .iter()
+            .filter(|feed| routes(feed) * 100 >= largest * u64::from(threshold))
+            .collect();
+        info!(
+            "{} of {} collector peers send full {} tables, the largest of {largest} routes",
+            selected.len(),
+            feeds.iter().filter(|feed| routes(feed) > 0).count(),
+            if is_v6 { "IPv6" } else { "IPv4" }
+        );
+        full.extend(selected.into_iter().map(|feed| (feed, is_v6)));
+    }
+    full
+}
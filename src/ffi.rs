@@ -0,0 +1,189 @@
+//! C API of the netblock scanner, built as the `bgp_scout` shared library so it can be embedded
+//! in C and C++ network tooling. The declarations are in `include/bgp_scout.h`.
+
+// Exchanging pointers with C callers cannot be done without unsafe code
+#![allow(unsafe_code)]
+
+mod scan;
+
+use ipnet::IpNet;
+use std::any::Any;
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::{c_char, CStr, CString};
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// Only return IPv4 prefixes
+pub const BGS_IPV4_ONLY: u32 = 1;
+/// Only return IPv6 prefixes
+pub const BGS_IPV6_ONLY: u32 = 1 << 1;
+/// Return the announced prefixes as they are instead of aggregating them
+pub const BGS_NO_AGGREGATE: u32 = 1 << 2;
+
+/// The call succeeded
+pub const BGS_OK: i32 = 0;
+/// The lookup failed, as the error message of the result tells
+pub const BGS_ERROR: i32 = 1;
+/// The library panicked, which was caught instead of unwinding into the caller
+pub const BGS_PANIC: i32 = 2;
+
+/// Prefixes found by `bgs_find_netblocks`, or the error that prevented finding them. Owned by
+/// the library until passed to `bgs_free_result`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BgsResult {
+    /// NUL-terminated prefixes in CIDR notation, sorted by address
+    pub prefixes: *mut *mut c_char,
+    /// Number of prefixes
+    pub len: usize,
+    /// NUL-terminated error message, or null when the lookup succeeded
+    pub error: *mut c_char,
+    /// `BGS_OK`, `BGS_ERROR` or `BGS_PANIC`
+    pub code: i32,
+}
+
+/// Returns the message a panic was raised with.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Builds a result holding an error message and its code.
+fn error_result(code: i32, message: &str) -> BgsResult {
+    BgsResult {
+        prefixes: ptr::null_mut(),
+        len: 0,
+        error: CString::new(message.replace('\0', " ")).map_or(ptr::null_mut(), CString::into_raw),
+        code,
+    }
+}
+
+/// Reads the arguments of `bgs_find_netblocks` and scans the MRT file.
+///
+/// # Safety
+///
+/// Same requirements as `bgs_find_netblocks`.
+unsafe fn find_netblocks(
+    mrt_file: *const c_char,
+    asns: *const u32,
+    asns_len: usize,
+    flags: u32,
+) -> Result<Vec<IpNet>, Box<dyn Error>> {
+    if mrt_file.is_null() {
+        return Err("No MRT file given".into());
+    }
+    // SAFETY: the caller passes a NUL-terminated string
+    let mrt_file = unsafe { CStr::from_ptr(mrt_file) }.to_str()?;
+    let origin_asns: HashSet<u32> = if asns.is_null() || asns_len == 0 {
+        HashSet::new()
+    } else {
+        // SAFETY: the caller passes an array of asns_len ASNs
+        unsafe { slice::from_raw_parts(asns, asns_len) }
+            .iter()
+            .copied()
+            .collect()
+    };
+    if origin_asns.is_empty() {
+        return Err("No ASNs given".into());
+    }
+
+    let prefix_origins = scan::scan_prefixes(
+        &File::open(mrt_file).map_err(|e| format!("{mrt_file}: {e}"))?,
+        &origin_asns,
+        flags & BGS_IPV4_ONLY != 0,
+        flags & BGS_IPV6_ONLY != 0,
+    )?;
+    let prefixes: Vec<IpNet> = prefix_origins.into_keys().collect();
+    let mut prefixes = if flags & BGS_NO_AGGREGATE == 0 {
+        IpNet::aggregate(&prefixes)
+    } else {
+        prefixes
+    };
+    prefixes.sort_unstable();
+    Ok(prefixes)
+}
+
+/// Finds the prefixes announced by the given origin ASNs in an uncompressed MRT file, aggregated
+/// unless `BGS_NO_AGGREGATE` is set in `flags`. Always returns a result, which holds an error
+/// message and code when the lookup failed and must be released with `bgs_free_result`. Panics
+/// are caught and reported as `BGS_PANIC`, as unwinding into C is undefined behavior.
+///
+/// # Safety
+///
+/// `mrt_file` must be a NUL-terminated string and `asns` must point to `asns_len` ASNs, or be
+/// null when `asns_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn bgs_find_netblocks(
+    mrt_file: *const c_char,
+    asns: *const u32,
+    asns_len: usize,
+    flags: u32,
+) -> *mut BgsResult {
+    let found = panic::catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: the caller upholds the requirements of bgs_find_netblocks
+        unsafe { find_netblocks(mrt_file, asns, asns_len, flags) }
+    }));
+    let result = match found {
+        Ok(Ok(prefixes)) => {
+            let prefixes: Box<[*mut c_char]> = prefixes
+                .iter()
+                .filter_map(|prefix| CString::new(prefix.to_string()).ok())
+                .map(CString::into_raw)
+                .collect();
+            BgsResult {
+                len: prefixes.len(),
+                prefixes: Box::into_raw(prefixes).cast(),
+                error: ptr::null_mut(),
+                code: BGS_OK,
+            }
+        }
+        Ok(Err(e)) => error_result(BGS_ERROR, &e.to_string()),
+        Err(payload) => error_result(
+            BGS_PANIC,
+            &format!("bgp_scout panicked: {}", panic_message(&*payload)),
+        ),
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Releases a result returned by `bgs_find_netblocks`. Does nothing when given null. Returns
+/// `BGS_OK`, or `BGS_PANIC` when releasing it panicked.
+///
+/// # Safety
+///
+/// `result` must have been returned by `bgs_find_netblocks` and not been released before.
+#[no_mangle]
+pub unsafe extern "C" fn bgs_free_result(result: *mut BgsResult) -> i32 {
+    if result.is_null() {
+        return BGS_OK;
+    }
+    let released = panic::catch_unwind(AssertUnwindSafe(|| {
+        // SAFETY: the result was boxed by bgs_find_netblocks and is released only once
+        let result = unsafe { Box::from_raw(result) };
+        if !result.prefixes.is_null() {
+            // SAFETY: the prefixes were a boxed slice of len strings, each from CString::into_raw
+            let prefixes = unsafe {
+                Box::from_raw(ptr::slice_from_raw_parts_mut(result.prefixes, result.len))
+            };
+            for prefix in prefixes.iter() {
+                // SAFETY: each prefix came from CString::into_raw in bgs_find_netblocks, and the
+                // boxed slice holding it is dropped here, so no pointer is freed twice
+                drop(unsafe { CString::from_raw(*prefix) });
+            }
+        }
+        if !result.error.is_null() {
+            // SAFETY: the error came from CString::into_raw
+            drop(unsafe { CString::from_raw(result.error) });
+        }
+    }));
+    match released {
+        Ok(()) => BGS_OK,
+        Err(_) => BGS_PANIC,
+    }
+}
@@ -0,0 +1,241 @@
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use crate::frr;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyTarget {
+    /// An nftables set, updated with nft -f
+    Nft,
+    /// An ipset set, updated with ipset restore
+    Ipset,
+    /// The ip and ipv6 prefix lists of FRR, updated with vtysh
+    Frr,
+}
+
+/// Identifies the firewall set to update
+#[derive(Debug)]
+pub struct SetTarget<'target> {
+    pub kind: ApplyTarget,
+    pub name: &'target str,
+    /// Family and table holding an nftables set, e.g. `inet filter`
+    pub nft_table: &'target str,
+}
+
+/// Current contents and address family of a firewall set
+#[derive(Debug)]
+pub struct SetContents {
+    pub is_v6: bool,
+    pub prefixes: BTreeSet<IpNet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NftOutput {
+    nftables: Vec<serde_json::Value>,
+}
+
+/// Parses an address or prefix as listed by ipset or nft, where single addresses have no length.
+pub fn parse_member(value: &str) -> Option<IpNet> {
+    IpNet::from_str(value)
+        .ok()
+        .or_else(|| IpAddr::from_str(value).ok().map(IpNet::from))
+        .map(|prefix| prefix.trunc())
+}
+
+/// Parses an nftables JSON set element, which is an address, a prefix object or an element
+/// wrapping either with counters or timeouts.
+fn parse_nft_element(element: &serde_json::Value) -> Option<IpNet> {
+    if let Some(address) = element.as_str() {
+        return parse_member(address);
+    }
+    if let Some(prefix) = element.get("prefix") {
+        let address = prefix.get("addr")?.as_str()?;
+        let len = prefix.get("len")?.as_u64()?;
+        return parse_member(&format!("{address}/{len}"));
+    }
+    parse_nft_element(element.get("elem")?.get("val")?)
+}
+
+/// Runs a program to completion, feeding it the input, and returns its stdout.
+pub fn run(program: &str, args: &[&str], input: Option<&str>) -> Result<String, Box<dyn Error>> {
+    debug!("Running {program} {}", args.join(" "));
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run {program}: {e}"))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "{program} {} failed with {}: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+pub fn read_set(target: &SetTarget<'_>) -> Result<SetContents, Box<dyn Error>> {
+    match target.kind {
+        ApplyTarget::Ipset => {
+            let saved = run("ipset", &["save", target.name], None)?;
+            let mut contents = SetContents {
+                is_v6: false,
+                prefixes: BTreeSet::new(),
+            };
+            for line in saved.lines() {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.as_slice() {
+                    ["create", _, ..] => {
+                        contents.is_v6 = fields
+                            .windows(2)
+                            .any(|pair| pair[0] == "family" && pair[1] == "inet6");
+                    }
+                    ["add", _, member, ..] => match parse_member(member) {
+                        Some(prefix) => {
+                            contents.prefixes.insert(prefix);
+                        }
+                        None => warn!("Ignoring unsupported ipset member {member}"),
+                    },
+                    _ => {}
+                }
+            }
+            Ok(contents)
+        }
+        ApplyTarget::Nft => {
+            let mut args = vec!["-j", "list", "set"];
+            args.extend(target.nft_table.split_whitespace());
+            args.push(target.name);
+            let output: NftOutput = serde_json::from_str(&run("nft", &args, None)?)?;
+            let set = output
+                .nftables
+                .iter()
+                .find_map(|entry| entry.get("set"))
+                .ok_or_else(|| format!("nft did not list set {}", target.name))?;
+            let mut contents = SetContents {
+                is_v6: set.get("type").and_then(|t| t.as_str()) == Some("ipv6_addr"),
+                prefixes: BTreeSet::new(),
+            };
+            for element in set
+                .get("elem")
+                .and_then(|elements| elements.as_array())
+                .into_iter()
+                .flatten()
+            {
+                match parse_nft_element(element) {
+                    Some(prefix) => {
+                        contents.prefixes.insert(prefix);
+                    }
+                    None => warn!("Ignoring unsupported nftables set element {element}"),
+                }
+            }
+            Ok(contents)
+        }
+        ApplyTarget::Frr => {
+            Err(format!("{} is an FRR prefix list, not a firewall set", target.name).into())
+        }
+    }
+}
+
+/// Builds the batch input adding and removing set members, in the format read by `nft -f` or
+/// `ipset restore`.
+fn delta_commands(target: &SetTarget<'_>, added: &[IpNet], removed: &[IpNet]) -> String {
+    let mut commands = String::new();
+    match target.kind {
+        ApplyTarget::Ipset => {
+            for prefix in removed {
+                commands.push_str(&format!("del {} {prefix}\n", target.name));
+            }
+            for prefix in added {
+                commands.push_str(&format!("add {} {prefix}\n", target.name));
+            }
+        }
+        ApplyTarget::Nft => {
+            for (verb, prefixes) in [("delete", removed), ("add", added)] {
+                if prefixes.is_empty() {
+                    continue;
+                }
+                let elements: Vec<String> = prefixes.iter().map(IpNet::to_string).collect();
+                commands.push_str(&format!(
+                    "{verb} element {} {} {{ {} }}\n",
+                    target.nft_table,
+                    target.name,
+                    elements.join(", ")
+                ));
+            }
+        }
+        // Prefix lists are updated entry by entry, see frr::apply
+        ApplyTarget::Frr => {}
+    }
+    commands
+}
+
+/// Updates a firewall set to hold the prefixes of its address family, adding and removing only
+/// the members that changed so existing connection tracking state is left alone. With `dry_run`
+/// the batch of changes is printed to stdout instead.
+pub fn apply(
+    target: &SetTarget<'_>,
+    prefixes: &[IpNet],
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    if target.kind == ApplyTarget::Frr {
+        return frr::apply(target.name, prefixes, dry_run);
+    }
+    let current = read_set(target)?;
+    let wanted: BTreeSet<IpNet> = prefixes
+        .iter()
+        .filter(|prefix| matches!(prefix, IpNet::V6(_)) == current.is_v6)
+        .map(IpNet::trunc)
+        .collect();
+    let skipped = prefixes.len() - wanted.len();
+    if skipped > 0 {
+        warn!(
+            "Skipping {skipped} prefixes not matching the address family of set {}",
+            target.name
+        );
+    }
+
+    let added: Vec<IpNet> = wanted.difference(&current.prefixes).copied().collect();
+    let removed: Vec<IpNet> = current.prefixes.difference(&wanted).copied().collect();
+    info!(
+        "Set {} update adds {} and removes {} prefixes",
+        target.name,
+        added.len(),
+        removed.len()
+    );
+    let commands = delta_commands(target, &added, &removed);
+    if dry_run {
+        print!("{commands}");
+        return Ok(());
+    }
+    if commands.is_empty() {
+        info!("Set {} is already up to date", target.name);
+        return Ok(());
+    }
+    match target.kind {
+        ApplyTarget::Ipset => run("ipset", &["restore"], Some(&commands))?,
+        ApplyTarget::Nft => run("nft", &["-f", "-"], Some(&commands))?,
+        ApplyTarget::Frr => return Ok(()),
+    };
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use bgpkit_parser::models::ElemType;
+use bgpkit_parser::BgpkitParser;
+use chrono::{DateTime, TimeDelta, Utc};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io;
+
+use crate::mrt;
+use crate::render::{self, ReportFormat};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// RIPE RIS publishes an updates file every five minutes
+const RIS_UPDATES_INTERVAL_SECONDS: i64 = 300;
+
+#[derive(Debug, Default, Serialize)]
+pub struct PrefixChurn {
+    pub prefix: IpNet,
+    pub announcements: u64,
+    pub withdrawals: u64,
+    pub unstable: bool,
+}
+
+impl PrefixChurn {
+    pub const fn events(&self) -> u64 {
+        self.announcements + self.withdrawals
+    }
+}
+
+/// Parses a window boundary given either as RFC 3339 or as unix seconds.
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| format!("Timestamp {value} is out of range"));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid time {value}: {e}"))
+}
+
+/// Builds the list of RIPE RIS updates file URLs covering the window `[start, end)`.
+pub fn ripe_updates_urls(rrc: u8, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<String> {
+    let interval = TimeDelta::seconds(RIS_UPDATES_INTERVAL_SECONDS);
+    let start_seconds = start.timestamp() - start.timestamp() % RIS_UPDATES_INTERVAL_SECONDS;
+    let mut urls = Vec::new();
+    let mut current = DateTime::from_timestamp(start_seconds, 0).unwrap_or(start);
+    while current < end {
+        urls.push(format!(
+            "https://data.ris.ripe.net/rrc{rrc:02}/{}/updates.{}.gz",
+            current.format("%Y.%m"),
+            current.format("%Y%m%d.%H%M")
+        ));
+        current += interval;
+    }
+    urls
+}
+
+/// Returns the end of the newest updates file window that RIS has most likely published.
+pub fn default_end() -> DateTime<Utc> {
+    let now = Utc::now().timestamp() - 2 * RIS_UPDATES_INTERVAL_SECONDS;
+    let end_seconds = now - now % RIS_UPDATES_INTERVAL_SECONDS;
+    DateTime::from_timestamp(end_seconds, 0).unwrap_or_else(Utc::now)
+}
+
+/// Counts announcements and withdrawals per prefix originated by the target ASNs.
+///
+/// Withdrawals carry no origin, so a withdrawal is only counted once the prefix has been seen
+/// announced by one of the target ASNs earlier in the window. Files must therefore be given in
+/// chronological order.
+pub fn scan_churn(
+    files: &[String],
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+    threshold: u64,
+) -> Result<Vec<PrefixChurn>, Box<dyn Error>> {
+    let mut churn: HashMap<IpNet, PrefixChurn> = HashMap::new();
+
+    for file_name in files {
+        debug!("Scanning updates file {file_name} for churn");
+        let mut parser = BgpkitParser::from_reader(mrt::open(file_name)?);
+
+        match (ipv4_only, ipv6_only) {
+            (true, false) => parser = parser.add_filter("ip_version", "ipv4")?,
+            (false, true) => parser = parser.add_filter("ip_version", "ipv6")?,
+            _ => {}
+        }
+
+        for elem in parser.into_elem_iter() {
+            let prefix = elem.prefix.prefix;
+            match elem.elem_type {
+                ElemType::ANNOUNCE => {
+                    let is_target = elem.origin_asns.as_ref().is_some_and(|origins| {
+                        origins
+                            .iter()
+                            .any(|asn| origin_asns.contains(&asn.to_u32()))
+                    });
+                    if is_target {
+                        let entry = churn.entry(prefix).or_insert_with(|| PrefixChurn {
+                            prefix,
+                            ..PrefixChurn::default()
+                        });
+                        entry.announcements += 1;
+                        trace!("Announcement of {prefix} by peer {}", elem.peer_ip);
+                    }
+                }
+                ElemType::WITHDRAW => {
+                    if let Some(entry) = churn.get_mut(&prefix) {
+                        entry.withdrawals += 1;
+                        trace!("Withdrawal of {prefix} by peer {}", elem.peer_ip);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut result: Vec<PrefixChurn> = churn
+        .into_values()
+        .map(|mut entry| {
+            entry.unstable = entry.events() >= threshold;
+            entry
+        })
+        .collect();
+    result.sort_by(|a, b| b.events().cmp(&a.events()).then(a.prefix.cmp(&b.prefix)));
+
+    debug!(
+        "Found churn for {} prefixes, {} unstable",
+        result.len(),
+        result.iter().filter(|entry| entry.unstable).count()
+    );
+
+    Ok(result)
+}
+
+pub fn render_report(report: &[PrefixChurn], format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), report)?,
+        ReportFormat::Text => {
+            for entry in report {
+                let flag = if entry.unstable { " UNSTABLE" } else { "" };
+                println!(
+                    "{} announcements={} withdrawals={}{flag}",
+                    entry.prefix, entry.announcements, entry.withdrawals
+                );
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = report
+                .iter()
+                .map(|entry| {
+                    vec![
+                        entry.prefix.to_string(),
+                        entry.announcements.to_string(),
+                        entry.withdrawals.to_string(),
+                        if entry.unstable { "yes" } else { "no" }.to_string(),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &["PREFIX", "ANNOUNCEMENTS", "WITHDRAWALS", "UNSTABLE"],
+                &rows,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use ipnet::IpNet;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::firewall;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Gap between the sequence numbers of prefix list entries, as FRR numbers them, which leaves
+/// room to insert entries by hand
+pub const SEQ_STEP: u32 = 5;
+
+/// Keyword starting the configuration of a prefix list of the family
+pub const fn prefix_list_keyword(prefix: &IpNet) -> &'static str {
+    match prefix {
+        IpNet::V4(_) => "ip",
+        IpNet::V6(_) => "ipv6",
+    }
+}
+
+/// Entry of a prefix list in the running configuration
+#[derive(Debug)]
+struct Entry {
+    /// `ip` or `ipv6`
+    keyword: String,
+    seq: u32,
+    /// The entry after its sequence number, e.g. `permit 192.0.2.0/24 le 32`
+    rule: String,
+    /// The prefix of a plain permit entry, as generated from results
+    permitted: Option<IpNet>,
+}
+
+/// Reads the entries of the IPv4 and IPv6 prefix lists with the given name from the running
+/// configuration.
+fn read_entries(name: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let config = firewall::run("vtysh", &["-c", "show running-config"], None)?;
+    let mut entries = Vec::new();
+    for line in config.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [keyword @ ("ip" | "ipv6"), "prefix-list", list, "seq", seq, rule @ ..] =
+            fields.as_slice()
+        else {
+            continue;
+        };
+        if *list != name {
+            continue;
+        }
+        let Ok(seq) = seq.parse() else {
+            warn!("Ignoring prefix list entry with invalid sequence number: {line}");
+            continue;
+        };
+        let permitted = match rule {
+            ["permit", prefix] => IpNet::from_str(prefix).ok(),
+            _ => None,
+        };
+        entries.push(Entry {
+            keyword: (*keyword).to_string(),
+            seq,
+            rule: rule.join(" "),
+            permitted,
+        });
+    }
+    Ok(entries)
+}
+
+/// Builds the vtysh configuration commands turning the prefix lists into ones permitting exactly
+/// the prefixes. New entries are numbered after the existing ones and added before any entry is
+/// removed, so a prefix replaced by one covering it is never denied in between.
+fn delta_commands(name: &str, entries: &[Entry], prefixes: &[IpNet]) -> Vec<String> {
+    let mut next_seq: BTreeMap<&str, u32> = BTreeMap::new();
+    for entry in entries {
+        let seq = next_seq.entry(entry.keyword.as_str()).or_default();
+        *seq = (*seq).max(entry.seq);
+    }
+
+    let wanted: HashSet<&IpNet> = prefixes.iter().collect();
+    let present: HashSet<IpNet> = entries.iter().filter_map(|entry| entry.permitted).collect();
+    let mut commands = Vec::new();
+    for prefix in prefixes {
+        if present.contains(prefix) {
+            continue;
+        }
+        let keyword = prefix_list_keyword(prefix);
+        let seq = next_seq.entry(keyword).or_default();
+        *seq += SEQ_STEP;
+        commands.push(format!(
+            "{keyword} prefix-list {name} seq {seq} permit {prefix}"
+        ));
+    }
+    for entry in entries {
+        let wanted = entry
+            .permitted
+            .is_some_and(|permitted| wanted.contains(&permitted));
+        if !wanted {
+            commands.push(format!(
+                "no {} prefix-list {name} seq {} {}",
+                entry.keyword, entry.seq, entry.rule
+            ));
+        }
+    }
+    commands
+}
+
+/// Updates the FRR prefix lists with the given name, `ip prefix-list` for IPv4 and
+/// `ipv6 prefix-list` for IPv6, to permit exactly the prefixes, adding and removing only the
+/// entries that changed. With `dry_run` the configuration commands are printed to stdout instead.
+pub fn apply(name: &str, prefixes: &[IpNet], dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let entries = read_entries(name)?;
+    let commands = delta_commands(name, &entries, prefixes);
+    info!(
+        "Prefix list {name} update has {} changes to {} entries",
+        commands.len(),
+        entries.len()
+    );
+    if dry_run {
+        for command in &commands {
+            println!("{command}");
+        }
+        return Ok(());
+    }
+    if commands.is_empty() {
+        info!("Prefix list {name} is already up to date");
+        return Ok(());
+    }
+    let mut args = vec!["-c", "configure terminal"];
+    for command in &commands {
+        args.extend(["-c", command.as_str()]);
+    }
+    firewall::run("vtysh", &args, None)?;
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::grpc::{self, Message, Value};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Default port of the GoBGP gRPC API
+const DEFAULT_GRPC_PORT: &str = "50051";
+
+/// gRPC service of the GoBGP API
+const SERVICE: &str = "apipb.GobgpApi";
+
+/// `Family.Afi` values of the GoBGP API
+const AFI_IP: u64 = 1;
+const AFI_IP6: u64 = 2;
+/// `Family.Safi` value of unicast routes
+const SAFI_UNICAST: u64 = 1;
+
+/// Identifies the GoBGP instance to update and the attributes of the injected routes
+#[derive(Debug)]
+pub struct GobgpTarget<'target> {
+    /// Address of the gRPC API, e.g. `127.0.0.1:50051`
+    pub grpc: &'target str,
+    /// Next hop of the injected routes [default: 0.0.0.0 or ::, which GoBGP announces as its own
+    /// address]
+    pub next_hop: Option<&'target str>,
+    /// Communities of the injected routes, e.g. `65000:100`
+    pub communities: &'target [String],
+}
+
+/// Splits a gRPC address into its host and port, accepting a bare host and bracketed IPv6
+/// addresses.
+fn split_address(address: &str) -> (&str, &str) {
+    if let Some(rest) = address.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once(']') {
+            return (host, port.strip_prefix(':').unwrap_or(DEFAULT_GRPC_PORT));
+        }
+    }
+    match address.split_once(':') {
+        // More than one colon is a bare IPv6 address
+        Some((host, port)) if !port.contains(':') => (host, port),
+        _ => (address, DEFAULT_GRPC_PORT),
+    }
+}
+
+/// Returns the `host:port` authority of the gRPC API, with IPv6 hosts in brackets.
+fn authority(address: &str) -> String {
+    match split_address(address) {
+        (host, port) if host.contains(':') => format!("[{host}]:{port}"),
+        (host, port) => format!("{host}:{port}"),
+    }
+}
+
+fn family(prefix: &IpNet) -> Message {
+    let afi = match prefix {
+        IpNet::V4(_) => AFI_IP,
+        IpNet::V6(_) => AFI_IP6,
+    };
+    Message::default().varint(1, afi).varint(2, SAFI_UNICAST)
+}
+
+/// Parses a community written as `asn:value` into its 32-bit form.
+fn parse_community(community: &str) -> Result<u64, Box<dyn Error>> {
+    let (asn, value) = community
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid community {community}, expected asn:value"))?;
+    let asn: u16 = asn
+        .parse()
+        .map_err(|_| format!("Invalid community {community}, expected asn:value"))?;
+    let value: u16 = value
+        .parse()
+        .map_err(|_| format!("Invalid community {community}, expected asn:value"))?;
+    Ok(u64::from(asn) << 16 | u64::from(value))
+}
+
+/// Builds the `Path` announcing a prefix, with an IGP origin, the next hop and communities.
+/// IPv4 routes carry their next hop in a NEXT_HOP attribute and IPv6 routes in MP_REACH_NLRI.
+fn path(target: &GobgpTarget<'_>, prefix: &IpNet) -> Result<Message, Box<dyn Error>> {
+    let nlri = Message::default()
+        .varint(1, u64::from(prefix.prefix_len()))
+        .string(2, &prefix.addr().to_string())
+        .any("apipb.IPAddressPrefix");
+    let origin = Message::default().any("apipb.OriginAttribute");
+    let next_hop_attribute = match prefix {
+        IpNet::V4(_) => Message::default()
+            .string(1, target.next_hop.unwrap_or("0.0.0.0"))
+            .any("apipb.NextHopAttribute"),
+        IpNet::V6(_) => Message::default()
+            .message(1, &family(prefix))
+            .string(2, target.next_hop.unwrap_or("::"))
+            .message(3, &nlri)
+            .any("apipb.MpReachNLRIAttribute"),
+    };
+    let mut path = Message::default()
+        .message(1, &nlri)
+        .message(2, &origin)
+        .message(2, &next_hop_attribute);
+    if !target.communities.is_empty() {
+        let communities = target
+            .communities
+            .iter()
+            .map(|community| parse_community(community))
+            .collect::<Result<Vec<u64>, _>>()?;
+        let attribute = Message::default()
+            .packed(1, &communities)
+            .any("apipb.CommunitiesAttribute");
+        path = path.message(2, &attribute);
+    }
+    Ok(path.message(9, &family(prefix)))
+}
+
+/// Reads the prefix of a `ListPathResponse` whose destination has a locally originated path,
+/// as opposed to paths learned from peers, which have a neighbor address.
+fn local_prefix(response: &[u8]) -> Result<Option<String>, Box<dyn Error>> {
+    let mut prefix = None;
+    let mut local = false;
+    for (field, value) in grpc::fields(response)? {
+        let (1, Value::Bytes(destination)) = (field, value) else {
+            continue;
+        };
+        for (field, value) in grpc::fields(destination)? {
+            match (field, value) {
+                (1, value) => prefix = value.as_str().map(str::to_string),
+                (2, Value::Bytes(path)) => {
+                    let neighbor = grpc::fields(path)?
+                        .into_iter()
+                        .find(|(field, _)| *field == 15)
+                        .and_then(|(_, value)| value.as_str());
+                    local |= matches!(neighbor, None | Some("" | "<nil>"));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(prefix.filter(|_| local))
+}
+
+/// Lists the prefixes originated locally in the global RIB, which are the routes previous runs
+/// injected.
+fn local_prefixes(
+    target: &GobgpTarget<'_>,
+    family: &Message,
+) -> Result<BTreeSet<IpNet>, Box<dyn Error>> {
+    // The table type is left at its default, the global RIB
+    let request = Message::default().message(3, family);
+    let responses = grpc::call(
+        &authority(target.grpc),
+        &format!("{SERVICE}/ListPath"),
+        &request,
+    )?;
+    let mut prefixes = BTreeSet::new();
+    for response in responses {
+        let Some(prefix) = local_prefix(&response)? else {
+            continue;
+        };
+        match IpNet::from_str(&prefix) {
+            Ok(prefix) => {
+                prefixes.insert(prefix);
+            }
+            Err(_) => warn!("Ignoring unsupported GoBGP RIB entry {prefix}"),
+        }
+    }
+    Ok(prefixes)
+}
+
+/// Injects the prefixes into the global RIB of a GoBGP instance, for it to announce to its
+/// peers. Locally originated routes that are no longer in the results are withdrawn, and routes
+/// already present are left alone, so repeated runs only send the changes.
+///
+/// GoBGP is driven through the AddPath, DeletePath and ListPath methods of its gRPC API, which
+/// must be reachable without TLS, as `gobgpd` serves it by default.
+pub fn update_rib(target: &GobgpTarget<'_>, prefixes: &[IpNet]) -> Result<(), Box<dyn Error>> {
+    let wanted: BTreeSet<IpNet> = prefixes.iter().map(IpNet::trunc).collect();
+    let ipv4 = family(&IpNet::V4(Default::default()));
+    let ipv6 = family(&IpNet::V6(Default::default()));
+    let mut current = local_prefixes(target, &ipv4)?;
+    current.extend(local_prefixes(target, &ipv6)?);
+
+    let added: Vec<&IpNet> = wanted.difference(&current).collect();
+    let removed: Vec<&IpNet> = current.difference(&wanted).collect();
+    info!(
+        "GoBGP {} update adds {} and removes {} prefixes",
+        target.grpc,
+        added.len(),
+        removed.len()
+    );
+    let address = authority(target.grpc);
+    for prefix in removed {
+        let request = Message::default()
+            .message(3, &family(prefix))
+            .message(4, &path(target, prefix)?);
+        grpc::call(&address, &format!("{SERVICE}/DeletePath"), &request)?;
+    }
+    for prefix in added {
+        let request = Message::default().message(3, &path(target, prefix)?);
+        grpc::call(&address, &format!("{SERVICE}/AddPath"), &request)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authority_brackets_ipv6_hosts() {
+        assert_eq!(authority("127.0.0.1:50052"), "127.0.0.1:50052");
+        assert_eq!(authority("gobgp"), "gobgp:50051");
+        assert_eq!(authority("::1"), "[::1]:50051");
+        assert_eq!(authority("[::1]:50052"), "[::1]:50052");
+    }
+
+    #[test]
+    fn ipv4_path_has_next_hop_and_communities() -> Result<(), Box<dyn Error>> {
+        let communities = ["65000:100".to_string(), "65000:200".to_string()];
+        let target = GobgpTarget {
+            grpc: "127.0.0.1",
+            next_hop: Some("192.0.2.1"),
+            communities: &communities,
+        };
+        let path = path(&target, &IpNet::from_str("198.51.100.0/24")?)?;
+
+        let nlri = Message::default()
+            .varint(1, 24)
+            .string(2, "198.51.100.0")
+            .any("apipb.IPAddressPrefix");
+        let next_hop = Message::default()
+            .string(1, "192.0.2.1")
+            .any("apipb.NextHopAttribute");
+        let communities = Message::default()
+            .packed(1, &[65000 << 16 | 100, 65000 << 16 | 200])
+            .any("apipb.CommunitiesAttribute");
+        let family = Message::default().varint(1, 1).varint(2, 1);
+        let expected = Message::default()
+            .message(1, &nlri)
+            .message(2, &Message::default().any("apipb.OriginAttribute"))
+            .message(2, &next_hop)
+            .message(2, &communities)
+            .message(9, &family);
+        assert_eq!(path.as_bytes(), expected.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_path_defaults_next_hop() -> Result<(), Box<dyn Error>> {
+        let target = GobgpTarget {
+            grpc: "127.0.0.1",
+            next_hop: None,
+            communities: &[],
+        };
+        let path = path(&target, &IpNet::from_str("2001:db8::/32")?)?;
+        let attributes: Vec<Value<'_>> = grpc::fields(path.as_bytes())?
+            .into_iter()
+            .filter(|(field, _)| *field == 2)
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(attributes.len(), 2);
+        let mp_reach = grpc::fields(attributes[1].as_bytes().unwrap_or_default())?;
+        assert_eq!(
+            mp_reach[0].1.as_str(),
+            Some("type.googleapis.com/apipb.MpReachNLRIAttribute")
+        );
+        let fields = grpc::fields(mp_reach[1].1.as_bytes().unwrap_or_default())?;
+        assert_eq!(fields[1], (2, Value::Bytes(b"::")));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_community_is_rejected() {
+        assert_eq!(parse_community("65000:100").ok(), Some(0xfde8_0064));
+        assert!(parse_community("65000").is_err());
+        assert!(parse_community("70000:1").is_err());
+    }
+
+    #[test]
+    fn local_prefix_skips_learned_routes() -> Result<(), Box<dyn Error>> {
+        let local_path = Message::default().string(15, "<nil>");
+        let learned_path = Message::default().string(15, "192.0.2.2");
+        let local = Message::default().message(
+            1,
+            &Message::default()
+                .string(1, "198.51.100.0/24")
+                .message(2, &local_path),
+        );
+        let learned = Message::default().message(
+            1,
+            &Message::default()
+                .string(1, "203.0.113.0/24")
+                .message(2, &learned_path),
+        );
+        assert_eq!(
+            local_prefix(local.as_bytes())?.as_deref(),
+            Some("198.51.100.0/24")
+        );
+        assert_eq!(local_prefix(learned.as_bytes())?, None);
+        Ok(())
+    }
+}
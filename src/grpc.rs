@@ -0,0 +1,410 @@
+use http_body_util::BodyExt;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, TE};
+use std::error::Error;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Length of the prefix of each gRPC message: a compression flag and a big-endian length
+const MESSAGE_PREFIX_LEN: usize = 5;
+
+/// Wire types of protobuf fields
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+/// A protobuf message written field by field. Fields holding the default value of their type
+/// are left out by the callers, as proto3 does.
+#[derive(Debug, Default, Clone)]
+pub struct Message(Vec<u8>);
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+impl Message {
+    fn key(&mut self, field: u32, wire_type: u64) {
+        put_varint(&mut self.0, u64::from(field) << 3 | wire_type);
+    }
+
+    pub fn varint(mut self, field: u32, value: u64) -> Self {
+        self.key(field, WIRE_VARINT);
+        put_varint(&mut self.0, value);
+        self
+    }
+
+    pub fn bytes(mut self, field: u32, value: &[u8]) -> Self {
+        self.key(field, WIRE_LEN);
+        put_varint(&mut self.0, value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(self, field: u32, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn message(self, field: u32, message: &Self) -> Self {
+        self.bytes(field, &message.0)
+    }
+
+    /// Writes a repeated scalar field in the packed encoding of proto3.
+    pub fn packed(self, field: u32, values: &[u64]) -> Self {
+        let mut packed = Vec::new();
+        for value in values {
+            put_varint(&mut packed, *value);
+        }
+        self.bytes(field, &packed)
+    }
+
+    /// Wraps the message in a `google.protobuf.Any` of the type.
+    pub fn any(&self, type_name: &str) -> Self {
+        Self::default()
+            .string(1, &format!("type.googleapis.com/{type_name}"))
+            .bytes(2, &self.0)
+    }
+
+    #[cfg(test)]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Value of a decoded protobuf field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'message> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'message [u8]),
+    Fixed32(u32),
+}
+
+impl<'message> Value<'message> {
+    pub fn as_bytes(self) -> Option<&'message [u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> Option<&'message str> {
+        std::str::from_utf8(self.as_bytes()?).ok()
+    }
+}
+
+fn get_varint(message: &[u8], offset: &mut usize) -> Result<u64, String> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *message.get(*offset).ok_or("truncated protobuf varint")?;
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("protobuf varint longer than 10 bytes".to_string())
+}
+
+fn take<'message>(
+    message: &'message [u8],
+    offset: &mut usize,
+    len: usize,
+) -> Result<&'message [u8], String> {
+    let value = message
+        .get(*offset..offset.saturating_add(len))
+        .ok_or("truncated protobuf field")?;
+    *offset += len;
+    Ok(value)
+}
+
+/// Decodes the fields of a protobuf message in order, as field numbers and values.
+pub fn fields(message: &[u8]) -> Result<Vec<(u32, Value<'_>)>, String> {
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    while offset < message.len() {
+        let key = get_varint(message, &mut offset)?;
+        let field = u32::try_from(key >> 3).map_err(|_| "invalid protobuf field number")?;
+        let value = match key & 7 {
+            WIRE_VARINT => Value::Varint(get_varint(message, &mut offset)?),
+            WIRE_FIXED64 => {
+                let bytes = take(message, &mut offset, 8)?;
+                Value::Fixed64(u64::from_le_bytes(
+                    bytes.try_into().map_err(|_| "truncated")?,
+                ))
+            }
+            WIRE_LEN => {
+                let len = usize::try_from(get_varint(message, &mut offset)?)
+                    .map_err(|_| "protobuf field too long")?;
+                Value::Bytes(take(message, &mut offset, len)?)
+            }
+            WIRE_FIXED32 => {
+                let bytes = take(message, &mut offset, 4)?;
+                Value::Fixed32(u32::from_le_bytes(
+                    bytes.try_into().map_err(|_| "truncated")?,
+                ))
+            }
+            wire_type => return Err(format!("unsupported protobuf wire type {wire_type}")),
+        };
+        fields.push((field, value));
+    }
+    Ok(fields)
+}
+
+/// Frames a message as the body of a gRPC request.
+fn frame(message: &Message) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut body = vec![0];
+    body.extend_from_slice(&u32::try_from(message.0.len())?.to_be_bytes());
+    body.extend_from_slice(&message.0);
+    Ok(body)
+}
+
+/// Splits the body of a gRPC response into its messages.
+fn unframe(mut body: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut messages = Vec::new();
+    while !body.is_empty() {
+        let Some((prefix, rest)) = body.split_first_chunk::<MESSAGE_PREFIX_LEN>() else {
+            return Err("truncated gRPC message".to_string());
+        };
+        if prefix[0] != 0 {
+            return Err("compressed gRPC messages are not supported".to_string());
+        }
+        let len = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+        if rest.len() < len {
+            return Err("truncated gRPC message".to_string());
+        }
+        messages.push(rest[..len].to_vec());
+        body = &rest[len..];
+    }
+    Ok(messages)
+}
+
+/// Decodes the percent-encoding of grpc-message.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the error of a call from the grpc-status and grpc-message of its trailers, or of its
+/// headers for responses without a body.
+fn check_status(method: &str, metadata: &HeaderMap) -> Result<bool, Box<dyn Error>> {
+    let Some(status) = metadata.get("grpc-status") else {
+        return Ok(false);
+    };
+    match status.to_str()? {
+        "0" => Ok(true),
+        status => {
+            let message = metadata
+                .get("grpc-message")
+                .and_then(|message| message.to_str().ok())
+                .map(percent_decode)
+                .unwrap_or_default();
+            Err(format!("{method} failed with gRPC status {status}: {message}").into())
+        }
+    }
+}
+
+/// Calls a gRPC method of a server at `host:port` over HTTP/2 without TLS, returning the messages
+/// it answered with: one for unary methods, any number for server streaming ones.
+pub fn call(
+    address: &str,
+    method: &str,
+    request: &Message,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let url = format!("http://{address}/{method}");
+    debug!("Calling gRPC {url}");
+    let body = frame(request)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .timeout(NETWORK_TIMEOUT)
+            .build()?;
+        let response = client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/grpc")
+            .header(TE, "trailers")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to call {url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("{method} failed with HTTP {status}").into());
+        }
+        let response = http::Response::from(response);
+        // Errors without a body come as a response of headers only
+        if check_status(method, response.headers())? {
+            return Ok(Vec::new());
+        }
+        let collected = response.into_body().collect().await?;
+        let trailers = collected.trailers().cloned().unwrap_or_default();
+        let messages = unframe(&collected.to_bytes())?;
+        if !check_status(method, &trailers)? {
+            return Err(format!("{method} ended without a gRPC status").into());
+        }
+        Ok(messages)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn message_encodes_protobuf_fields() -> Result<(), Box<dyn Error>> {
+        let message = Message::default()
+            .varint(1, 150)
+            .string(2, "testing")
+            .packed(4, &[3, 270]);
+        assert_eq!(
+            message.as_bytes(),
+            [
+                0x08, 0x96, 0x01, 0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g', 0x22, 0x03,
+                0x03, 0x8e, 0x02
+            ]
+        );
+        let decoded = fields(message.as_bytes())?;
+        assert_eq!(decoded[0], (1, Value::Varint(150)));
+        assert_eq!(decoded[1].1.as_str(), Some("testing"));
+        assert!(fields(&[0x12, 0x07, b't']).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn messages_are_framed_with_their_length() -> Result<(), Box<dyn Error>> {
+        let message = Message::default().varint(1, 1);
+        let body = frame(&message)?;
+        assert_eq!(body, [0, 0, 0, 0, 2, 0x08, 0x01]);
+        let mut stream = body.clone();
+        stream.extend_from_slice(&body);
+        assert_eq!(unframe(&stream)?, [vec![0x08, 0x01], vec![0x08, 0x01]]);
+        assert!(unframe(&body[..6]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn grpc_message_is_percent_decoded() {
+        assert_eq!(percent_decode("no%20such%20path%"), "no such path%");
+    }
+
+    /// Serves one call of a method over h2c, answering with the messages and status.
+    fn serve(
+        listener: TcpListener,
+        messages: Vec<Vec<u8>>,
+        status: &'static str,
+    ) -> thread::JoinHandle<Result<(String, Vec<u8>), String>> {
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())?;
+            runtime.block_on(async move {
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                let listener =
+                    tokio::net::TcpListener::from_std(listener).map_err(|e| e.to_string())?;
+                let (socket, _) = listener.accept().await.map_err(|e| e.to_string())?;
+                let mut connection = h2::server::handshake(socket)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let (request, mut respond) = connection
+                    .accept()
+                    .await
+                    .ok_or("no request")?
+                    .map_err(|e| e.to_string())?;
+                // Drive the connection until the client has read the response and hung up
+                let driver =
+                    tokio::spawn(async move { while connection.accept().await.is_some() {} });
+                let path = request.uri().path().to_string();
+                let mut body = request.into_body();
+                let mut received = Vec::new();
+                while let Some(data) = body.data().await {
+                    received.extend_from_slice(&data.map_err(|e| e.to_string())?);
+                }
+                let response = http::Response::builder()
+                    .header("content-type", "application/grpc")
+                    .body(())
+                    .map_err(|e| e.to_string())?;
+                let mut send = respond
+                    .send_response(response, false)
+                    .map_err(|e| e.to_string())?;
+                for message in messages {
+                    let framed = frame(&Message(message)).map_err(|e| e.to_string())?;
+                    send.send_data(framed.into(), false)
+                        .map_err(|e| e.to_string())?;
+                }
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", status.parse().map_err(|_| "status")?);
+                trailers.insert(
+                    "grpc-message",
+                    "path%20not%20found".parse().map_err(|_| "message")?,
+                );
+                send.send_trailers(trailers).map_err(|e| e.to_string())?;
+                driver.await.map_err(|e| e.to_string())?;
+                Ok((path, received))
+            })
+        })
+    }
+
+    #[test]
+    fn call_returns_streamed_messages() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let server = serve(listener, vec![vec![0x08, 0x01], vec![0x08, 0x02]], "0");
+
+        let request = Message::default().string(1, "request");
+        let messages = call(&address, "apipb.GobgpApi/ListPath", &request)?;
+        assert_eq!(messages, [vec![0x08, 0x01], vec![0x08, 0x02]]);
+
+        let (path, received) = server.join().map_err(|_| "server panicked")??;
+        assert_eq!(path, "/apipb.GobgpApi/ListPath");
+        assert_eq!(received, frame(&request)?);
+        Ok(())
+    }
+
+    #[test]
+    fn call_fails_with_grpc_status() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let server = serve(listener, Vec::new(), "5");
+
+        let error = call(&address, "apipb.GobgpApi/DeletePath", &Message::default())
+            .err()
+            .ok_or("call succeeded")?;
+        assert_eq!(
+            error.to_string(),
+            "apipb.GobgpApi/DeletePath failed with gRPC status 5: path not found"
+        );
+        server.join().map_err(|_| "server panicked")??;
+        Ok(())
+    }
+}
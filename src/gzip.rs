@@ -1,9 +1,16 @@
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
 use std::{fs, io};
 
+use crate::download;
+use tracing::info_span;
+
 pub fn decompress(input_file: &str, output_file: &str) -> io::Result<()> {
+    let _span = info_span!("decompress", file = input_file).entered();
+
     // Open the gzip-compressed file
     let file_in = File::open(input_file)?;
     let buf_reader = BufReader::new(file_in);
@@ -12,15 +19,51 @@ pub fn decompress(input_file: &str, output_file: &str) -> io::Result<()> {
     let mut decoder = GzDecoder::new(buf_reader);
 
     // Open the output file
-    let output_file_tmp = output_file.to_owned() + ".tmp";
+    let output_file_tmp = download::temp_path(output_file);
     let file_out = File::create(&output_file_tmp)?;
     let mut buf_writer = BufWriter::new(file_out);
 
-    // Copy all decompressed bytes from the decoder to the output file
-    io::copy(&mut decoder, &mut buf_writer)?;
+    // Copy all decompressed bytes from the decoder to the output file, which also checks the
+    // CRC and length trailer
+    if let Err(e) = io::copy(&mut decoder, &mut buf_writer).and_then(|_| buf_writer.flush()) {
+        drop(buf_writer);
+        let _ = fs::remove_file(&output_file_tmp);
+        return Err(e);
+    }
+
+    fs::rename(output_file_tmp, output_file)?;
+
+    Ok(())
+}
+
+/// Decodes a gzip file without keeping the output, failing when it is truncated or its CRC or
+/// length trailer does not match the content.
+pub fn verify(input_file: &str) -> io::Result<()> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(input_file)?));
+    io::copy(&mut decoder, &mut io::sink())?;
+    Ok(())
+}
+
+/// Compresses a file, writing the output under a temporary name first so it only appears once
+/// complete.
+pub fn compress(input_file: &str, output_file: &str) -> io::Result<()> {
+    let _span = info_span!("compress", file = input_file).entered();
 
-    // Ensure all data is flushed to the output file
-    buf_writer.flush()?;
+    let mut reader = BufReader::new(File::open(input_file)?);
+    let output_file_tmp = download::temp_path(output_file);
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(&output_file_tmp)?),
+        Compression::default(),
+    );
+    if let Err(e) = io::copy(&mut reader, &mut encoder)
+        .and_then(|_| encoder.try_finish())
+        .and_then(|_| encoder.get_mut().flush())
+    {
+        drop(encoder);
+        let _ = fs::remove_file(&output_file_tmp);
+        return Err(e);
+    }
+    drop(encoder);
 
     fs::rename(output_file_tmp, output_file)?;
 
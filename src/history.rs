@@ -0,0 +1,276 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::render::{self, ReportFormat};
+use crate::table::OriginTable;
+use crate::{asn, index, source};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// RIPE RIS dumps a bview every eight hours, at 00:00, 08:00 and 16:00 UTC
+const RIS_BVIEW_INTERVAL_SECONDS: i64 = 8 * 3600;
+
+/// What a timeline follows
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    /// Prefixes originated by an ASN, and what happens to them afterwards
+    Asn(u32),
+    /// Prefixes covering or inside a prefix
+    Prefix(IpNet),
+}
+
+/// Parses a timeline target given as an ASN (e.g. AS13335), an IP address or a prefix.
+pub fn parse_target(value: &str) -> Result<Target, String> {
+    if let Ok(asn) = asn::parse_asn(value) {
+        return Ok(Target::Asn(asn));
+    }
+    IpNet::from_str(value)
+        .map(|prefix| prefix.trunc())
+        .or_else(|_| IpAddr::from_str(value).map(IpNet::from))
+        .map(Target::Prefix)
+        .map_err(|_| format!("{value} is not an ASN, IP address or prefix"))
+}
+
+/// Parses a step between snapshots such as `1d`, `12h` or `8h`, which must be a whole number of
+/// bview intervals.
+pub fn parse_step(value: &str) -> Result<TimeDelta, String> {
+    let (count, unit) = value.split_at(value.len().saturating_sub(1));
+    let count: i64 = count
+        .parse()
+        .map_err(|_| format!("Invalid step {value}, expected e.g. 1d or 8h"))?;
+    let step = match unit {
+        "d" => TimeDelta::try_days(count),
+        "h" => TimeDelta::try_hours(count),
+        _ => None,
+    }
+    .ok_or_else(|| format!("Invalid step {value}, expected e.g. 1d or 8h"))?;
+    if step.num_seconds() <= 0 || step.num_seconds() % RIS_BVIEW_INTERVAL_SECONDS != 0 {
+        return Err(format!(
+            "Step {value} is not a multiple of the {}h bview interval",
+            RIS_BVIEW_INTERVAL_SECONDS / 3600
+        ));
+    }
+    Ok(step)
+}
+
+/// Returns the bview times from `from` to `to`, starting at the last bview dumped before `from`.
+pub fn snapshot_times(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    step: TimeDelta,
+) -> Vec<DateTime<Utc>> {
+    let from_seconds = from.timestamp() - from.timestamp().rem_euclid(RIS_BVIEW_INTERVAL_SECONDS);
+    let mut times = Vec::new();
+    let mut current = DateTime::from_timestamp(from_seconds, 0).unwrap_or(from);
+    while current <= to {
+        times.push(current);
+        current += step;
+    }
+    times
+}
+
+pub fn ripe_archive_bview_url(rrc: u8, time: DateTime<Utc>) -> String {
+    format!(
+        "https://data.ris.ripe.net/rrc{rrc:02}/{}/bview.{}.gz",
+        time.format("%Y.%m"),
+        time.format("%Y%m%d.%H%M")
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    /// Announced in the first snapshot
+    Present,
+    Appeared,
+    Disappeared,
+    OriginChanged,
+}
+
+impl EventKind {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Present => "present",
+            Self::Appeared => "appeared",
+            Self::Disappeared => "disappeared",
+            Self::OriginChanged => "origin-changed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineEvent {
+    /// Time of the first snapshot showing the change
+    pub time: String,
+    pub event: EventKind,
+    pub prefix: IpNet,
+    pub origin_asns: Vec<u32>,
+    pub previous_origin_asns: Vec<u32>,
+}
+
+/// Selects the prefixes of a snapshot relevant to the target, with their origins. Prefixes
+/// tracked from earlier snapshots stay selected, so an ASN losing a prefix to another origin shows
+/// up as an origin change rather than a disappearance.
+fn select(
+    table: &OriginTable,
+    target: Target,
+    tracked: &BTreeMap<IpNet, BTreeSet<u32>>,
+) -> BTreeMap<IpNet, BTreeSet<u32>> {
+    let mut selected: BTreeMap<IpNet, BTreeSet<u32>> = match target {
+        Target::Asn(asn) => table
+            .iter()
+            .filter(|(_, origins)| origins.contains(&asn))
+            .map(|(prefix, origins)| (*prefix, origins.clone()))
+            .collect(),
+        Target::Prefix(target) => table
+            .iter()
+            .filter(|(prefix, _)| target.contains(*prefix) || prefix.contains(&target))
+            .map(|(prefix, origins)| (*prefix, origins.clone()))
+            .collect(),
+    };
+    for prefix in tracked.keys() {
+        if let Some(origins) = table.get(prefix) {
+            selected.entry(*prefix).or_insert_with(|| origins.clone());
+        }
+    }
+    selected
+}
+
+/// Compares a snapshot against the previous one.
+fn diff(
+    time: DateTime<Utc>,
+    previous: Option<&BTreeMap<IpNet, BTreeSet<u32>>>,
+    current: &BTreeMap<IpNet, BTreeSet<u32>>,
+) -> Vec<TimelineEvent> {
+    let event =
+        |kind, prefix: IpNet, origins: Option<&BTreeSet<u32>>, previous: Option<&BTreeSet<u32>>| {
+            TimelineEvent {
+                time: time.to_rfc3339(),
+                event: kind,
+                prefix,
+                origin_asns: origins.into_iter().flatten().copied().collect(),
+                previous_origin_asns: previous.into_iter().flatten().copied().collect(),
+            }
+        };
+    let Some(previous) = previous else {
+        return current
+            .iter()
+            .map(|(prefix, origins)| event(EventKind::Present, *prefix, Some(origins), None))
+            .collect();
+    };
+
+    let prefixes: BTreeSet<&IpNet> = previous.keys().chain(current.keys()).collect();
+    prefixes
+        .into_iter()
+        .filter_map(|prefix| match (previous.get(prefix), current.get(prefix)) {
+            (None, Some(origins)) => Some(event(EventKind::Appeared, *prefix, Some(origins), None)),
+            (Some(before), None) => {
+                Some(event(EventKind::Disappeared, *prefix, None, Some(before)))
+            }
+            (Some(before), Some(origins)) if before != origins => Some(event(
+                EventKind::OriginChanged,
+                *prefix,
+                Some(origins),
+                Some(before),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Walks the RIPE RIS archive bviews at the given times and builds the timeline of the target's
+/// prefixes. Snapshots missing from the archive are skipped, so a change is attributed to the
+/// first snapshot that could be loaded after it.
+pub fn build_timeline(
+    rrc: u8,
+    times: &[DateTime<Utc>],
+    target: Target,
+    verify_cache_interval: Duration,
+) -> Result<Vec<TimelineEvent>, Box<dyn Error>> {
+    let mut events = Vec::new();
+    let mut previous: Option<BTreeMap<IpNet, BTreeSet<u32>>> = None;
+    for time in times {
+        let url = ripe_archive_bview_url(rrc, *time);
+        let _span = info_span!("snapshot", url).entered();
+        let table = match source::fetch_mrt(&url, verify_cache_interval)
+            .and_then(|mrt_file| index::load_or_build(&mrt_file))
+        {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("Skipping snapshot {time}: {e}");
+                continue;
+            }
+        };
+        let current = select(
+            &table,
+            target,
+            previous.as_ref().unwrap_or(&BTreeMap::new()),
+        );
+        debug!("Snapshot {time} has {} matching prefixes", current.len());
+        events.extend(diff(*time, previous.as_ref(), &current));
+        previous = Some(current);
+    }
+    if previous.is_none() {
+        return Err(format!("None of the {} snapshots could be loaded", times.len()).into());
+    }
+    Ok(events)
+}
+
+fn join_asns(asns: &[u32]) -> String {
+    asns.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn render_timeline(
+    events: &[TimelineEvent],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), events)?,
+        ReportFormat::Text => {
+            for event in events {
+                let previous = if event.previous_origin_asns.is_empty() {
+                    String::new()
+                } else {
+                    format!(" previous={}", join_asns(&event.previous_origin_asns))
+                };
+                println!(
+                    "{} {} {} origins={}{previous}",
+                    event.time,
+                    event.event.as_str(),
+                    event.prefix,
+                    join_asns(&event.origin_asns)
+                );
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = events
+                .iter()
+                .map(|event| {
+                    vec![
+                        event.time.clone(),
+                        event.event.as_str().to_string(),
+                        event.prefix.to_string(),
+                        join_asns(&event.origin_asns),
+                        join_asns(&event.previous_origin_asns),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &["TIME", "EVENT", "PREFIX", "ORIGINS", "PREVIOUS ORIGINS"],
+                &rows,
+            )?;
+        }
+    }
+    Ok(())
+}
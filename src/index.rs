@@ -0,0 +1,301 @@
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::render::{self, ReportFormat};
+use crate::table::{self, OriginTable};
+use crate::{asn, download};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Identifies the index format, bumped whenever the layout changes
+const INDEX_MAGIC: &[u8; 8] = b"BGPSIDX\x01";
+
+/// Returns the path of the index kept next to an MRT file.
+pub fn index_path(mrt_file: &str) -> String {
+    format!("{mrt_file}.idx")
+}
+
+/// Size and modification time of the MRT file an index was built from, used to detect stale
+/// indexes.
+pub fn source_stamp(mrt_file: &str) -> io::Result<(u64, u64, u32)> {
+    let metadata = fs::metadata(mrt_file)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+fn read_u8(reader: &mut dyn Read) -> io::Result<u8> {
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u16(reader: &mut dyn Read) -> io::Result<u16> {
+    let mut buffer = [0; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_be_bytes(buffer))
+}
+
+fn read_u32(reader: &mut dyn Read) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+fn read_u64(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_be_bytes(buffer))
+}
+
+/// Writes the origin table in a compact binary form: a header identifying the source MRT file
+/// followed by one entry per prefix holding its address family, length, address and origins.
+pub fn write_index(mrt_file: &str, table: &OriginTable) -> Result<(), Box<dyn Error>> {
+    let (length, seconds, nanos) = source_stamp(mrt_file)?;
+    let path = index_path(mrt_file);
+    // Write to a temporary file first so a concurrent reader never sees a partial index
+    let temp_path = download::temp_path(&path);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writer.write_all(INDEX_MAGIC)?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(&seconds.to_be_bytes())?;
+    writer.write_all(&nanos.to_be_bytes())?;
+    writer.write_all(&(table.len() as u64).to_be_bytes())?;
+
+    for (prefix, origins) in table {
+        match prefix {
+            IpNet::V4(v4) => {
+                writer.write_all(&[4, v4.prefix_len()])?;
+                writer.write_all(&v4.network().octets())?;
+            }
+            IpNet::V6(v6) => {
+                writer.write_all(&[6, v6.prefix_len()])?;
+                writer.write_all(&v6.network().octets())?;
+            }
+        }
+        let count = u16::try_from(origins.len())?;
+        writer.write_all(&count.to_be_bytes())?;
+        for origin in origins {
+            writer.write_all(&origin.to_be_bytes())?;
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp_path, &path)?;
+    debug!("Wrote index of {} prefixes to {path}", table.len());
+    Ok(())
+}
+
+/// Loads the index of an MRT file, returning `None` when it is missing, stale or was written in
+/// another format.
+pub fn read_index(mrt_file: &str) -> Result<Option<OriginTable>, Box<dyn Error>> {
+    let mut table = OriginTable::new();
+    if !visit_index(mrt_file, &mut |prefix, origins| {
+        table.insert(prefix, origins);
+    })? {
+        return Ok(None);
+    }
+    debug!(
+        "Loaded index of {} prefixes from {}",
+        table.len(),
+        index_path(mrt_file)
+    );
+    Ok(Some(table))
+}
+
+/// Reads the index of an MRT file entry by entry without loading it, returning false when it is
+/// missing, stale or was written in another format.
+pub fn visit_index(
+    mrt_file: &str,
+    visit: &mut dyn FnMut(IpNet, BTreeSet<u32>),
+) -> Result<bool, Box<dyn Error>> {
+    let path = index_path(mrt_file);
+    let Ok(file) = File::open(&path) else {
+        debug!("No index found at {path}");
+        return Ok(false);
+    };
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    if reader.read_exact(&mut magic).is_err() || &magic != INDEX_MAGIC {
+        debug!("Ignoring index {path} in an unknown format");
+        return Ok(false);
+    }
+    let stamp = (
+        read_u64(&mut reader)?,
+        read_u64(&mut reader)?,
+        read_u32(&mut reader)?,
+    );
+    if stamp != source_stamp(mrt_file)? {
+        debug!("Ignoring stale index {path}");
+        return Ok(false);
+    }
+
+    let count = read_u64(&mut reader)?;
+    for _ in 0..count {
+        let family = read_u8(&mut reader)?;
+        let prefix_len = read_u8(&mut reader)?;
+        let prefix = match family {
+            4 => {
+                let mut octets = [0; 4];
+                reader.read_exact(&mut octets)?;
+                IpNet::V4(Ipv4Net::new(Ipv4Addr::from(octets), prefix_len)?)
+            }
+            6 => {
+                let mut octets = [0; 16];
+                reader.read_exact(&mut octets)?;
+                IpNet::V6(Ipv6Net::new(Ipv6Addr::from(octets), prefix_len)?)
+            }
+            _ => {
+                return Err(format!("Corrupt index {path}: unknown address family {family}").into())
+            }
+        };
+        let origin_count = read_u16(&mut reader)?;
+        let mut origins = BTreeSet::new();
+        for _ in 0..origin_count {
+            origins.insert(read_u32(&mut reader)?);
+        }
+        visit(prefix, origins);
+    }
+    Ok(true)
+}
+
+/// Returns the origin table of an MRT file from its index, parsing the file and writing a new
+/// index when none is usable. Failing to write the index only skips caching it.
+pub fn load_or_build(mrt_file: &str) -> Result<OriginTable, Box<dyn Error>> {
+    if let Some(table) = read_index(mrt_file)? {
+        return Ok(table);
+    }
+    let table = table::scan_origins(mrt_file, false, false)?;
+    if let Err(e) = write_index(mrt_file, &table) {
+        warn!("Could not write index for {mrt_file}: {e}");
+    }
+    Ok(table)
+}
+
+/// Selects the prefixes originated by the target ASNs from an origin table.
+pub fn origin_prefixes(
+    table: &OriginTable,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+) -> HashMap<IpNet, HashSet<u32>> {
+    table
+        .iter()
+        .filter(|(prefix, _)| match prefix {
+            IpNet::V4(_) => !ipv6_only,
+            IpNet::V6(_) => !ipv4_only,
+        })
+        .filter_map(|(prefix, origins)| {
+            let matching: HashSet<u32> = origins
+                .iter()
+                .filter(|origin| origin_asns.contains(origin))
+                .copied()
+                .collect();
+            (!matching.is_empty()).then_some((*prefix, matching))
+        })
+        .collect()
+}
+
+/// Answer to a single ASN or address lookup
+#[derive(Debug, Serialize)]
+pub struct QueryAnswer {
+    pub query: String,
+    /// Prefixes originated by a queried ASN, or the most specific prefix covering a queried
+    /// address
+    pub prefixes: Vec<IpNet>,
+    pub origin_asns: Vec<u32>,
+}
+
+/// Looks up an ASN (e.g. AS13335) or an address or prefix in an origin table.
+pub fn query(table: &OriginTable, query: &str) -> Result<QueryAnswer, Box<dyn Error>> {
+    if let Ok(asn) = asn::parse_asn(query) {
+        let prefixes = table
+            .iter()
+            .filter(|(_, origins)| origins.contains(&asn))
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        return Ok(QueryAnswer {
+            query: query.to_string(),
+            prefixes,
+            origin_asns: vec![asn],
+        });
+    }
+
+    let target = match IpNet::from_str(query) {
+        Ok(prefix) => prefix,
+        Err(_) => IpNet::from(
+            IpAddr::from_str(query)
+                .map_err(|_| format!("{query} is not an ASN, IP address or prefix"))?,
+        ),
+    };
+    // Walk from the queried length towards the default route so the longest match wins
+    let covering = (0..=target.prefix_len()).rev().find_map(|len| {
+        let candidate = IpNet::new(target.addr(), len).ok()?.trunc();
+        table.get_key_value(&candidate)
+    });
+    Ok(match covering {
+        Some((prefix, origins)) => QueryAnswer {
+            query: query.to_string(),
+            prefixes: vec![*prefix],
+            origin_asns: origins.iter().copied().collect(),
+        },
+        None => QueryAnswer {
+            query: query.to_string(),
+            prefixes: Vec::new(),
+            origin_asns: Vec::new(),
+        },
+    })
+}
+
+pub fn render_answers(answers: &[QueryAnswer], format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    if format == ReportFormat::Json {
+        serde_json::to_writer(io::stdout(), answers)?;
+        return Ok(());
+    }
+
+    // One row per prefix, so ASN lookups list every announced prefix
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for answer in answers {
+        let origin_asns = answer
+            .origin_asns
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if answer.prefixes.is_empty() {
+            rows.push(vec![answer.query.clone(), "-".to_string(), "-".to_string()]);
+        }
+        for prefix in &answer.prefixes {
+            rows.push(vec![
+                answer.query.clone(),
+                prefix.to_string(),
+                origin_asns.clone(),
+            ]);
+        }
+    }
+    match format {
+        ReportFormat::Table => {
+            render::write_table(
+                &mut io::stdout(),
+                &["QUERY", "PREFIX", "ORIGIN ASNS"],
+                &rows,
+            )?;
+        }
+        _ => {
+            for row in rows {
+                println!("{}", row.join(" "));
+            }
+        }
+    }
+    Ok(())
+}
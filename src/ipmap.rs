@@ -0,0 +1,368 @@
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::peeringdb::Network;
+use crate::rdap::{self, Registration};
+use crate::render::{self, ReportFormat};
+use crate::table::OriginTable;
+use crate::trie::PrefixTrie;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The announced prefix and origin covering a single address
+#[derive(Debug, Clone, Serialize)]
+pub struct IpMapping {
+    pub ip: IpAddr,
+    pub prefix: Option<IpNet>,
+    pub origin_asns: Vec<u32>,
+    pub as_name: Option<String>,
+    pub country: Option<String>,
+    /// Whether the address is announced, set when the registries are asked about the addresses
+    /// no prefix covers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<&'static str>,
+    /// Allocation of an address no prefix covers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration: Option<Registration>,
+}
+
+pub const ANNOUNCED: &str = "announced";
+pub const REGISTERED_NOT_ANNOUNCED: &str = "registered but not announced";
+pub const UNREGISTERED: &str = "unregistered";
+
+impl IpMapping {
+    /// Returns the status, the registered allocation and its name as CSV fields.
+    fn registration_fields(&self) -> [String; 3] {
+        let registration = self.registration.as_ref();
+        [
+            self.status.unwrap_or_default().to_string(),
+            registration
+                .and_then(|registration| registration.network)
+                .map(|network| network.to_string())
+                .unwrap_or_default(),
+            registration
+                .and_then(|registration| registration.name.clone())
+                .unwrap_or_default(),
+        ]
+    }
+}
+
+/// Opens a file for reading, or stdin when the file name is `-`.
+fn open_input(file_name: &str) -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    Ok(if file_name == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(
+            File::open(file_name).map_err(|e| format!("{file_name}: {e}"))?,
+        ))
+    })
+}
+
+/// Reads one IP address per line from a file, or from stdin when the file name is `-`. Blank
+/// lines and `#` comments are ignored.
+pub fn read_ips(file_name: &str) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+    let reader = open_input(file_name)?;
+    let mut ips = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let value = line.split('#').next().unwrap_or_default().trim();
+        if value.is_empty() {
+            continue;
+        }
+        match IpAddr::from_str(value) {
+            Ok(ip) => ips.push(ip),
+            Err(_) => warn!("Skipping invalid IP address {value}"),
+        }
+    }
+    debug!("Read {} IP addresses from {file_name}", ips.len());
+    Ok(ips)
+}
+
+/// Maps each address to the most specific announced prefix of the origin table covering it,
+/// through a trie of the prefixes.
+pub fn map_ips(ips: &[IpAddr], table: OriginTable) -> Vec<IpMapping> {
+    let trie: PrefixTrie<Vec<u32>> = table
+        .into_iter()
+        .map(|(prefix, origins)| (prefix, origins.into_iter().collect()))
+        .collect();
+    ips.iter()
+        .map(|&ip| {
+            let (prefix, origin_asns) = match trie.longest_match(ip) {
+                Some((prefix, origins)) => (Some(*prefix), origins.clone()),
+                None => (None, Vec::new()),
+            };
+            IpMapping {
+                ip,
+                prefix,
+                origin_asns,
+                as_name: None,
+                country: None,
+                status: None,
+                registration: None,
+            }
+        })
+        .collect()
+}
+
+/// Fills in the AS names of mappings from the PeeringDB records of their origins.
+pub fn name_origins(mappings: &mut [IpMapping], networks: &[Network]) {
+    let names: HashMap<u32, &str> = networks
+        .iter()
+        .map(|network| (network.asn, network.name.as_str()))
+        .collect();
+    for mapping in mappings {
+        let origin_names: Vec<&str> = mapping
+            .origin_asns
+            .iter()
+            .filter_map(|asn| names.get(asn).copied())
+            .collect();
+        if !origin_names.is_empty() {
+            mapping.as_name = Some(origin_names.join(", "));
+        }
+    }
+}
+
+/// Marks each mapping as announced or not, looking up the registration of the addresses no
+/// prefix covers with RDAP.
+pub fn add_registrations(
+    mappings: &mut [IpMapping],
+    rdap_url: &str,
+    verify_cache_interval: Duration,
+) {
+    let unrouted: Vec<IpAddr> = mappings
+        .iter()
+        .filter(|mapping| mapping.prefix.is_none())
+        .map(|mapping| mapping.ip)
+        .collect();
+    debug!(
+        "Looking up the registrations of {} unrouted addresses",
+        unrouted.len()
+    );
+    let mut registrations =
+        rdap::lookup_all(rdap_url, &unrouted, verify_cache_interval).into_iter();
+    for mapping in mappings {
+        if mapping.prefix.is_some() {
+            mapping.status = Some(ANNOUNCED);
+            continue;
+        }
+        mapping.registration = registrations.next().flatten();
+        mapping.status = Some(if mapping.registration.is_some() {
+            REGISTERED_NOT_ANNOUNCED
+        } else {
+            UNREGISTERED
+        });
+    }
+}
+
+/// Returns the origins of the mappings, each once.
+pub fn origin_asns(mappings: &[IpMapping]) -> Vec<u32> {
+    let asns: BTreeSet<u32> = mappings
+        .iter()
+        .flat_map(|mapping| mapping.origin_asns.iter().copied())
+        .collect();
+    asns.into_iter().collect()
+}
+
+/// Reads the next record of CSV text, whose quoted fields may hold separators, doubled quotes
+/// and line breaks. Returns `None` at the end of the input.
+fn read_csv_record(reader: &mut dyn BufRead) -> io::Result<Option<Vec<String>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    loop {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                '\r' | '\n' if !quoted => {}
+                c => field.push(c),
+            }
+        }
+        if !quoted {
+            break;
+        }
+        // The quoted field continues on the next line
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+    }
+    fields.push(field);
+    Ok(Some(fields))
+}
+
+fn write_csv_record(output: &mut dyn Write, fields: &[String]) -> io::Result<()> {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    writeln!(output, "{}", fields.join(","))
+}
+
+/// Copies a CSV file, or stdin when the file name is `-`, appending the announced prefix, origin
+/// ASNs and AS name of the address in the named column to every row. `map` resolves the
+/// distinct addresses of the column at once, so bulk backends are queried once. Rows whose
+/// column is not an address get empty fields. With `registrations` the status and registered
+/// allocation of the address are appended too.
+pub fn enrich_csv(
+    file_name: &str,
+    column: &str,
+    registrations: bool,
+    map: impl FnOnce(&[IpAddr]) -> Result<Vec<IpMapping>, Box<dyn Error>>,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = open_input(file_name)?;
+    let mut header =
+        read_csv_record(&mut reader)?.ok_or_else(|| format!("{file_name} is empty"))?;
+    let index = header
+        .iter()
+        .position(|name| name == column)
+        .ok_or_else(|| format!("{file_name} has no {column} column"))?;
+
+    let mut rows = Vec::new();
+    while let Some(row) = read_csv_record(&mut reader)? {
+        rows.push(row);
+    }
+    let ips: BTreeSet<IpAddr> = rows
+        .iter()
+        .filter_map(|row| row.get(index))
+        .filter_map(|value| IpAddr::from_str(value.trim()).ok())
+        .collect();
+    debug!(
+        "Mapping {} distinct addresses of {} rows",
+        ips.len(),
+        rows.len()
+    );
+    let ips: Vec<IpAddr> = ips.into_iter().collect();
+    let mappings: HashMap<IpAddr, IpMapping> = map(&ips)?
+        .into_iter()
+        .map(|mapping| (mapping.ip, mapping))
+        .collect();
+
+    let mut fields = vec!["prefix", "origin_asns", "as_name"];
+    if registrations {
+        fields.extend(["status", "registered_network", "registered_name"]);
+    }
+    header.extend(fields.iter().map(|field| format!("{column}_{field}")));
+    write_csv_record(output, &header)?;
+    for mut row in rows {
+        let mapping = row
+            .get(index)
+            .and_then(|value| IpAddr::from_str(value.trim()).ok())
+            .and_then(|ip| mappings.get(&ip));
+        match mapping {
+            Some(mapping) => {
+                row.extend([
+                    mapping.prefix.map(|p| p.to_string()).unwrap_or_default(),
+                    join_asns(&mapping.origin_asns),
+                    mapping.as_name.clone().unwrap_or_default(),
+                ]);
+                if registrations {
+                    row.extend(mapping.registration_fields());
+                }
+            }
+            None => row.resize(row.len() + fields.len(), String::new()),
+        }
+        write_csv_record(output, &row)?;
+    }
+    Ok(())
+}
+
+/// Writes the mappings, with their status and registered allocation when `registrations` is
+/// set.
+pub fn render_mappings(
+    mappings: &[IpMapping],
+    registrations: bool,
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => {
+            // One object per line so results can be streamed into other tools
+            for mapping in mappings {
+                println!("{}", serde_json::to_string(mapping)?);
+            }
+        }
+        ReportFormat::Text => {
+            let mut header = "ip,prefix,origin_asns,as_name,country".to_string();
+            if registrations {
+                header.push_str(",status,registered_network,registered_name");
+            }
+            println!("{header}");
+            for mapping in mappings {
+                let mut fields = vec![
+                    mapping.ip.to_string(),
+                    mapping.prefix.map(|p| p.to_string()).unwrap_or_default(),
+                    join_asns(&mapping.origin_asns),
+                    mapping.as_name.clone().unwrap_or_default(),
+                    mapping.country.clone().unwrap_or_default(),
+                ];
+                if registrations {
+                    fields.extend(mapping.registration_fields());
+                }
+                let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                println!("{}", fields.join(","));
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = mappings
+                .iter()
+                .map(|mapping| {
+                    let mut row = vec![
+                        mapping.ip.to_string(),
+                        mapping
+                            .prefix
+                            .map_or_else(|| "-".to_string(), |p| p.to_string()),
+                        join_asns(&mapping.origin_asns),
+                        mapping.as_name.clone().unwrap_or_else(|| "-".to_string()),
+                        mapping.country.clone().unwrap_or_else(|| "-".to_string()),
+                    ];
+                    if registrations {
+                        row.extend(mapping.registration_fields().map(|field| {
+                            if field.is_empty() {
+                                "-".to_string()
+                            } else {
+                                field
+                            }
+                        }));
+                    }
+                    row
+                })
+                .collect();
+            let mut header = vec!["IP", "PREFIX", "ORIGIN ASNS", "AS NAME", "COUNTRY"];
+            if registrations {
+                header.extend(["STATUS", "REGISTERED NETWORK", "REGISTERED NAME"]);
+            }
+            render::write_table(&mut io::stdout(), &header, &rows)?;
+        }
+    }
+    Ok(())
+}
+
+fn join_asns(asns: &[u32]) -> String {
+    asns.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a CSV field when it contains a separator or quote.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
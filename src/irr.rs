@@ -0,0 +1,138 @@
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::asn;
+use crate::render::{self, ReportFormat};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+pub const DEFAULT_IRR_HOST: &str = "whois.radb.net:43";
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Sends a single IRRd `!` query and returns the response data, or `None` when the server has
+/// no matching objects.
+fn irrd_query(host: &str, query: &str) -> Result<Option<String>, Box<dyn Error>> {
+    debug!("Querying IRR {host} with {query}");
+    let mut stream = TcpStream::connect(host)?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.write_all(format!("{query}\n").as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (status, data) = response.split_once('\n').unwrap_or((&response, ""));
+    match status.chars().next() {
+        Some('A') => {
+            let data = data.trim_end();
+            Ok(Some(
+                data.strip_suffix('C').unwrap_or(data).trim().to_string(),
+            ))
+        }
+        Some('C' | 'D') => Ok(None),
+        Some('F') => Err(format!("IRR query {query} failed: {}", status[1..].trim()).into()),
+        _ => Err(format!("Unexpected IRR response to {query}: {status}").into()),
+    }
+}
+
+/// Recursively expands an AS-SET into its member ASNs. Source-qualified names such as
+/// `RIPE::AS-EXAMPLE` are queried without the source prefix.
+pub fn expand_as_set(host: &str, as_set: &str) -> Result<HashSet<u32>, Box<dyn Error>> {
+    let name = as_set.rsplit("::").next().unwrap_or(as_set);
+    let members = irrd_query(host, &format!("!i{name},1"))?.unwrap_or_default();
+    let asns: HashSet<u32> = members
+        .split_whitespace()
+        .filter_map(|member| asn::parse_asn(member).ok())
+        .collect();
+    debug!("AS-SET {as_set} expanded to {} ASNs", asns.len());
+    Ok(asns)
+}
+
+/// Returns the prefixes of the route and route6 objects registered with the given origin ASNs.
+pub fn registered_prefixes(
+    host: &str,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+) -> Result<HashSet<IpNet>, Box<dyn Error>> {
+    let mut prefixes = HashSet::new();
+    for asn in origin_asns {
+        let mut queries = Vec::new();
+        if !ipv6_only {
+            queries.push(format!("!gAS{asn}"));
+        }
+        if !ipv4_only {
+            queries.push(format!("!6AS{asn}"));
+        }
+        for query in queries {
+            let data = irrd_query(host, &query)?.unwrap_or_default();
+            for value in data.split_whitespace() {
+                match IpNet::from_str(value) {
+                    Ok(prefix) => {
+                        prefixes.insert(prefix.trunc());
+                    }
+                    Err(_) => warn!("IRR returned invalid prefix {value}"),
+                }
+            }
+        }
+    }
+    debug!("Found {} registered route objects", prefixes.len());
+    Ok(prefixes)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditReport {
+    pub target: String,
+    pub asns: BTreeSet<u32>,
+    pub announced_unregistered: BTreeSet<IpNet>,
+    pub registered_unannounced: BTreeSet<IpNet>,
+}
+
+/// Compares the exact prefixes announced in BGP against the registered route objects.
+pub fn audit(
+    target: &str,
+    origin_asns: &HashSet<u32>,
+    announced: &HashSet<IpNet>,
+    registered: &HashSet<IpNet>,
+) -> AuditReport {
+    AuditReport {
+        target: target.to_string(),
+        asns: origin_asns.iter().copied().collect(),
+        announced_unregistered: announced.difference(registered).copied().collect(),
+        registered_unannounced: registered.difference(announced).copied().collect(),
+    }
+}
+
+pub fn render_report(report: &AuditReport, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    let statuses = report
+        .announced_unregistered
+        .iter()
+        .map(|prefix| ("announced-unregistered", prefix))
+        .chain(
+            report
+                .registered_unannounced
+                .iter()
+                .map(|prefix| ("registered-unannounced", prefix)),
+        );
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), report)?,
+        ReportFormat::Text => {
+            for (status, prefix) in statuses {
+                println!("{status} {prefix}");
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = statuses
+                .map(|(status, prefix)| vec![status.to_string(), prefix.to_string()])
+                .collect();
+            render::write_table(&mut io::stdout(), &["STATUS", "PREFIX"], &rows)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,450 @@
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use crate::telemetry;
+
+/// Format of the log lines written to stderr
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregation systems
+    Json,
+}
+
+/// Levels enabled per target, parsed from `RUST_LOG` directives such as
+/// `warn,bgp_scout::download=debug`
+#[derive(Debug)]
+struct Filter {
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(spec: &str, default: LevelFilter) -> Self {
+        let mut filter = Self {
+            default,
+            directives: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => match LevelFilter::from_str(level) {
+                    Ok(level) => filter.directives.push((target.to_string(), level)),
+                    Err(_) => eprintln!("Ignoring invalid RUST_LOG directive {directive}"),
+                },
+                None => match LevelFilter::from_str(directive) {
+                    Ok(level) => filter.default = level,
+                    // A bare target enables everything it logs
+                    Err(_) => filter
+                        .directives
+                        .push((directive.to_string(), LevelFilter::TRACE)),
+                },
+            }
+        }
+        // Longest targets first, so the most specific directive wins
+        filter
+            .directives
+            .sort_by_key(|(target, _)| Reverse(target.len()));
+        filter
+    }
+
+    fn enabled(&self, target: &str, level: Level) -> bool {
+        let filter = self
+            .directives
+            .iter()
+            .find(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .map_or(self.default, |(_, level)| *level);
+        level <= filter
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+/// Collects the message and fields of an event or span
+#[derive(Debug, Default)]
+struct FieldVisitor {
+    fields: Map<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .insert(field.name().to_string(), json!(format!("{value:?}")));
+    }
+}
+
+#[derive(Debug)]
+struct SpanData {
+    name: &'static str,
+    target: &'static str,
+    level: Level,
+    fields: Map<String, Value>,
+    parent: Option<u64>,
+    started: Instant,
+    started_at: SystemTime,
+    /// Handles to the span still alive; it closes when the last one is dropped
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Writes events to stderr as text or JSON lines, reporting how long each span took when it
+/// closes so every phase of a run is timed. Spans are also handed to the telemetry exporter
+/// when it is enabled, whatever the log level.
+#[derive(Debug)]
+struct Logger {
+    filter: Filter,
+    format: LogFormat,
+    export_spans: bool,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+fn field_text(fields: &Map<String, Value>) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Logger {
+    fn entered_span() -> Option<u64> {
+        ENTERED.with(|entered| entered.borrow().last().copied())
+    }
+
+    /// Writes one log line, within the span `span` and its parents.
+    fn write(
+        &self,
+        level: Level,
+        target: &str,
+        message: &str,
+        mut fields: Map<String, Value>,
+        span: Option<u64>,
+    ) {
+        let Ok(spans) = self.spans.lock() else {
+            return;
+        };
+        let mut chain = Vec::new();
+        let mut next = span;
+        while let Some(data) = next.and_then(|id| spans.get(&id)) {
+            chain.push(data);
+            next = data.parent;
+        }
+        chain.reverse();
+
+        let line = match self.format {
+            LogFormat::Text => {
+                let mut line = format!(
+                    "[{} {:<5} {target}] ",
+                    Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                    level.as_str()
+                );
+                for data in &chain {
+                    line.push_str(data.name);
+                    if !data.fields.is_empty() {
+                        line.push_str(&format!("{{{}}}", field_text(&data.fields)));
+                    }
+                    line.push_str(": ");
+                }
+                line.push_str(message);
+                if !fields.is_empty() {
+                    line.push(' ');
+                    line.push_str(&field_text(&fields));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let spans: Vec<Value> = chain
+                    .iter()
+                    .map(|data| {
+                        let mut span = data.fields.clone();
+                        span.insert("name".to_string(), json!(data.name));
+                        Value::Object(span)
+                    })
+                    .collect();
+                fields.insert("message".to_string(), json!(message));
+                json!({
+                    "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "level": level.as_str(),
+                    "target": target,
+                    "fields": fields,
+                    "spans": spans,
+                })
+                .to_string()
+            }
+        };
+        drop(spans);
+        let _ = writeln!(io::stderr().lock(), "{line}");
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        (self.export_spans && metadata.is_span())
+            || self.filter.enabled(metadata.target(), *metadata.level())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        if self.export_spans {
+            return Some(LevelFilter::TRACE);
+        }
+        Some(self.filter.max_level())
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut visitor = FieldVisitor::default();
+        attributes.record(&mut visitor);
+        let parent = if attributes.is_contextual() {
+            Self::entered_span()
+        } else {
+            attributes.parent().map(Id::into_u64)
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let metadata = attributes.metadata();
+        if let Ok(mut spans) = self.spans.lock() {
+            spans.insert(
+                id,
+                SpanData {
+                    name: metadata.name(),
+                    target: metadata.target(),
+                    level: *metadata.level(),
+                    fields: visitor.fields,
+                    parent,
+                    started: Instant::now(),
+                    started_at: SystemTime::now(),
+                    refs: 1,
+                },
+            );
+        }
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        if let Some(data) = self
+            .spans
+            .lock()
+            .ok()
+            .as_mut()
+            .and_then(|spans| spans.get_mut(&span.into_u64()))
+        {
+            data.fields.extend(visitor.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let message = match visitor.fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(message) => message.to_string(),
+            None => String::new(),
+        };
+        let span = if event.is_contextual() {
+            Self::entered_span()
+        } else {
+            event.parent().map(Id::into_u64)
+        };
+        let metadata = event.metadata();
+        self.write(
+            *metadata.level(),
+            metadata.target(),
+            &message,
+            visitor.fields,
+            span,
+        );
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .ok()
+            .as_mut()
+            .and_then(|spans| spans.get_mut(&span.into_u64()))
+        {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let id = span.into_u64();
+        let closed = {
+            let Ok(mut spans) = self.spans.lock() else {
+                return false;
+            };
+            match spans.get_mut(&id) {
+                Some(data) if data.refs > 1 => {
+                    data.refs -= 1;
+                    None
+                }
+                Some(data) => {
+                    if self.export_spans {
+                        telemetry::record_span(telemetry::FinishedSpan {
+                            id,
+                            parent: data.parent,
+                            name: data.name,
+                            fields: data.fields.clone(),
+                            started_at: data.started_at,
+                            ended_at: SystemTime::now(),
+                        });
+                    }
+                    Some((data.level, data.target, data.started.elapsed()))
+                }
+                None => None,
+            }
+        };
+        let Some((level, target, elapsed)) = closed else {
+            return false;
+        };
+        // Report the span with its parents while it is still known, then forget it
+        if self.filter.enabled(target, level) {
+            let (message, fields) = match self.format {
+                LogFormat::Text => (
+                    format!("finished in {:.3}s", elapsed.as_secs_f64()),
+                    Map::new(),
+                ),
+                LogFormat::Json => {
+                    let mut fields = Map::new();
+                    fields.insert("elapsed_ms".to_string(), json!(elapsed.as_millis()));
+                    ("finished".to_string(), fields)
+                }
+            };
+            self.write(level, target, &message, fields, Some(id));
+        }
+        if let Ok(mut spans) = self.spans.lock() {
+            spans.remove(&id);
+        }
+        true
+    }
+}
+
+/// Forwards records of dependencies logging through the `log` crate to the logger
+#[derive(Debug)]
+struct LogBridge(Arc<Logger>);
+
+const fn tracing_level(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::ERROR,
+        log::Level::Warn => Level::WARN,
+        log::Level::Info => Level::INFO,
+        log::Level::Debug => Level::DEBUG,
+        log::Level::Trace => Level::TRACE,
+    }
+}
+
+impl log::Log for LogBridge {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        self.0
+            .filter
+            .enabled(metadata.target(), tracing_level(metadata.level()))
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.0.write(
+                tracing_level(record.level()),
+                record.target(),
+                &record.args().to_string(),
+                Map::new(),
+                Logger::entered_span(),
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends log events and span timings to stderr, and spans to the telemetry exporter when it was
+/// initialized first. Without verbosity flags the `RUST_LOG` environment variable is honored,
+/// defaulting to warnings only.
+pub fn init(level: LevelFilter, use_env: bool, format: LogFormat) {
+    let spec = if use_env {
+        env::var("RUST_LOG").unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let logger = Arc::new(Logger {
+        filter: Filter::parse(&spec, level),
+        format,
+        export_spans: telemetry::enabled(),
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    });
+    let max_level = match logger.filter.max_level().into_level() {
+        Some(Level::ERROR) => log::LevelFilter::Error,
+        Some(Level::WARN) => log::LevelFilter::Warn,
+        Some(Level::INFO) => log::LevelFilter::Info,
+        Some(Level::DEBUG) => log::LevelFilter::Debug,
+        Some(Level::TRACE) => log::LevelFilter::Trace,
+        None => log::LevelFilter::Off,
+    };
+    if log::set_boxed_logger(Box::new(LogBridge(Arc::clone(&logger)))).is_ok() {
+        log::set_max_level(max_level);
+    }
+    if let Err(e) = tracing::subscriber::set_global_default(logger) {
+        eprintln!("Could not initialize logging: {e}");
+    }
+}
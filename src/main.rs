@@ -1,207 +1,2952 @@
+mod aggregate;
+mod archive;
+mod as2org;
+mod asn;
+mod asrel;
+mod attributes;
+mod aws;
+mod batch;
+mod cache;
+mod collectors;
+mod cron;
+mod cymru;
+mod diff;
+mod disk;
 mod download;
+mod elems;
+mod feeds;
+mod firewall;
+mod flap;
+mod frr;
+mod gcs;
+mod gobgp;
+mod grpc;
 mod gzip;
+mod history;
+mod index;
+mod ipmap;
+mod irr;
+mod logging;
+mod monitor;
+mod mrt;
+mod netrc;
+mod notify;
+mod output;
+mod parquet;
+mod pathgraph;
+mod peer;
+mod peeringdb;
+mod plugin;
+mod prepend;
+mod provenance;
+mod rdap;
+mod redis;
+mod render;
+mod result_cache;
+mod ripestat;
+mod rir;
+mod rpc;
+mod rpki;
+mod scan;
+mod sign;
+mod source;
+mod stats;
+mod storage;
+mod systemd;
+mod table;
+mod telemetry;
+mod template;
+mod top;
+mod trie;
+mod watch;
+mod yaml;
+mod zstd;
 
-use bgpkit_parser::BgpkitParser;
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use ipnet::IpNet;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
 use std::error::Error;
-use std::fs;
-use std::fs::File;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io::{self, BufReader};
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
+use std::thread;
 use std::time::Duration;
 
+use render::{
+    address_count, Direction, Format, RenderOptions, Renderer, ReportFormat, RpzAction,
+    RtbhPlatform,
+};
+
+use tracing::level_filters::LevelFilter;
 #[allow(unused_imports)]
-use log::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, info_span, trace, warn};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Commands>,
+
+    /// Stay resident answering JSON-RPC 2.0 requests read from stdin, one per line, with the
+    /// find_netblocks, lookup_ip and contains methods
+    #[clap(long)]
+    rpc: bool,
+
+    /// Check the MRT sources used by --rpc requests for newer dumps on a cron schedule in UTC such
+    /// as "0 */4 * * *", rebuilding their origin tables in the background while requests are
+    /// answered from the previous ones
+    #[clap(long, requires = "rpc")]
+    refresh_cron: Option<cron::Schedule>,
+
+    /// Answer HTTP health checks on GET /healthz and report the sources, RIB times, index ages,
+    /// prefix counts and last refresh of --rpc on GET /status, listening on an address such as
+    /// 127.0.0.1:8080
+    #[clap(long, requires = "rpc")]
+    status_listen: Option<String>,
+
+    /// Fail /healthz once a RIB being served is older than this many seconds, besides when the
+    /// last refresh failed
+    #[clap(long, requires = "status_listen")]
+    health_max_age: Option<u64>,
+
+    /// Increase log verbosity (-v info, -vv debug, -vvv trace), logs are written to stderr
+    #[clap(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Suppress all log output
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of the log lines, json includes the timing of each phase as elapsed_ms
+    #[clap(long, value_enum, global = true, default_value_t = logging::LogFormat::Text)]
+    log_format: logging::LogFormat,
+
+    /// Export the spans of each phase and the run duration, bytes downloaded and prefixes found
+    /// to an OTLP/HTTP collector such as http://localhost:4318 [env: OTEL_EXPORTER_OTLP_ENDPOINT]
+    #[clap(long, global = true)]
+    otlp_endpoint: Option<String>,
+
+    /// Reject reserved, private and documentation ASNs instead of warning about them
+    #[clap(long, global = true)]
+    strict: bool,
+
+    /// Run as a systemd Type=notify service: report readiness once --rpc, --watch,
+    /// --refresh-cron or monitor-prefixes --live is up, and with WatchdogSec= send keep-alives
+    /// until an update, refresh or stream read runs for longer than it
+    #[clap(long, global = true)]
+    systemd: bool,
+
+    /// Gzip-compress every output file, not only those whose name ends in .gz or .zst, appending
+    /// .gz to the names lacking either
+    #[clap(long, global = true)]
+    compress: bool,
+
+    /// Remove the least recently used downloads when the disk is too full for a download or its
+    /// decompression, instead of failing
+    #[clap(long, global = true)]
+    prune_cache: bool,
+
+    /// Which copies of gzipped downloads to keep in the cache: both the download and its
+    /// decompressed copy, or only one of them to halve the disk footprint
+    #[clap(long, global = true, value_enum, default_value_t = download::CacheMode::Both)]
+    cache_mode: download::CacheMode,
+
+    /// Header sent with downloads from the hosts of the URLs given, such as 'X-Token: secret', for
+    /// mirrors behind header-based auth gateways, and never to other hosts; may be given more
+    /// than once
+    #[clap(long, global = true, value_name = "NAME: VALUE")]
+    header: Vec<String>,
+
+    /// User-Agent sent with downloads and uploads instead of bgp-scout and its version
+    #[clap(long, global = true)]
+    user_agent: Option<String>,
+
+    /// User name and password of HTTP basic auth for --url downloads, as USER:PASSWORD or USER
+    /// with the password in BGP_SCOUT_HTTP_PASSWORD; defaults to BGP_SCOUT_HTTP_USER. Hosts
+    /// listed in ~/.netrc or $NETRC use its credentials otherwise
+    #[clap(long, global = true, value_name = "USER[:PASSWORD]")]
+    http_user: Option<String>,
+
+    /// Bearer token sent with --url downloads; defaults to BGP_SCOUT_BEARER_TOKEN
+    #[clap(long, global = true, conflicts_with = "http_user")]
+    bearer_token: Option<String>,
+
+    /// PEM file of the client certificate presented to mirrors requiring mutual TLS
+    #[clap(long, global = true)]
+    tls_cert: Option<String>,
+
+    /// PEM file of the PKCS#8 private key of --tls-cert, if not kept in the certificate file
+    #[clap(long, global = true, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// PEM file of CA certificates to trust besides those of the system, for mirrors with
+    /// certificates of a private CA
+    #[clap(long, global = true)]
+    tls_ca: Option<String>,
+
+    /// Trust only the CA certificates of --tls-ca, pinning the CA of the mirrors
+    #[clap(long, global = true, requires = "tls_ca")]
+    no_system_roots: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Find netblocks based on provided parameters
+    FindNetblocks {
+        #[clap(flatten)]
+        asns: AsnArgs,
+
+        #[clap(flatten)]
+        args: NetblockArgs,
+    },
+    /// Answer many find-netblocks queries from a single download and parse of the MRT source
+    Batch {
+        /// YAML file with a list of queries, each with the find-netblocks arguments in `args`,
+        /// an optional `output` file and an optional `name`
+        #[clap(long)]
+        file: String,
+
+        #[clap(flatten)]
+        source: MrtSource,
+    },
+    /// Export the origin ASNs of every prefix in a RIB
+    ExportTable {
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Export format
+        #[clap(long, value_enum, default_value_t = table::ExportFormat::Csv)]
+        format: table::ExportFormat,
+
+        /// Write the export to this file instead of stdout, compressed if it ends in .gz or .zst
+        #[clap(short, long)]
+        output: Option<String>,
+
+        #[clap(flatten)]
+        filters: Filters,
+    },
+    /// Find netblocks originated by the ASNs an RIR has delegated to a country
+    FindCountryNetblocks {
+        /// ISO 3166 alpha-2 country code
+        #[arg(required = true, index = 1)]
+        country: String,
+
+        #[clap(flatten)]
+        args: NetblockArgs,
+    },
+    /// Count announce/withdraw churn per prefix over a window of update files
+    FlapReport {
+        #[clap(flatten)]
+        asns: AsnArgs,
+
+        /// Updates MRT files in chronological order, conflicts with specifying RIPE RRC
+        #[clap(short = 'f', long = "updates-file", conflicts_with = "rrc")]
+        updates_files: Vec<String>,
+
+        /// Specify RIPE RRC server number (00-26) or auto to fetch updates files from [default: 01]
+        #[clap(short = 'r', long, value_parser = source::parse_rrc)]
+        rrc: Option<source::Rrc>,
+
+        /// Start of the window, RFC 3339 or unix seconds [default: one hour before end]
+        #[clap(long, conflicts_with = "updates_files")]
+        start: Option<String>,
+
+        /// End of the window, RFC 3339 or unix seconds [default: latest published updates file]
+        #[clap(long, conflicts_with = "updates_files")]
+        end: Option<String>,
+
+        /// Number of announce and withdraw events at which a prefix is flagged unstable
+        #[clap(long, default_value_t = 10)]
+        threshold: u64,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        #[clap(flatten)]
+        filters: Filters,
+    },
+    /// Timeline of when prefixes appeared, disappeared or changed origin across archived bviews
+    History {
+        /// ASN (e.g. AS13335), IP address or prefix to follow
+        #[arg(required = true, index = 1, value_parser = history::parse_target)]
+        target: history::Target,
+
+        /// Start of the range, RFC 3339 or unix seconds
+        #[clap(long, required = true)]
+        from: String,
+
+        /// End of the range, RFC 3339 or unix seconds [default: now]
+        #[clap(long)]
+        to: Option<String>,
+
+        /// Time between snapshots, in days (d) or hours (h), a multiple of the 8h bview interval
+        #[clap(long, default_value = "1d", value_parser = history::parse_step)]
+        step: TimeDelta,
+
+        /// Specify RIPE RRC server number (00-26) or auto to fetch archived bviews from [default: 01]
+        #[clap(short = 'r', long, value_parser = source::parse_rrc)]
+        rrc: Option<source::Rrc>,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+    },
+    /// Compare IRR route objects against the prefixes actually announced
+    IrrAudit {
+        /// ASN (e.g. AS13335) or AS-SET to audit
+        #[arg(required = true, index = 1)]
+        target: String,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// IRR whois server to query, as host:port
+        #[clap(long, default_value = irr::DEFAULT_IRR_HOST)]
+        irr_host: String,
+
+        /// Audit the IRR AS-SET registered for the ASN in PeeringDB instead of the ASN alone
+        #[clap(long)]
+        peeringdb_as_set: bool,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        #[clap(flatten)]
+        filters: Filters,
+    },
+    /// Classify the ASes adjacent to an ASN in announced paths as providers, peers or customers
+    AsNeighbors {
+        /// ASN (e.g. AS13335) whose neighbors to list
+        #[arg(required = true, index = 1, value_parser = asn::parse_asn)]
+        asn: u32,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// URL or file of the CAIDA as-rel dataset [default: the latest monthly serial-1 dataset]
+        #[clap(long)]
+        as_rel: Option<String>,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Report where and how heavily an ASN prepends itself on the paths to each of its prefixes,
+    /// per upstream
+    PrependReport {
+        /// ASN (e.g. AS13335) whose prefixes to audit
+        #[arg(required = true, index = 1, value_parser = asn::parse_asn)]
+        asn: u32,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Report the share of the prefixes and address space of an ASN its ROAs make RPKI valid,
+    /// listing the prefixes they do not
+    RpkiCoverage {
+        /// ASN (e.g. AS13335) whose prefixes to validate
+        #[arg(required = true, index = 1, value_parser = asn::parse_asn)]
+        asn: u32,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// URL or file of the validated ROA JSON export used for RPKI validation
+        #[clap(long, default_value = rpki::DEFAULT_ROA_URL)]
+        rpki_roas: String,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// List every origin ASN announcing space inside a prefix, with how many prefixes and how
+    /// much of it each announces
+    FindAsns {
+        /// Supernet (e.g. 12.0.0.0/8) whose announced space to break down by origin
+        #[arg(required = true, index = 1)]
+        prefix: IpNet,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Rank the prefixes and origin ASNs of a full RIB dump
+    Top {
+        /// Rankings to report, all of them by default
+        #[clap(long, value_enum, value_delimiter = ',')]
+        report: Vec<top::TopReport>,
+
+        /// Entries of each ranking, per address family for the largest blocks
+        #[clap(short = 'n', long, default_value_t = 10)]
+        count: usize,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Summarize the prefixes, prefix lengths, origins and path lengths of a RIB dump
+    TableStats {
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Map IP addresses to their announced prefix and origin ASN
+    MapIps {
+        /// File with one IP address per line, or - for stdin
+        #[clap(long, default_value = "-")]
+        file: String,
+
+        /// Data source used to map addresses
+        #[clap(long, value_enum, default_value_t = MapBackend::Cymru)]
+        backend: MapBackend,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        #[clap(flatten)]
+        registry: RegistryFallback,
+
+        /// Output format, text is CSV and json is newline-delimited JSON objects
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Append the announced prefix, origin ASNs and AS name of an address column to the rows of
+    /// a CSV file
+    EnrichCsv {
+        /// Name of the column holding the addresses, as given in the header row
+        #[clap(long)]
+        column: String,
+
+        /// CSV file with a header row, or - for stdin
+        #[clap(long, default_value = "-")]
+        file: String,
+
+        /// Write the enriched CSV to this file instead of stdout, compressed if it ends in .gz or
+        /// .zst
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// Data source used to map addresses
+        #[clap(long, value_enum, default_value_t = MapBackend::Cymru)]
+        backend: MapBackend,
+
+        /// Leave the AS names empty instead of looking them up in PeeringDB with the mrt backend
+        #[clap(long)]
+        no_as_names: bool,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        #[clap(flatten)]
+        registry: RegistryFallback,
+    },
+    /// List the output plugins, built in and found on the PATH
+    ListPlugins {
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// List the RIPE RIS and RouteViews route collectors
+    ListCollectors {
+        /// Fetch RIS peer counts and the time of the latest RIB dumps
+        #[clap(long)]
+        live: bool,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Table)]
+        format: ReportFormat,
+    },
+    /// Alert when monitored prefixes are announced by unexpected origins or more-specifics appear
+    MonitorPrefixes {
+        /// Prefixes to monitor
+        #[arg(required = true, index = 1, value_delimiter = ',')]
+        prefixes: Vec<IpNet>,
+
+        /// ASNs allowed to originate the monitored prefixes
+        #[clap(long, required = true, value_delimiter = ',', value_parser = asn::parse_asn)]
+        expected_origins: Vec<u32>,
+
+        /// Longest more-specific the expected origins may announce without an alert [default: the monitored prefix length]
+        #[clap(long)]
+        max_length: Option<u8>,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Follow the RIS Live update stream instead of scanning a RIB, printing alerts as they occur
+        #[clap(long)]
+        live: bool,
+
+        /// Output format, json is newline-delimited JSON objects with --live
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+
+        /// Also send alerts to slack://hooks.slack.com/services/... or
+        /// smtp[s]://[user:password@]host[:port]?from=<address>&to=<address>, may be repeated
+        #[clap(long, value_parser = notify::parse_sink)]
+        notify: Vec<notify::Sink>,
+    },
+    /// Summarize the contents of an MRT file
+    MrtInfo {
+        /// MRT file, or URL of a gzipped MRT file to download
+        #[arg(required = true, index = 1)]
+        file: String,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Check every record of an MRT file, exiting with an error when any are corrupt
+    MrtValidate {
+        /// MRT file, gzipped MRT files are decompressed while validating
+        #[arg(required = true, index = 1)]
+        file: String,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Print the BGP elements of an MRT file, like bgpdump -m or as JSON
+    Dump {
+        /// MRT file, or URL of a gzipped MRT file to download. Gzipped MRT files are decompressed
+        /// while reading
+        #[arg(required = true, index = 1)]
+        file: String,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = elems::DumpFormat::Bgpdump)]
+        format: elems::DumpFormat,
+
+        /// Write the elements to this file instead of stdout, compressed if it ends in .gz or .zst
+        #[clap(short, long)]
+        output: Option<String>,
+
+        #[clap(flatten)]
+        filters: ElemFilters,
+    },
+    /// Convert the RIB entries or updates of an MRT file to CSV or JSON lines, for loading into
+    /// databases
+    Convert {
+        /// MRT file, or URL of a gzipped MRT file to download. Gzipped MRT files are decompressed
+        /// while reading
+        #[clap(long = "in")]
+        input: String,
+
+        /// File to write, in the format its extension (.csv, .jsonl or .ndjson) names unless
+        /// --format is given, compressed if it ends in .gz or .zst
+        #[clap(long = "out")]
+        output: String,
+
+        /// Output format [default: from the extension of --out]
+        #[clap(long, value_enum)]
+        format: Option<elems::ConvertFormat>,
+
+        /// Columns to write, in this order in CSV [default: all]
+        #[clap(long, value_enum, value_delimiter = ',')]
+        columns: Vec<elems::Column>,
+
+        /// Number of threads decoding records [default: the number of CPUs]
+        #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+        threads: Option<u16>,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        #[clap(flatten)]
+        filters: ElemFilters,
+    },
+    /// Write the records of an MRT file with elements matching the filters to a new MRT file,
+    /// such as the routes of one network out of a full bview
+    Extract {
+        /// MRT file, or URL of a gzipped MRT file to download. Gzipped MRT files are decompressed
+        /// while reading
+        #[arg(required = true, index = 1)]
+        file: String,
+
+        /// MRT file to write, compressed if it ends in .gz or .zst
+        #[clap(short, long)]
+        output: String,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        #[clap(flatten)]
+        filters: ElemFilters,
+    },
+    /// Merge MRT files into one chronologically ordered MRT file, with a single peer index table
+    /// for RIB dumps of several collectors
+    MrtMerge {
+        /// MRT files, or URLs of gzipped MRT files to download. Gzipped MRT files are decompressed
+        /// while reading
+        #[arg(required = true, index = 1, num_args = 2..)]
+        files: Vec<String>,
+
+        /// MRT file to write, compressed if it ends in .gz or .zst
+        #[clap(short, long)]
+        output: String,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+    },
+    /// Graph the AS paths reaching the prefixes of an ASN
+    PathGraph {
+        /// ASN (e.g. AS13335) whose prefixes the paths reach
+        #[arg(required = true, index = 1, value_parser = asn::parse_asn)]
+        asn: u32,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Graph format
+        #[clap(long, value_enum, default_value_t = pathgraph::GraphFormat::Dot)]
+        format: pathgraph::GraphFormat,
+
+        /// Write the graph to this file instead of stdout, compressed if it ends in .gz or .zst
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Accept a BGP session from a router and record the routes it advertises as an MRT file
+    Peer {
+        /// Address and port to listen on for the session
+        #[clap(long, default_value = "0.0.0.0:179")]
+        listen: String,
+
+        /// Local ASN presented to the router, the router's own ASN for iBGP
+        #[clap(long, value_parser = asn::parse_asn)]
+        local_asn: u32,
+
+        /// BGP identifier presented to the router
+        #[clap(long)]
+        router_id: Ipv4Addr,
+
+        /// ASN the router must present, any ASN is accepted when omitted
+        #[clap(long, value_parser = asn::parse_asn)]
+        peer_asn: Option<u32>,
+
+        /// Proposed hold time, in seconds
+        #[clap(long, default_value_t = 90)]
+        hold_time: u16,
+
+        /// Stop after this many seconds without messages when the router never sends End-of-RIB
+        #[clap(long, default_value_t = 60)]
+        idle_timeout_seconds: u64,
+
+        /// MRT file to write the received routes to, usable as --mrt-file for the other subcommands
+        #[clap(short, long)]
+        output: String,
+    },
+    /// Look up the prefixes of ASNs and the origins of addresses in the prefix index of an MRT file
+    Query {
+        /// ASNs (e.g. AS13335), IP addresses or prefixes to look up
+        #[arg(required = true, index = 1)]
+        queries: Vec<String>,
+
+        #[clap(flatten)]
+        source: MrtSource,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Manage the download cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Check if one netblock contains another
+    NetblockContains {
+        /// The netblock to search for
+        #[clap(value_parser)]
+        needle: String,
+
+        /// The netblock to check containment
+        #[clap(value_parser)]
+        haystack: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Remove downloads unused for a while, content no download references, expired query results
+    /// and files left behind by interrupted runs
+    Gc {
+        /// Remove downloads unused for this many days
+        #[clap(long, default_value_t = 30)]
+        max_age_days: u64,
+
+        /// List what would be removed without removing it
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Download and index the latest RIB dumps ahead of time, such as off-peak from cron, so
+    /// later queries find them cached
+    Warm {
+        /// RIPE RIS collectors to warm, as ranges like 00-25 or single numbers separated by
+        /// commas, skipping inactive ones [default: 01 when no --url is given]
+        #[clap(long, value_parser = source::parse_rrc_range, value_delimiter = ',')]
+        rrc: Vec<RangeInclusive<u8>>,
+
+        /// URLs of other MRT files to warm, such as RouteViews RIB dumps
+        #[clap(long)]
+        url: Vec<String>,
+
+        /// Number of sources downloaded and indexed at once [default: the number of CPUs]
+        #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+        threads: Option<u16>,
+
+        /// Verification interval for cache, in seconds, shortened when the server allows less
+        #[clap(long, default_value_t = 86400)]
+        verify_cache_seconds: u64,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Scan a full MRT RIB dump
+    Mrt,
+    /// Query the RIPEstat announced-prefixes API
+    Ripestat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MapBackend {
+    /// Query the Team Cymru bulk whois interface
+    Cymru,
+    /// Match the longest announced prefix of an MRT RIB dump, offline once downloaded
+    Mrt,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PushTarget {
+    /// Replace the addresses of an AWS WAFv2 IP set
+    AwsWaf,
+    /// Inject the prefixes as routes into a GoBGP instance, through its gRPC API
+    Gobgp,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SortKey {
+    /// Numeric address order
+    Prefix,
+    /// Prefix length, shortest first
+    Length,
+    /// Number of addresses covered, fewest first
+    Addresses,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[command(group(clap::ArgGroup::new("schedule").args(["watch", "refresh_cron"])))]
+struct NetblockArgs {
+    /// Data source used to find announced prefixes
+    #[clap(long, value_enum, default_value_t = Backend::Mrt)]
+    backend: Backend,
+
+    #[clap(flatten)]
+    source: MrtSource,
+
+    /// Exclude specified subnets from results
+    #[clap(long, value_delimiter = ',')]
+    exclude_subnets: Option<Vec<String>>,
+
+    /// Output format
+    #[clap(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Render the results through a Tera-style template file instead of a built-in format, with
+    /// `prefixes`, `networks` and `metadata` as variables
+    #[clap(long, conflicts_with = "format")]
+    template: Option<String>,
+
+    /// Render the results with an output plugin: a built-in format, or an executable named
+    /// bgp-scout-output-<PLUGIN> on the PATH reading the results as NDJSON from its stdin
+    #[clap(long, conflicts_with_all = ["format", "template"])]
+    plugin: Option<String>,
+
+    /// Policy of the records generated by --format rpz
+    #[clap(long, value_enum, default_value_t = RpzAction::Drop)]
+    rpz_action: RpzAction,
+
+    /// SOA serial of generated zones [default: current unix time]
+    #[clap(long)]
+    zone_serial: Option<u32>,
+
+    /// Name of the ACL, policy or rule prefix generated by the bind-acl, unbound, nginx, envoy,
+    /// k8s-networkpolicy, gcloud, flowspec, rtbh and frr-vtysh formats
+    #[clap(long, default_value = "bgp-scout")]
+    acl_name: String,
+
+    /// Namespace of the policy generated by --format k8s-networkpolicy
+    #[clap(long, default_value = "default")]
+    namespace: String,
+
+    /// Traffic direction restricted by the k8s-networkpolicy, gcloud and flowspec formats, matching
+    /// flowspec traffic by destination for egress and by source for ingress
+    #[clap(long, value_enum, default_value_t = Direction::Egress)]
+    direction: Direction,
+
+    /// VPC network of the firewall rules generated by --format gcloud
+    #[clap(long, default_value = "default")]
+    gcp_network: String,
+
+    /// Rate limit traffic matched by --format flowspec to this many bytes per second instead of
+    /// discarding it
+    #[clap(long)]
+    flowspec_rate_limit: Option<u64>,
+
+    /// Router syntax of the blackhole routes generated by --format rtbh
+    #[clap(long, value_enum, default_value_t = RtbhPlatform::Ios)]
+    rtbh_platform: RtbhPlatform,
+
+    /// Tag of the blackhole routes generated by --format rtbh, matched by the policy announcing
+    /// them with the blackhole community
+    #[clap(long, default_value_t = 666)]
+    rtbh_tag: u32,
+
+    /// Next hop of the routes announced by --format exabgp, `self` for the address of the ExaBGP
+    /// session
+    #[clap(long, default_value = "self")]
+    exabgp_next_hop: String,
+
+    /// Communities attached to the routes announced by --format exabgp, e.g. 65535:666
+    #[clap(long, value_delimiter = ',')]
+    exabgp_community: Vec<String>,
+
+    /// Embed the data source with its Last-Modified and ETag, the tool version, the command line
+    /// and every filter as comments in the output, or as metadata in JSON
+    #[clap(long)]
+    provenance: bool,
+
+    /// Output IP addresses as ranges instead of prefixes, with --format text, json, csv or table
+    #[clap(long, default_value_t = false)]
+    ip_ranges: bool,
+
+    /// Drop prefixes covered by other prefixes in the result set before aggregation
+    #[clap(long)]
+    minimize: bool,
+
+    /// Output the announced prefixes verbatim instead of aggregating them
+    #[clap(long)]
+    no_aggregate: bool,
+
+    /// Split or widen IPv4 results into blocks of a fixed prefix length, e.g. /24
+    #[clap(long, value_parser = parse_prefix_len)]
+    split_to: Option<u8>,
+
+    /// Split or widen IPv6 results into blocks of a fixed prefix length, e.g. /48
+    #[clap(long, value_parser = parse_prefix_len)]
+    split_to_v6: Option<u8>,
+
+    /// Write the IPv4 results to this file or s3:// or gs:// URL instead of stdout, as JSON if it
+    /// ends in .json, and compressed if it ends in .gz or .zst
+    #[clap(long)]
+    output_v4: Option<String>,
+
+    /// Write the IPv6 results to this file or s3:// or gs:// URL instead of stdout, as JSON if it
+    /// ends in .json, and compressed if it ends in .gz or .zst
+    #[clap(long)]
+    output_v6: Option<String>,
+
+    /// Sign the files written by --output-v4 and --output-v6 with this minisign or PKCS#8 Ed25519
+    /// secret key, writing a minisign signature next to each file
+    #[clap(long)]
+    sign_key: Option<String>,
+
+    /// Publish the results to a Redis SET at this URL instead of stdout, e.g. redis://host/0
+    #[clap(long, requires = "redis_key")]
+    output_redis: Option<String>,
+
+    /// Redis key of the SET replaced with the results, e.g. asn:13335
+    #[clap(long, requires = "output_redis")]
+    redis_key: Option<String>,
+
+    /// Push the results to a cloud service or route server instead of stdout
+    #[clap(long, value_enum, requires_ifs = [("aws-waf", "ipset_id"), ("aws-waf", "ipset_name"), ("gobgp", "grpc")])]
+    push: Option<PushTarget>,
+
+    /// Id of the AWS WAF IP set to update
+    #[clap(long)]
+    ipset_id: Option<String>,
+
+    /// Name of the AWS WAF IP set to update
+    #[clap(long)]
+    ipset_name: Option<String>,
+
+    /// Scope of the AWS WAF IP set
+    #[clap(long, value_enum, default_value_t = aws::WafScope::Regional)]
+    ipset_scope: aws::WafScope,
+
+    /// AWS region of a regional IP set [default: AWS_REGION environment variable]
+    #[clap(long)]
+    aws_region: Option<String>,
+
+    /// Address of the gRPC API of the GoBGP instance updated by --push gobgp, e.g. 127.0.0.1:50051
+    #[clap(long)]
+    grpc: Option<String>,
+
+    /// Next hop of the routes injected by --push gobgp [default: 0.0.0.0 or ::, which GoBGP
+    /// announces as its own address]
+    #[clap(long)]
+    gobgp_next_hop: Option<String>,
+
+    /// Communities of the routes injected by --push gobgp, e.g. 65000:100
+    #[clap(long, value_delimiter = ',')]
+    gobgp_community: Vec<String>,
+
+    /// Update a local firewall set or FRR prefix lists with only the added and removed prefixes
+    /// instead of printing
+    #[clap(long, value_enum, requires = "set_name")]
+    apply: Option<firewall::ApplyTarget>,
+
+    /// Print the changes --apply would make instead of applying them
+    #[clap(long, requires = "apply")]
+    apply_dry_run: bool,
+
+    /// Name of the nftables or ipset set, or of the FRR prefix lists, updated by --apply
+    #[clap(long)]
+    set_name: Option<String>,
+
+    /// Family and table of the nftables set updated by --apply
+    #[clap(long, default_value = "inet filter")]
+    nft_table: String,
+
+    /// Print only the prefixes to add to and remove from a deployed list to match the results:
+    /// nft:<table>/<set>, ipset:<set>, or a file with one prefix per line. Written as +/- lines,
+    /// or as json or a table with --format
+    #[clap(long, value_parser = diff::parse_existing, conflicts_with_all = ["count", "output_v4", "output_v6", "template", "plugin"])]
+    diff_against: Option<diff::Existing>,
+
+    /// Break the results down per origin ASN instead of merging them, in text or json format
+    #[clap(long, conflicts_with_all = ["count", "output_v4", "output_v6", "output_redis", "push", "apply", "template", "plugin", "diff_against"])]
+    group_by_asn: bool,
+
+    /// Print only the number of resulting prefixes
+    #[clap(long)]
+    count: bool,
+
+    /// Include the total number of addresses covered when counting
+    #[clap(long, requires = "count")]
+    count_addresses: bool,
+
+    /// Log the IPv4 addresses and IPv6 /64s covered before and after exclusions, at info level
+    /// so shown with -v
+    #[clap(long)]
+    totals: bool,
+
+    /// Sort order of the results
+    #[clap(long, value_enum, default_value_t = SortKey::Prefix)]
+    sort: SortKey,
+
+    /// Reverse the sort order
+    #[clap(long)]
+    descending: bool,
+
+    /// Annotate each prefix with its RIR allocation and flag unallocated space
+    #[clap(long)]
+    rir_annotate: bool,
+
+    /// Enrich the target ASNs with PeeringDB network details
+    #[clap(long)]
+    peeringdb: bool,
+
+    /// Only keep prefixes originated solely by the target ASNs, dropping anycast and MOAS
+    /// prefixes other networks originate as well, as seen by every collector peer
+    #[clap(long)]
+    exclusive: bool,
+
+    /// Drop prefixes any of these ASNs originates too, such as those a subsidiary announces
+    /// alongside the target ASNs, as seen by every collector peer
+    #[clap(long, value_delimiter = ',', value_parser = asn::parse_asn)]
+    exclude_origin_asns: Vec<u32>,
+
+    /// Only keep prefixes whose announcement is RPKI invalid
+    #[clap(long, conflicts_with = "only_unknown")]
+    only_invalid: bool,
+
+    /// Only keep prefixes whose announcement is not covered by any ROA
+    #[clap(long, conflicts_with = "only_invalid")]
+    only_unknown: bool,
+
+    /// URL or file of the validated ROA JSON export used for RPKI validation
+    #[clap(long, default_value = rpki::DEFAULT_ROA_URL)]
+    rpki_roas: String,
+
+    /// Only keep routes learned from collector peers of these ASNs, as listed in the peer index
+    /// table of the MRT file, for the view of particular networks rather than the union of all
+    /// peers. The MRT file is scanned instead of using its index
+    #[clap(long, value_delimiter = ',', value_parser = asn::parse_asn)]
+    peer_asn: Vec<u32>,
+
+    /// Only keep routes learned from the collector peers with these addresses, besides those of
+    /// --peer-asn
+    #[clap(long, value_delimiter = ',')]
+    peer_ip: Vec<IpAddr>,
+
+    /// Only keep routes learned from collector peers sending (near) full tables, leaving out the
+    /// partial feeds of stub networks, per address family. Combined with --peer-asn or --peer-ip,
+    /// only the selected peers with full tables are kept. The routes of each peer are counted
+    /// once per MRT file and kept next to it
+    #[clap(long)]
+    full_feed_only: bool,
+
+    /// Include the AS path, next hop, MED and communities of a representative route of each
+    /// prefix in json and csv output, or only the listed ones. The route with the shortest AS
+    /// path is chosen. Aggregates of several announcements have none, so this is mostly used with
+    /// --no-aggregate
+    #[clap(long, value_enum, value_delimiter = ',', num_args = 0.., conflicts_with_all = ["low_memory", "rir_annotate", "group_by_asn"])]
+    with_attributes: Option<Vec<attributes::Field>>,
+
+    /// Percentage of the routes of the largest feed a peer must send to count as full
+    #[clap(long, requires = "full_feed_only", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    full_feed_threshold: u8,
+
+    /// Stop scanning the MRT file once this many matching prefixes are found, returning partial
+    /// results
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_prefixes: Option<u64>,
+
+    /// Stop scanning the MRT file after this many seconds, returning partial results
+    #[clap(long)]
+    scan_timeout: Option<u64>,
+
+    /// Aggregate the prefixes as they are read instead of collecting them first, so memory stays
+    /// bounded by the size of the aggregated result on full tables and large cones. The origins of
+    /// each prefix are not kept, and no index is built
+    #[clap(long, conflicts_with_all = ["no_aggregate", "group_by_asn", "only_invalid", "only_unknown", "exclusive", "exclude_origin_asns", "max_prefixes", "scan_timeout"])]
+    low_memory: bool,
+
+    /// Reuse the results of an identical query made within this many seconds
+    #[clap(long, default_value_t = 3600)]
+    result_cache_seconds: u64,
+
+    /// Look the prefixes up again instead of reusing the results of an identical query
+    #[clap(long)]
+    no_result_cache: bool,
+
+    /// Keep running, looking the prefixes up again every this many seconds and publishing the
+    /// results whenever they change. With --format exabgp only the announce and withdraw commands
+    /// for the changes are written. The MRT source is checked for a newer dump per
+    /// --verify-cache-seconds
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    watch: Option<u64>,
+
+    /// Keep running like --watch, but look the prefixes up on a cron schedule in UTC such as
+    /// "0 */4 * * *", checking the MRT source for a newer dump at every run
+    #[clap(long)]
+    refresh_cron: Option<cron::Schedule>,
+
+    /// Send a summary of the changes seen by --watch or --refresh-cron to slack://hooks.slack.com/services/... or
+    /// smtp[s]://[user:password@]host[:port]?from=<address>&to=<address>, may be repeated
+    #[clap(long, requires = "schedule", value_parser = notify::parse_sink)]
+    notify: Vec<notify::Sink>,
+
+    #[clap(flatten)]
+    filters: Filters,
+}
+
+#[derive(Parser, Debug)]
+struct AsnArgs {
+    #[arg(required_unless_present_any = ["asn_file", "org"], index = 1, value_delimiter = ',', value_parser = asn::parse_asn)]
+    origin_asns: Vec<u32>,
+
+    /// File with one ASN per line, or - for stdin, merged with the ASNs given as arguments
+    #[clap(long)]
+    asn_file: Option<String>,
+
+    /// Add the ASNs of organizations whose name contains this text, looked up in PeeringDB
+    #[clap(long)]
+    org: Option<String>,
+
+    /// URL or file of a CAIDA as2org dataset used by --org instead of PeeringDB
+    #[clap(long, requires = "org")]
+    as2org: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct MrtSource {
+    /// MRT file, conflicts with specifying RIPE RRC or URL
+    #[clap(short = 'f', long, conflicts_with = "rrc", conflicts_with = "url")]
+    mrt_file: Option<String>,
+
+    /// Specify RIPE RRC server number (00-26) or auto to pick the nearest [default: 01], conflicts with specifying URL or MRT file directly
+    #[clap(short = 'r', long, conflicts_with = "url", conflicts_with = "mrt_file", value_parser = source::parse_rrc)]
+    rrc: Option<source::Rrc>,
+
+    /// Specify an entire URL, including ftp:// and rsync:// archives and s3:// and gs:// objects
+    /// fetched with the usual AWS or Google credentials, conflicts with specifying RRC or MRT file
+    /// directly
+    #[clap(long, conflicts_with = "rrc", conflicts_with = "mrt_file")]
+    url: Option<String>,
+
+    /// Verification interval for cache, in seconds, shortened when the server allows less
+    #[clap(long, default_value_t = 86400)]
+    verify_cache_seconds: u64,
+
+    /// Parse the MRT file directly instead of using or writing its prefix index
+    #[clap(long)]
+    no_index: bool,
+}
+
+/// Lookup of the registered allocation of addresses no announced prefix covers
+#[derive(Parser, Debug, Clone)]
+struct RegistryFallback {
+    /// Look up the addresses no announced prefix covers with RDAP, reporting the allocation of
+    /// those registered but not announced
+    #[clap(long)]
+    registry_fallback: bool,
+
+    /// RDAP service queried by --registry-fallback, by default a bootstrap service redirecting to
+    /// the registry of each address
+    #[clap(long, default_value = rdap::DEFAULT_RDAP_URL, requires = "registry_fallback")]
+    rdap_url: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct Filters {
+    /// Filter by IPv4 only
+    #[clap(short = '4', long, conflicts_with("ipv6_only"))]
+    ipv4_only: bool,
+
+    /// Filter by IPv6 only
+    #[clap(short = '6', long, conflicts_with("ipv4_only"))]
+    ipv6_only: bool,
+}
+
+/// Conditions selecting the BGP elements of an MRT file
+#[derive(Parser, Debug, Clone)]
+struct ElemFilters {
+    /// Only keep routes originated by these ASNs
+    #[clap(long, value_delimiter = ',', value_parser = asn::parse_asn)]
+    origin_asn: Vec<u32>,
+
+    /// Only keep elements of these prefixes
+    #[clap(long, value_delimiter = ',')]
+    prefix: Vec<IpNet>,
+
+    /// Also keep elements of prefixes more specific than --prefix
+    #[clap(long, requires = "prefix")]
+    more_specifics: bool,
+
+    /// Also keep elements of prefixes less specific than --prefix
+    #[clap(long, requires = "prefix")]
+    less_specifics: bool,
+
+    /// Only keep elements from collector peers of these ASNs
+    #[clap(long, value_delimiter = ',', value_parser = asn::parse_asn)]
+    peer_asn: Vec<u32>,
+
+    /// Only keep elements from the collector peers with these addresses, besides those of
+    /// --peer-asn
+    #[clap(long, value_delimiter = ',')]
+    peer_ip: Vec<IpAddr>,
+
+    /// Only keep routes whose AS path, written as space-separated ASNs, matches this regular
+    /// expression, such as "^174 " or " 13335$"
+    #[clap(long)]
+    as_path: Option<regex::Regex>,
+
+    /// Only keep announcements or withdrawals
+    #[clap(long = "type", value_enum)]
+    elem_type: Option<elems::ElemKind>,
+
+    /// Only keep elements at or after this time, RFC 3339 or unix seconds
+    #[clap(long, value_parser = flap::parse_time)]
+    start: Option<DateTime<Utc>>,
+
+    /// Only keep elements before this time, RFC 3339 or unix seconds
+    #[clap(long, value_parser = flap::parse_time)]
+    end: Option<DateTime<Utc>>,
+
+    #[clap(flatten)]
+    filters: Filters,
+}
+
+fn elem_filter(filters: &ElemFilters) -> elems::ElemFilter {
+    elems::ElemFilter {
+        origin_asns: filters.origin_asn.iter().copied().collect(),
+        prefixes: filters.prefix.iter().map(IpNet::trunc).collect(),
+        more_specifics: filters.more_specifics,
+        less_specifics: filters.less_specifics,
+        peers: scan::PeerFilter {
+            asns: filters.peer_asn.iter().copied().collect(),
+            ips: filters.peer_ip.iter().copied().collect(),
+            feeds: HashSet::new(),
+        },
+        as_path: filters.as_path.clone(),
+        kind: filters.elem_type,
+        start: filters.start,
+        end: filters.end,
+        ipv4_only: filters.filters.ipv4_only,
+        ipv6_only: filters.filters.ipv6_only,
+    }
+}
+
+/// Drops every prefix that is entirely covered by another prefix in the set, leaving the
+/// smallest set of covering prefixes.
+fn minimize_prefixes(prefixes: &[IpNet]) -> Vec<IpNet> {
+    let mut sorted: Vec<IpNet> = prefixes.iter().map(IpNet::trunc).collect();
+    // Covering prefixes sort before the prefixes they contain
+    sorted.sort();
+    sorted.dedup();
+
+    let mut result: Vec<IpNet> = Vec::with_capacity(sorted.len());
+    for prefix in sorted {
+        match result.last() {
+            Some(last) if last.contains(&prefix) => {
+                trace!("Dropping prefix {prefix} covered by {last}");
+            }
+            _ => result.push(prefix),
+        }
+    }
+    result
+}
+
+/// Limits splitting a single prefix to at most 2^24 blocks
+const MAX_SPLIT_BITS: u8 = 24;
+
+fn parse_prefix_len(value: &str) -> Result<u8, String> {
+    value
+        .trim_start_matches('/')
+        .parse::<u8>()
+        .map_err(|e| format!("Invalid prefix length {value}: {e}"))
+}
+
+/// Normalizes prefixes to a fixed length per family: shorter prefixes are split into blocks of
+/// that length and longer prefixes are widened to the block containing them.
+fn split_prefixes(
+    prefixes: &[IpNet],
+    v4_len: Option<u8>,
+    v6_len: Option<u8>,
+) -> Result<Vec<IpNet>, Box<dyn Error>> {
+    let mut result = Vec::new();
+    for prefix in prefixes {
+        let target_len = match prefix {
+            IpNet::V4(_) => v4_len,
+            IpNet::V6(_) => v6_len,
+        };
+        let Some(target_len) = target_len else {
+            result.push(*prefix);
+            continue;
+        };
+        if target_len > prefix.max_prefix_len() {
+            return Err(format!("Cannot split {prefix} to invalid length /{target_len}").into());
+        }
+
+        if target_len <= prefix.prefix_len() {
+            result.push(IpNet::new(prefix.network(), target_len)?.trunc());
+        } else {
+            if target_len - prefix.prefix_len() > MAX_SPLIT_BITS {
+                return Err(format!(
+                    "Splitting {prefix} to /{target_len} would produce more than 2^{MAX_SPLIT_BITS} blocks"
+                )
+                .into());
+            }
+            result.extend(prefix.subnets(target_len)?);
+        }
+    }
+    result.sort();
+    result.dedup();
+    Ok(result)
+}
+
+/// Sorts prefixes numerically, IPv4 before IPv6, using the network address and prefix length to
+/// break ties so the output order is stable across runs.
+fn sort_prefixes(prefixes: &mut [IpNet], key: SortKey, descending: bool) {
+    match key {
+        SortKey::Prefix => prefixes.sort(),
+        SortKey::Length => {
+            prefixes.sort_by(|a, b| a.prefix_len().cmp(&b.prefix_len()).then(a.cmp(b)))
+        }
+        // The number of addresses in a prefix grows with its host bits
+        SortKey::Addresses => prefixes.sort_by(|a, b| {
+            let a_host_bits = a.max_prefix_len() - a.prefix_len();
+            let b_host_bits = b.max_prefix_len() - b.prefix_len();
+            a_host_bits.cmp(&b_host_bits).then(a.cmp(b))
+        }),
+    }
+    if descending {
+        prefixes.reverse();
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let otlp_endpoint = cli
+        .otlp_endpoint
+        .clone()
+        .or_else(|| env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok());
+    if let Some(endpoint) = &otlp_endpoint {
+        telemetry::init(endpoint);
+    }
+    init_logger(cli.verbose, cli.quiet, cli.log_format);
+    if cli.systemd {
+        systemd::init();
+    }
+    if cli.compress {
+        output::compress_all();
+    }
+    if cli.prune_cache {
+        disk::prune_cache_when_full();
+    }
+    download::set_cache_mode(cli.cache_mode);
+    download::configure_client(&download::ClientOptions {
+        headers: cli.header.clone(),
+        user_agent: cli.user_agent.clone(),
+        http_user: cli.http_user.clone(),
+        bearer_token: cli.bearer_token.clone(),
+        tls_cert: cli.tls_cert.clone(),
+        tls_key: cli.tls_key.clone(),
+        tls_ca: cli.tls_ca.clone(),
+        no_system_roots: cli.no_system_roots,
+    })?;
+
+    let result = info_span!(telemetry::RUN_SPAN).in_scope(|| run(&cli));
+    telemetry::export(result.as_ref().err().map(ToString::to_string).as_deref());
+    result
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let command = match (&cli.command, cli.rpc) {
+        (None, true) => {
+            return rpc::serve(
+                cli.strict,
+                cli.refresh_cron.as_ref(),
+                cli.status_listen.as_deref(),
+                cli.health_max_age,
+            )
+        }
+        (Some(_), true) => return Err("--rpc takes its requests from stdin, not a command".into()),
+        (Some(command), false) => command,
+        (None, false) => return Err("No command given, see --help".into()),
+    };
+    match command {
+        Commands::FindNetblocks { asns, args } => {
+            let verify_cache_interval = Duration::from_secs(args.source.verify_cache_seconds);
+            let origin_asns = resolve_asns(asns, verify_cache_interval, cli.strict)?;
+            asn::check_asns(&origin_asns, cli.strict)?;
+            run_netblocks(&origin_asns, args)?;
+        }
+        Commands::Batch { file, source } => batch::run(file, source, cli.strict)?,
+        Commands::FindCountryNetblocks { country, args } => {
+            let verify_cache_interval = Duration::from_secs(args.source.verify_cache_seconds);
+            let delegations = rir::fetch_delegations(verify_cache_interval)?;
+            let origin_asns: HashSet<u32> = rir::country_asns(&delegations, country)
+                .into_iter()
+                .collect();
+            debug!(
+                "Found {} ASNs delegated to country {country}",
+                origin_asns.len()
+            );
+            if origin_asns.is_empty() {
+                return Err(format!("No ASNs are delegated to country {country}").into());
+            }
+            run_netblocks(&origin_asns, args)?;
+        }
+        Commands::ExportTable {
+            source,
+            format,
+            output,
+            filters,
+        } => {
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, filters.ipv4_only, filters.ipv6_only)?
+            } else {
+                let mut table = index::load_or_build(&mrt_file)?;
+                table.retain(|prefix, _| match prefix {
+                    IpNet::V4(_) => !filters.ipv6_only,
+                    IpNet::V6(_) => !filters.ipv4_only,
+                });
+                table
+            };
+            let mut writer = output::Output::create_or_stdout(output.as_deref())?;
+            table::write_export(&mut writer, &table, *format)?;
+            writer.finish()?;
+        }
+        Commands::FlapReport {
+            asns,
+            updates_files,
+            rrc,
+            start,
+            end,
+            threshold,
+            format,
+            verify_cache_seconds,
+            filters,
+        } => {
+            let origin_asns =
+                resolve_asns(asns, Duration::from_secs(*verify_cache_seconds), cli.strict)?;
+            asn::check_asns(&origin_asns, cli.strict)?;
+
+            let files = if updates_files.is_empty() {
+                let end = end.as_deref().map(flap::parse_time).transpose()?;
+                let end = end.unwrap_or_else(flap::default_end);
+                let start = start.as_deref().map(flap::parse_time).transpose()?;
+                let start = start.unwrap_or(end - TimeDelta::hours(1));
+                if start >= end {
+                    return Err(format!("Window start {start} is not before end {end}").into());
+                }
+
+                let verify_cache_interval = Duration::from_secs(*verify_cache_seconds);
+                let rrc = source::resolve_rrc(*rrc, verify_cache_interval)?;
+                let urls = flap::ripe_updates_urls(rrc, start, end);
+                debug!(
+                    "Fetching {} updates files from {start} to {end}",
+                    urls.len()
+                );
+                urls.iter()
+                    .map(|url| source::fetch_mrt(url, verify_cache_interval))
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                updates_files.clone()
+            };
+
+            let report = flap::scan_churn(
+                &files,
+                &origin_asns,
+                filters.ipv4_only,
+                filters.ipv6_only,
+                *threshold,
+            )?;
+            flap::render_report(&report, *format)?;
+        }
+        Commands::History {
+            target,
+            from,
+            to,
+            step,
+            rrc,
+            format,
+            verify_cache_seconds,
+        } => {
+            let from = flap::parse_time(from)?;
+            let to = to.as_deref().map(flap::parse_time).transpose()?;
+            let to = to.unwrap_or_else(Utc::now);
+            if from > to {
+                return Err(format!("Range start {from} is after end {to}").into());
+            }
+
+            let verify_cache_interval = Duration::from_secs(*verify_cache_seconds);
+            let rrc = source::resolve_rrc(*rrc, verify_cache_interval)?;
+            let times = history::snapshot_times(from, to, *step);
+            info!(
+                "Walking {} bviews of RRC {rrc:02} from {from} to {to}",
+                times.len()
+            );
+            let events = history::build_timeline(rrc, &times, *target, verify_cache_interval)?;
+            history::render_timeline(&events, *format)?;
+        }
+        Commands::IrrAudit {
+            target,
+            source,
+            irr_host,
+            peeringdb_as_set,
+            format,
+            filters,
+        } => {
+            let target_asn = asn::parse_asn(target).ok();
+            if let Some(asn) = target_asn {
+                asn::check_asns(&[asn], cli.strict)?;
+            }
+            let origin_asns = match target_asn {
+                Some(asn) if *peeringdb_as_set => {
+                    let verify_cache_interval = Duration::from_secs(source.verify_cache_seconds);
+                    let networks = peeringdb::fetch_networks(&[asn], verify_cache_interval)?;
+                    let as_set = networks
+                        .first()
+                        .map(|network| network.irr_as_set.clone())
+                        .filter(|as_set| !as_set.is_empty())
+                        .ok_or_else(|| format!("PeeringDB has no IRR AS-SET for AS{asn}"))?;
+                    debug!("Using PeeringDB AS-SET {as_set} for AS{asn}");
+                    irr::expand_as_set(irr_host, &as_set)?
+                }
+                Some(asn) => HashSet::from([asn]),
+                None => irr::expand_as_set(irr_host, target)?,
+            };
+            if origin_asns.is_empty() {
+                return Err(format!("No ASNs found for {target}").into());
+            }
+
+            let registered = irr::registered_prefixes(
+                irr_host,
+                &origin_asns,
+                filters.ipv4_only,
+                filters.ipv6_only,
+            )?;
+
+            let announced: HashSet<IpNet> = mrt_prefixes(
+                &source::resolve_mrt(source)?,
+                source.no_index,
+                &origin_asns,
+                filters.ipv4_only,
+                filters.ipv6_only,
+            )?
+            .into_keys()
+            .collect();
+
+            let report = irr::audit(target, &origin_asns, &announced, &registered);
+            irr::render_report(&report, *format)?;
+        }
+        Commands::AsNeighbors {
+            asn,
+            source,
+            as_rel,
+            format,
+        } => {
+            asn::check_asns(&[*asn], cli.strict)?;
+            let relationships = asrel::load(
+                as_rel.as_deref(),
+                Duration::from_secs(source.verify_cache_seconds),
+            )?;
+            let mrt_file = source::resolve_mrt(source)?;
+            let neighbors = asrel::neighbors(&mrt_file, *asn, &relationships)?;
+            asrel::render_neighbors(&neighbors, *format)?;
+        }
+        Commands::PrependReport {
+            asn,
+            source,
+            format,
+        } => {
+            asn::check_asns(&[*asn], cli.strict)?;
+            let mrt_file = source::resolve_mrt(source)?;
+            let prepending = prepend::prepending(&mrt_file, *asn)?;
+            prepend::render_prepending(&prepending, *format)?;
+        }
+        Commands::RpkiCoverage {
+            asn,
+            source,
+            rpki_roas,
+            format,
+        } => {
+            asn::check_asns(&[*asn], cli.strict)?;
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, false, false)?
+            } else {
+                index::load_or_build(&mrt_file)?
+            };
+            let prefixes: Vec<IpNet> = table
+                .iter()
+                .filter(|(_, origins)| origins.contains(asn))
+                .map(|(prefix, _)| *prefix)
+                .collect();
+            if prefixes.is_empty() {
+                warn!("AS{asn} announces no prefixes in {mrt_file}");
+            }
+            let roas =
+                rpki::load_roas(rpki_roas, Duration::from_secs(source.verify_cache_seconds))?;
+            rpki::render_coverage(&rpki::Coverage::measure(*asn, &prefixes, &roas), *format)?;
+        }
+        Commands::FindAsns {
+            prefix,
+            source,
+            format,
+        } => {
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, false, false)?
+            } else {
+                index::load_or_build(&mrt_file)?
+            };
+            let origins = table::origins_within(&table, &prefix.trunc());
+            table::render_origins_within(&origins, *format)?;
+        }
+        Commands::Top {
+            report,
+            count,
+            source,
+            format,
+        } => {
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, false, false)?
+            } else {
+                index::load_or_build(&mrt_file)?
+            };
+            top::render_top(&top::top(&table, report, *count), *format)?;
+        }
+        Commands::TableStats { source, format } => {
+            let mrt_file = source::resolve_mrt(source)?;
+            stats::render_table_stats(&stats::table_stats(&mrt_file)?, *format)?;
+        }
+        Commands::MapIps {
+            file,
+            backend,
+            source,
+            registry,
+            format,
+        } => {
+            let ips = ipmap::read_ips(file)?;
+            let mut mappings = map_ips(&ips, *backend, source)?;
+            if registry.registry_fallback {
+                ipmap::add_registrations(
+                    &mut mappings,
+                    &registry.rdap_url,
+                    Duration::from_secs(source.verify_cache_seconds),
+                );
+            }
+            ipmap::render_mappings(&mappings, registry.registry_fallback, *format)?;
+        }
+        Commands::EnrichCsv {
+            column,
+            file,
+            output,
+            backend,
+            no_as_names,
+            source,
+            registry,
+        } => {
+            let verify_cache_interval = Duration::from_secs(source.verify_cache_seconds);
+            let mut writer = output::Output::create_or_stdout(output.as_deref())?;
+            ipmap::enrich_csv(
+                file,
+                column,
+                registry.registry_fallback,
+                |ips| {
+                    let mut mappings = map_ips(ips, *backend, source)?;
+                    if *backend == MapBackend::Mrt && !no_as_names {
+                        let networks = peeringdb::fetch_networks(
+                            &ipmap::origin_asns(&mappings),
+                            verify_cache_interval,
+                        )?;
+                        ipmap::name_origins(&mut mappings, &networks);
+                    }
+                    if registry.registry_fallback {
+                        ipmap::add_registrations(
+                            &mut mappings,
+                            &registry.rdap_url,
+                            verify_cache_interval,
+                        );
+                    }
+                    Ok(mappings)
+                },
+                &mut writer,
+            )?;
+            writer.finish()?;
+        }
+        Commands::ListPlugins { format } => {
+            plugin::render_plugins(&plugin::discover(), *format)?;
+        }
+        Commands::ListCollectors {
+            live,
+            verify_cache_seconds,
+            format,
+        } => {
+            let collectors =
+                collectors::catalogue(*live, Duration::from_secs(*verify_cache_seconds))?;
+            collectors::render_catalogue(&collectors, *format)?;
+        }
+        Commands::MonitorPrefixes {
+            prefixes,
+            expected_origins,
+            max_length,
+            source,
+            live,
+            format,
+            notify,
+        } => {
+            asn::check_asns(expected_origins, cli.strict)?;
+            let monitor = monitor::Monitor {
+                prefixes: prefixes.iter().map(IpNet::trunc).collect(),
+                expected_origins: expected_origins.iter().copied().collect(),
+                max_length: *max_length,
+            };
+            if *live {
+                monitor::follow_ris_live(&monitor, *format, notify)?;
+            } else {
+                let mrt_file = source::resolve_mrt(source)?;
+                let table = if source.no_index {
+                    table::scan_origins(&mrt_file, false, false)?
+                } else {
+                    index::load_or_build(&mrt_file)?
+                };
+                let alerts = monitor.check_table(&table);
+                monitor::render_alerts(&alerts, *format)?;
+                monitor::notify_alerts(notify, &alerts);
+                if !alerts.is_empty() {
+                    return Err(format!("Found {} alerts", alerts.len()).into());
+                }
+            }
+        }
+        Commands::MrtInfo {
+            file,
+            verify_cache_seconds,
+            format,
+        } => {
+            let file = if file.contains("://") {
+                source::fetch_mrt(file, Duration::from_secs(*verify_cache_seconds))?
+            } else {
+                file.clone()
+            };
+            let info = mrt::inspect(&file)?;
+            mrt::render_info(&info, *format)?;
+        }
+        Commands::MrtValidate { file, format } => {
+            let report = mrt::validate(file)?;
+            mrt::render_validation(&report, *format)?;
+            if !report.problems.is_empty() {
+                return Err(format!("{file} has {} corrupt records", report.problems.len()).into());
+            }
+        }
+        Commands::Dump {
+            file,
+            verify_cache_seconds,
+            format,
+            output,
+            filters,
+        } => {
+            let file = if file.contains("://") {
+                source::fetch_mrt(file, Duration::from_secs(*verify_cache_seconds))?
+            } else {
+                file.clone()
+            };
+            let mut writer = output::Output::create_or_stdout(output.as_deref())?;
+            elems::dump(&file, &elem_filter(filters), *format, &mut writer)?;
+            writer.finish()?;
+        }
+        Commands::Convert {
+            input,
+            output,
+            format,
+            columns,
+            threads,
+            verify_cache_seconds,
+            filters,
+        } => {
+            let format = format
+                .or_else(|| elems::ConvertFormat::from_path(output::uncompressed_name(output)))
+                .ok_or_else(|| {
+                    format!("Cannot tell the format of {output} from its extension, use --format")
+                })?;
+            let columns = if columns.is_empty() {
+                elems::Column::ALL.to_vec()
+            } else {
+                columns.clone()
+            };
+            let threads = threads.map_or_else(
+                || thread::available_parallelism().map_or(1, NonZeroUsize::get),
+                usize::from,
+            );
+            let input = if input.contains("://") {
+                source::fetch_mrt(input, Duration::from_secs(*verify_cache_seconds))?
+            } else {
+                input.clone()
+            };
+            let mut writer = output::Output::create(output)?;
+            let written = elems::convert(
+                &input,
+                &elem_filter(filters),
+                format,
+                &columns,
+                threads,
+                &mut writer,
+            )?;
+            writer.finish()?;
+            info!(
+                "Wrote {written} elements to {}",
+                output::output_name(output)
+            );
+        }
+        Commands::Extract {
+            file,
+            output,
+            verify_cache_seconds,
+            filters,
+        } => {
+            let file = if file.contains("://") {
+                source::fetch_mrt(file, Duration::from_secs(*verify_cache_seconds))?
+            } else {
+                file.clone()
+            };
+            let mut writer = output::Output::create(output)?;
+            let (read, written) = elems::extract(&file, &elem_filter(filters), &mut writer)?;
+            writer.finish()?;
+            info!(
+                "Wrote {written} of {read} records to {}",
+                output::output_name(output)
+            );
+        }
+        Commands::MrtMerge {
+            files,
+            output,
+            verify_cache_seconds,
+        } => {
+            let files = files
+                .iter()
+                .map(|file| {
+                    if file.contains("://") {
+                        source::fetch_mrt(file, Duration::from_secs(*verify_cache_seconds))
+                    } else {
+                        Ok(file.clone())
+                    }
+                })
+                .collect::<Result<Vec<String>, _>>()?;
+            let mut writer = output::Output::create(output)?;
+            let written = mrt::merge(&files, &mut writer)?;
+            writer.finish()?;
+            info!("Wrote {written} records to {}", output::output_name(output));
+        }
+        Commands::PathGraph {
+            asn,
+            source,
+            format,
+            output,
+        } => {
+            asn::check_asns(&[*asn], cli.strict)?;
+            let mrt_file = source::resolve_mrt(source)?;
+            let graph = pathgraph::build(&mrt_file, *asn)?;
+            let mut writer = output::Output::create_or_stdout(output.as_deref())?;
+            pathgraph::write_graph(&mut writer, &graph, *format)?;
+            writer.finish()?;
+        }
+        Commands::Peer {
+            listen,
+            local_asn,
+            router_id,
+            peer_asn,
+            hold_time,
+            idle_timeout_seconds,
+            output,
+        } => {
+            let config = peer::PeerConfig {
+                listen,
+                local_asn: *local_asn,
+                router_id: *router_id,
+                peer_asn: *peer_asn,
+                hold_time: *hold_time,
+                idle_timeout: Duration::from_secs(*idle_timeout_seconds),
+            };
+            let mut writer = output::Output::create(output)?;
+            let summary = peer::collect(&config, &mut writer)?;
+            writer.finish()?;
+            info!(
+                "Recorded {} updates from AS{} ({}) to {output}{}",
+                summary.updates,
+                summary.peer_asn,
+                summary.peer_address,
+                if summary.complete {
+                    ""
+                } else {
+                    ", the routing table may be incomplete"
+                }
+            );
+        }
+        Commands::Query {
+            queries,
+            source,
+            format,
+        } => {
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, false, false)?
+            } else {
+                index::load_or_build(&mrt_file)?
+            };
+            let answers = queries
+                .iter()
+                .map(|query| index::query(&table, query))
+                .collect::<Result<Vec<_>, _>>()?;
+            index::render_answers(&answers, *format)?;
+        }
+        Commands::Cache {
+            command:
+                CacheCommand::Gc {
+                    max_age_days,
+                    dry_run,
+                    format,
+                },
+        } => {
+            let max_age = Duration::from_secs(max_age_days * 86400);
+            let report = cache::gc(max_age, *dry_run)?;
+            cache::render_gc(&report, *dry_run, *format)?;
+        }
+        Commands::Cache {
+            command:
+                CacheCommand::Warm {
+                    rrc,
+                    url,
+                    threads,
+                    verify_cache_seconds,
+                    format,
+                },
+        } => {
+            let mut urls: Vec<String> = rrc
+                .iter()
+                .cloned()
+                .flatten()
+                .filter(|&rrc| {
+                    let active = collectors::is_active_rrc(rrc);
+                    if !active {
+                        info!("Skipping inactive collector rrc{rrc:02}");
+                    }
+                    active
+                })
+                .map(source::ripe_bview_url)
+                .collect();
+            for url in url {
+                download::authorize_url(url);
+            }
+            urls.extend(url.iter().cloned());
+            if rrc.is_empty() && url.is_empty() {
+                urls.push(source::ripe_bview_url(source::DEFAULT_RRC));
+            }
+            let mut seen = HashSet::new();
+            urls.retain(|url| seen.insert(url.clone()));
+            let threads = threads.map_or_else(
+                || thread::available_parallelism().map_or(1, NonZeroUsize::get),
+                usize::from,
+            );
+            let warmed = cache::warm(&urls, threads, Duration::from_secs(*verify_cache_seconds));
+            cache::render_warm(&warmed, *format)?;
+            let failed = warmed
+                .iter()
+                .filter(|warmed| warmed.error.is_some())
+                .count();
+            if failed > 0 {
+                return Err(format!("Could not warm {failed} of {} sources", warmed.len()).into());
+            }
+        }
+        Commands::NetblockContains { needle, haystack } => {
+            let needle_net: IpNet = IpNet::from_str(needle)?;
+            let haystack_net: IpNet = IpNet::from_str(haystack)?;
+            if haystack_net.contains(&needle_net.addr()) {
+                println!("{haystack} contains {needle}");
+            } else {
+                println!("{haystack} does not contain {needle}");
+            }
+        }
+    }
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Cli {
-    #[clap(subcommand)]
-    command: Commands,
+    Ok(())
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Find netblocks based on provided parameters
-    FindNetblocks {
-        #[arg(required = true, index = 1, value_delimiter = ',')]
-        origin_asns: Vec<u32>,
+/// Combines the ASNs given as arguments with those read from an ASN file and those of the
+/// organizations matching --org.
+fn resolve_asns(
+    asns: &AsnArgs,
+    verify_cache_interval: Duration,
+    strict: bool,
+) -> Result<HashSet<u32>, Box<dyn Error>> {
+    let mut merged: HashSet<u32> = asns.origin_asns.iter().copied().collect();
+    if let Some(asn_file) = &asns.asn_file {
+        merged.extend(asn::read_asn_file(asn_file, strict)?);
+    }
+    if let Some(org) = &asns.org {
+        let org_asns: Vec<u32> = match &asns.as2org {
+            Some(as2org) => as2org::search_org_asns(as2org, org, verify_cache_interval)?
+                .into_keys()
+                .collect(),
+            None => peeringdb::search_org_networks(org, verify_cache_interval)?
+                .iter()
+                .map(|network| network.asn)
+                .collect(),
+        };
+        if org_asns.is_empty() {
+            return Err(format!("No ASNs found for organization {org}").into());
+        }
+        info!("Organization {org} resolved to {} ASNs", org_asns.len());
+        merged.extend(org_asns);
+    }
+    if merged.is_empty() {
+        return Err("No ASNs given".into());
+    }
+    Ok(merged)
+}
 
-        /// MRT file, conflicts with specifying RIPE RRC or URL
-        #[clap(short = 'f', long, conflicts_with = "rrc", conflicts_with = "url")]
-        mrt_file: Option<String>,
+/// An MRT file with its origin table already loaded, shared by the queries of a batch
+#[derive(Debug)]
+struct LoadedMrt {
+    mrt_file: String,
+    table: table::OriginTable,
+}
 
-        /// Specify RIPE RRC server number (00-25) [default: 01], conflicts with specifying URL or MRT file directly
-        #[clap(short = 'r', long, conflicts_with = "url", conflicts_with = "mrt_file", value_parser = clap::value_parser!(u8).range(0..=25))]
-        rrc: Option<u8>,
+/// Prefixes found by a netblock query
+#[derive(Debug)]
+struct Netblocks {
+    /// Results after exclusions, aggregation, splitting and sorting
+    prefixes: Vec<IpNet>,
+    /// Origins of the announced prefixes, unless dropped by --low-memory
+    origins: Option<scan::PrefixOrigins>,
+    /// Representative route attributes of the announced prefixes, with --with-attributes
+    attributes: Option<attributes::PrefixAttributes>,
+    /// Why the lookup stopped early, leaving the results partial
+    stopped: Option<scan::StopReason>,
+}
 
-        /// Specify an entire URL, conflicts with specifying RRC or MRT file directly
-        #[clap(long, conflicts_with = "rrc", conflicts_with = "mrt_file")]
-        url: Option<String>,
+fn find_netblocks(
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+    loaded: Option<&LoadedMrt>,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let netblocks = lookup_netblocks(origin_asns, args, loaded)?;
+    publish_netblocks(origin_asns, args, &netblocks, output)
+}
 
-        /// Exclude specified subnets from results
-        #[clap(long, value_delimiter = ',')]
-        exclude_subnets: Option<Vec<String>>,
+/// Answers a netblock query once, or keeps answering it with --watch or --refresh-cron.
+fn run_netblocks(origin_asns: &HashSet<u32>, args: &NetblockArgs) -> Result<(), Box<dyn Error>> {
+    match (args.watch, &args.refresh_cron) {
+        (Some(seconds), _) => watch::run(
+            origin_asns,
+            args,
+            &watch::Trigger::Interval(Duration::from_secs(seconds)),
+        ),
+        (None, Some(schedule)) => {
+            // Every scheduled run checks the source, rather than the cache deciding when to
+            let mut args = args.clone();
+            args.source.verify_cache_seconds = 0;
+            watch::run(origin_asns, &args, &watch::Trigger::Cron(schedule.clone()))
+        }
+        (None, None) => find_netblocks(origin_asns, args, None, &mut io::stdout()),
+    }
+}
 
-        /// Output as JSON objects
-        #[clap(long)]
-        json: bool,
+/// Looks up the prefixes of a query and shapes them into its results.
+fn lookup_netblocks(
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+    loaded: Option<&LoadedMrt>,
+) -> Result<Netblocks, Box<dyn Error>> {
+    let excluded_subnets = transform_subnets_ipnet(&args.exclude_subnets);
 
-        /// Output IP addresses as ranges
-        #[clap(long, default_value_t = false)]
-        ip_ranges: bool,
+    if args.with_attributes.is_some() && !matches!(args.format, Format::Json | Format::Csv) {
+        return Err("--with-attributes needs --format json or csv".into());
+    }
+    if args.ip_ranges
+        && args.plugin.is_none()
+        && (args.template.is_some() || !args.format.supports_ranges())
+    {
+        return Err("--ip-ranges needs --format text, json, csv or table".into());
+    }
+    let selects_peers =
+        !args.peer_asn.is_empty() || !args.peer_ip.is_empty() || args.full_feed_only;
+    let mrt_file = match (args.backend, loaded) {
+        (Backend::Ripestat, _) if selects_peers => {
+            return Err("--peer-asn, --peer-ip and --full-feed-only select peers of MRT collectors, they need --backend mrt".into());
+        }
+        (Backend::Ripestat, _) if args.exclusive || !args.exclude_origin_asns.is_empty() => {
+            return Err("--exclusive and --exclude-origin-asns need every origin of the prefixes, they need --backend mrt".into());
+        }
+        (Backend::Ripestat, _) if args.with_attributes.is_some() => {
+            return Err(
+                "--with-attributes reads the routes of an MRT file, it needs --backend mrt".into(),
+            );
+        }
+        (Backend::Mrt, Some(loaded)) => Some(loaded.mrt_file.clone()),
+        (Backend::Mrt, None) => Some(source::resolve_mrt(&args.source)?),
+        (Backend::Ripestat, _) => None,
+    };
+    // Partial results are never cached, nor answered from complete cached ones, and low memory
+    // lookups do not keep the origins the cache holds
+    let cache_key = if args.no_result_cache || args.low_memory || scan_limits(args).is_limited() {
+        None
+    } else {
+        Some(result_cache::QueryKey {
+            source: match &mrt_file {
+                Some(mrt_file) => result_cache::mrt_source(mrt_file)?,
+                None => "ripestat".to_string(),
+            },
+            origin_asns: origin_asns.iter().copied().collect(),
+            ipv4_only: args.filters.ipv4_only,
+            ipv6_only: args.filters.ipv6_only,
+            peer_asns: args.peer_asn.iter().copied().collect(),
+            peer_ips: args.peer_ip.iter().copied().collect(),
+            full_feed_threshold: args.full_feed_only.then_some(args.full_feed_threshold),
+            rpki_filter: match rpki_filter(args) {
+                Some(validity) => Some((
+                    format!("{validity:?}"),
+                    args.rpki_roas.clone(),
+                    rpki::roa_digest(
+                        &args.rpki_roas,
+                        Duration::from_secs(args.source.verify_cache_seconds),
+                    )?,
+                )),
+                None => None,
+            },
+            exclusive: args.exclusive,
+            excluded_origins: args.exclude_origin_asns.iter().copied().collect(),
+        })
+    };
+    let cached = cache_key
+        .as_ref()
+        .and_then(|key| result_cache::load(key, Duration::from_secs(args.result_cache_seconds)));
+    let peers = peer_filter(args, mrt_file.as_deref())?;
+    let (prefix_origins, stopped) = match cached {
+        Some(prefix_origins) => (Some(prefix_origins), None),
+        None if args.low_memory => (None, None),
+        None => {
+            let table = loaded.map(|loaded| &loaded.table);
+            let (prefix_origins, stopped) =
+                lookup_prefix_origins(mrt_file.as_deref(), table, origin_asns, &peers, args)?;
+            if let Some(key) = &cache_key {
+                result_cache::store(key, &prefix_origins);
+            }
+            (Some(prefix_origins), stopped)
+        }
+    };
 
-        /// Verification interval for cache, in seconds
-        #[clap(long, default_value_t = 86400)]
-        verify_cache_seconds: u64,
+    let prefixes: Vec<IpNet> = match &prefix_origins {
+        Some(prefix_origins) => prefix_origins.keys().copied().collect(),
+        None => streamed_prefixes(mrt_file.as_deref(), loaded, origin_asns, &peers, args)?,
+    };
+    let attributes = match (&args.with_attributes, &mrt_file) {
+        (Some(fields), Some(mrt_file)) => Some(attributes::collect(
+            mrt_file,
+            &prefixes.iter().copied().collect(),
+            &peers,
+            if fields.is_empty() {
+                attributes::Field::ALL.to_vec()
+            } else {
+                fields.clone()
+            },
+        )?),
+        _ => None,
+    };
+    let prefixes_len = prefixes.len();
+    if let Some(stopped) = stopped {
+        warn!("Lookup {stopped}, the {prefixes_len} prefixes found are partial results");
+    }
+    if args.totals {
+        info!("Before exclusions: {}", format_address_space(&prefixes));
+    }
 
-        #[clap(flatten)]
-        filters: Filters,
-    },
-    /// Check if one netblock contains another
-    NetblockContains {
-        /// The netblock to search for
-        #[clap(value_parser)]
-        needle: String,
+    let filtered_prefixes = match &excluded_subnets {
+        Some(excluded) => exclude_subnets(&prefixes, excluded.clone())?,
+        None => prefixes,
+    };
+    trace!("Filtered prefixes after excluded subnets:\n{filtered_prefixes:#?}");
+    debug!(
+        "Prefixes before excluded subnet filtering: {} After: {}",
+        prefixes_len,
+        filtered_prefixes.len()
+    );
 
-        /// The netblock to check containment
-        #[clap(value_parser)]
-        haystack: String,
-    },
+    if args.totals {
+        info!(
+            "After exclusions: {}",
+            format_address_space(&filtered_prefixes)
+        );
+    }
+
+    Ok(Netblocks {
+        prefixes: shape_prefixes(filtered_prefixes, args)?,
+        origins: prefix_origins,
+        attributes,
+        stopped,
+    })
 }
 
-#[derive(Parser, Debug)]
-struct Filters {
-    /// Filter by IPv4 only
-    #[clap(short = '4', long, conflicts_with("ipv6_only"))]
-    ipv4_only: bool,
+/// Writes the results of a query in the requested format, or publishes them to the requested
+/// destinations.
+fn publish_netblocks(
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+    netblocks: &Netblocks,
+    output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let aggregated_prefixes = &netblocks.prefixes;
+    let prefix_origins = &netblocks.origins;
+    let stopped = netblocks.stopped;
+    let verify_cache_interval = Duration::from_secs(args.source.verify_cache_seconds);
 
-    /// Filter by IPv6 only
-    #[clap(short = '6', long, conflicts_with("ipv4_only"))]
-    ipv6_only: bool,
-}
+    if args.count {
+        return render_count(
+            output,
+            aggregated_prefixes,
+            args.count_addresses,
+            stopped.is_some(),
+            args.format,
+        );
+    }
 
-fn prefix_to_range(prefix: &IpNet) -> String {
-    format!("{}-{}", prefix.network(), prefix.broadcast())
-}
+    let networks = if args.peeringdb {
+        let mut asns: Vec<u32> = origin_asns.iter().copied().collect();
+        asns.sort_unstable();
+        Some(peeringdb::fetch_networks(&asns, verify_cache_interval)?)
+    } else {
+        None
+    };
 
-fn transform_subnets_string(subnets: &[IpNet], ranges: bool) -> Vec<String> {
-    let mut result = Vec::new();
-    for subnet in subnets {
-        if ranges {
-            result.push(prefix_to_range(subnet));
+    let delegations = if args.rir_annotate {
+        Some(rir::fetch_delegations(verify_cache_interval)?)
+    } else {
+        None
+    };
+
+    let provenance = if args.provenance {
+        let mut provenance = provenance::Provenance::new(
+            describe_source(args)?,
+            provenance_filters(args, origin_asns),
+        );
+        provenance.partial = stopped.map(|stopped| format!("lookup {stopped}"));
+        Some(provenance)
+    } else {
+        None
+    };
+    let options = RenderOptions {
+        ranges: args.ip_ranges,
+        networks: networks.as_deref(),
+        delegations: delegations.as_deref(),
+        origins: prefix_origins.as_ref(),
+        attributes: netblocks.attributes.as_ref(),
+        rpz_action: args.rpz_action,
+        zone_serial: args.zone_serial,
+        acl_name: &args.acl_name,
+        namespace: &args.namespace,
+        direction: args.direction,
+        gcp_network: &args.gcp_network,
+        flowspec_rate_limit: args.flowspec_rate_limit,
+        rtbh_platform: args.rtbh_platform,
+        rtbh_tag: args.rtbh_tag,
+        exabgp_next_hop: &args.exabgp_next_hop,
+        exabgp_communities: &args.exabgp_community,
+        provenance: provenance.as_ref(),
+    };
+
+    if args.group_by_asn {
+        let excluded_subnets = transform_subnets_ipnet(&args.exclude_subnets);
+        let mut asns: Vec<u32> = origin_asns.iter().copied().collect();
+        asns.sort_unstable();
+        let mut groups = Vec::with_capacity(asns.len());
+        for asn in asns {
+            let prefixes: Vec<IpNet> = prefix_origins
+                .iter()
+                .flatten()
+                .filter(|(_, origins)| origins.contains(&asn))
+                .map(|(prefix, _)| *prefix)
+                .collect();
+            let filtered_prefixes = match &excluded_subnets {
+                Some(excluded) => exclude_subnets(&prefixes, excluded.clone())?,
+                None => prefixes,
+            };
+            groups.push((asn, shape_prefixes(filtered_prefixes, args)?));
+        }
+        return render_grouped(output, &groups, args.format, &options);
+    }
+
+    // Compared before --apply updates the set, so the changes it makes are shown
+    if let Some(existing) = &args.diff_against {
+        let diff = diff::diff(existing, aggregated_prefixes)?;
+        diff::render_diff(output, &diff, args.format, args.ip_ranges)?;
+    }
+    if let (Some(redis_url), Some(redis_key)) = (&args.output_redis, &args.redis_key) {
+        let members: Vec<String> = aggregated_prefixes
+            .iter()
+            .map(|prefix| render::format_prefix(prefix, args.ip_ranges))
+            .collect();
+        redis::replace_set(redis_url, redis_key, &members)?;
+    }
+    if let (Some(PushTarget::AwsWaf), Some(id), Some(name)) =
+        (args.push, &args.ipset_id, &args.ipset_name)
+    {
+        let target = aws::IpSetTarget {
+            id,
+            name,
+            scope: args.ipset_scope,
+            region: args.aws_region.as_deref(),
+        };
+        aws::update_ip_set(&target, aggregated_prefixes)?;
+    }
+    if let (Some(PushTarget::Gobgp), Some(grpc)) = (args.push, &args.grpc) {
+        let target = gobgp::GobgpTarget {
+            grpc,
+            next_hop: args.gobgp_next_hop.as_deref(),
+            communities: &args.gobgp_community,
+        };
+        gobgp::update_rib(&target, aggregated_prefixes)?;
+    }
+    if let (Some(kind), Some(name)) = (args.apply, &args.set_name) {
+        let target = firewall::SetTarget {
+            kind,
+            name,
+            nft_table: &args.nft_table,
+        };
+        firewall::apply(&target, aggregated_prefixes, args.apply_dry_run)?;
+    }
+    // Published or compared results are only printed when also written to files
+    if (args.output_redis.is_some()
+        || args.push.is_some()
+        || args.apply.is_some()
+        || args.diff_against.is_some())
+        && args.output_v4.is_none()
+        && args.output_v6.is_none()
+    {
+        return Ok(());
+    }
+
+    let signing_key = match &args.sign_key {
+        Some(_) if args.output_v4.is_none() && args.output_v6.is_none() => {
+            return Err("--sign-key signs output files, give --output-v4 or --output-v6".into());
+        }
+        Some(_)
+            if [&args.output_v4, &args.output_v6]
+                .into_iter()
+                .flatten()
+                .any(|output| storage::is_object_url(output)) =>
+        {
+            return Err("--sign-key cannot sign files uploaded to s3:// or gs://".into());
+        }
+        Some(sign_key) => Some(sign::SigningKey::load(sign_key)?),
+        None => None,
+    };
+    let renderer: Box<dyn Renderer> = match (&args.template, &args.plugin) {
+        (Some(template), _) => Box::new(template::TemplateRenderer {
+            template: template::Template::from_file(template)?,
+        }),
+        (None, Some(plugin)) => plugin::find(plugin)?,
+        (None, None) => args.format.renderer(),
+    };
+    if args.output_v4.is_none() && args.output_v6.is_none() {
+        return renderer.render(output, aggregated_prefixes, &options);
+    }
+
+    let (v4_prefixes, v6_prefixes): (Vec<IpNet>, Vec<IpNet>) = aggregated_prefixes
+        .iter()
+        .partition(|prefix| matches!(prefix, IpNet::V4(_)));
+    let mut stdout_prefixes = Vec::new();
+    for (output_file, prefixes) in [
+        (&args.output_v4, v4_prefixes),
+        (&args.output_v6, v6_prefixes),
+    ] {
+        let Some(output_file) = output_file else {
+            stdout_prefixes.extend(prefixes);
+            continue;
+        };
+        debug!("Writing {} prefixes to {output_file}", prefixes.len());
+        let mut writer = output::Output::create(output_file)?;
+        // Each destination may use its own format, selected by its file extension
+        if output::uncompressed_name(output_file).ends_with(".json") {
+            Format::Json
+                .renderer()
+                .render(&mut writer, &prefixes, &options)?;
         } else {
-            result.push(subnet.to_string());
+            renderer.render(&mut writer, &prefixes, &options)?;
+        }
+        writer.finish()?;
+        if let Some(signing_key) = &signing_key {
+            let output_file = output::output_name(output_file);
+            let mut asns: Vec<u32> = origin_asns.iter().copied().collect();
+            asns.sort_unstable();
+            let comment = sign::trusted_comment(&output_file, &describe_source(args)?, &asns);
+            let signature_file = signing_key.sign_file(&output_file, &comment)?;
+            info!("Wrote signature {signature_file}");
         }
     }
-    result
+    if !stdout_prefixes.is_empty() {
+        renderer.render(output, &stdout_prefixes, &options)?;
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    init_logger();
-    let cli = Cli::parse();
+/// Describes where the prefixes of a query come from, for signatures and provenance metadata.
+fn describe_source(args: &NetblockArgs) -> Result<String, Box<dyn Error>> {
+    Ok(match args.backend {
+        Backend::Ripestat => "RIPEstat announced-prefixes".to_string(),
+        Backend::Mrt => match source::mrt_url(&args.source)? {
+            Some(url) => url,
+            None => args.source.mrt_file.clone().unwrap_or_default(),
+        },
+    })
+}
+
+/// Lists every setting that affects which prefixes a query outputs, for its provenance.
+fn provenance_filters(args: &NetblockArgs, origin_asns: &HashSet<u32>) -> BTreeMap<String, String> {
+    let mut asns: Vec<u32> = origin_asns.iter().copied().collect();
+    asns.sort_unstable();
+    let asns: Vec<String> = asns.iter().map(|asn| format!("AS{asn}")).collect();
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let mut filters = BTreeMap::new();
+    for (name, value) in [
+        ("origins", asns.join(",")),
+        ("backend", format!("{:?}", args.backend).to_lowercase()),
+        ("ipv4-only", args.filters.ipv4_only.to_string()),
+        ("ipv6-only", args.filters.ipv6_only.to_string()),
+        (
+            "peer-asns",
+            optional(
+                Some(
+                    args.peer_asn
+                        .iter()
+                        .map(|asn| format!("AS{asn}"))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )
+                .filter(|asns| !asns.is_empty()),
+            ),
+        ),
+        (
+            "peer-ips",
+            optional(
+                Some(
+                    args.peer_ip
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )
+                .filter(|ips| !ips.is_empty()),
+            ),
+        ),
+        (
+            "full-feed-only",
+            optional(
+                args.full_feed_only
+                    .then(|| format!("{}%", args.full_feed_threshold)),
+            ),
+        ),
+        (
+            "exclude-subnets",
+            optional(
+                args.exclude_subnets
+                    .as_ref()
+                    .map(|subnets| subnets.join(",")),
+            ),
+        ),
+        (
+            "rpki",
+            optional(rpki_filter(args).map(|validity| format!("{validity:?}").to_lowercase())),
+        ),
+        ("rpki-roas", args.rpki_roas.clone()),
+        ("exclusive", args.exclusive.to_string()),
+        (
+            "exclude-origin-asns",
+            optional(
+                Some(
+                    args.exclude_origin_asns
+                        .iter()
+                        .map(|asn| format!("AS{asn}"))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                )
+                .filter(|asns| !asns.is_empty()),
+            ),
+        ),
+        ("minimize", args.minimize.to_string()),
+        ("no-aggregate", args.no_aggregate.to_string()),
+        (
+            "split-to",
+            optional(args.split_to.map(|len| format!("/{len}"))),
+        ),
+        (
+            "split-to-v6",
+            optional(args.split_to_v6.map(|len| format!("/{len}"))),
+        ),
+        (
+            "max-prefixes",
+            optional(args.max_prefixes.map(|max| max.to_string())),
+        ),
+        (
+            "scan-timeout",
+            optional(args.scan_timeout.map(|seconds| format!("{seconds}s"))),
+        ),
+        ("sort", format!("{:?}", args.sort).to_lowercase()),
+        ("descending", args.descending.to_string()),
+    ] {
+        filters.insert(name.to_string(), value);
+    }
+    filters
+}
+
+/// Returns the RPKI validity the results are restricted to, if any.
+fn rpki_filter(args: &NetblockArgs) -> Option<rpki::Validity> {
+    if args.only_invalid {
+        Some(rpki::Validity::Invalid)
+    } else if args.only_unknown {
+        Some(rpki::Validity::Unknown)
+    } else {
+        None
+    }
+}
+
+/// Returns the collector peers the results are restricted to, empty for every peer. With
+/// --full-feed-only these are the selected peers, or all of them, that send full tables.
+fn peer_filter(
+    args: &NetblockArgs,
+    mrt_file: Option<&str>,
+) -> Result<scan::PeerFilter, Box<dyn Error>> {
+    let selected = scan::PeerFilter {
+        asns: args.peer_asn.iter().copied().collect(),
+        ips: args.peer_ip.iter().copied().collect(),
+        feeds: HashSet::new(),
+    };
+    let (true, Some(mrt_file)) = (args.full_feed_only, mrt_file) else {
+        return Ok(selected);
+    };
+    let peer_feeds = feeds::peer_feeds(mrt_file)?;
+    let full: HashSet<(IpAddr, bool)> = feeds::full_feeds(&peer_feeds, args.full_feed_threshold)
+        .into_iter()
+        .filter(|(feed, _)| {
+            selected.is_empty()
+                || selected.asns.contains(&feed.asn)
+                || selected.ips.contains(&feed.ip)
+        })
+        .map(|(feed, is_v6)| (feed.ip, is_v6))
+        .collect();
+    if full.is_empty() {
+        return Err(format!(
+            "None of the selected collector peers of {mrt_file} sends a full table"
+        )
+        .into());
+    }
+    Ok(scan::PeerFilter {
+        feeds: full,
+        ..scan::PeerFilter::default()
+    })
+}
+
+fn scan_limits(args: &NetblockArgs) -> scan::ScanLimits {
+    scan::ScanLimits {
+        max_prefixes: args
+            .max_prefixes
+            .map(|max| usize::try_from(max).unwrap_or(usize::MAX)),
+        timeout: args.scan_timeout.map(Duration::from_secs),
+    }
+}
 
-    match &cli.command {
-        Commands::FindNetblocks {
+/// Looks up the prefixes originated by the target ASNs in the selected backend, or in an already
+/// loaded origin table of the MRT file, keeping only those matching the RPKI filter. Origin tables
+/// hold the routes of every peer, so lookups restricted to some peers scan the MRT file. Returns
+/// why the lookup stopped early when a scan limit cut it short. --max-prefixes counts the
+/// prefixes left after filtering, so filtered lookups scan on and are cut afterwards.
+fn lookup_prefix_origins(
+    mrt_file: Option<&str>,
+    table: Option<&table::OriginTable>,
+    origin_asns: &HashSet<u32>,
+    peers: &scan::PeerFilter,
+    args: &NetblockArgs,
+) -> Result<(scan::PrefixOrigins, Option<scan::StopReason>), Box<dyn Error>> {
+    let verify_cache_interval = Duration::from_secs(args.source.verify_cache_seconds);
+    let limits = scan_limits(args);
+    let rpki_wanted = rpki_filter(args);
+    let filters_origins = args.exclusive || !args.exclude_origin_asns.is_empty();
+    let scan_limits = if rpki_wanted.is_some() || filters_origins {
+        scan::ScanLimits {
+            max_prefixes: None,
+            ..limits
+        }
+    } else {
+        limits
+    };
+    let (mut prefix_origins, mut stopped) = match (mrt_file, table) {
+        (Some(mrt_file), _) if !peers.is_empty() => scan::scan_prefixes_limited(
+            mrt::open(mrt_file)?,
             origin_asns,
-            mrt_file,
-            json,
-            exclude_subnets,
-            ip_ranges,
-            verify_cache_seconds,
-            filters,
-            rrc,
-            url,
-        } => {
-            let origin_asns = origin_asns.iter().copied().collect();
-            let excluded_subnets = transform_subnets_ipnet(exclude_subnets);
+            args.filters.ipv4_only,
+            args.filters.ipv6_only,
+            peers,
+            scan_limits,
+        )?,
+        (Some(_), Some(table)) => (
+            index::origin_prefixes(
+                table,
+                origin_asns,
+                args.filters.ipv4_only,
+                args.filters.ipv6_only,
+            ),
+            None,
+        ),
+        (Some(mrt_file), None) if scan_limits.is_limited() => {
+            limited_mrt_prefixes(mrt_file, origin_asns, args, scan_limits)?
+        }
+        (Some(mrt_file), None) => (
+            mrt_prefixes(
+                mrt_file,
+                args.source.no_index,
+                origin_asns,
+                args.filters.ipv4_only,
+                args.filters.ipv6_only,
+            )?,
+            None,
+        ),
+        (None, _) => (
+            ripestat::announced_prefixes(
+                origin_asns,
+                args.filters.ipv4_only,
+                args.filters.ipv6_only,
+                verify_cache_interval,
+            )?,
+            None,
+        ),
+    };
 
-            let mrt_file_path = if let Some(file) = mrt_file {
-                file.clone()
-            } else {
-                let download_url = match (url, rrc) {
-                    (Some(u), _) => u.clone(),
-                    (None, rrc) => format!(
-                        "https://data.ris.ripe.net/rrc{:02}/latest-bview.gz",
-                        rrc.unwrap_or(1)
-                    ),
-                };
+    if filters_origins {
+        if let Some(mrt_file) = mrt_file {
+            filter_other_origins(&mut prefix_origins, mrt_file, table, origin_asns, args)?;
+        }
+    }
 
-                let mut hasher = DefaultHasher::new();
-                download_url.hash(&mut hasher);
-                let hash = hasher.finish();
+    if let Some(wanted) = rpki_wanted {
+        let roas = rpki::load_roas(&args.rpki_roas, verify_cache_interval)?;
+        let before_len = prefix_origins.len();
+        prefix_origins.retain(|prefix, origins| {
+            origins
+                .iter()
+                .any(|origin| roas.validate(prefix, *origin) == wanted)
+        });
+        debug!(
+            "Prefixes before RPKI {wanted:?} filtering: {before_len} After: {}",
+            prefix_origins.len()
+        );
+    }
 
-                fs::create_dir_all(".cache")?;
-                let output_file_gzip = format!(".cache/{hash:x}-latest-bview.gz");
-                let output_file_mrt = format!(".cache/{hash:x}-latest-bview.mrt");
-                let verify_cache_interval = Duration::from_secs(*verify_cache_seconds);
+    // Lookups that did not scan, such as from an index, or were filtered after scanning are cut
+    // to the lowest prefixes instead
+    if let Some(max) = limits.max_prefixes {
+        if prefix_origins.len() > max {
+            let mut prefixes: Vec<IpNet> = prefix_origins.keys().copied().collect();
+            prefixes.sort_unstable();
+            for prefix in &prefixes[max..] {
+                prefix_origins.remove(prefix);
+            }
+            stopped = Some(scan::StopReason::MaxPrefixes(max));
+        }
+    }
+    Ok((prefix_origins, stopped))
+}
 
-                debug!("Using {download_url} for MRT source");
-                download::cached_gzip(
-                    &download_url,
-                    &output_file_gzip,
-                    &output_file_mrt,
-                    verify_cache_interval,
-                )?
+/// Maps addresses to their announced prefix and origins with the chosen backend.
+fn map_ips(
+    ips: &[IpAddr],
+    backend: MapBackend,
+    source: &MrtSource,
+) -> Result<Vec<ipmap::IpMapping>, Box<dyn Error>> {
+    match backend {
+        MapBackend::Cymru => cymru::map_ips(ips),
+        MapBackend::Mrt => {
+            let mrt_file = source::resolve_mrt(source)?;
+            let table = if source.no_index {
+                table::scan_origins(&mrt_file, false, false)?
+            } else {
+                index::load_or_build(&mrt_file)?
             };
+            Ok(ipmap::map_ips(ips, table))
+        }
+    }
+}
 
-            let mrt_file = File::open(mrt_file_path)?;
-            let prefixes = scan_prefixes(
-                &mrt_file,
-                &origin_asns,
-                filters.ipv4_only,
-                filters.ipv6_only,
-            )?;
-            let prefixes_len = prefixes.len();
-
-            let filtered_prefixes = match excluded_subnets {
-                Some(excluded) => crate::exclude_subnets(&prefixes, excluded)?,
-                None => prefixes,
+/// Drops the prefixes other ASNs than the target ones originate as well with --exclusive, and
+/// those the ASNs of --exclude-origin-asns originate too. Lookups restricted to some peers only
+/// see the origins of their routes, so every origin is taken from the origin table of the MRT
+/// file.
+fn filter_other_origins(
+    prefix_origins: &mut scan::PrefixOrigins,
+    mrt_file: &str,
+    table: Option<&table::OriginTable>,
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+) -> Result<(), Box<dyn Error>> {
+    let loaded;
+    let table = match table {
+        Some(table) => table,
+        None => {
+            loaded = if args.source.no_index {
+                table::scan_origins(mrt_file, args.filters.ipv4_only, args.filters.ipv6_only)?
+            } else {
+                index::load_or_build(mrt_file)?
             };
-            trace!("Filtered prefixes after excluded subnets:\n{filtered_prefixes:#?}");
-            debug!(
-                "Prefixes before excluded subnet filtering: {} After: {}",
-                prefixes_len,
-                filtered_prefixes.len()
-            );
+            &loaded
+        }
+    };
+    let excluded: HashSet<u32> = args.exclude_origin_asns.iter().copied().collect();
+    let before_len = prefix_origins.len();
+    prefix_origins.retain(|prefix, _| {
+        table.get(prefix).is_some_and(|origins| {
+            origins.iter().all(|origin| {
+                !excluded.contains(origin) && (!args.exclusive || origin_asns.contains(origin))
+            })
+        })
+    });
+    debug!(
+        "Prefixes before dropping those with other origins: {before_len} After: {}",
+        prefix_origins.len()
+    );
+    Ok(())
+}
 
-            let aggregated_prefixes = IpNet::aggregate(&filtered_prefixes);
+/// Aggregates the prefixes originated by the target ASNs as they are read from the index or the
+/// MRT file, without collecting them or their origins first. No index is built, as that would
+/// need the whole table in memory.
+fn streamed_prefixes(
+    mrt_file: Option<&str>,
+    loaded: Option<&LoadedMrt>,
+    origin_asns: &HashSet<u32>,
+    peers: &scan::PeerFilter,
+    args: &NetblockArgs,
+) -> Result<Vec<IpNet>, Box<dyn Error>> {
+    let (ipv4_only, ipv6_only) = (args.filters.ipv4_only, args.filters.ipv6_only);
+    let wanted = |prefix: &IpNet, origins: &BTreeSet<u32>| {
+        let wanted_family = match prefix {
+            IpNet::V4(_) => !ipv6_only,
+            IpNet::V6(_) => !ipv4_only,
+        };
+        wanted_family && origins.iter().any(|origin| origin_asns.contains(origin))
+    };
+    let mut aggregator = aggregate::StreamingAggregator::default();
+    match (mrt_file, loaded) {
+        (Some(mrt_file), _) if !peers.is_empty() => {
+            scan::scan_matches(
+                mrt::open(mrt_file)?,
+                origin_asns,
+                ipv4_only,
+                ipv6_only,
+                peers,
+                &mut |prefix, _| aggregator.insert(prefix),
+            )?;
+        }
+        (Some(_), Some(loaded)) => {
+            for (prefix, origins) in &loaded.table {
+                if wanted(prefix, origins) {
+                    aggregator.insert(*prefix);
+                }
+            }
+        }
+        (Some(mrt_file), None) => {
+            let indexed = !args.source.no_index
+                && index::visit_index(mrt_file, &mut |prefix, origins| {
+                    if wanted(&prefix, &origins) {
+                        aggregator.insert(prefix);
+                    }
+                })?;
+            if !indexed {
+                scan::scan_matches(
+                    mrt::open(mrt_file)?,
+                    origin_asns,
+                    ipv4_only,
+                    ipv6_only,
+                    &scan::PeerFilter::default(),
+                    &mut |prefix, _| aggregator.insert(prefix),
+                )?;
+            }
+        }
+        (None, _) => {
+            let verify_cache_interval = Duration::from_secs(args.source.verify_cache_seconds);
+            let announced = ripestat::announced_prefixes(
+                origin_asns,
+                ipv4_only,
+                ipv6_only,
+                verify_cache_interval,
+            )?;
+            for prefix in announced.into_keys() {
+                aggregator.insert(prefix);
+            }
+        }
+    }
+    let inserted = aggregator.inserted();
+    let prefixes = aggregator.into_prefixes();
+    debug!(
+        "Aggregated {inserted} streamed prefixes into {}",
+        prefixes.len()
+    );
+    Ok(prefixes)
+}
 
-            trace!("Aggregated prefixes:\n{aggregated_prefixes:#?}");
-            debug!(
-                "Prefixes before aggregation: {} After: {}",
-                filtered_prefixes.len(),
-                aggregated_prefixes.len()
-            );
+/// Finds the prefixes announced by the origin ASNs in an MRT file within the scan limits. An
+/// existing index answers right away, but none is built, as a limited scan does not see the
+/// whole file.
+fn limited_mrt_prefixes(
+    mrt_file: &str,
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+    limits: scan::ScanLimits,
+) -> Result<(scan::PrefixOrigins, Option<scan::StopReason>), Box<dyn Error>> {
+    let (ipv4_only, ipv6_only) = (args.filters.ipv4_only, args.filters.ipv6_only);
+    if !args.source.no_index {
+        if let Some(table) = index::read_index(mrt_file)? {
+            let prefix_origins = index::origin_prefixes(&table, origin_asns, ipv4_only, ipv6_only);
+            return Ok((prefix_origins, None));
+        }
+    }
+    scan::scan_prefixes_limited(
+        mrt::open(mrt_file)?,
+        origin_asns,
+        ipv4_only,
+        ipv6_only,
+        &scan::PeerFilter::default(),
+        limits,
+    )
+}
 
-            render_output(&aggregated_prefixes, *json, *ip_ranges)?;
+/// Writes the results of each origin ASN, as a commented section per ASN in text format or as
+/// an array of per-ASN objects in JSON.
+fn render_grouped(
+    output: &mut dyn Write,
+    groups: &[(u32, Vec<IpNet>)],
+    format: Format,
+    options: &RenderOptions<'_>,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Text => {
+            if let Some(networks) = options.networks {
+                peeringdb::render_header(output, networks)?;
+            }
+            if let Some(provenance) = options.provenance {
+                provenance.write_comments(output, "#")?;
+            }
+            let section_options = RenderOptions {
+                networks: None,
+                provenance: None,
+                ..*options
+            };
+            for (asn, prefixes) in groups {
+                writeln!(output, "# AS{asn}")?;
+                render::TextRenderer.render(output, prefixes, &section_options)?;
+            }
+            Ok(())
         }
-        Commands::NetblockContains { needle, haystack } => {
-            let needle_net: IpNet = IpNet::from_str(needle)?;
-            let haystack_net: IpNet = IpNet::from_str(haystack)?;
-            if haystack_net.contains(&needle_net.addr()) {
-                println!("{haystack} contains {needle}");
-            } else {
-                println!("{haystack} does not contain {needle}");
+        Format::Json => {
+            let mut payload = Vec::with_capacity(groups.len());
+            for (asn, prefixes) in groups {
+                payload.push(serde_json::json!({
+                    "asn": asn,
+                    "prefixes": render::prefixes_value(prefixes, options)?,
+                }));
             }
+            render::write_json_document(output, serde_json::Value::Array(payload), options)
         }
+        _ => Err(format!("--group-by-asn does not support the {format:?} format").into()),
     }
-
-    Ok(())
 }
 
-fn render_output(prefixes: &[IpNet], json: bool, ranges: bool) -> Result<(), Box<dyn Error>> {
-    let mut output = io::stdout();
-    let prefix_strings = transform_subnets_string(prefixes, ranges);
-    if json {
-        serde_json::to_writer(&mut output, &prefix_strings)?;
+/// Applies the minimizing, aggregation, splitting and sorting options to the filtered prefixes.
+fn shape_prefixes(
+    filtered_prefixes: Vec<IpNet>,
+    args: &NetblockArgs,
+) -> Result<Vec<IpNet>, Box<dyn Error>> {
+    let filtered_prefixes = if args.minimize {
+        let minimized = minimize_prefixes(&filtered_prefixes);
+        debug!(
+            "Prefixes before minimizing: {} After: {}",
+            filtered_prefixes.len(),
+            minimized.len()
+        );
+        minimized
+    } else {
+        filtered_prefixes
+    };
+
+    let mut aggregated_prefixes = if args.no_aggregate {
+        debug!("Skipping aggregation");
+        filtered_prefixes.clone()
     } else {
-        for prefix in prefix_strings {
-            println!("{prefix}");
+        let _span = info_span!("aggregate", prefixes = filtered_prefixes.len()).entered();
+        IpNet::aggregate(&filtered_prefixes)
+    };
+
+    trace!("Aggregated prefixes:\n{aggregated_prefixes:#?}");
+    debug!(
+        "Prefixes before aggregation: {} After: {}",
+        filtered_prefixes.len(),
+        aggregated_prefixes.len()
+    );
+
+    if args.split_to.is_some() || args.split_to_v6.is_some() {
+        aggregated_prefixes =
+            split_prefixes(&aggregated_prefixes, args.split_to, args.split_to_v6)?;
+        debug!(
+            "Prefixes after splitting to a fixed length: {}",
+            aggregated_prefixes.len()
+        );
+    }
+
+    sort_prefixes(&mut aggregated_prefixes, args.sort, args.descending);
+    telemetry::add_prefixes_found(aggregated_prefixes.len());
+    Ok(aggregated_prefixes)
+}
+
+/// Describes the address space covered by a set of possibly overlapping prefixes as the number
+/// of IPv4 addresses and IPv6 /64 subnets.
+fn format_address_space(prefixes: &[IpNet]) -> String {
+    let (ipv4_addresses, ipv6_subnets) = render::address_space(prefixes);
+    format!("{ipv4_addresses} IPv4 addresses, {ipv6_subnets} IPv6 /64s")
+}
+
+/// Prints the number of prefixes, flagged when the results are partial.
+fn render_count(
+    output: &mut dyn Write,
+    prefixes: &[IpNet],
+    addresses: bool,
+    partial: bool,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let mut ipv4_addresses: u128 = 0;
+    let mut ipv6_addresses: u128 = 0;
+    for prefix in prefixes {
+        match prefix {
+            IpNet::V4(_) => ipv4_addresses = ipv4_addresses.saturating_add(address_count(prefix)),
+            IpNet::V6(_) => ipv6_addresses = ipv6_addresses.saturating_add(address_count(prefix)),
+        }
+    }
+
+    if format == Format::Json {
+        let mut count = serde_json::json!({ "prefixes": prefixes.len() });
+        if addresses {
+            count["ipv4_addresses"] = serde_json::json!(ipv4_addresses);
+            count["ipv6_addresses"] = serde_json::json!(ipv6_addresses.to_string());
+        }
+        if partial {
+            count["partial"] = serde_json::json!(true);
         }
+        writeln!(output, "{count}")?;
+        return Ok(());
+    }
+    let flag = if partial { " (partial)" } else { "" };
+    if addresses {
+        writeln!(
+            output,
+            "{} prefixes, {ipv4_addresses} IPv4 addresses, {ipv6_addresses} IPv6 addresses{flag}",
+            prefixes.len()
+        )?;
+    } else {
+        writeln!(output, "{}{flag}", prefixes.len())?;
     }
     Ok(())
 }
@@ -224,84 +2969,32 @@ fn transform_subnets_ipnet(opts: &Option<Vec<String>>) -> Option<Vec<IpNet>> {
     }
 }
 
-fn scan_prefixes(
-    file: &File,
+/// Finds the prefixes announced by the origin ASNs in an MRT file, using its prefix index unless
+/// disabled.
+fn mrt_prefixes(
+    mrt_file_path: &str,
+    no_index: bool,
     origin_asns: &HashSet<u32>,
     ipv4_only: bool,
     ipv6_only: bool,
-) -> Result<Vec<IpNet>, Box<dyn Error>> {
-    let mut reader = BufReader::new(file);
-    let mut parser = BgpkitParser::from_reader(&mut reader);
-
-    match (ipv4_only, ipv6_only) {
-        (true, false) => {
-            debug!("Filtering for only IPv4");
-            parser = parser
-                .add_filter("ip_version", "ipv4")
-                .expect("Failed to add IPv4 filter");
-        }
-        (false, true) => {
-            debug!("Filtering for only IPv6");
-            parser = parser
-                .add_filter("ip_version", "ipv6")
-                .expect("Failed to add IPv6 filter");
-        }
-        _ => {}
-    }
-
-    debug!("Filtering for only announce records");
-    parser = parser.add_filter("type", "announce")?;
-
-    let before = instant::Instant::now();
-
-    debug!(
-        "Scanning MRT file for prefixes associated with AS numbers {:?}...",
-        origin_asns
-    );
-    let mut prefixes = HashSet::new();
-
-    if origin_asns.len() == 1 {
-        // There's only one AS number, use bgpkit-parser native filter as it's faster
-        debug!("Using native filtering for origin AS");
-        parser = parser.add_filter("origin_asn", "53429")?;
-        for elem in parser.into_elem_iter() {
-            if prefixes.insert(elem.prefix.prefix) {
-                debug!("Found new matching prefix {}", elem.prefix.prefix);
-            }
-        }
-    } else {
-        // Since bgpkit-parser doesn't support filtering on more than one origin, filter manually
-        debug!("Using standard filtering for origin AS");
-        for elem in parser.into_elem_iter() {
-            if let Some(elem_origin_asns) = &elem.origin_asns {
-                if elem_origin_asns
-                    .iter()
-                    .any(|asn| origin_asns.contains(&asn.to_u32()))
-                    && prefixes.insert(elem.prefix.prefix)
-                {
-                    trace!("Found new matching prefix {}", elem.prefix.prefix);
-                }
-            }
-        }
+) -> Result<HashMap<IpNet, HashSet<u32>>, Box<dyn Error>> {
+    if no_index {
+        return scan::scan_prefixes(mrt::open(mrt_file_path)?, origin_asns, ipv4_only, ipv6_only);
     }
-
-    let after = instant::Instant::now();
-
-    #[allow(clippy::cast_precision_loss)]
-    let elapsed_seconds = ((after - before).as_millis() as f64) / 1000.0;
-
-    debug!(
-        "Finished scanning MRT file after {} seconds",
-        elapsed_seconds
-    );
-
-    Ok(prefixes.iter().copied().collect())
+    let table = index::load_or_build(mrt_file_path)?;
+    Ok(index::origin_prefixes(
+        &table,
+        origin_asns,
+        ipv4_only,
+        ipv6_only,
+    ))
 }
 
 fn exclude_subnets(
     prefixes: &[IpNet],
     excluded_subnets: Vec<IpNet>,
 ) -> Result<Vec<IpNet>, Box<dyn Error>> {
+    let _span = info_span!("exclude", subnets = excluded_subnets.len()).entered();
     let mut result = Vec::new();
     let excluded_set: HashSet<IpNet> = excluded_subnets.into_iter().collect();
 
@@ -340,12 +3033,15 @@ fn exclude_subnets(
     Ok(result)
 }
 
-#[cfg(feature = "diagnostic_logging")]
-fn init_logger() {
-    env_logger::init();
-}
-
-#[cfg(not(feature = "diagnostic_logging"))]
-fn init_logger() {
-    // No-op when diagnostic logging is not enabled
+/// Configures logging on stderr so stdout stays machine-parseable. Without verbosity flags the
+/// `RUST_LOG` environment variable is honored, defaulting to warnings only.
+fn init_logger(verbose: u8, quiet: bool, format: logging::LogFormat) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::OFF,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    logging::init(level, !quiet && verbose == 0, format);
 }
@@ -0,0 +1,294 @@
+use ipnet::IpNet;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+
+use crate::notify::{self, Notification, Sink};
+use crate::render::{self, ReportFormat};
+use crate::systemd;
+use crate::table::OriginTable;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const RIS_LIVE_STREAM_URL: &str =
+    "https://ris-live.ripe.net/v1/stream/?format=json&client=bgp-scout";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    /// A monitored prefix announced by an unexpected origin
+    Origin,
+    /// A more-specific of a monitored prefix announced by an unexpected origin
+    MoreSpecific,
+    /// A more-specific of a monitored prefix announced by an expected origin, which may still be
+    /// a leak or a misconfiguration
+    ExpectedMoreSpecific,
+}
+
+impl AlertKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Origin => "origin",
+            Self::MoreSpecific => "more-specific",
+            Self::ExpectedMoreSpecific => "expected-more-specific",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub prefix: IpNet,
+    pub monitored_prefix: IpNet,
+    pub origin_asn: u32,
+    /// Collector and peer that reported the announcement, for live alerts
+    pub seen_by: Option<String>,
+}
+
+/// Prefixes to watch and the origins allowed to announce them
+#[derive(Debug)]
+pub struct Monitor {
+    pub prefixes: Vec<IpNet>,
+    pub expected_origins: HashSet<u32>,
+    /// Longest more-specific the expected origins may announce without an alert, defaulting to
+    /// the length of the monitored prefix itself
+    pub max_length: Option<u8>,
+}
+
+impl Monitor {
+    /// Checks an announcement of a prefix, returning an alert for every unexpected origin when
+    /// the prefix is a monitored prefix or one of its more-specifics, and for expected origins
+    /// announcing more-specifics beyond the allowed length.
+    pub fn check(&self, prefix: IpNet, origins: &[u32], seen_by: Option<&str>) -> Vec<Alert> {
+        let prefix = prefix.trunc();
+        // Attribute the announcement to the most specific monitored prefix covering it
+        let Some(monitored_prefix) = self
+            .prefixes
+            .iter()
+            .filter(|monitored| monitored.contains(&prefix))
+            .max_by_key(|monitored| monitored.prefix_len())
+            .copied()
+        else {
+            return Vec::new();
+        };
+        let max_length = self
+            .max_length
+            .unwrap_or_else(|| monitored_prefix.prefix_len());
+        origins
+            .iter()
+            .filter_map(|&origin_asn| {
+                let expected = self.expected_origins.contains(&origin_asn);
+                let kind = match (prefix == monitored_prefix, expected) {
+                    (true, false) => AlertKind::Origin,
+                    (false, false) => AlertKind::MoreSpecific,
+                    (false, true) if prefix.prefix_len() > max_length => {
+                        AlertKind::ExpectedMoreSpecific
+                    }
+                    // Expected origins announcing the prefix or an allowed more-specific
+                    _ => return None,
+                };
+                Some((kind, origin_asn))
+            })
+            .map(|(kind, origin_asn)| Alert {
+                kind,
+                prefix,
+                monitored_prefix,
+                origin_asn,
+                seen_by: seen_by.map(str::to_string),
+            })
+            .collect()
+    }
+
+    /// Checks every prefix of a RIB.
+    pub fn check_table(&self, table: &OriginTable) -> Vec<Alert> {
+        table
+            .iter()
+            .flat_map(|(prefix, origins)| {
+                let origins: Vec<u32> = origins.iter().copied().collect();
+                self.check(*prefix, &origins, None)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RisLiveMessage {
+    #[serde(rename = "type")]
+    message_type: String,
+    data: Option<RisLiveUpdate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RisLiveUpdate {
+    host: Option<String>,
+    peer: Option<String>,
+    #[serde(default)]
+    path: Vec<serde_json::Value>,
+    #[serde(default)]
+    announcements: Vec<RisLiveAnnouncement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RisLiveAnnouncement {
+    #[serde(default)]
+    prefixes: Vec<String>,
+}
+
+/// Returns the origins of a RIS Live path, whose last element is an ASN or an AS_SET.
+fn path_origins(path: &[serde_json::Value]) -> Vec<u32> {
+    match path.last() {
+        Some(serde_json::Value::Array(set)) => set
+            .iter()
+            .filter_map(|asn| asn.as_u64())
+            .filter_map(|asn| u32::try_from(asn).ok())
+            .collect(),
+        Some(asn) => asn
+            .as_u64()
+            .and_then(|asn| u32::try_from(asn).ok())
+            .into_iter()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Sends the alerts to the notification sinks as one summary.
+pub fn notify_alerts(sinks: &[Sink], alerts: &[Alert]) {
+    if sinks.is_empty() || alerts.is_empty() {
+        return;
+    }
+    let notification = Notification {
+        subject: format!("{} alerts for monitored prefixes", alerts.len()),
+        lines: alerts.iter().map(alert_line).collect(),
+    };
+    notify::send(sinks, &notification);
+}
+
+/// Follows the RIS Live stream of updates from every collector, printing each alert as it occurs
+/// until the stream ends. JSON alerts are written one object per line.
+///
+/// Alerts are also sent to the notification sinks, the first time each prefix is seen announced
+/// by each unexpected origin, as every peer of every collector reports the same announcement.
+pub fn follow_ris_live(
+    monitor: &Monitor,
+    format: ReportFormat,
+    sinks: &[Sink],
+) -> Result<(), Box<dyn Error>> {
+    let mut notified: HashSet<(AlertKind, IpNet, u32)> = HashSet::new();
+    info!("Following the RIS Live stream");
+    // The stream never completes, so only the connection attempt is bounded
+    let response = Client::builder()
+        .timeout(None)
+        .build()?
+        .get(RIS_LIVE_STREAM_URL)
+        .send()?
+        .error_for_status()?;
+    systemd::ready();
+    systemd::status("Following the RIS Live stream");
+    let mut lines = BufReader::new(response).lines();
+    loop {
+        // A stream that stops delivering updates is as good as down
+        let busy = systemd::busy();
+        let Some(line) = lines.next() else {
+            break;
+        };
+        drop(busy);
+        let line = line?;
+        let message: RisLiveMessage = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("Skipping undecodable RIS Live message: {e}");
+                continue;
+            }
+        };
+        let Some(update) = message
+            .data
+            .filter(|_| message.message_type == "ris_message")
+        else {
+            continue;
+        };
+        let origins = path_origins(&update.path);
+        let seen_by = format!(
+            "{} {}",
+            update.host.as_deref().unwrap_or("-"),
+            update.peer.as_deref().unwrap_or("-")
+        );
+        for prefix in update
+            .announcements
+            .iter()
+            .flat_map(|announcement| &announcement.prefixes)
+        {
+            let Ok(prefix) = IpNet::from_str(prefix) else {
+                continue;
+            };
+            for alert in monitor.check(prefix, &origins, Some(&seen_by)) {
+                if format == ReportFormat::Json {
+                    println!("{}", serde_json::to_string(&alert)?);
+                } else {
+                    println!("{}", alert_line(&alert));
+                }
+                if !sinks.is_empty()
+                    && notified.insert((alert.kind, alert.prefix, alert.origin_asn))
+                {
+                    let notification = Notification {
+                        subject: format!(
+                            "{} alert for monitored prefix {}",
+                            alert.kind.as_str(),
+                            alert.monitored_prefix
+                        ),
+                        lines: vec![alert_line(&alert)],
+                    };
+                    notify::send(sinks, &notification);
+                }
+            }
+        }
+    }
+    Err("The RIS Live stream ended".into())
+}
+
+/// Formats an alert as a single line, used for text output and streamed alerts.
+fn alert_line(alert: &Alert) -> String {
+    let mut line = format!(
+        "{} {} (monitoring {}) announced by AS{}",
+        alert.kind.as_str(),
+        alert.prefix,
+        alert.monitored_prefix,
+        alert.origin_asn
+    );
+    if let Some(seen_by) = &alert.seen_by {
+        line.push_str(&format!(", seen by {seen_by}"));
+    }
+    line
+}
+
+pub fn render_alerts(alerts: &[Alert], format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), alerts)?,
+        ReportFormat::Text => {
+            for alert in alerts {
+                println!("{}", alert_line(alert));
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = alerts
+                .iter()
+                .map(|alert| {
+                    vec![
+                        alert.kind.as_str().to_string(),
+                        alert.prefix.to_string(),
+                        alert.monitored_prefix.to_string(),
+                        format!("AS{}", alert.origin_asn),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &["KIND", "PREFIX", "MONITORED", "ORIGIN"],
+                &rows,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,802 @@
+use bgpkit_parser::models::{
+    Bgp4MpEnum, ElemType, MrtMessage, Peer, PeerIndexTable, TableDumpV2Message,
+};
+use bgpkit_parser::{parse_mrt_record, BgpkitParser, Elementor, ParserError};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::render::{self, ReportFormat};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(Debug, Serialize)]
+pub struct PeerInfo {
+    pub asn: u32,
+    pub ip: IpAddr,
+    pub bgp_id: Ipv4Addr,
+}
+
+/// Summary of the contents of an MRT file
+#[derive(Debug, Default, Serialize)]
+pub struct MrtInfo {
+    pub file: String,
+    pub records: u64,
+    pub undecodable_records: u64,
+    /// Number of records per MRT entry type
+    pub record_types: BTreeMap<String, u64>,
+    pub first_timestamp: Option<String>,
+    pub last_timestamp: Option<String>,
+    pub collector_bgp_id: Option<Ipv4Addr>,
+    pub view_name: Option<String>,
+    pub peers: Vec<PeerInfo>,
+    pub ipv4_prefixes: u64,
+    pub ipv6_prefixes: u64,
+    /// RIB entries of table dumps, or announcements of updates files
+    pub ipv4_routes: u64,
+    pub ipv6_routes: u64,
+    pub ipv4_withdrawals: u64,
+    pub ipv6_withdrawals: u64,
+}
+
+fn format_timestamp(timestamp: u32) -> Option<String> {
+    DateTime::from_timestamp(i64::from(timestamp), 0).map(|time| time.to_rfc3339())
+}
+
+/// Walks every record of an MRT file, counting record types, prefixes and routes per address
+/// family and collecting the peer index table of table dumps.
+pub fn inspect(file_name: &str) -> Result<MrtInfo, Box<dyn Error>> {
+    let mut parser = BgpkitParser::from_reader(open(file_name)?);
+    let mut info = MrtInfo {
+        file: file_name.to_string(),
+        ..MrtInfo::default()
+    };
+    let mut first_timestamp = None;
+    let mut last_timestamp = None;
+    let mut ipv4_prefixes = HashSet::new();
+    let mut ipv6_prefixes = HashSet::new();
+    let mut elementor = Elementor::new();
+
+    loop {
+        let record = match parser.next_record() {
+            Ok(record) => record,
+            Err(e) => match e.error {
+                ParserError::EofExpected => break,
+                ParserError::IoError(err) | ParserError::EofError(err) => {
+                    warn!(
+                        "Stopped reading {file_name} after {} records: {err}",
+                        info.records
+                    );
+                    info.undecodable_records += 1;
+                    break;
+                }
+                err => {
+                    debug!("Undecodable record in {file_name}: {err}");
+                    info.undecodable_records += 1;
+                    continue;
+                }
+            },
+        };
+
+        info.records += 1;
+        let timestamp = record.common_header.timestamp;
+        first_timestamp =
+            Some(first_timestamp.map_or(timestamp, |first: u32| first.min(timestamp)));
+        last_timestamp = Some(last_timestamp.map_or(timestamp, |last: u32| last.max(timestamp)));
+        *info
+            .record_types
+            .entry(format!("{:?}", record.common_header.entry_type))
+            .or_default() += 1;
+
+        match &record.message {
+            MrtMessage::TableDumpV2Message(TableDumpV2Message::PeerIndexTable(table)) => {
+                info.collector_bgp_id = Some(table.collector_bgp_id);
+                info.view_name = Some(table.view_name.clone()).filter(|name| !name.is_empty());
+                let mut peers: Vec<(&u16, _)> = table.id_peer_map.iter().collect();
+                peers.sort_by_key(|(index, _)| **index);
+                info.peers = peers
+                    .into_iter()
+                    .map(|(_, peer)| PeerInfo {
+                        asn: peer.peer_asn.to_u32(),
+                        ip: peer.peer_address,
+                        bgp_id: peer.peer_bgp_id,
+                    })
+                    .collect();
+            }
+            MrtMessage::TableDumpV2Message(TableDumpV2Message::RibAfi(entries)) => {
+                let routes = entries.rib_entries.len() as u64;
+                match entries.prefix.prefix {
+                    IpNet::V4(_) => {
+                        ipv4_prefixes.insert(entries.prefix.prefix);
+                        info.ipv4_routes += routes;
+                    }
+                    IpNet::V6(_) => {
+                        ipv6_prefixes.insert(entries.prefix.prefix);
+                        info.ipv6_routes += routes;
+                    }
+                }
+            }
+            MrtMessage::Bgp4Mp(Bgp4MpEnum::Message(_)) => {
+                for elem in elementor.record_to_elems(record) {
+                    let prefix = elem.prefix.prefix;
+                    match (prefix, elem.elem_type) {
+                        (IpNet::V4(_), ElemType::ANNOUNCE) => {
+                            ipv4_prefixes.insert(prefix);
+                            info.ipv4_routes += 1;
+                        }
+                        (IpNet::V6(_), ElemType::ANNOUNCE) => {
+                            ipv6_prefixes.insert(prefix);
+                            info.ipv6_routes += 1;
+                        }
+                        (IpNet::V4(_), ElemType::WITHDRAW) => info.ipv4_withdrawals += 1,
+                        (IpNet::V6(_), ElemType::WITHDRAW) => info.ipv6_withdrawals += 1,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    info.first_timestamp = first_timestamp.and_then(format_timestamp);
+    info.last_timestamp = last_timestamp.and_then(format_timestamp);
+    info.ipv4_prefixes = ipv4_prefixes.len() as u64;
+    info.ipv6_prefixes = ipv6_prefixes.len() as u64;
+    Ok(info)
+}
+
+pub fn render_info(info: &MrtInfo, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    if format == ReportFormat::Json {
+        serde_json::to_writer(io::stdout(), info)?;
+        return Ok(());
+    }
+
+    let record_types: Vec<String> = info
+        .record_types
+        .iter()
+        .map(|(record_type, count)| format!("{record_type}: {count}"))
+        .collect();
+    println!("file: {}", info.file);
+    println!("records: {} ({})", info.records, record_types.join(", "));
+    println!("undecodable records: {}", info.undecodable_records);
+    println!(
+        "time range: {} - {}",
+        info.first_timestamp.as_deref().unwrap_or("-"),
+        info.last_timestamp.as_deref().unwrap_or("-")
+    );
+    if let Some(collector_bgp_id) = info.collector_bgp_id {
+        println!(
+            "collector: {collector_bgp_id} (view: {})",
+            info.view_name.as_deref().unwrap_or("-")
+        );
+    }
+    println!(
+        "ipv4: {} prefixes, {} routes, {} withdrawals",
+        info.ipv4_prefixes, info.ipv4_routes, info.ipv4_withdrawals
+    );
+    println!(
+        "ipv6: {} prefixes, {} routes, {} withdrawals",
+        info.ipv6_prefixes, info.ipv6_routes, info.ipv6_withdrawals
+    );
+    if !info.peers.is_empty() {
+        println!("peers: {}", info.peers.len());
+        let rows: Vec<Vec<String>> = info
+            .peers
+            .iter()
+            .map(|peer| {
+                vec![
+                    format!("AS{}", peer.asn),
+                    peer.ip.to_string(),
+                    peer.bgp_id.to_string(),
+                ]
+            })
+            .collect();
+        render::write_table(&mut io::stdout(), &["ASN", "IP", "BGP ID"], &rows)?;
+    }
+    Ok(())
+}
+
+/// Length of the MRT common header preceding every record body
+const MRT_HEADER_LEN: usize = 12;
+
+/// A record that could not be read or decoded
+#[derive(Debug, Serialize)]
+pub struct RecordProblem {
+    /// Byte offset of the record header, within the decompressed stream for gzipped files
+    pub offset: u64,
+    pub problem: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub file: String,
+    pub records: u64,
+    /// Records of types the parser does not support, which are not counted as corruption
+    pub unsupported_records: u64,
+    pub problems: Vec<RecordProblem>,
+}
+
+/// Fills the buffer as far as possible, returning the number of bytes read before the end of the
+/// input.
+fn read_fully(reader: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Reads the next record of an MRT file, common header included, without decoding it. Returns
+/// None at the end of the file.
+pub fn read_record(reader: &mut dyn Read) -> Result<Option<Vec<u8>>, String> {
+    let mut header = [0_u8; MRT_HEADER_LEN];
+    match read_fully(reader, &mut header).map_err(|e| format!("read error: {e}"))? {
+        0 => return Ok(None),
+        MRT_HEADER_LEN => {}
+        len => return Err(format!("truncated header of {len} bytes")),
+    }
+    let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let mut record = header.to_vec();
+    record.resize(MRT_HEADER_LEN + body_len, 0);
+    let read = read_fully(reader, &mut record[MRT_HEADER_LEN..])
+        .map_err(|e| format!("read error: {e}"))?;
+    if read < body_len {
+        return Err(format!(
+            "truncated record, expected {body_len} bytes but found {read}"
+        ));
+    }
+    Ok(Some(record))
+}
+
+/// Whether a record read by [`read_record`] is a TABLE_DUMP_V2 peer index table.
+pub fn is_peer_table(record: &[u8]) -> bool {
+    record.get(4..8) == Some(&[0, 13, 0, 1])
+}
+
+/// Opens an MRT file for reading, decompressing it when its name ends in .gz.
+pub fn open(file_name: &str) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    let file = BufReader::new(File::open(file_name)?);
+    Ok(if file_name.ends_with(".gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    })
+}
+
+/// Returns the time of the first record of an MRT file, which for a table dump is the time of
+/// the RIB snapshot. Empty files have none.
+pub fn dump_time(file_name: &str) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+    let mut reader = open(file_name)?;
+    let mut timestamp = [0; 4];
+    if read_fully(&mut reader, &mut timestamp)? < timestamp.len() {
+        return Ok(None);
+    }
+    Ok(DateTime::from_timestamp(
+        i64::from(u32::from_be_bytes(timestamp)),
+        0,
+    ))
+}
+
+/// Walks every record of an MRT file, framing records by their common header so that truncated
+/// and undecodable records can be reported with their offsets.
+pub fn validate(file_name: &str) -> Result<ValidationReport, Box<dyn Error>> {
+    let mut reader = open(file_name)?;
+    let mut report = ValidationReport {
+        file: file_name.to_string(),
+        records: 0,
+        unsupported_records: 0,
+        problems: Vec::new(),
+    };
+
+    let mut offset: u64 = 0;
+    loop {
+        let mut header = [0_u8; MRT_HEADER_LEN];
+        let header_len = match read_fully(&mut reader, &mut header) {
+            Ok(len) => len,
+            Err(e) => {
+                report.problems.push(RecordProblem {
+                    offset,
+                    problem: format!("read error: {e}"),
+                });
+                break;
+            }
+        };
+        if header_len == 0 {
+            break;
+        }
+        if header_len < MRT_HEADER_LEN {
+            report.problems.push(RecordProblem {
+                offset,
+                problem: format!("truncated header of {header_len} bytes"),
+            });
+            break;
+        }
+
+        let body_len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let mut record = header.to_vec();
+        record.resize(MRT_HEADER_LEN + body_len as usize, 0);
+        let read = match read_fully(&mut reader, &mut record[MRT_HEADER_LEN..]) {
+            Ok(read) => read,
+            Err(e) => {
+                report.problems.push(RecordProblem {
+                    offset,
+                    problem: format!("read error: {e}"),
+                });
+                break;
+            }
+        };
+        if read < body_len as usize {
+            report.problems.push(RecordProblem {
+                offset,
+                problem: format!("truncated record, expected {body_len} bytes but found {read}"),
+            });
+            break;
+        }
+
+        report.records += 1;
+        match parse_mrt_record(&mut Cursor::new(&record)) {
+            Ok(_) => {}
+            Err(e) => match e.error {
+                ParserError::Unsupported(message) => {
+                    trace!("Unsupported record at offset {offset}: {message}");
+                    report.unsupported_records += 1;
+                }
+                error => report.problems.push(RecordProblem {
+                    offset,
+                    problem: format!("undecodable record: {error}"),
+                }),
+            },
+        }
+        offset += record.len() as u64;
+    }
+
+    debug!(
+        "Validated {} records of {file_name}, found {} problems",
+        report.records,
+        report.problems.len()
+    );
+    Ok(report)
+}
+
+pub fn render_validation(
+    report: &ValidationReport,
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), report)?,
+        ReportFormat::Text => {
+            for problem in &report.problems {
+                println!("offset {}: {}", problem.offset, problem.problem);
+            }
+            println!(
+                "{}: {} records, {} unsupported, {} problems",
+                report.file,
+                report.records,
+                report.unsupported_records,
+                report.problems.len()
+            );
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = report
+                .problems
+                .iter()
+                .map(|problem| vec![problem.offset.to_string(), problem.problem.clone()])
+                .collect();
+            render::write_table(&mut io::stdout(), &["OFFSET", "PROBLEM"], &rows)?;
+        }
+    }
+    Ok(())
+}
+
+/// MRT type and subtypes of BGP4MP messages, from RFC 6396
+const BGP4MP: u16 = 16;
+const BGP4MP_MESSAGE: u16 = 1;
+const BGP4MP_MESSAGE_AS4: u16 = 4;
+
+/// Writes a BGP message received from a peer as a BGP4MP record, using 4-byte ASNs when the
+/// session negotiated them.
+pub fn write_bgp4mp_message(
+    output: &mut dyn Write,
+    timestamp: u32,
+    peer: (u32, IpAddr),
+    local: (u32, IpAddr),
+    as4: bool,
+    message: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let (peer_asn, peer_ip) = peer;
+    let (local_asn, local_ip) = local;
+    let mut body = Vec::with_capacity(message.len() + 44);
+    if as4 {
+        body.extend_from_slice(&peer_asn.to_be_bytes());
+        body.extend_from_slice(&local_asn.to_be_bytes());
+    } else {
+        body.extend_from_slice(&u16::try_from(peer_asn)?.to_be_bytes());
+        body.extend_from_slice(&u16::try_from(local_asn)?.to_be_bytes());
+    }
+    // Interface index, unused
+    body.extend_from_slice(&0_u16.to_be_bytes());
+    match (peer_ip, local_ip) {
+        (IpAddr::V4(peer_ip), IpAddr::V4(local_ip)) => {
+            body.extend_from_slice(&1_u16.to_be_bytes());
+            body.extend_from_slice(&peer_ip.octets());
+            body.extend_from_slice(&local_ip.octets());
+        }
+        (IpAddr::V6(peer_ip), IpAddr::V6(local_ip)) => {
+            body.extend_from_slice(&2_u16.to_be_bytes());
+            body.extend_from_slice(&peer_ip.octets());
+            body.extend_from_slice(&local_ip.octets());
+        }
+        _ => return Err("Peer and local addresses must belong to the same family".into()),
+    }
+    body.extend_from_slice(message);
+
+    let subtype = if as4 {
+        BGP4MP_MESSAGE_AS4
+    } else {
+        BGP4MP_MESSAGE
+    };
+    output.write_all(&timestamp.to_be_bytes())?;
+    output.write_all(&BGP4MP.to_be_bytes())?;
+    output.write_all(&subtype.to_be_bytes())?;
+    output.write_all(&u32::try_from(body.len())?.to_be_bytes())?;
+    output.write_all(&body)?;
+    Ok(())
+}
+
+/// Reads a big-endian u16 of a record, failing on records too short to hold it.
+fn record_u16(record: &[u8], offset: usize) -> Result<u16, String> {
+    record
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| "truncated RIB entries".to_string())
+}
+
+/// Returns the time of a record read by [`read_record`], with the microseconds of extended
+/// timestamp types, for ordering records.
+fn record_time(record: &[u8]) -> (u32, u32) {
+    let seconds = u32::from_be_bytes([record[0], record[1], record[2], record[3]]);
+    let entry_type = u16::from_be_bytes([record[4], record[5]]);
+    // BGP4MP_ET, ISIS_ET and OSPFv3_ET carry microseconds ahead of the message
+    let microseconds = match (entry_type, record.get(MRT_HEADER_LEN..MRT_HEADER_LEN + 4)) {
+        (17 | 33 | 49, Some(bytes)) => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        _ => 0,
+    };
+    (seconds, microseconds)
+}
+
+/// Rewrites the sequence number and the peer indexes of a TABLE_DUMP_V2 RIB record in place, to
+/// refer to the merged peer index table.
+fn remap_rib_record(
+    record: &mut [u8],
+    sequence: u32,
+    peer_indexes: &HashMap<u16, u16>,
+) -> Result<(), String> {
+    let subtype = record_u16(record, 6)?;
+    let add_path = match subtype {
+        2..=6 => false,
+        8..=11 => true,
+        _ => return Err(format!("TABLE_DUMP_V2 subtype {subtype} is not supported")),
+    };
+    record
+        .get_mut(MRT_HEADER_LEN..MRT_HEADER_LEN + 4)
+        .ok_or("truncated RIB entries")?
+        .copy_from_slice(&sequence.to_be_bytes());
+    // RIB_GENERIC has the AFI and SAFI ahead of the prefix
+    let mut offset = MRT_HEADER_LEN + 4 + if subtype == 6 { 3 } else { 0 };
+    let prefix_len = *record.get(offset).ok_or("truncated RIB entries")?;
+    offset += 1 + usize::from(prefix_len).div_ceil(8);
+    let entries = record_u16(record, offset)?;
+    offset += 2;
+    for _ in 0..entries {
+        let index = record_u16(record, offset)?;
+        let merged = peer_indexes
+            .get(&index)
+            .ok_or_else(|| format!("peer index {index} is not in the peer index table"))?;
+        record[offset..offset + 2].copy_from_slice(&merged.to_be_bytes());
+        // Peer index, originated time and, with ADD-PATH, the path identifier
+        offset += 6 + if add_path { 4 } else { 0 };
+        let attributes_len = record_u16(record, offset)?;
+        offset += 2 + usize::from(attributes_len);
+    }
+    Ok(())
+}
+
+/// An MRT file being merged, with the record to be written next
+struct MergeInput {
+    file_name: String,
+    reader: Box<dyn Read + Send>,
+    next: Option<Vec<u8>>,
+    /// Indexes of its peers in the merged peer index table
+    peer_indexes: HashMap<u16, u16>,
+}
+
+impl MergeInput {
+    fn advance(&mut self) -> Result<(), Box<dyn Error>> {
+        self.next = read_record(&mut self.reader)
+            .map_err(|e| format!("Could not read {}: {e}", self.file_name))?;
+        if self.next.as_deref().is_some_and(is_peer_table) {
+            return Err(format!(
+                "{} has more than one peer index table, which cannot be merged",
+                self.file_name
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Merges MRT files into one, writing their records in chronological order. The peer index
+/// tables of RIB dumps are merged into a single table written first, and the peer indexes of
+/// their RIB entries renumbered to match, so RIB dumps of several collectors become one. Records
+/// of the same time keep the order of the files. Returns the number of records written.
+pub fn merge(file_names: &[String], output: &mut dyn Write) -> Result<u64, Box<dyn Error>> {
+    let mut inputs = Vec::with_capacity(file_names.len());
+    let mut merged_peers: Vec<Peer> = Vec::new();
+    let mut merged_table: Option<(u32, PeerIndexTable)> = None;
+    for file_name in file_names {
+        let mut input = MergeInput {
+            file_name: file_name.clone(),
+            reader: open(file_name)?,
+            next: None,
+            peer_indexes: HashMap::new(),
+        };
+        let first = read_record(&mut input.reader)
+            .map_err(|e| format!("Could not read {file_name}: {e}"))?;
+        match first {
+            Some(record) if is_peer_table(&record) => {
+                let MrtMessage::TableDumpV2Message(TableDumpV2Message::PeerIndexTable(table)) =
+                    parse_mrt_record(&mut Cursor::new(&record))
+                        .map_err(|e| {
+                            format!("Undecodable peer index table in {file_name}: {}", e.error)
+                        })?
+                        .message
+                else {
+                    return Err(format!("Undecodable peer index table in {file_name}").into());
+                };
+                let mut peers: Vec<(&u16, &Peer)> = table.id_peer_map.iter().collect();
+                peers.sort_by_key(|(index, _)| **index);
+                for (index, peer) in peers {
+                    let merged_index = match merged_peers.iter().position(|merged| merged == peer) {
+                        Some(position) => position,
+                        None => {
+                            merged_peers.push(*peer);
+                            merged_peers.len() - 1
+                        }
+                    };
+                    let merged_index = u16::try_from(merged_index)
+                        .map_err(|_| "The merged peer index table has more than 65535 peers")?;
+                    input.peer_indexes.insert(*index, merged_index);
+                }
+                let timestamp = record_time(&record).0;
+                match &mut merged_table {
+                    Some((earliest, _)) => *earliest = (*earliest).min(timestamp),
+                    None => merged_table = Some((timestamp, table)),
+                }
+                input.advance()?;
+            }
+            first => input.next = first,
+        }
+        debug!(
+            "Merging {file_name} with {} peers",
+            input.peer_indexes.len()
+        );
+        inputs.push(input);
+    }
+
+    let mut written = 0;
+    if let Some((timestamp, mut table)) = merged_table {
+        // The collector and view of the first RIB dump name the merged one
+        table.id_peer_map = (0..=u16::MAX).zip(merged_peers.iter().copied()).collect();
+        table.peer_addr_id_map = table
+            .id_peer_map
+            .iter()
+            .map(|(index, peer)| (peer.peer_address, *index))
+            .collect();
+        let body = table.encode();
+        output.write_all(&timestamp.to_be_bytes())?;
+        output.write_all(&13_u16.to_be_bytes())?;
+        output.write_all(&1_u16.to_be_bytes())?;
+        output.write_all(&u32::try_from(body.len())?.to_be_bytes())?;
+        output.write_all(&body)?;
+        written += 1;
+        info!("Merged peer index table of {} peers", merged_peers.len());
+    }
+
+    let mut queue: BinaryHeap<Reverse<((u32, u32), usize)>> = inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(position, input)| {
+            Some(Reverse((record_time(input.next.as_ref()?), position)))
+        })
+        .collect();
+    let mut sequence: u32 = 0;
+    while let Some(Reverse((_, position))) = queue.pop() {
+        let input = &mut inputs[position];
+        let Some(mut record) = input.next.take() else {
+            continue;
+        };
+        if record.get(4..6) == Some(&[0, 13]) {
+            remap_rib_record(&mut record, sequence, &input.peer_indexes)
+                .map_err(|e| format!("Could not merge a record of {}: {e}", input.file_name))?;
+            sequence = sequence.wrapping_add(1);
+        }
+        output.write_all(&record)?;
+        written += 1;
+        input.advance()?;
+        if let Some(next) = &input.next {
+            queue.push(Reverse((record_time(next), position)));
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// ORIGIN IGP and an AS_PATH of AS64500, with the 4-byte ASNs of TABLE_DUMP_V2
+    const ATTRIBUTES: [u8; 13] = [0x40, 1, 1, 0, 0x40, 2, 6, 2, 1, 0, 0, 0xfb, 0xf4];
+
+    fn record(timestamp: u32, entry_type: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+        let mut record = timestamp.to_be_bytes().to_vec();
+        record.extend_from_slice(&entry_type.to_be_bytes());
+        record.extend_from_slice(&subtype.to_be_bytes());
+        record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        record.extend_from_slice(body);
+        record
+    }
+
+    /// A PEER_INDEX_TABLE record of IPv4 peers with 4-byte ASNs, using their addresses as BGP IDs.
+    fn peer_table(timestamp: u32, peers: &[(Ipv4Addr, u32)]) -> Vec<u8> {
+        let mut body = vec![192, 0, 2, 254, 0, 0];
+        body.extend_from_slice(&(peers.len() as u16).to_be_bytes());
+        for (address, asn) in peers {
+            body.push(0x02);
+            body.extend_from_slice(&address.octets());
+            body.extend_from_slice(&address.octets());
+            body.extend_from_slice(&asn.to_be_bytes());
+        }
+        record(timestamp, 13, 1, &body)
+    }
+
+    /// A RIB_IPV4_UNICAST record, or RIB_IPV4_UNICAST_ADDPATH with `add_path`, with an entry
+    /// per peer index.
+    fn rib_record(
+        timestamp: u32,
+        sequence: u32,
+        prefix: ([u8; 3], u8),
+        peer_indexes: &[u16],
+        add_path: bool,
+    ) -> Vec<u8> {
+        let mut body = sequence.to_be_bytes().to_vec();
+        body.push(prefix.1);
+        body.extend_from_slice(&prefix.0);
+        body.extend_from_slice(&(peer_indexes.len() as u16).to_be_bytes());
+        for (path_id, index) in peer_indexes.iter().enumerate() {
+            body.extend_from_slice(&index.to_be_bytes());
+            body.extend_from_slice(&timestamp.to_be_bytes());
+            if add_path {
+                body.extend_from_slice(&(path_id as u32 + 1).to_be_bytes());
+            }
+            body.extend_from_slice(&(ATTRIBUTES.len() as u16).to_be_bytes());
+            body.extend_from_slice(&ATTRIBUTES);
+        }
+        record(timestamp, 13, if add_path { 8 } else { 2 }, &body)
+    }
+
+    #[test]
+    fn remap_rib_records() -> Result<(), Box<dyn Error>> {
+        let peer_indexes = HashMap::from([(0, 2), (1, 0)]);
+        for add_path in [false, true] {
+            let mut record = rib_record(100, 0, ([192, 0, 2], 24), &[0, 1], add_path);
+            remap_rib_record(&mut record, 7, &peer_indexes)?;
+            assert_eq!(
+                record,
+                rib_record(100, 7, ([192, 0, 2], 24), &[2, 0], add_path)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn remap_invalid_rib_records() {
+        let peer_indexes = HashMap::from([(0, 0)]);
+        let mut unknown_peer = rib_record(100, 0, ([192, 0, 2], 24), &[0, 1], false);
+        assert!(remap_rib_record(&mut unknown_peer, 0, &peer_indexes).is_err());
+        let mut table = peer_table(100, &[(Ipv4Addr::new(192, 0, 2, 1), 64501)]);
+        assert!(remap_rib_record(&mut table, 0, &peer_indexes).is_err());
+        let mut truncated = rib_record(100, 0, ([192, 0, 2], 24), &[0], false);
+        truncated.truncate(truncated.len() - ATTRIBUTES.len() - 3);
+        assert!(remap_rib_record(&mut truncated, 0, &peer_indexes).is_err());
+    }
+
+    #[test]
+    fn merge_rib_dumps() -> Result<(), Box<dyn Error>> {
+        let peer_1 = (Ipv4Addr::new(10, 0, 0, 1), 64501);
+        let peer_2 = (Ipv4Addr::new(10, 0, 0, 2), 64502);
+        let peer_3 = (Ipv4Addr::new(10, 0, 0, 3), 64503);
+        let mut first = peer_table(100, &[peer_1, peer_2]);
+        first.extend(rib_record(100, 0, ([192, 0, 2], 24), &[0, 1], false));
+        first.extend(rib_record(300, 1, ([198, 51, 100], 24), &[1], false));
+        // The second dump lists the shared peer first
+        let mut second = peer_table(90, &[peer_2, peer_3]);
+        second.extend(rib_record(200, 0, ([203, 0, 113], 24), &[0, 1], false));
+
+        let dir = env::temp_dir().join(format!("bgp-scout-merge-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let file_names =
+            [dir.join("first.mrt"), dir.join("second.mrt")].map(|path| path.display().to_string());
+        fs::write(&file_names[0], first)?;
+        fs::write(&file_names[1], second)?;
+        let mut merged = Vec::new();
+        let written = merge(&file_names, &mut merged);
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(written?, 4);
+
+        let mut reader = Cursor::new(&merged);
+        let mut records = Vec::new();
+        while let Some(record) = read_record(&mut reader)? {
+            records.push(record);
+        }
+        assert!(is_peer_table(&records[0]));
+        assert_eq!(record_time(&records[0]), (90, 0));
+        // RIB records are renumbered in the order they are written
+        let sequences: Vec<u32> = records[1..]
+            .iter()
+            .map(|record| u32::from_be_bytes([record[12], record[13], record[14], record[15]]))
+            .collect();
+        assert_eq!(sequences, [0, 1, 2]);
+
+        let routes: Vec<(String, IpAddr, u32)> = BgpkitParser::from_reader(Cursor::new(merged))
+            .into_elem_iter()
+            .map(|elem| {
+                (
+                    elem.prefix.to_string(),
+                    elem.peer_ip,
+                    elem.peer_asn.to_u32(),
+                )
+            })
+            .collect();
+        let route = |prefix: &str, (address, asn): (Ipv4Addr, u32)| {
+            (prefix.to_string(), IpAddr::V4(address), asn)
+        };
+        assert_eq!(
+            routes,
+            [
+                route("192.0.2.0/24", peer_1),
+                route("192.0.2.0/24", peer_2),
+                route("203.0.113.0/24", peer_2),
+                route("203.0.113.0/24", peer_3),
+                route("198.51.100.0/24", peer_2),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_rejects_second_peer_table() -> Result<(), Box<dyn Error>> {
+        let peers = [(Ipv4Addr::new(10, 0, 0, 1), 64501)];
+        let mut dump = peer_table(100, &peers);
+        dump.extend(peer_table(100, &peers));
+        let dir = env::temp_dir().join(format!("bgp-scout-merge-twice-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let file_name = dir.join("dump.mrt").display().to_string();
+        fs::write(&file_name, dump)?;
+        let merged = merge(&[file_name], &mut Vec::new());
+        fs::remove_dir_all(&dir)?;
+        assert!(merged.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Returns the path of the netrc file: $NETRC, or .netrc in the home directory.
+fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+    Some(PathBuf::from(env::var_os("HOME")?).join(".netrc"))
+}
+
+/// Finds the login and password of the host in netrc text: the entry of its machine, or the
+/// default entry, which comes last.
+fn parse(text: &str, host: &str) -> Option<(String, String)> {
+    // Macro definitions run to the next blank line and hold no credentials
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for line in text.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                in_macro = true;
+                break;
+            }
+            tokens.push(token);
+        }
+    }
+
+    let mut matched = false;
+    let (mut login, mut password) = (None, None);
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" if matched => break,
+            "machine" => matched = tokens.next() == Some(host),
+            "default" => matched = true,
+            "login" if matched => login = tokens.next(),
+            "password" if matched => password = tokens.next(),
+            "login" | "password" | "account" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    if !matched {
+        return None;
+    }
+    Some((login?.to_string(), password?.to_string()))
+}
+
+/// Looks up the login and password netrc holds for the host, as curl --netrc-optional does.
+pub fn lookup(host: &str) -> Option<(String, String)> {
+    let path = netrc_path()?;
+    let text = fs::read_to_string(&path).ok()?;
+    let credentials = parse(&text, host)?;
+    debug!("Using the credentials {} holds for {host}", path.display());
+    Some(credentials)
+}
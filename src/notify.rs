@@ -0,0 +1,260 @@
+use base64::Engine;
+use chrono::Utc;
+use native_tls::TlsConnector;
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const DEFAULT_SMTP_PORT: u16 = 587;
+const DEFAULT_SMTPS_PORT: u16 = 465;
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps notifications about large changes readable; the full changes are in the output
+const MAX_LINES: usize = 50;
+
+/// Destination of change notifications
+#[derive(Debug, Clone)]
+pub enum Sink {
+    /// Slack incoming webhook URL
+    Slack(String),
+    Email(EmailSink),
+}
+
+/// SMTP relay and addresses of email notifications
+#[derive(Debug, Clone)]
+pub struct EmailSink {
+    host: String,
+    port: u16,
+    /// TLS from the start of the connection, rather than upgrading with STARTTLS
+    implicit_tls: bool,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
+}
+
+impl fmt::Display for Sink {
+    // Leaves out the webhook path and credentials, which are secrets
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Slack(_) => write!(f, "Slack webhook"),
+            Self::Email(email) => write!(f, "email via {}:{}", email.host, email.port),
+        }
+    }
+}
+
+/// Parses a sink given as `slack://hooks.slack.com/services/...` or
+/// `smtp[s]://[user:password@]host[:port]?from=<address>&to=<address>[,<address>...]`.
+/// `smtp://` upgrades to TLS with STARTTLS when the relay offers it, and never sends credentials
+/// without.
+pub fn parse_sink(value: &str) -> Result<Sink, String> {
+    let url = Url::parse(value).map_err(|e| format!("Invalid notification URL {value}: {e}"))?;
+    match url.scheme() {
+        "slack" => {
+            let host = url.host_str().ok_or("Slack webhook URL has no host")?;
+            Ok(Sink::Slack(format!("https://{host}{}", url.path())))
+        }
+        scheme @ ("smtp" | "smtps") => {
+            let implicit_tls = scheme == "smtps";
+            let mut from = None;
+            let mut to = Vec::new();
+            for (key, value) in url.query_pairs() {
+                match key.as_ref() {
+                    "from" => from = Some(value.to_string()),
+                    "to" => to.extend(value.split(',').map(|to| to.trim().to_string())),
+                    _ => return Err(format!("Unknown SMTP URL parameter {key}")),
+                }
+            }
+            if to.is_empty() {
+                return Err("SMTP URL has no to= recipients".to_string());
+            }
+            Ok(Sink::Email(EmailSink {
+                host: url.host_str().ok_or("SMTP URL has no host")?.to_string(),
+                port: url.port().unwrap_or(if implicit_tls {
+                    DEFAULT_SMTPS_PORT
+                } else {
+                    DEFAULT_SMTP_PORT
+                }),
+                implicit_tls,
+                username: Some(url.username())
+                    .filter(|username| !username.is_empty())
+                    .map(ToString::to_string),
+                password: url.password().map(ToString::to_string),
+                from: from.ok_or("SMTP URL has no from= address")?,
+                to,
+            }))
+        }
+        scheme => Err(format!(
+            "Unsupported notification URL scheme {scheme}, expected slack, smtp or smtps"
+        )),
+    }
+}
+
+/// A summary of a change, with one line per detail
+#[derive(Debug)]
+pub struct Notification {
+    pub subject: String,
+    pub lines: Vec<String>,
+}
+
+impl Notification {
+    /// The detail lines, cut to `MAX_LINES` with a note of how many were left out.
+    fn body_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.lines.iter().take(MAX_LINES).cloned().collect();
+        if self.lines.len() > MAX_LINES {
+            lines.push(format!("... and {} more", self.lines.len() - MAX_LINES));
+        }
+        lines
+    }
+}
+
+fn send_slack(webhook: &str, notification: &Notification) -> Result<(), Box<dyn Error>> {
+    let mut text = format!("*{}*", notification.subject);
+    if !notification.lines.is_empty() {
+        text.push_str(&format!("\n```{}```", notification.body_lines().join("\n")));
+    }
+    Client::new()
+        .post(webhook)
+        .timeout(NETWORK_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::json!({ "text": text }).to_string())
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Reads an SMTP reply, which may span several lines, and checks it is of the expected class.
+fn read_reply<S: Read>(stream: &mut BufReader<S>, expected: u16) -> Result<String, Box<dyn Error>> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err("SMTP server closed the connection".into());
+        }
+        reply.push_str(&line);
+        // The last line of a reply has a space after its code, the others a hyphen
+        if line.as_bytes().get(3) != Some(&b'-') {
+            break;
+        }
+    }
+    let code: u16 = reply
+        .get(..3)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("Invalid SMTP reply: {}", reply.trim_end()))?;
+    if code / 100 != expected / 100 {
+        return Err(format!("SMTP server replied {}", reply.trim_end()).into());
+    }
+    Ok(reply)
+}
+
+fn command<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    line: &str,
+    expected: u16,
+) -> Result<String, Box<dyn Error>> {
+    stream
+        .get_mut()
+        .write_all(format!("{line}\r\n").as_bytes())?;
+    read_reply(stream, expected)
+}
+
+/// Builds the message, dot-stuffing lines starting with a dot as the DATA command requires.
+fn email_message(sink: &EmailSink, notification: &Notification) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        sink.from,
+        sink.to.join(", "),
+        notification.subject,
+        Utc::now().to_rfc2822()
+    );
+    for line in notification.body_lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(&line);
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// Authenticates when credentials are given, then sends the message over an established session.
+fn deliver<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    sink: &EmailSink,
+    notification: &Notification,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(username) = &sink.username {
+        let password = sink.password.as_deref().unwrap_or_default();
+        let credentials =
+            base64::engine::general_purpose::STANDARD.encode(format!("\0{username}\0{password}"));
+        command(stream, &format!("AUTH PLAIN {credentials}"), 235)?;
+    }
+    command(stream, &format!("MAIL FROM:<{}>", sink.from), 250)?;
+    for to in &sink.to {
+        command(stream, &format!("RCPT TO:<{to}>"), 250)?;
+    }
+    command(stream, "DATA", 354)?;
+    let message = email_message(sink, notification);
+    command(stream, &format!("{message}."), 250)?;
+    if let Err(e) = command(stream, "QUIT", 221) {
+        debug!("SMTP QUIT failed after delivery: {e}");
+    }
+    Ok(())
+}
+
+fn send_email(sink: &EmailSink, notification: &Notification) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect((sink.host.as_str(), sink.port))?;
+    stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+    stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+    let connector = TlsConnector::new()?;
+    if sink.implicit_tls {
+        let mut stream = BufReader::new(connector.connect(&sink.host, stream)?);
+        read_reply(&mut stream, 220)?;
+        command(&mut stream, "EHLO localhost", 250)?;
+        return deliver(&mut stream, sink, notification);
+    }
+
+    let mut stream = BufReader::new(stream);
+    read_reply(&mut stream, 220)?;
+    let extensions = command(&mut stream, "EHLO localhost", 250)?;
+    let starttls = extensions
+        .lines()
+        .any(|line| line.get(4..).is_some_and(|ext| ext.trim() == "STARTTLS"));
+    if starttls {
+        command(&mut stream, "STARTTLS", 220)?;
+        let mut stream = BufReader::new(connector.connect(&sink.host, stream.into_inner())?);
+        command(&mut stream, "EHLO localhost", 250)?;
+        return deliver(&mut stream, sink, notification);
+    }
+    if sink.username.is_some() {
+        return Err(format!(
+            "{} does not offer STARTTLS, not sending the credentials in the clear",
+            sink.host
+        )
+        .into());
+    }
+    deliver(&mut stream, sink, notification)
+}
+
+/// Sends a notification to every sink. Failures are only logged, so an unreachable sink never
+/// stops the watch or monitor it reports on.
+pub fn send(sinks: &[Sink], notification: &Notification) {
+    for sink in sinks {
+        let result = match sink {
+            Sink::Slack(webhook) => send_slack(webhook, notification),
+            Sink::Email(email) => send_email(email, notification),
+        };
+        match result {
+            Ok(()) => debug!("Sent notification to {sink}"),
+            Err(e) => warn!("Could not send notification to {sink}: {e}"),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::zstd::ZstdEncoder;
+use crate::{download, storage};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Whether --compress was given, compressing every output file whatever its name
+static COMPRESS: AtomicBool = AtomicBool::new(false);
+
+/// Compresses every output file from now on, not only those whose name ends in .gz or .zst.
+pub fn compress_all() {
+    COMPRESS.store(true, Ordering::Relaxed);
+}
+
+/// Returns the name an output file is written under: with --compress, names lacking the .gz or
+/// .zst suffix get .gz, so compressed files are never mistaken for plain text.
+pub fn output_name(path: &str) -> String {
+    if COMPRESS.load(Ordering::Relaxed) && uncompressed_name(path) == path {
+        format!("{path}.gz")
+    } else {
+        path.to_string()
+    }
+}
+
+/// Destination the results are written to
+#[derive(Debug)]
+enum Writer {
+    Stdout(BufWriter<Stdout>),
+    File(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(Box<ZstdEncoder<BufWriter<File>>>),
+}
+
+/// File or stdout results are written to. Files are gzip-compressed when their name ends in .gz
+/// or --compress is given, zstd-compressed when it ends in .zst, and s3:// or gs:// outputs are written to a temporary file uploaded
+/// once complete, so [`Output::finish`] must be called to complete them.
+#[derive(Debug)]
+pub struct Output {
+    writer: Writer,
+    /// Temporary file and object URL of an output uploaded to object storage
+    upload: Option<(String, String)>,
+}
+
+impl Output {
+    /// Creates the output file, compressed per its name and --compress, which appends .gz to the
+    /// name when missing. [`output_name`] gives the name actually written.
+    pub fn create(path: &str) -> Result<Self, Box<dyn Error>> {
+        let named = output_name(path);
+        if named != path {
+            info!("Writing {path} as {named}, as --compress gzip-compresses it");
+        }
+        let path = named.as_str();
+        let (local_path, upload) = if storage::is_object_url(path) {
+            let name = path.rsplit('/').next().unwrap_or("output");
+            let local_path = download::temp_path(&env::temp_dir().join(name).display().to_string());
+            debug!("Writing {path} to {local_path} until uploaded");
+            (local_path.clone(), Some((local_path, path.to_string())))
+        } else {
+            (path.to_string(), None)
+        };
+        let file =
+            BufWriter::new(File::create(&local_path).map_err(|e| format!("{local_path}: {e}"))?);
+        let writer = if path.ends_with(".gz") {
+            debug!("Writing {path} gzip-compressed");
+            Writer::Gzip(GzEncoder::new(file, Compression::default()))
+        } else if path.ends_with(".zst") {
+            debug!("Writing {path} zstd-compressed");
+            Writer::Zstd(Box::new(ZstdEncoder::new(file)))
+        } else {
+            Writer::File(file)
+        };
+        Ok(Self { writer, upload })
+    }
+
+    /// Creates the output file when there is one, or writes to stdout, which is never compressed.
+    pub fn create_or_stdout(path: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        match path {
+            Some(path) => Self::create(path),
+            None => Ok(Self {
+                writer: Writer::Stdout(BufWriter::new(io::stdout())),
+                upload: None,
+            }),
+        }
+    }
+
+    /// Flushes the output, writing the last block and trailer of compressed files, and uploads outputs to
+    /// object storage. A failed upload keeps the temporary file so the results are not lost.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.writer {
+            Writer::Stdout(mut writer) => writer.flush()?,
+            Writer::File(mut writer) => writer.flush()?,
+            Writer::Gzip(encoder) => encoder.finish()?.flush()?,
+            Writer::Zstd(encoder) => encoder.finish()?.flush()?,
+        }
+        if let Some((local_path, url)) = self.upload {
+            storage::upload(&local_path, &url)
+                .map_err(|e| format!("{e}, the results are kept in {local_path}"))?;
+            fs::remove_file(&local_path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.writer {
+            Writer::Stdout(writer) => writer.write(buf),
+            Writer::File(writer) => writer.write(buf),
+            Writer::Gzip(encoder) => encoder.write(buf),
+            Writer::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Writer::Stdout(writer) => writer.flush(),
+            Writer::File(writer) => writer.flush(),
+            Writer::Gzip(encoder) => encoder.flush(),
+            Writer::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Returns the name of an output file without the .gz or .zst suffix, for telling its format
+/// from the extension before it.
+pub fn uncompressed_name(path: &str) -> &str {
+    path.strip_suffix(".gz")
+        .or_else(|| path.strip_suffix(".zst"))
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// Writes the text to an output file with the suffix, returning the bytes written.
+    fn write_output(suffix: &str, text: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let path = env::temp_dir()
+            .join(format!("bgp-scout-output-{}{suffix}", std::process::id()))
+            .display()
+            .to_string();
+        let mut output = Output::create(&path)?;
+        for line in text.lines() {
+            writeln!(output, "{line}")?;
+        }
+        output.finish()?;
+        let written = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        Ok(written)
+    }
+
+    fn table() -> String {
+        (0..20_000)
+            .map(|index| {
+                format!(
+                    "10.{}.{}.0/24,{}\n",
+                    index % 256,
+                    index / 256,
+                    13335 + index % 7
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gz_output_round_trips() -> Result<(), Box<dyn Error>> {
+        let text = table();
+        let written = write_output(".csv.gz", &text)?;
+        assert!(written.len() < text.len());
+        let mut decompressed = String::new();
+        GzDecoder::new(written.as_slice()).read_to_string(&mut decompressed)?;
+        assert_eq!(decompressed, text);
+        Ok(())
+    }
+
+    #[test]
+    fn zst_output_round_trips() -> Result<(), Box<dyn Error>> {
+        let text = table();
+        let written = write_output(".csv.zst", &text)?;
+        assert!(written.len() < text.len());
+        assert_eq!(crate::zstd::decompress(&written)?, text.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn compression_suffix_is_not_part_of_the_format() {
+        assert_eq!(uncompressed_name("table.json.gz"), "table.json");
+        assert_eq!(uncompressed_name("table.json.zst"), "table.json");
+        assert_eq!(uncompressed_name("table.json"), "table.json");
+    }
+}
@@ -0,0 +1,645 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::table::OriginTable;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Magic number starting and ending Parquet files
+const MAGIC: &[u8] = b"PAR1";
+
+/// Rows written per data page, keeping pages around a megabyte
+const PAGE_ROWS: usize = 65536;
+
+/// Field types of the Thrift compact protocol
+const THRIFT_I32: u8 = 5;
+const THRIFT_I64: u8 = 6;
+const THRIFT_BINARY: u8 = 8;
+const THRIFT_LIST: u8 = 9;
+const THRIFT_STRUCT: u8 = 12;
+
+/// Physical types of Parquet columns
+const TYPE_INT32: i64 = 1;
+const TYPE_BYTE_ARRAY: i64 = 6;
+
+/// Repetitions of Parquet fields
+const REQUIRED: i64 = 0;
+const REPEATED: i64 = 2;
+
+/// Converted types annotating the physical ones
+const CONVERTED_UTF8: i64 = 0;
+const CONVERTED_LIST: i64 = 3;
+const CONVERTED_UINT_32: i64 = 13;
+
+/// Encodings of values and levels
+const ENCODING_PLAIN: i64 = 0;
+const ENCODING_RLE: i64 = 3;
+
+/// A field of the schema, of a physical type for columns or with children for groups
+#[derive(Debug)]
+struct SchemaElement {
+    physical_type: Option<i64>,
+    repetition: Option<i64>,
+    name: &'static str,
+    children: Option<i64>,
+    converted_type: Option<i64>,
+}
+
+/// Schema of the export, depth first: the root, the prefix column, and the origin ASNs in the
+/// three-level structure of lists
+const SCHEMA: [SchemaElement; 5] = [
+    SchemaElement {
+        physical_type: None,
+        repetition: None,
+        name: "schema",
+        children: Some(2),
+        converted_type: None,
+    },
+    SchemaElement {
+        physical_type: Some(TYPE_BYTE_ARRAY),
+        repetition: Some(REQUIRED),
+        name: "prefix",
+        children: None,
+        converted_type: Some(CONVERTED_UTF8),
+    },
+    SchemaElement {
+        physical_type: None,
+        repetition: Some(REQUIRED),
+        name: "origin_asns",
+        children: Some(1),
+        converted_type: Some(CONVERTED_LIST),
+    },
+    SchemaElement {
+        physical_type: None,
+        repetition: Some(REPEATED),
+        name: "list",
+        children: Some(1),
+        converted_type: None,
+    },
+    SchemaElement {
+        physical_type: Some(TYPE_INT32),
+        repetition: Some(REQUIRED),
+        name: "element",
+        children: None,
+        converted_type: Some(CONVERTED_UINT_32),
+    },
+];
+
+/// Writer of Thrift structures in the compact protocol, in which the Parquet footer and page
+/// headers are serialized
+#[derive(Debug)]
+struct Thrift {
+    bytes: Vec<u8>,
+    /// Last field id of each open structure, as ids are written as deltas
+    last_fields: Vec<i16>,
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+const fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+impl Thrift {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            last_fields: vec![0],
+        }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last_fields.last_mut();
+        let delta = last.as_ref().map_or(id, |last| id - **last);
+        if (1..=15).contains(&delta) {
+            self.bytes.push((delta as u8) << 4 | kind);
+        } else {
+            self.bytes.push(kind);
+            put_varint(&mut self.bytes, zigzag(i64::from(id)));
+        }
+        if let Some(last) = last {
+            *last = id;
+        }
+    }
+
+    fn i32(&mut self, id: i16, value: i64) {
+        self.field(id, THRIFT_I32);
+        put_varint(&mut self.bytes, zigzag(value));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, THRIFT_I64);
+        put_varint(&mut self.bytes, zigzag(value));
+    }
+
+    fn string(&mut self, id: i16, value: &str) {
+        self.field(id, THRIFT_BINARY);
+        put_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Starts a list field, whose elements are written next.
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, THRIFT_LIST);
+        if len < 15 {
+            self.bytes.push((len as u8) << 4 | kind);
+        } else {
+            self.bytes.push(0xf0 | kind);
+            put_varint(&mut self.bytes, len as u64);
+        }
+    }
+
+    fn list_i32(&mut self, value: i64) {
+        put_varint(&mut self.bytes, zigzag(value));
+    }
+
+    fn list_string(&mut self, value: &str) {
+        put_varint(&mut self.bytes, value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Starts a structure field, or a structure element of a list without an id.
+    fn begin(&mut self, id: Option<i16>) {
+        if let Some(id) = id {
+            self.field(id, THRIFT_STRUCT);
+        }
+        self.last_fields.push(0);
+    }
+
+    fn end(&mut self) {
+        self.bytes.push(0);
+        self.last_fields.pop();
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.end();
+        self.bytes
+    }
+}
+
+/// Encodes levels with the run length encoding of the RLE/bit-packing hybrid, with their length
+/// before them as data pages v1 have it. Levels are 0 or 1, so their bit width is 1.
+fn encode_levels(levels: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    for run in levels.chunk_by(|a, b| a == b) {
+        put_varint(&mut runs, (run.len() as u64) << 1);
+        runs.push(run[0]);
+    }
+    let mut encoded = (runs.len() as u32).to_le_bytes().to_vec();
+    encoded.extend_from_slice(&runs);
+    encoded
+}
+
+/// Metadata of a written column chunk, for the footer
+#[derive(Debug)]
+struct ColumnChunk {
+    path: &'static [&'static str],
+    physical_type: i64,
+    offset: u64,
+    size: u64,
+    values: u64,
+}
+
+/// Writer counting the bytes written, for the offsets of the footer
+struct Counter<'output> {
+    output: &'output mut dyn Write,
+    written: u64,
+}
+
+impl Counter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.output.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Writes a data page of PLAIN values after their levels, returning its size.
+    fn page(&mut self, values: usize, data: &[u8]) -> Result<u64, Box<dyn Error>> {
+        let size = i64::try_from(data.len())?;
+        let mut header = Thrift::new();
+        header.i32(1, 0);
+        header.i32(2, size);
+        header.i32(3, size);
+        header.begin(Some(5));
+        header.i32(1, i64::try_from(values)?);
+        header.i32(2, ENCODING_PLAIN);
+        header.i32(3, ENCODING_RLE);
+        header.i32(4, ENCODING_RLE);
+        header.end();
+        let header = header.finish();
+        self.write(&header)?;
+        self.write(data)?;
+        Ok((header.len() + data.len()) as u64)
+    }
+}
+
+fn write_prefixes(
+    output: &mut Counter<'_>,
+    rows: &[(String, Vec<u32>)],
+) -> Result<ColumnChunk, Box<dyn Error>> {
+    let offset = output.written;
+    let mut size = 0;
+    for page in rows.chunks(PAGE_ROWS) {
+        // Required top-level values have no levels
+        let mut data = Vec::new();
+        for (prefix, _) in page {
+            data.extend_from_slice(&u32::try_from(prefix.len())?.to_le_bytes());
+            data.extend_from_slice(prefix.as_bytes());
+        }
+        size += output.page(page.len(), &data)?;
+    }
+    Ok(ColumnChunk {
+        path: &["prefix"],
+        physical_type: TYPE_BYTE_ARRAY,
+        offset,
+        size,
+        values: rows.len() as u64,
+    })
+}
+
+/// Writes the origin ASNs as a list per row: a repetition level of 0 starts a row, and a
+/// definition level of 0 marks an empty list, which has no value.
+fn write_origins(
+    output: &mut Counter<'_>,
+    rows: &[(String, Vec<u32>)],
+) -> Result<ColumnChunk, Box<dyn Error>> {
+    let offset = output.written;
+    let mut size = 0;
+    let mut values = 0;
+    for page in rows.chunks(PAGE_ROWS) {
+        let mut repetition_levels = Vec::new();
+        let mut definition_levels = Vec::new();
+        let mut plain = Vec::new();
+        for (_, origins) in page {
+            if origins.is_empty() {
+                repetition_levels.push(0);
+                definition_levels.push(0);
+            }
+            for (index, origin) in origins.iter().enumerate() {
+                repetition_levels.push(u8::from(index > 0));
+                definition_levels.push(1);
+                plain.extend_from_slice(&origin.to_le_bytes());
+            }
+        }
+        let mut data = encode_levels(&repetition_levels);
+        data.extend_from_slice(&encode_levels(&definition_levels));
+        data.extend_from_slice(&plain);
+        size += output.page(repetition_levels.len(), &data)?;
+        values += repetition_levels.len() as u64;
+    }
+    Ok(ColumnChunk {
+        path: &["origin_asns", "list", "element"],
+        physical_type: TYPE_INT32,
+        offset,
+        size,
+        values,
+    })
+}
+
+/// Serializes the row group of the column chunks.
+fn row_group(
+    metadata: &mut Thrift,
+    rows: i64,
+    columns: &[ColumnChunk],
+) -> Result<(), Box<dyn Error>> {
+    metadata.begin(None);
+    metadata.list(1, THRIFT_STRUCT, columns.len());
+    for column in columns {
+        let offset = i64::try_from(column.offset)?;
+        let size = i64::try_from(column.size)?;
+        metadata.begin(None);
+        metadata.i64(2, offset);
+        metadata.begin(Some(3));
+        metadata.i32(1, column.physical_type);
+        metadata.list(2, THRIFT_I32, 2);
+        metadata.list_i32(ENCODING_PLAIN);
+        metadata.list_i32(ENCODING_RLE);
+        metadata.list(3, THRIFT_BINARY, column.path.len());
+        for name in column.path {
+            metadata.list_string(name);
+        }
+        metadata.i32(4, 0);
+        metadata.i64(5, i64::try_from(column.values)?);
+        metadata.i64(6, size);
+        metadata.i64(7, size);
+        metadata.i64(9, offset);
+        metadata.end();
+        metadata.end();
+    }
+    let total_size: u64 = columns.iter().map(|column| column.size).sum();
+    metadata.i64(2, i64::try_from(total_size)?);
+    metadata.i64(3, rows);
+    metadata.end();
+    Ok(())
+}
+
+/// Serializes the file metadata: the schema, then a single row group of the column chunks.
+fn footer(rows: usize, columns: &[ColumnChunk]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let rows = i64::try_from(rows)?;
+    let mut metadata = Thrift::new();
+    metadata.i32(1, 1);
+    metadata.list(2, THRIFT_STRUCT, SCHEMA.len());
+    for element in SCHEMA {
+        metadata.begin(None);
+        if let Some(physical_type) = element.physical_type {
+            metadata.i32(1, physical_type);
+        }
+        if let Some(repetition) = element.repetition {
+            metadata.i32(3, repetition);
+        }
+        metadata.string(4, element.name);
+        if let Some(children) = element.children {
+            metadata.i32(5, children);
+        }
+        if let Some(converted_type) = element.converted_type {
+            metadata.i32(6, converted_type);
+        }
+        metadata.end();
+    }
+    metadata.i64(3, rows);
+    // An empty table has no row group rather than one of empty column chunks
+    metadata.list(4, THRIFT_STRUCT, usize::from(rows > 0));
+    if rows > 0 {
+        row_group(&mut metadata, rows, columns)?;
+    }
+    metadata.string(6, concat!("bgp-scout version ", env!("CARGO_PKG_VERSION")));
+    Ok(metadata.finish())
+}
+
+/// Writes the table as an uncompressed Parquet file with a `prefix` string column and an
+/// `origin_asns` column of lists of unsigned 32-bit integers, in a single row group.
+pub fn write_table(output: &mut dyn Write, table: &OriginTable) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<(String, Vec<u32>)> = table
+        .iter()
+        .map(|(prefix, origins)| (prefix.to_string(), origins.iter().copied().collect()))
+        .collect();
+    let mut output = Counter { output, written: 0 };
+    output.write(MAGIC)?;
+    let columns = [
+        write_prefixes(&mut output, &rows)?,
+        write_origins(&mut output, &rows)?,
+    ];
+    let footer = footer(rows.len(), &columns)?;
+    output.write(&footer)?;
+    output.write(&u32::try_from(footer.len())?.to_le_bytes())?;
+    output.write(MAGIC)?;
+    debug!("Wrote {} rows as Parquet", rows.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipnet::IpNet;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::net::Ipv4Addr;
+
+    /// A value read back in the Thrift compact protocol
+    #[derive(Debug, Clone, PartialEq)]
+    enum Thrifted {
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Thrifted>),
+        Struct(BTreeMap<i16, Thrifted>),
+    }
+
+    impl Thrifted {
+        fn field(&self, id: i16) -> Result<&Self, String> {
+            match self {
+                Self::Struct(fields) => fields.get(&id).ok_or(format!("no field {id}")),
+                _ => Err("not a structure".to_string()),
+            }
+        }
+
+        fn int(&self, id: i16) -> Result<i64, String> {
+            match self.field(id)? {
+                Self::Int(value) => Ok(*value),
+                _ => Err(format!("field {id} is not an integer")),
+            }
+        }
+
+        fn list(&self, id: i16) -> Result<&[Self], String> {
+            match self.field(id)? {
+                Self::List(values) => Ok(values),
+                _ => Err(format!("field {id} is not a list")),
+            }
+        }
+    }
+
+    struct Reader<'bytes> {
+        bytes: &'bytes [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> Result<u8, String> {
+            let byte = *self.bytes.get(self.position).ok_or("truncated")?;
+            self.position += 1;
+            Ok(byte)
+        }
+
+        fn varint(&mut self) -> Result<u64, String> {
+            let mut value = 0;
+            for shift in (0..64).step_by(7) {
+                let byte = self.byte()?;
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    return Ok(value);
+                }
+            }
+            Err("varint too long".to_string())
+        }
+
+        fn value(&mut self, kind: u8) -> Result<Thrifted, String> {
+            Ok(match kind {
+                3..=6 => {
+                    let value = self.varint()?;
+                    Thrifted::Int((value >> 1) as i64 ^ -((value & 1) as i64))
+                }
+                THRIFT_BINARY => {
+                    let len = self.varint()? as usize;
+                    let value = self.bytes[self.position..self.position + len].to_vec();
+                    self.position += len;
+                    Thrifted::Binary(value)
+                }
+                THRIFT_LIST => {
+                    let header = self.byte()?;
+                    let len = match header >> 4 {
+                        15 => self.varint()? as usize,
+                        len => usize::from(len),
+                    };
+                    let values = (0..len)
+                        .map(|_| self.value(header & 0x0f))
+                        .collect::<Result<_, _>>()?;
+                    Thrifted::List(values)
+                }
+                THRIFT_STRUCT => {
+                    let mut fields = BTreeMap::new();
+                    let mut id = 0;
+                    loop {
+                        let header = self.byte()?;
+                        if header == 0 {
+                            break;
+                        }
+                        id = match header >> 4 {
+                            0 => {
+                                let value = self.varint()?;
+                                ((value >> 1) as i64 ^ -((value & 1) as i64)) as i16
+                            }
+                            delta => id + i16::from(delta),
+                        };
+                        fields.insert(id, self.value(header & 0x0f)?);
+                    }
+                    Thrifted::Struct(fields)
+                }
+                kind => return Err(format!("unexpected type {kind}")),
+            })
+        }
+    }
+
+    /// Reads the Thrift structure at a position, returning it and where it ends.
+    fn read_struct(bytes: &[u8], position: usize) -> Result<(Thrifted, usize), String> {
+        let mut reader = Reader { bytes, position };
+        let value = reader.value(THRIFT_STRUCT)?;
+        Ok((value, reader.position))
+    }
+
+    /// Decodes levels written in runs of the RLE/bit-packing hybrid after their length.
+    fn decode_levels(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+        let len = u32::from_le_bytes(data[..4].try_into().map_err(|_| "truncated")?) as usize;
+        let mut reader = Reader {
+            bytes: &data[4..4 + len],
+            position: 0,
+        };
+        let mut levels = Vec::new();
+        while reader.position < len {
+            let header = reader.varint()?;
+            if header & 1 == 1 {
+                return Err("unexpected bit-packed run".to_string());
+            }
+            let value = reader.byte()?;
+            levels.extend(std::iter::repeat_n(value, (header >> 1) as usize));
+        }
+        Ok((levels, 4 + len))
+    }
+
+    /// Reads the data pages of a column chunk, returning their value counts and data.
+    fn read_pages(file: &[u8], column: &Thrifted) -> Result<Vec<(i64, Vec<u8>)>, String> {
+        let metadata = column.field(3)?;
+        let mut position = metadata.int(9)? as usize;
+        let end = position + metadata.int(7)? as usize;
+        let mut pages = Vec::new();
+        while position < end {
+            let (header, data_start) = read_struct(file, position)?;
+            assert_eq!(header.int(1)?, 0);
+            let data_header = header.field(5)?;
+            assert_eq!(data_header.int(2)?, ENCODING_PLAIN);
+            let size = header.int(3)? as usize;
+            pages.push((
+                data_header.int(1)?,
+                file[data_start..data_start + size].to_vec(),
+            ));
+            position = data_start + size;
+        }
+        assert_eq!(position, end);
+        Ok(pages)
+    }
+
+    #[test]
+    fn writes_readable_parquet() -> Result<(), Box<dyn Error>> {
+        let mut table = OriginTable::new();
+        for index in 0..70_000_u32 {
+            let prefix = IpNet::new(Ipv4Addr::from(0x0a00_0000 + (index << 8)).into(), 24)?;
+            let origins: BTreeSet<u32> = match index % 3 {
+                0 => BTreeSet::new(),
+                1 => [13335].into(),
+                _ => [4_200_000_000, index].into(),
+            };
+            table.insert(prefix, origins);
+        }
+        let mut file = Vec::new();
+        write_table(&mut file, &table)?;
+
+        assert!(file.starts_with(MAGIC) && file.ends_with(MAGIC));
+        let footer_len = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into()?);
+        let footer_start = file.len() - 8 - footer_len as usize;
+        let (metadata, footer_end) = read_struct(&file, footer_start)?;
+        assert_eq!(footer_end, file.len() - 8);
+        assert_eq!(metadata.int(3)?, 70_000);
+        let names: Vec<Thrifted> = metadata
+            .list(2)?
+            .iter()
+            .map(|element| element.field(4).cloned())
+            .collect::<Result<_, _>>()?;
+        let expected: Vec<Thrifted> = ["schema", "prefix", "origin_asns", "list", "element"]
+            .iter()
+            .map(|name| Thrifted::Binary(name.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(names, expected);
+        let row_groups = metadata.list(4)?;
+        assert_eq!(row_groups.len(), 1);
+        let columns = row_groups[0].list(1)?;
+
+        let mut prefixes = Vec::new();
+        let prefix_pages = read_pages(&file, &columns[0])?;
+        assert_eq!(prefix_pages.len(), 2);
+        for (values, data) in prefix_pages {
+            let mut position = 0;
+            for _ in 0..values {
+                let len = u32::from_le_bytes(data[position..position + 4].try_into()?) as usize;
+                prefixes.push(String::from_utf8(
+                    data[position + 4..position + 4 + len].to_vec(),
+                )?);
+                position += 4 + len;
+            }
+            assert_eq!(position, data.len());
+        }
+
+        let mut origins: Vec<BTreeSet<u32>> = Vec::new();
+        for (values, data) in read_pages(&file, &columns[1])? {
+            let (repetition_levels, repetition_len) = decode_levels(&data)?;
+            let (definition_levels, definition_len) = decode_levels(&data[repetition_len..])?;
+            assert_eq!(repetition_levels.len(), values as usize);
+            assert_eq!(definition_levels.len(), values as usize);
+            let mut plain = data[repetition_len + definition_len..].chunks_exact(4);
+            for (repetition, definition) in repetition_levels.iter().zip(&definition_levels) {
+                if *repetition == 0 {
+                    origins.push(BTreeSet::new());
+                }
+                if *definition == 1 {
+                    let value = plain.next().ok_or("missing value")?;
+                    if let Some(row) = origins.last_mut() {
+                        row.insert(u32::from_le_bytes(value.try_into()?));
+                    }
+                }
+            }
+            assert!(plain.next().is_none());
+        }
+
+        let expected: Vec<(String, BTreeSet<u32>)> = table
+            .into_iter()
+            .map(|(prefix, origins)| (prefix.to_string(), origins))
+            .collect();
+        let read: Vec<(String, BTreeSet<u32>)> = prefixes.into_iter().zip(origins).collect();
+        assert_eq!(read, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_table_has_no_row_group() -> Result<(), Box<dyn Error>> {
+        let mut file = Vec::new();
+        write_table(&mut file, &OriginTable::new())?;
+        let footer_len = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into()?);
+        assert_eq!(file.len(), 4 + footer_len as usize + 8);
+        let (metadata, _) = read_struct(&file, 4)?;
+        assert_eq!(metadata.int(3)?, 0);
+        assert!(metadata.list(4)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use bgpkit_parser::BgpkitParser;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::mrt;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT digraph
+    #[default]
+    Dot,
+    /// JSON object with node and edge lists
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: u32,
+    pub to: u32,
+    /// Number of paths using this adjacency
+    pub paths: u64,
+}
+
+/// AS adjacencies of the paths reaching an origin ASN. Edges point in the direction
+/// announcements propagate, from the origin towards the collector peers.
+#[derive(Debug, Serialize)]
+pub struct PathGraph {
+    pub asn: u32,
+    pub paths: u64,
+    pub nodes: BTreeSet<u32>,
+    pub edges: Vec<Edge>,
+}
+
+/// Scans an MRT file for every AS path of prefixes originated by the target ASN and collects the
+/// adjacencies between consecutive ASes, with AS prepending collapsed.
+pub fn build(file_name: &str, asn: u32) -> Result<PathGraph, Box<dyn Error>> {
+    let parser = BgpkitParser::from_reader(mrt::open(file_name)?)
+        .add_filter("type", "announce")?
+        .add_filter("origin_asn", &asn.to_string())?;
+
+    debug!("Scanning {file_name} for AS paths reaching AS{asn}");
+    let mut paths = 0;
+    let mut nodes = BTreeSet::from([asn]);
+    let mut edges: BTreeMap<(u32, u32), u64> = BTreeMap::new();
+    for elem in parser.into_elem_iter() {
+        let Some(path) = elem
+            .as_path
+            .as_ref()
+            .and_then(|path| path.to_u32_vec_opt(true))
+        else {
+            continue;
+        };
+        paths += 1;
+        nodes.extend(path.iter().copied());
+        // Paths are listed from the collector peer towards the origin
+        for pair in path.windows(2) {
+            *edges.entry((pair[1], pair[0])).or_default() += 1;
+        }
+    }
+    debug!(
+        "Found {paths} paths with {} ASes and {} adjacencies",
+        nodes.len(),
+        edges.len()
+    );
+
+    Ok(PathGraph {
+        asn,
+        paths,
+        nodes,
+        edges: edges
+            .into_iter()
+            .map(|((from, to), paths)| Edge { from, to, paths })
+            .collect(),
+    })
+}
+
+pub fn write_graph(
+    output: &mut dyn Write,
+    graph: &PathGraph,
+    format: GraphFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        GraphFormat::Json => {
+            serde_json::to_writer(&mut *output, graph)?;
+            writeln!(output)?;
+        }
+        GraphFormat::Dot => {
+            writeln!(output, "digraph \"AS{}\" {{", graph.asn)?;
+            writeln!(output, "  rankdir=LR;")?;
+            writeln!(output, "  node [shape=box];")?;
+            for node in &graph.nodes {
+                if *node == graph.asn {
+                    writeln!(
+                        output,
+                        "  \"AS{node}\" [style=filled, fillcolor=lightblue];"
+                    )?;
+                } else {
+                    writeln!(output, "  \"AS{node}\";")?;
+                }
+            }
+            for edge in &graph.edges {
+                writeln!(
+                    output,
+                    "  \"AS{}\" -> \"AS{}\" [label=\"{}\"];",
+                    edge.from, edge.to, edge.paths
+                )?;
+            }
+            writeln!(output, "}}")?;
+        }
+    }
+    Ok(())
+}
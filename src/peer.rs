@@ -0,0 +1,528 @@
+use chrono::Utc;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::mrt;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const BGP_HEADER_LEN: usize = 19;
+const BGP_MAX_MESSAGE_LEN: usize = 4096;
+const BGP_VERSION: u8 = 4;
+
+const MSG_OPEN: u8 = 1;
+const MSG_UPDATE: u8 = 2;
+const MSG_NOTIFICATION: u8 = 3;
+const MSG_KEEPALIVE: u8 = 4;
+const MSG_ROUTE_REFRESH: u8 = 5;
+
+const CAP_MULTIPROTOCOL: u8 = 1;
+const CAP_GRACEFUL_RESTART: u8 = 64;
+const CAP_FOUR_OCTET_AS: u8 = 65;
+
+const ATTR_MP_UNREACH_NLRI: u8 = 15;
+
+/// Hold time until the OPEN of the router arrives, the large value suggested by RFC 4271
+const OPEN_HOLD_TIME: Duration = Duration::from_secs(240);
+
+/// NOTIFICATION error code of an expired hold timer, which has no subcodes
+const HOLD_TIMER_EXPIRED: u8 = 4;
+
+/// Placeholder ASN sent in the 2-byte OPEN field when the local ASN does not fit, from RFC 6793
+const AS_TRANS: u16 = 23456;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+/// Settings of the passive BGP session
+#[derive(Debug)]
+pub struct PeerConfig<'config> {
+    pub listen: &'config str,
+    pub local_asn: u32,
+    pub router_id: Ipv4Addr,
+    /// ASN the router must present, any ASN is accepted when unset
+    pub peer_asn: Option<u32>,
+    pub hold_time: u16,
+    /// How long to wait for further messages before giving up on End-of-RIB markers
+    pub idle_timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct SessionSummary {
+    pub peer_asn: u32,
+    pub peer_address: IpAddr,
+    pub updates: u64,
+    /// Whether the router signalled End-of-RIB for every negotiated address family
+    pub complete: bool,
+}
+
+/// Capabilities and identity from the OPEN message of the router
+#[derive(Debug)]
+struct PeerOpen {
+    asn: u32,
+    hold_time: u16,
+    four_octet_as: bool,
+    families: HashSet<(u16, u8)>,
+}
+
+fn encode_message(message_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![0xff; 16];
+    let len = (BGP_HEADER_LEN + body.len()) as u16;
+    message.extend_from_slice(&len.to_be_bytes());
+    message.push(message_type);
+    message.extend_from_slice(body);
+    message
+}
+
+fn open_message(config: &PeerConfig<'_>) -> Vec<u8> {
+    let mut capabilities = Vec::new();
+    for afi in [AFI_IPV4, AFI_IPV6] {
+        capabilities.extend_from_slice(&[CAP_MULTIPROTOCOL, 4]);
+        capabilities.extend_from_slice(&afi.to_be_bytes());
+        capabilities.extend_from_slice(&[0, SAFI_UNICAST]);
+    }
+    // Graceful restart without any address families only announces End-of-RIB support
+    capabilities.extend_from_slice(&[CAP_GRACEFUL_RESTART, 2, 0, 0]);
+    capabilities.extend_from_slice(&[CAP_FOUR_OCTET_AS, 4]);
+    capabilities.extend_from_slice(&config.local_asn.to_be_bytes());
+
+    let my_as = u16::try_from(config.local_asn).unwrap_or(AS_TRANS);
+    let mut body = vec![BGP_VERSION];
+    body.extend_from_slice(&my_as.to_be_bytes());
+    body.extend_from_slice(&config.hold_time.to_be_bytes());
+    body.extend_from_slice(&config.router_id.octets());
+    body.push((capabilities.len() + 2) as u8);
+    body.push(2);
+    body.push(capabilities.len() as u8);
+    body.extend_from_slice(&capabilities);
+    encode_message(MSG_OPEN, &body)
+}
+
+fn notification(code: u8, subcode: u8) -> Vec<u8> {
+    encode_message(MSG_NOTIFICATION, &[code, subcode])
+}
+
+fn is_timeout(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
+
+/// Reads one BGP message, returning its type and the full message including the header.
+fn read_message(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), Box<dyn Error>> {
+    let mut header = [0; BGP_HEADER_LEN];
+    stream.read_exact(&mut header)?;
+    if header[..16].iter().any(|&byte| byte != 0xff) {
+        return Err("Received a BGP message with an invalid marker".into());
+    }
+    let len = usize::from(u16::from_be_bytes([header[16], header[17]]));
+    if !(BGP_HEADER_LEN..=BGP_MAX_MESSAGE_LEN).contains(&len) {
+        return Err(format!("Received a BGP message with invalid length {len}").into());
+    }
+    let mut message = header.to_vec();
+    message.resize(len, 0);
+    stream.read_exact(&mut message[BGP_HEADER_LEN..])?;
+    Ok((header[18], message))
+}
+
+fn parse_open(message: &[u8]) -> Result<PeerOpen, Box<dyn Error>> {
+    let body = &message[BGP_HEADER_LEN..];
+    if body.len() < 10 {
+        return Err("Received a truncated OPEN message".into());
+    }
+    if body[0] != BGP_VERSION {
+        return Err(format!("Router uses unsupported BGP version {}", body[0]).into());
+    }
+    let mut open = PeerOpen {
+        asn: u32::from(u16::from_be_bytes([body[1], body[2]])),
+        hold_time: u16::from_be_bytes([body[3], body[4]]),
+        four_octet_as: false,
+        families: HashSet::new(),
+    };
+
+    let params_len = usize::from(body[9]);
+    let params = body
+        .get(10..10 + params_len)
+        .ok_or("Received an OPEN message with truncated parameters")?;
+    let mut offset = 0;
+    while offset + 2 <= params.len() {
+        let (param_type, param_len) = (params[offset], usize::from(params[offset + 1]));
+        let value = params
+            .get(offset + 2..offset + 2 + param_len)
+            .ok_or("Received an OPEN message with a truncated parameter")?;
+        offset += 2 + param_len;
+        // Only capabilities are defined as optional parameters
+        if param_type != 2 {
+            continue;
+        }
+        let mut cap_offset = 0;
+        while cap_offset + 2 <= value.len() {
+            let (code, cap_len) = (value[cap_offset], usize::from(value[cap_offset + 1]));
+            let cap = value
+                .get(cap_offset + 2..cap_offset + 2 + cap_len)
+                .ok_or("Received an OPEN message with a truncated capability")?;
+            cap_offset += 2 + cap_len;
+            match (code, cap) {
+                (CAP_MULTIPROTOCOL, [afi_high, afi_low, _, safi]) => {
+                    open.families
+                        .insert((u16::from_be_bytes([*afi_high, *afi_low]), *safi));
+                }
+                (CAP_FOUR_OCTET_AS, [a, b, c, d]) => {
+                    open.four_octet_as = true;
+                    open.asn = u32::from_be_bytes([*a, *b, *c, *d]);
+                }
+                _ => trace!("Ignoring capability {code} of the router"),
+            }
+        }
+    }
+    // Routers without multiprotocol capabilities only exchange IPv4 unicast routes
+    if open.families.is_empty() {
+        open.families.insert((AFI_IPV4, SAFI_UNICAST));
+    }
+    Ok(open)
+}
+
+/// Returns the address family of an End-of-RIB marker, which is an UPDATE without routes for
+/// IPv4 unicast or one holding only an empty MP_UNREACH_NLRI attribute otherwise.
+fn end_of_rib(message: &[u8]) -> Option<(u16, u8)> {
+    let body = &message[BGP_HEADER_LEN..];
+    if body.len() == 4 && body.iter().all(|&byte| byte == 0) {
+        return Some((AFI_IPV4, SAFI_UNICAST));
+    }
+    let withdrawn_len = u16::from_be_bytes([*body.first()?, *body.get(1)?]);
+    let attributes_len = usize::from(u16::from_be_bytes([*body.get(2)?, *body.get(3)?]));
+    if withdrawn_len != 0 || attributes_len + 4 != body.len() {
+        return None;
+    }
+    let attributes = &body[4..];
+    let extended_length = attributes.first()? & 0x10 != 0;
+    let (value_offset, value_len) = if extended_length {
+        (
+            4,
+            usize::from(u16::from_be_bytes([
+                *attributes.get(2)?,
+                *attributes.get(3)?,
+            ])),
+        )
+    } else {
+        (3, usize::from(*attributes.get(2)?))
+    };
+    match attributes.get(value_offset..) {
+        Some([afi_high, afi_low, safi])
+            if *attributes.get(1)? == ATTR_MP_UNREACH_NLRI && value_len == 3 =>
+        {
+            Some((u16::from_be_bytes([*afi_high, *afi_low]), *safi))
+        }
+        _ => None,
+    }
+}
+
+/// Listens for a single BGP session from a router, writing every UPDATE it sends to the output as
+/// MRT BGP4MP records until it has signalled End-of-RIB for each negotiated address family or
+/// stays idle for the idle timeout. Nothing is ever advertised to the router.
+pub fn collect(
+    config: &PeerConfig<'_>,
+    output: &mut dyn Write,
+) -> Result<SessionSummary, Box<dyn Error>> {
+    if config.hold_time != 0 && config.hold_time < 3 {
+        return Err("Hold time must be zero or at least 3 seconds".into());
+    }
+    let listener = TcpListener::bind(config.listen)?;
+    info!("Waiting for a BGP session on {}", config.listen);
+    let (stream, _) = listener.accept()?;
+    session(config, stream, output)
+}
+
+/// Runs the BGP session of an accepted connection for [`collect`].
+///
+/// The negotiated hold time is enforced: when the router sends nothing for that long, which is
+/// also checked before the idle timeout when shorter, the session ends with a HOLD TIMER EXPIRED
+/// NOTIFICATION and an error.
+fn session(
+    config: &PeerConfig<'_>,
+    mut stream: TcpStream,
+    output: &mut dyn Write,
+) -> Result<SessionSummary, Box<dyn Error>> {
+    let peer_address = stream.peer_addr()?.ip().to_canonical();
+    let local_address = stream.local_addr()?.ip().to_canonical();
+    info!("Accepted connection from {peer_address}");
+
+    stream.write_all(&open_message(config))?;
+    stream.set_read_timeout(Some(OPEN_HOLD_TIME))?;
+    let (message_type, message) = match read_message(&mut stream) {
+        Ok(message) => message,
+        Err(e) if is_timeout(e.as_ref()) => {
+            let _ = stream.write_all(&notification(HOLD_TIMER_EXPIRED, 0));
+            return Err("Hold timer expired waiting for the OPEN message of the router".into());
+        }
+        Err(e) => return Err(e),
+    };
+    if message_type != MSG_OPEN {
+        return Err(format!("Expected an OPEN message but received type {message_type}").into());
+    }
+    let open = parse_open(&message)?;
+    if config.peer_asn.is_some_and(|asn| asn != open.asn) {
+        // Bad peer AS
+        stream.write_all(&notification(2, 2))?;
+        return Err(format!("Router presented unexpected AS{}", open.asn).into());
+    }
+    let hold_time = config.hold_time.min(open.hold_time);
+    let mut pending_families: HashSet<(u16, u8)> = open
+        .families
+        .iter()
+        .copied()
+        .filter(|&(afi, safi)| matches!(afi, AFI_IPV4 | AFI_IPV6) && safi == SAFI_UNICAST)
+        .collect();
+    info!(
+        "Session established with AS{} ({}), hold time {hold_time} seconds",
+        open.asn,
+        if open.asn == config.local_asn {
+            "iBGP"
+        } else {
+            "eBGP"
+        }
+    );
+    stream.write_all(&encode_message(MSG_KEEPALIVE, &[]))?;
+
+    // Keep the session alive from a separate thread while this one blocks reading updates
+    let (stop, stopped) = mpsc::channel::<()>();
+    let keepalive = if hold_time > 0 {
+        let mut keepalive_stream = stream.try_clone()?;
+        let interval = Duration::from_secs(u64::from(hold_time / 3));
+        Some(thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(e) = keepalive_stream.write_all(&encode_message(MSG_KEEPALIVE, &[])) {
+                    warn!("Could not send KEEPALIVE: {e}");
+                    break;
+                }
+            }
+        }))
+    } else {
+        None
+    };
+    // Every message restarts both timers, so reads wait for whichever expires first
+    let hold_timer = Duration::from_secs(u64::from(hold_time));
+    let hold_timer_first = hold_time > 0 && hold_timer <= config.idle_timeout;
+    stream.set_read_timeout(Some(if hold_timer_first {
+        hold_timer
+    } else {
+        config.idle_timeout
+    }))?;
+
+    let mut summary = SessionSummary {
+        peer_asn: open.asn,
+        peer_address,
+        updates: 0,
+        complete: false,
+    };
+    let result = loop {
+        let (message_type, message) = match read_message(&mut stream) {
+            Ok(message) => message,
+            Err(e) if is_timeout(e.as_ref()) && hold_timer_first => {
+                let _ = stream.write_all(&notification(HOLD_TIMER_EXPIRED, 0));
+                break Err(format!(
+                    "Hold timer expired, the router sent nothing for {hold_time} seconds"
+                )
+                .into());
+            }
+            Err(e) if is_timeout(e.as_ref()) => {
+                warn!(
+                    "No End-of-RIB received for {} address families before the idle timeout",
+                    pending_families.len()
+                );
+                break Ok(());
+            }
+            Err(e) => break Err(e),
+        };
+        match message_type {
+            MSG_UPDATE => {
+                if let Some(family) = end_of_rib(&message) {
+                    debug!("Received End-of-RIB for AFI {} SAFI {}", family.0, family.1);
+                    pending_families.remove(&family);
+                    if pending_families.is_empty() {
+                        summary.complete = true;
+                        break Ok(());
+                    }
+                    continue;
+                }
+                summary.updates += 1;
+                mrt::write_bgp4mp_message(
+                    output,
+                    Utc::now().timestamp() as u32,
+                    (open.asn, peer_address),
+                    (config.local_asn, local_address),
+                    open.four_octet_as,
+                    &message,
+                )?;
+            }
+            MSG_NOTIFICATION => {
+                let code = message.get(BGP_HEADER_LEN).copied().unwrap_or_default();
+                let subcode = message.get(BGP_HEADER_LEN + 1).copied().unwrap_or_default();
+                break Err(
+                    format!("Router closed the session with NOTIFICATION {code}/{subcode}").into(),
+                );
+            }
+            MSG_KEEPALIVE | MSG_ROUTE_REFRESH => trace!("Received message type {message_type}"),
+            _ => warn!("Ignoring unknown BGP message type {message_type}"),
+        }
+    };
+
+    drop(stop);
+    if let Some(keepalive) = keepalive {
+        let _ = keepalive.join();
+    }
+    if result.is_ok() {
+        // Cease, administrative shutdown
+        let _ = stream.write_all(&notification(6, 2));
+    }
+    result.map(|()| summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(local_asn: u32) -> PeerConfig<'static> {
+        PeerConfig {
+            listen: "127.0.0.1:179",
+            local_asn,
+            router_id: Ipv4Addr::new(192, 0, 2, 1),
+            peer_asn: None,
+            hold_time: 90,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn parse_own_open() -> Result<(), Box<dyn Error>> {
+        let open = parse_open(&open_message(&config(4_200_000_000)))?;
+        assert_eq!(open.asn, 4_200_000_000);
+        assert_eq!(open.hold_time, 90);
+        assert!(open.four_octet_as);
+        assert_eq!(
+            open.families,
+            HashSet::from([(AFI_IPV4, SAFI_UNICAST), (AFI_IPV6, SAFI_UNICAST)])
+        );
+
+        // 4-byte ASNs are sent as AS_TRANS in the 2-byte field
+        let message = open_message(&config(4_200_000_000));
+        assert_eq!(
+            message[BGP_HEADER_LEN + 1..BGP_HEADER_LEN + 3],
+            AS_TRANS.to_be_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_open_without_capabilities() -> Result<(), Box<dyn Error>> {
+        // Version 4, AS 64500, hold time 180, BGP identifier 192.0.2.2, no optional parameters
+        let body = [4, 0xfb, 0xf4, 0, 180, 192, 0, 2, 2, 0];
+        let open = parse_open(&encode_message(MSG_OPEN, &body))?;
+        assert_eq!(open.asn, 64500);
+        assert_eq!(open.hold_time, 180);
+        assert!(!open.four_octet_as);
+        assert_eq!(open.families, HashSet::from([(AFI_IPV4, SAFI_UNICAST)]));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_open() {
+        let version_3 = [3, 0xfb, 0xf4, 0, 180, 192, 0, 2, 2, 0];
+        assert!(parse_open(&encode_message(MSG_OPEN, &version_3)).is_err());
+        assert!(parse_open(&encode_message(MSG_OPEN, &[4, 0xfb, 0xf4])).is_err());
+        // Parameters longer than the message
+        let truncated = [4, 0xfb, 0xf4, 0, 180, 192, 0, 2, 2, 8, 2, 6, 65, 4];
+        assert!(parse_open(&encode_message(MSG_OPEN, &truncated)).is_err());
+        // A capability longer than its parameter
+        let truncated = [4, 0xfb, 0xf4, 0, 180, 192, 0, 2, 2, 4, 2, 2, 65, 4];
+        assert!(parse_open(&encode_message(MSG_OPEN, &truncated)).is_err());
+    }
+
+    #[test]
+    fn end_of_rib_markers() {
+        assert_eq!(
+            end_of_rib(&encode_message(MSG_UPDATE, &[0, 0, 0, 0])),
+            Some((AFI_IPV4, SAFI_UNICAST))
+        );
+        // An empty MP_UNREACH_NLRI for IPv6 unicast
+        let ipv6 = [0, 0, 0, 6, 0x80, ATTR_MP_UNREACH_NLRI, 3, 0, 2, 1];
+        assert_eq!(
+            end_of_rib(&encode_message(MSG_UPDATE, &ipv6)),
+            Some((AFI_IPV6, SAFI_UNICAST))
+        );
+        // The same attribute with an extended length
+        let extended = [0, 0, 0, 7, 0x90, ATTR_MP_UNREACH_NLRI, 0, 3, 0, 2, 1];
+        assert_eq!(
+            end_of_rib(&encode_message(MSG_UPDATE, &extended)),
+            Some((AFI_IPV6, SAFI_UNICAST))
+        );
+    }
+
+    #[test]
+    fn updates_are_not_end_of_rib() {
+        // Withdrawing 192.0.2.0/24
+        let withdrawal = [0, 4, 24, 192, 0, 2, 0, 0];
+        assert_eq!(end_of_rib(&encode_message(MSG_UPDATE, &withdrawal)), None);
+        // MP_UNREACH_NLRI withdrawing 2001:db8::/32
+        let mp_withdrawal = [
+            0,
+            0,
+            0,
+            11,
+            0x80,
+            ATTR_MP_UNREACH_NLRI,
+            8,
+            0,
+            2,
+            1,
+            32,
+            0x20,
+            0x01,
+            0x0d,
+            0xb8,
+        ];
+        assert_eq!(
+            end_of_rib(&encode_message(MSG_UPDATE, &mp_withdrawal)),
+            None
+        );
+        // ORIGIN alone
+        let origin = [0, 0, 0, 4, 0x40, 1, 1, 0];
+        assert_eq!(end_of_rib(&encode_message(MSG_UPDATE, &origin)), None);
+        assert_eq!(end_of_rib(&encode_message(MSG_UPDATE, &[0, 0])), None);
+    }
+
+    #[test]
+    fn silent_router_expires_hold_timer() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        // A router that offers a 3 second hold time, then sends nothing after its OPEN
+        let router = thread::spawn(move || -> Result<Vec<u8>, String> {
+            let mut stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+            let open = [4, 0xfb, 0xf4, 0, 3, 192, 0, 2, 2, 0];
+            stream
+                .write_all(&encode_message(MSG_OPEN, &open))
+                .map_err(|e| e.to_string())?;
+            loop {
+                match read_message(&mut stream).map_err(|e| e.to_string())? {
+                    (MSG_NOTIFICATION, message) => break Ok(message),
+                    _ => continue,
+                }
+            }
+        });
+        let (stream, _) = listener.accept()?;
+
+        let mut output = Vec::new();
+        let result = session(&config(64500), stream, &mut output);
+        assert!(result.is_err_and(|e| e.to_string().contains("Hold timer expired")));
+        let notification = router.join().map_err(|_| "router thread panicked")??;
+        assert_eq!(notification[BGP_HEADER_LEN..], [HOLD_TIMER_EXPIRED, 0]);
+        Ok(())
+    }
+}
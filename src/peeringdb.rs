@@ -0,0 +1,153 @@
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+const PEERINGDB_API: &str = "https://www.peeringdb.com/api";
+
+/// Keeps the query strings well below common URL length limits
+const ASNS_PER_REQUEST: usize = 150;
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Organization {
+    id: u32,
+    name: String,
+}
+
+/// A PeeringDB network record, reduced to the fields used for enrichment
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Network {
+    pub asn: u32,
+    pub name: String,
+    #[serde(default, skip_serializing)]
+    pub org_id: u32,
+    #[serde(default, skip_deserializing)]
+    pub org_name: Option<String>,
+    #[serde(default, rename(deserialize = "info_type"))]
+    pub network_type: String,
+    #[serde(default, rename(deserialize = "info_traffic"))]
+    pub traffic: String,
+    #[serde(default)]
+    pub irr_as_set: String,
+}
+
+fn fetch_data<T: for<'de> Deserialize<'de>>(
+    url: &str,
+    verify_cache_interval: Duration,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    debug!("Querying PeeringDB {url}");
+    let path = source::fetch_file(url, verify_cache_interval)?;
+    let response: ApiResponse<T> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(response.data)
+}
+
+fn join_ids(ids: &[u32]) -> String {
+    ids.iter().map(u32::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Looks up the PeeringDB network and organization records for the given ASNs. ASNs without a
+/// PeeringDB record are omitted.
+pub fn fetch_networks(
+    asns: &[u32],
+    verify_cache_interval: Duration,
+) -> Result<Vec<Network>, Box<dyn Error>> {
+    let mut networks: Vec<Network> = Vec::new();
+    for chunk in asns.chunks(ASNS_PER_REQUEST) {
+        let url = format!("{PEERINGDB_API}/net?asn__in={}", join_ids(chunk));
+        networks.extend(fetch_data::<Network>(&url, verify_cache_interval)?);
+    }
+
+    let mut org_ids: Vec<u32> = networks.iter().map(|network| network.org_id).collect();
+    org_ids.sort_unstable();
+    org_ids.dedup();
+    let mut org_names = HashMap::new();
+    for chunk in org_ids.chunks(ASNS_PER_REQUEST) {
+        let url = format!("{PEERINGDB_API}/org?id__in={}", join_ids(chunk));
+        for org in fetch_data::<Organization>(&url, verify_cache_interval)? {
+            org_names.insert(org.id, org.name);
+        }
+    }
+
+    for network in &mut networks {
+        network.org_name = org_names.get(&network.org_id).cloned();
+    }
+    networks.sort_by_key(|network| network.asn);
+
+    debug!(
+        "Found {} of {} ASNs in PeeringDB",
+        networks.len(),
+        asns.len()
+    );
+    Ok(networks)
+}
+
+/// Finds the networks of every PeeringDB organization whose name contains the search term,
+/// ignoring case.
+pub fn search_org_networks(
+    name: &str,
+    verify_cache_interval: Duration,
+) -> Result<Vec<Network>, Box<dyn Error>> {
+    let url = Url::parse_with_params(&format!("{PEERINGDB_API}/org"), [("name__contains", name)])?;
+    let orgs = fetch_data::<Organization>(url.as_str(), verify_cache_interval)?;
+    for org in &orgs {
+        info!(
+            "Organization {name} matched PeeringDB organization {}",
+            org.name
+        );
+    }
+    let org_names: HashMap<u32, String> = orgs.into_iter().map(|org| (org.id, org.name)).collect();
+    let org_ids: Vec<u32> = org_names.keys().copied().collect();
+
+    let mut networks: Vec<Network> = Vec::new();
+    for chunk in org_ids.chunks(ASNS_PER_REQUEST) {
+        let url = format!("{PEERINGDB_API}/net?org_id__in={}", join_ids(chunk));
+        networks.extend(fetch_data::<Network>(&url, verify_cache_interval)?);
+    }
+    for network in &mut networks {
+        network.org_name = org_names.get(&network.org_id).cloned();
+    }
+    networks.sort_by_key(|network| network.asn);
+    debug!(
+        "Found {} networks of {} organizations matching {name}",
+        networks.len(),
+        org_names.len()
+    );
+    Ok(networks)
+}
+
+/// Prints the network details as comment lines ahead of plain text results.
+pub fn render_header(output: &mut dyn Write, networks: &[Network]) -> io::Result<()> {
+    for network in networks {
+        writeln!(
+            output,
+            "# AS{} {} (org: {}, type: {}, traffic: {}, irr: {})",
+            network.asn,
+            network.name,
+            network.org_name.as_deref().unwrap_or("-"),
+            or_dash(&network.network_type),
+            or_dash(&network.traffic),
+            or_dash(&network.irr_as_set),
+        )?;
+    }
+    Ok(())
+}
+
+fn or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
@@ -0,0 +1,311 @@
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::provenance::Provenance;
+use crate::render::{self, address_count, Format, RenderOptions, Renderer, ReportFormat};
+use crate::{peeringdb, template};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// External plugins are executables on the PATH named with this prefix followed by the plugin
+/// name, the way git and cargo discover subcommands
+const EXTERNAL_PREFIX: &str = "bgp-scout-output-";
+
+/// An output format selectable with `--plugin`, either built in or provided by an executable
+pub trait OutputPlugin: Renderer {
+    fn name(&self) -> &str;
+    fn description(&self) -> String;
+}
+
+/// A built-in output format, exposed as a plugin
+#[derive(Debug)]
+struct BuiltinPlugin {
+    name: String,
+    format: Format,
+}
+
+impl Renderer for BuiltinPlugin {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.format.renderer().render(output, prefixes, options)
+    }
+}
+
+impl OutputPlugin for BuiltinPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> String {
+        self.format
+            .to_possible_value()
+            .and_then(|value| value.get_help().map(ToString::to_string))
+            .unwrap_or_default()
+    }
+}
+
+/// An executable rendering the results it reads as NDJSON from its stdin. The first line holds
+/// the render options, followed by one line per prefix; whatever the executable writes to its
+/// stdout becomes the output.
+#[derive(Debug)]
+struct ExternalPlugin {
+    name: String,
+    path: PathBuf,
+}
+
+/// First line sent to an external plugin
+#[derive(Debug, Serialize)]
+struct PluginHeader<'data> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: &'static str,
+    count: usize,
+    ranges: bool,
+    rpz_action: Option<String>,
+    zone_serial: Option<u32>,
+    acl_name: &'data str,
+    namespace: &'data str,
+    direction: Option<String>,
+    gcp_network: &'data str,
+    flowspec_rate_limit: Option<u64>,
+    rtbh_platform: Option<String>,
+    rtbh_tag: u32,
+    exabgp_next_hop: &'data str,
+    exabgp_communities: &'data [String],
+    networks: Option<&'data [peeringdb::Network]>,
+    provenance: Option<&'data Provenance>,
+}
+
+fn value_name<T: ValueEnum>(value: &T) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+impl ExternalPlugin {
+    fn write_input(
+        input: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let header = PluginHeader {
+            kind: "options",
+            version: env!("CARGO_PKG_VERSION"),
+            count: prefixes.len(),
+            ranges: options.ranges,
+            rpz_action: value_name(&options.rpz_action),
+            zone_serial: options.zone_serial,
+            acl_name: options.acl_name,
+            namespace: options.namespace,
+            direction: value_name(&options.direction),
+            gcp_network: options.gcp_network,
+            flowspec_rate_limit: options.flowspec_rate_limit,
+            rtbh_platform: value_name(&options.rtbh_platform),
+            rtbh_tag: options.rtbh_tag,
+            exabgp_next_hop: options.exabgp_next_hop,
+            exabgp_communities: options.exabgp_communities,
+            networks: options.networks,
+            provenance: options.provenance,
+        };
+        serde_json::to_writer(&mut *input, &header)?;
+        writeln!(input)?;
+
+        let origins = options
+            .origins
+            .map(|origins| template::covered_origins(prefixes, origins))
+            .unwrap_or_default();
+        for prefix in prefixes {
+            serde_json::to_writer(
+                &mut *input,
+                &json!({
+                    "type": "prefix",
+                    "prefix": prefix.to_string(),
+                    "first": prefix.network().to_string(),
+                    "last": prefix.broadcast().to_string(),
+                    "family": if matches!(prefix, IpNet::V4(_)) { 4 } else { 6 },
+                    "addresses": address_count(prefix).to_string(),
+                    "origin_asns": origins.get(prefix).cloned().unwrap_or_default(),
+                }),
+            )?;
+            writeln!(input)?;
+        }
+        input.flush()?;
+        Ok(())
+    }
+}
+
+impl Renderer for ExternalPlugin {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        debug!(
+            "Rendering {} prefixes with {}",
+            prefixes.len(),
+            self.path.display()
+        );
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("Could not run plugin {}: {e}", self.path.display()))?;
+        let stdin = child.stdin.take().ok_or("Plugin stdin is not piped")?;
+        let mut stdout = child.stdout.take().ok_or("Plugin stdout is not piped")?;
+
+        // Feed the input while copying the output, so a plugin writing before it has read
+        // everything cannot deadlock against a full pipe
+        let written = thread::scope(|scope| {
+            let writer = scope.spawn(move || {
+                let mut input = BufWriter::new(stdin);
+                match Self::write_input(&mut input, prefixes, options) {
+                    // A plugin may exit without reading all of its input
+                    Err(e)
+                        if e.downcast_ref::<io::Error>()
+                            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) =>
+                    {
+                        Ok(())
+                    }
+                    result => result.map_err(|e| e.to_string()),
+                }
+            });
+            let copied = io::copy(&mut stdout, output);
+            let written = writer.join().map_err(|_| "Plugin input writer panicked")?;
+            copied?;
+            written.map_err(Box::<dyn Error>::from)
+        });
+        let status = child.wait()?;
+        written.map_err(|e| format!("Could not send results to plugin {}: {e}", self.name))?;
+        if !status.success() {
+            return Err(format!("Plugin {} failed with {status}", self.name).into());
+        }
+        Ok(())
+    }
+}
+
+impl OutputPlugin for ExternalPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+fn builtin_plugins() -> Vec<Box<dyn OutputPlugin>> {
+    Format::value_variants()
+        .iter()
+        .filter_map(|format| {
+            let plugin: Box<dyn OutputPlugin> = Box::new(BuiltinPlugin {
+                name: value_name(format)?,
+                format: *format,
+            });
+            Some(plugin)
+        })
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Finds the external plugins on the PATH. A plugin shadowed by an earlier PATH entry or by a
+/// built-in format is skipped.
+fn external_plugins() -> Vec<Box<dyn OutputPlugin>> {
+    let builtins = builtin_plugins();
+    let mut plugins: Vec<Box<dyn OutputPlugin>> = Vec::new();
+    let Some(path) = env::var_os("PATH") else {
+        return plugins;
+    };
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, PathBuf)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                let name = file_name.strip_prefix(EXTERNAL_PREFIX)?.to_string();
+                Some((name, entry.path()))
+            })
+            .filter(|(name, path)| !name.is_empty() && is_executable(path))
+            .collect();
+        found.sort();
+        for (name, path) in found {
+            if builtins.iter().any(|builtin| builtin.name() == name) {
+                warn!(
+                    "Ignoring plugin {}, {name} is a built-in format",
+                    path.display()
+                );
+            } else if plugins.iter().any(|plugin| plugin.name() == name) {
+                debug!("Ignoring shadowed plugin {}", path.display());
+            } else {
+                plugins.push(Box::new(ExternalPlugin { name, path }));
+            }
+        }
+    }
+    plugins
+}
+
+/// Returns the built-in output formats followed by the external plugins found on the PATH.
+pub fn discover() -> Vec<Box<dyn OutputPlugin>> {
+    let mut plugins = builtin_plugins();
+    plugins.extend(external_plugins());
+    plugins
+}
+
+/// Looks up an output plugin by name.
+pub fn find(name: &str) -> Result<Box<dyn OutputPlugin>, Box<dyn Error>> {
+    discover()
+        .into_iter()
+        .find(|plugin| plugin.name() == name)
+        .ok_or_else(|| {
+            format!("No output plugin {name}, expected a built-in format or {EXTERNAL_PREFIX}{name} on the PATH").into()
+        })
+}
+
+pub fn render_plugins(
+    plugins: &[Box<dyn OutputPlugin>],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => {
+            let plugins: Vec<_> = plugins
+                .iter()
+                .map(|plugin| json!({"name": plugin.name(), "description": plugin.description()}))
+                .collect();
+            serde_json::to_writer(io::stdout(), &plugins)?;
+        }
+        ReportFormat::Text => {
+            for plugin in plugins {
+                println!("{}", plugin.name());
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = plugins
+                .iter()
+                .map(|plugin| vec![plugin.name().to_string(), plugin.description()])
+                .collect();
+            render::write_table(&mut io::stdout(), &["NAME", "DESCRIPTION"], &rows)?;
+        }
+    }
+    Ok(())
+}
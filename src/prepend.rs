@@ -0,0 +1,153 @@
+use bgpkit_parser::BgpkitParser;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+use crate::mrt;
+use crate::render::{self, ReportFormat};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Prepending seen on the paths to a prefix of the target AS through one upstream
+#[derive(Debug, Serialize)]
+pub struct Prepending {
+    pub prefix: IpNet,
+    /// AS the target announces the prefix to on these paths, none when the collector peer is
+    /// the target itself
+    pub upstream: Option<u32>,
+    pub paths: u64,
+    /// Paths where the target repeats itself
+    pub prepended_paths: u64,
+    /// Most extra copies of the target on one path
+    pub max_prepends: usize,
+    /// Extra copies of the target per path, on average
+    pub mean_prepends: f64,
+    /// Paths where an AS between the target and the collector repeats itself
+    pub transit_prepended_paths: u64,
+}
+
+/// Counts per prefix and upstream while scanning
+#[derive(Debug, Default)]
+struct Counts {
+    paths: u64,
+    prepended_paths: u64,
+    max_prepends: usize,
+    total_prepends: u64,
+    transit_prepended_paths: u64,
+}
+
+/// Scans the AS paths of an MRT file reaching the prefixes originated by the target AS for
+/// prepending, by the target towards each upstream and by the transit ASes further along.
+pub fn prepending(file_name: &str, asn: u32) -> Result<Vec<Prepending>, Box<dyn Error>> {
+    let parser = BgpkitParser::from_reader(mrt::open(file_name)?)
+        .add_filter("type", "announce")?
+        .add_filter("origin_asn", &asn.to_string())?;
+
+    debug!("Scanning {file_name} for prepending by AS{asn}");
+    let mut counts: BTreeMap<(IpNet, Option<u32>), Counts> = BTreeMap::new();
+    for elem in parser.into_elem_iter() {
+        let Some(path) = elem
+            .as_path
+            .as_ref()
+            .and_then(|path| path.to_u32_vec_opt(false))
+        else {
+            continue;
+        };
+        // Paths run from the collector peer towards the origin
+        let copies = path.iter().rev().take_while(|&&hop| hop == asn).count();
+        if copies == 0 {
+            continue;
+        }
+        let transit = &path[..path.len() - copies];
+        let upstream = transit.last().copied();
+        let entry = counts.entry((elem.prefix.prefix, upstream)).or_default();
+        let prepends = copies - 1;
+        entry.paths += 1;
+        entry.total_prepends += prepends as u64;
+        entry.max_prepends = entry.max_prepends.max(prepends);
+        if prepends > 0 {
+            entry.prepended_paths += 1;
+        }
+        if transit.windows(2).any(|hops| hops[0] == hops[1]) {
+            entry.transit_prepended_paths += 1;
+        }
+    }
+
+    let prepending: Vec<Prepending> = counts
+        .into_iter()
+        .map(|((prefix, upstream), counts)| Prepending {
+            prefix,
+            upstream,
+            paths: counts.paths,
+            prepended_paths: counts.prepended_paths,
+            max_prepends: counts.max_prepends,
+            #[allow(clippy::cast_precision_loss)]
+            mean_prepends: counts.total_prepends as f64 / counts.paths as f64,
+            transit_prepended_paths: counts.transit_prepended_paths,
+        })
+        .collect();
+    debug!(
+        "Found {} prefix and upstream pairs of AS{asn}",
+        prepending.len()
+    );
+    Ok(prepending)
+}
+
+fn upstream_name(upstream: Option<u32>) -> String {
+    upstream.map_or_else(|| "-".to_string(), |asn| format!("AS{asn}"))
+}
+
+pub fn render_prepending(
+    prepending: &[Prepending],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), prepending)?,
+        ReportFormat::Text => {
+            for entry in prepending {
+                println!(
+                    "{} upstream={} paths={} prepended={} max={} mean={:.2} transit_prepended={}",
+                    entry.prefix,
+                    upstream_name(entry.upstream),
+                    entry.paths,
+                    entry.prepended_paths,
+                    entry.max_prepends,
+                    entry.mean_prepends,
+                    entry.transit_prepended_paths
+                );
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = prepending
+                .iter()
+                .map(|entry| {
+                    vec![
+                        entry.prefix.to_string(),
+                        upstream_name(entry.upstream),
+                        entry.paths.to_string(),
+                        entry.prepended_paths.to_string(),
+                        entry.max_prepends.to_string(),
+                        format!("{:.2}", entry.mean_prepends),
+                        entry.transit_prepended_paths.to_string(),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &[
+                    "PREFIX",
+                    "UPSTREAM",
+                    "PATHS",
+                    "PREPENDED",
+                    "MAX",
+                    "MEAN",
+                    "TRANSIT PREPENDED",
+                ],
+                &rows,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::cache;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Where a set of results came from and how it was produced, embedded in output so it can be
+/// traced back to its inputs
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    pub tool: String,
+    pub generated_at: String,
+    /// Command line that produced the output
+    pub command: String,
+    /// URL or file the prefixes were looked up in
+    pub source: String,
+    /// Last-Modified of a downloaded source, or the modification time of a local file
+    pub source_last_modified: Option<String>,
+    pub source_etag: Option<String>,
+    pub source_sha256: Option<String>,
+    /// Every setting affecting which prefixes are output, including defaults
+    pub filters: BTreeMap<String, String>,
+    /// Why the results are incomplete, when a scan limit stopped the lookup early
+    pub partial: Option<String>,
+}
+
+/// Quotes a command line argument for a POSIX shell when it contains special characters.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+impl Provenance {
+    /// Describes results derived from a source URL or file, looking up the validators of a
+    /// downloaded source in the cache.
+    pub fn new(source: String, filters: BTreeMap<String, String>) -> Self {
+        let mut provenance = Self {
+            tool: format!("bgp-scout {}", env!("CARGO_PKG_VERSION")),
+            generated_at: Utc::now().to_rfc3339(),
+            command: env::args()
+                .map(|arg| shell_quote(&arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            source_last_modified: None,
+            source_etag: None,
+            source_sha256: None,
+            source,
+            filters,
+            partial: None,
+        };
+        if provenance.source.contains("://") {
+            match cache::read_manifest(&cache::manifest_path(&provenance.source)) {
+                Some(manifest) => {
+                    provenance.source_last_modified = manifest.last_modified;
+                    provenance.source_etag = manifest.etag;
+                    provenance.source_sha256 =
+                        manifest.object.split('.').next().map(str::to_string);
+                }
+                None => debug!("No cached download of {}", provenance.source),
+            }
+        } else if let Ok(modified) = fs::metadata(&provenance.source).and_then(|m| m.modified()) {
+            provenance.source_last_modified = Some(DateTime::<Utc>::from(modified).to_rfc3339());
+        }
+        provenance
+    }
+
+    /// Writes the provenance as comment lines starting with the given comment marker.
+    pub fn write_comments(&self, output: &mut dyn Write, comment: &str) -> io::Result<()> {
+        writeln!(
+            output,
+            "{comment} Generated by {} at {}",
+            self.tool, self.generated_at
+        )?;
+        writeln!(output, "{comment} Command: {}", self.command)?;
+        writeln!(output, "{comment} Source: {}", self.source)?;
+        for (name, value) in [
+            ("Last-Modified", &self.source_last_modified),
+            ("ETag", &self.source_etag),
+            ("SHA-256", &self.source_sha256),
+        ] {
+            if let Some(value) = value {
+                writeln!(output, "{comment} Source {name}: {value}")?;
+            }
+        }
+        let filters: Vec<String> = self
+            .filters
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        writeln!(output, "{comment} Filters: {}", filters.join(" "))?;
+        if let Some(partial) = &self.partial {
+            writeln!(output, "{comment} Partial results: {partial}")?;
+        }
+        Ok(())
+    }
+}
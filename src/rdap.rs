@@ -0,0 +1,104 @@
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// RDAP bootstrap service redirecting each query to the registry responsible for the address
+pub const DEFAULT_RDAP_URL: &str = "https://rdap.org";
+
+/// Prefix of the `cidr0_cidrs` extension listing the network as CIDR blocks
+#[derive(Debug, Deserialize)]
+struct Cidr {
+    v4prefix: Option<Ipv4Addr>,
+    v6prefix: Option<Ipv6Addr>,
+    length: u8,
+}
+
+/// The fields of an RDAP IP network object used to describe a registration
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IpNetwork {
+    start_address: IpAddr,
+    end_address: IpAddr,
+    name: Option<String>,
+    country: Option<String>,
+    #[serde(default, rename = "cidr0_cidrs")]
+    cidrs: Vec<Cidr>,
+}
+
+/// Allocation a registry holds for an address, which may not be announced at all
+#[derive(Debug, Clone, Serialize)]
+pub struct Registration {
+    /// First CIDR block of the allocation, none when the registry only gives the range
+    pub network: Option<IpNet>,
+    pub start_address: IpAddr,
+    pub end_address: IpAddr,
+    pub name: Option<String>,
+    pub country: Option<String>,
+}
+
+impl Registration {
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.start_address <= ip && ip <= self.end_address
+    }
+}
+
+impl Cidr {
+    fn network(&self) -> Option<IpNet> {
+        match (self.v4prefix, self.v6prefix) {
+            (Some(v4), _) => Ipv4Net::new(v4, self.length).ok().map(IpNet::V4),
+            (None, Some(v6)) => Ipv6Net::new(v6, self.length).ok().map(IpNet::V6),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Looks up the registration of an address with RDAP. Addresses no registry knows of, and
+/// failed queries, give `None`.
+fn lookup(rdap_url: &str, ip: IpAddr, verify_cache_interval: Duration) -> Option<Registration> {
+    let url = format!("{}/ip/{ip}", rdap_url.trim_end_matches('/'));
+    debug!("Querying RDAP {url}");
+    let network = source::fetch_file(&url, verify_cache_interval).and_then(|path| {
+        Ok(serde_json::from_str::<IpNetwork>(&fs::read_to_string(
+            path,
+        )?)?)
+    });
+    match network {
+        Ok(network) => Some(Registration {
+            network: network.cidrs.iter().find_map(Cidr::network),
+            start_address: network.start_address,
+            end_address: network.end_address,
+            name: network.name,
+            country: network.country,
+        }),
+        Err(e) => {
+            warn!("No registration found for {ip}: {e}");
+            None
+        }
+    }
+}
+
+/// Looks up the registrations of addresses, querying once per allocation: addresses inside an
+/// allocation found before reuse it.
+pub fn lookup_all(
+    rdap_url: &str,
+    ips: &[IpAddr],
+    verify_cache_interval: Duration,
+) -> Vec<Option<Registration>> {
+    let mut found: Vec<Registration> = Vec::new();
+    ips.iter()
+        .map(|&ip| {
+            if let Some(registration) = found.iter().find(|known| known.contains(ip)) {
+                return Some(registration.clone());
+            }
+            let registration = lookup(rdap_url, ip, verify_cache_interval)?;
+            found.push(registration.clone());
+            Some(registration)
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use reqwest::Url;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const DEFAULT_REDIS_PORT: u16 = 6379;
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Keeps each SADD command to a reasonable size for very large result sets
+const MEMBERS_PER_COMMAND: usize = 1_000;
+
+/// A minimal RESP client supporting the handful of commands needed to publish results
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    /// Connects to a `redis://[[user]:password@]host[:port][/db]` URL, authenticating and
+    /// selecting the database when given.
+    fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        let url = Url::parse(url)?;
+        if url.scheme() != "redis" {
+            return Err(format!("Unsupported Redis URL scheme {}", url.scheme()).into());
+        }
+        let host = url.host_str().ok_or("Redis URL has no host")?;
+        let port = url.port().unwrap_or(DEFAULT_REDIS_PORT);
+        debug!("Connecting to Redis at {host}:{port}");
+
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+        stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        if let Some(password) = url.password() {
+            match url.username() {
+                "" => connection.command(&["AUTH", password])?,
+                username => connection.command(&["AUTH", username, password])?,
+            };
+        }
+        let database = url.path().trim_start_matches('/');
+        if !database.is_empty() {
+            connection.command(&["SELECT", database])?;
+        }
+        Ok(connection)
+    }
+
+    /// Sends a command and returns its reply, converting error replies into errors.
+    fn command<T: AsRef<str>>(&mut self, args: &[T]) -> Result<String, Box<dyn Error>> {
+        let mut request = format!("*{}\r\n", args.len());
+        for arg in args {
+            let arg = arg.as_ref();
+            request.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+        }
+        self.writer.write_all(request.as_bytes())?;
+        self.read_reply()
+    }
+
+    fn read_reply(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err("Redis closed the connection".into());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (kind, value) = line.split_at(line.len().min(1));
+        match kind {
+            "+" | ":" => Ok(value.to_string()),
+            "-" => Err(format!("Redis error: {value}").into()),
+            "$" => {
+                let Ok(len) = usize::try_from(value.parse::<i64>()?) else {
+                    return Ok(String::new());
+                };
+                // Bulk strings are followed by a CRLF that is not part of the length
+                let mut data = vec![0; len + 2];
+                self.reader.read_exact(&mut data)?;
+                data.truncate(len);
+                Ok(String::from_utf8_lossy(&data).into_owned())
+            }
+            "*" => {
+                let count = value.parse::<i64>()?;
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    items.push(self.read_reply()?);
+                }
+                Ok(items.join(" "))
+            }
+            _ => Err(format!("Unexpected Redis reply {line}").into()),
+        }
+    }
+}
+
+/// Replaces the Redis SET at `key` with the given members. The members are written to a
+/// temporary key first and renamed over the target, so readers never see a partial set.
+pub fn replace_set(url: &str, key: &str, members: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(url)?;
+
+    if members.is_empty() {
+        // Redis has no empty sets, deleting the key is the equivalent
+        debug!("No members to publish, deleting Redis key {key}");
+        connection.command(&["DEL", key])?;
+        return Ok(());
+    }
+
+    let temp_key = format!("{key}.tmp.{}", std::process::id());
+    connection.command(&["DEL", &temp_key])?;
+    for chunk in members.chunks(MEMBERS_PER_COMMAND) {
+        let mut args = vec!["SADD", &temp_key];
+        args.extend(chunk.iter().map(String::as_str));
+        connection.command(&args)?;
+    }
+    connection.command(&["RENAME", &temp_key, key])?;
+    debug!("Published {} members to Redis key {key}", members.len());
+    Ok(())
+}
@@ -0,0 +1,885 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::attributes::PrefixAttributes;
+use crate::provenance::Provenance;
+use crate::{frr, peeringdb, rir};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Output format of the results
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One result per line
+    #[default]
+    Text,
+    /// JSON document
+    Json,
+    /// CSV with a header row, one prefix per row
+    Csv,
+    /// Aligned table for interactive use
+    Table,
+    /// rbldnsd ip4trie/ip6trie dataset for serving a DNSBL
+    Rbldns,
+    /// Response Policy Zone with an rpz-ip trigger per prefix
+    Rpz,
+    /// BIND acl statement
+    BindAcl,
+    /// Unbound access-control lines
+    Unbound,
+    /// nginx allow directives followed by deny all
+    Nginx,
+    /// Envoy RBAC principals matching the source IP
+    Envoy,
+    /// Kubernetes NetworkPolicy with an ipBlock per prefix
+    K8sNetworkpolicy,
+    /// gcloud commands creating GCP VPC firewall rules
+    Gcloud,
+    /// ExaBGP flow routes discarding or rate limiting traffic to each prefix
+    Flowspec,
+    /// Remotely triggered blackhole static routes for IOS, Junos or FRR
+    Rtbh,
+    /// ExaBGP API commands announcing each prefix, for a process run by ExaBGP
+    Exabgp,
+    /// Shell script running vtysh to replace FRR prefix lists permitting each prefix
+    FrrVtysh,
+}
+
+/// Output format of the report subcommands
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One result per line
+    #[default]
+    Text,
+    /// JSON document
+    Json,
+    /// Aligned table for interactive use
+    Table,
+}
+
+impl Format {
+    pub fn renderer(self) -> Box<dyn Renderer> {
+        match self {
+            Self::Text => Box::new(TextRenderer),
+            Self::Json => Box::new(JsonRenderer),
+            Self::Csv => Box::new(CsvRenderer),
+            Self::Table => Box::new(TableRenderer),
+            Self::Rbldns => Box::new(RbldnsRenderer),
+            Self::Rpz => Box::new(RpzRenderer),
+            Self::BindAcl => Box::new(BindAclRenderer),
+            Self::Unbound => Box::new(UnboundRenderer),
+            Self::Nginx => Box::new(NginxRenderer),
+            Self::Envoy => Box::new(EnvoyRenderer),
+            Self::K8sNetworkpolicy => Box::new(NetworkPolicyRenderer),
+            Self::Gcloud => Box::new(GcloudRenderer),
+            Self::Flowspec => Box::new(FlowspecRenderer),
+            Self::Rtbh => Box::new(RtbhRenderer),
+            Self::Exabgp => Box::new(ExabgpRenderer),
+            Self::FrrVtysh => Box::new(FrrVtyshRenderer),
+        }
+    }
+
+    /// Whether the format can list addresses as ranges, the others only take prefixes
+    pub const fn supports_ranges(self) -> bool {
+        matches!(self, Self::Text | Self::Json | Self::Csv | Self::Table)
+    }
+}
+
+/// Policy applied by resolvers to responses resolving into a listed prefix
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RpzAction {
+    /// Answer with NXDOMAIN
+    Nxdomain,
+    /// Answer with NODATA
+    Nodata,
+    /// Drop the response without answering
+    #[default]
+    Drop,
+}
+
+impl RpzAction {
+    const fn target(self) -> &'static str {
+        match self {
+            Self::Nxdomain => ".",
+            Self::Nodata => "*.",
+            Self::Drop => "rpz-drop.",
+        }
+    }
+}
+
+/// Router configuration syntax of generated blackhole routes
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RtbhPlatform {
+    /// Cisco IOS routes to Null0
+    #[default]
+    Ios,
+    /// Junos set commands for discard routes
+    Junos,
+    /// FRRouting blackhole routes
+    Frr,
+}
+
+/// Traffic direction restricted by a generated policy
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    Ingress,
+    #[default]
+    Egress,
+}
+
+/// Options and enrichment data shared by every renderer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderOptions<'data> {
+    /// Print prefixes as first-last address ranges
+    pub ranges: bool,
+    /// PeeringDB records of the target ASNs
+    pub networks: Option<&'data [peeringdb::Network]>,
+    /// RIR delegations used to annotate each prefix
+    pub delegations: Option<&'data [rir::Delegation]>,
+    /// Origin ASNs of the announced prefixes, before aggregation
+    pub origins: Option<&'data HashMap<IpNet, HashSet<u32>>>,
+    /// Representative route attributes of the announced prefixes
+    pub attributes: Option<&'data PrefixAttributes>,
+    /// Policy of the RPZ records
+    pub rpz_action: RpzAction,
+    /// SOA serial of generated zones, defaults to the current unix time
+    pub zone_serial: Option<u32>,
+    /// Name of generated ACLs and policies
+    pub acl_name: &'data str,
+    /// Kubernetes namespace of generated policies
+    pub namespace: &'data str,
+    /// Traffic direction of generated policies
+    pub direction: Direction,
+    /// GCP VPC network of generated firewall rules
+    pub gcp_network: &'data str,
+    /// Rate in bytes per second generated flow routes limit traffic to, instead of discarding it
+    pub flowspec_rate_limit: Option<u64>,
+    /// Router syntax of generated blackhole routes
+    pub rtbh_platform: RtbhPlatform,
+    /// Tag of generated blackhole routes, matched by the policy announcing them
+    pub rtbh_tag: u32,
+    /// Next hop of the routes announced through ExaBGP, `self` for the local address
+    pub exabgp_next_hop: &'data str,
+    /// Communities attached to the routes announced through ExaBGP
+    pub exabgp_communities: &'data [String],
+    /// Provenance embedded as comments, or as metadata in JSON
+    pub provenance: Option<&'data Provenance>,
+}
+
+/// Writes the provenance of the results as comment lines, when requested.
+fn write_provenance(
+    output: &mut dyn Write,
+    options: &RenderOptions<'_>,
+    comment: &str,
+) -> io::Result<()> {
+    match options.provenance {
+        Some(provenance) => provenance.write_comments(output, comment),
+        None => Ok(()),
+    }
+}
+
+/// Writes a set of result prefixes in a particular output format.
+pub trait Renderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Debug)]
+pub struct TextRenderer;
+
+impl Renderer for TextRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        write_provenance(output, options, "#")?;
+        if let Some(networks) = options.networks {
+            peeringdb::render_header(output, networks)?;
+        }
+        if let Some(delegations) = options.delegations {
+            let annotations = rir::annotate(prefixes, delegations, options.ranges);
+            rir::render_annotations(output, &annotations)?;
+            return Ok(());
+        }
+        for prefix in prefixes {
+            writeln!(output, "{}", format_prefix(prefix, options.ranges))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        write_json_document(output, prefixes_value(prefixes, options)?, options)
+    }
+}
+
+/// Converts prefixes to their JSON representation, annotated with their RIR allocation when
+/// delegations are given, or as objects with their route attributes when those are.
+pub fn prefixes_value(
+    prefixes: &[IpNet],
+    options: &RenderOptions<'_>,
+) -> serde_json::Result<serde_json::Value> {
+    match (options.delegations, options.attributes) {
+        (Some(delegations), _) => {
+            serde_json::to_value(rir::annotate(prefixes, delegations, options.ranges))
+        }
+        (None, Some(attributes)) => Ok(serde_json::Value::Array(
+            prefixes
+                .iter()
+                .map(|prefix| {
+                    let mut object = serde_json::Map::new();
+                    object.insert(
+                        "prefix".to_string(),
+                        format_prefix(prefix, options.ranges).into(),
+                    );
+                    attributes.insert_json(&mut object, prefix);
+                    serde_json::Value::Object(object)
+                })
+                .collect(),
+        )),
+        (None, None) => serde_json::to_value(
+            prefixes
+                .iter()
+                .map(|prefix| format_prefix(prefix, options.ranges))
+                .collect::<Vec<_>>(),
+        ),
+    }
+}
+
+/// Writes a JSON result, wrapping it together with the PeeringDB network records when enrichment
+/// is enabled and the provenance metadata when requested.
+pub fn write_json_document(
+    output: &mut dyn Write,
+    payload: serde_json::Value,
+    options: &RenderOptions<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let document = match (options.networks, options.provenance) {
+        (None, None) => payload,
+        (networks, provenance) => {
+            let mut document = serde_json::Map::new();
+            if let Some(provenance) = provenance {
+                document.insert("metadata".to_string(), serde_json::to_value(provenance)?);
+            }
+            if let Some(networks) = networks {
+                document.insert("networks".to_string(), serde_json::to_value(networks)?);
+            }
+            document.insert("prefixes".to_string(), payload);
+            serde_json::Value::Object(document)
+        }
+    };
+    serde_json::to_writer(output, &document)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct CsvRenderer;
+
+impl Renderer for CsvRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        write_provenance(output, options, "#")?;
+        let mut header = vec!["prefix"];
+        if let Some(attributes) = options.attributes {
+            header.extend(attributes.fields().iter().map(|field| field.name()));
+        }
+        writeln!(output, "{}", header.join(","))?;
+        for prefix in prefixes {
+            let mut row = vec![format_prefix(prefix, options.ranges)];
+            if let Some(attributes) = options.attributes {
+                row.extend(attributes.csv_cells(prefix));
+            }
+            writeln!(output, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct TableRenderer;
+
+impl Renderer for TableRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        write_provenance(output, options, "#")?;
+        let mut header = vec!["PREFIX", "SIZE", "FAMILY"];
+        if options.networks.is_some() {
+            header.push("AS NAME");
+        }
+        let annotations = options
+            .delegations
+            .map(|delegations| rir::annotate(prefixes, delegations, options.ranges));
+        if annotations.is_some() {
+            header.push("COUNTRY");
+        }
+
+        let mut rows = Vec::with_capacity(prefixes.len());
+        for (index, prefix) in prefixes.iter().enumerate() {
+            let mut row = vec![
+                format_prefix(prefix, options.ranges),
+                address_count(prefix).to_string(),
+                match prefix {
+                    IpNet::V4(_) => "IPv4".to_string(),
+                    IpNet::V6(_) => "IPv6".to_string(),
+                },
+            ];
+            if let Some(networks) = options.networks {
+                row.push(as_names(prefix, networks, options.origins));
+            }
+            if let Some(annotations) = &annotations {
+                row.push(
+                    annotations
+                        .get(index)
+                        .and_then(|annotation| annotation.country.clone())
+                        .unwrap_or_else(|| "-".to_string()),
+                );
+            }
+            rows.push(row);
+        }
+        write_table(output, &header, &rows)?;
+        Ok(())
+    }
+}
+
+/// Answer returned for listed addresses, with the TXT record explaining the listing
+const DNSBL_DEFAULT_ANSWER: &str = ":127.0.0.2:Listed by bgp-scout";
+
+#[derive(Debug)]
+pub struct RbldnsRenderer;
+
+impl Renderer for RbldnsRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        // rbldnsd loads IPv4 and IPv6 entries into separate ip4trie and ip6trie datasets
+        let has_v4 = prefixes.iter().any(|prefix| matches!(prefix, IpNet::V4(_)));
+        let has_v6 = prefixes.iter().any(|prefix| matches!(prefix, IpNet::V6(_)));
+        if has_v4 && has_v6 {
+            warn!("rbldnsd datasets hold a single address family, consider --output-v4 and --output-v6");
+        }
+
+        writeln!(output, "# rbldnsd dataset generated by bgp-scout")?;
+        write_provenance(output, options, "#")?;
+        writeln!(output, "{DNSBL_DEFAULT_ANSWER}")?;
+        // Entries are always CIDR since ip6trie datasets do not accept ranges
+        for prefix in prefixes {
+            writeln!(output, "{prefix}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Time to live of the generated zone records, kept short so updates take effect quickly
+const ZONE_TTL: u32 = 300;
+
+#[derive(Debug)]
+pub struct RpzRenderer;
+
+impl Renderer for RpzRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let serial = match options.zone_serial {
+            Some(serial) => serial,
+            None => u32::try_from(Utc::now().timestamp())?,
+        };
+        writeln!(output, "; Response Policy Zone generated by bgp-scout")?;
+        write_provenance(output, options, ";")?;
+        writeln!(output, "$TTL {ZONE_TTL}")?;
+        writeln!(
+            output,
+            "@ SOA localhost. hostmaster.localhost. {serial} 3600 600 86400 {ZONE_TTL}"
+        )?;
+        writeln!(output, "@ NS localhost.")?;
+        let target = options.rpz_action.target();
+        for prefix in prefixes {
+            writeln!(output, "{} CNAME {target}", rpz_ip_owner(prefix))?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the rpz-ip trigger owner name of a prefix: the prefix length followed by the address
+/// labels in reverse order, with `zz` standing in for the `::` of an IPv6 address.
+fn rpz_ip_owner(prefix: &IpNet) -> String {
+    let mut labels = match prefix {
+        IpNet::V4(v4) => v4
+            .network()
+            .octets()
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>(),
+        IpNet::V6(v6) => {
+            let address = v6.network().to_string();
+            match address.split_once("::") {
+                Some((left, right)) => left
+                    .split(':')
+                    .filter(|group| !group.is_empty())
+                    .chain(["zz"])
+                    .chain(right.split(':').filter(|group| !group.is_empty()))
+                    .map(str::to_string)
+                    .collect(),
+                None => address.split(':').map(str::to_string).collect(),
+            }
+        }
+    };
+    labels.reverse();
+    format!("{}.{}.rpz-ip", prefix.prefix_len(), labels.join("."))
+}
+
+#[derive(Debug)]
+pub struct BindAclRenderer;
+
+impl Renderer for BindAclRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        write_provenance(output, options, "#")?;
+        writeln!(output, "acl \"{}\" {{", options.acl_name)?;
+        for prefix in prefixes {
+            writeln!(output, "    {prefix};")?;
+        }
+        writeln!(output, "}};")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnboundRenderer;
+
+impl Renderer for UnboundRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        writeln!(output, "# ACL {} generated by bgp-scout", options.acl_name)?;
+        write_provenance(output, options, "#")?;
+        for prefix in prefixes {
+            writeln!(output, "access-control: {prefix} allow")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct NginxRenderer;
+
+impl Renderer for NginxRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        writeln!(output, "# ACL {} generated by bgp-scout", options.acl_name)?;
+        write_provenance(output, options, "#")?;
+        for prefix in prefixes {
+            writeln!(output, "allow {prefix};")?;
+        }
+        writeln!(output, "deny all;")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct EnvoyRenderer;
+
+impl Renderer for EnvoyRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        // YAML fragment to embed in an RBAC policy
+        writeln!(output, "# ACL {} generated by bgp-scout", options.acl_name)?;
+        write_provenance(output, options, "#")?;
+        writeln!(output, "principals:")?;
+        writeln!(output, "- or_ids:")?;
+        writeln!(output, "    ids:")?;
+        for prefix in prefixes {
+            writeln!(output, "    - source_ip:")?;
+            writeln!(output, "        address_prefix: {}", prefix.network())?;
+            writeln!(output, "        prefix_len: {}", prefix.prefix_len())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct NetworkPolicyRenderer;
+
+impl Renderer for NetworkPolicyRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (policy_type, rule, peer) = match options.direction {
+            Direction::Ingress => ("Ingress", "ingress", "from"),
+            Direction::Egress => ("Egress", "egress", "to"),
+        };
+        write_provenance(output, options, "#")?;
+        writeln!(output, "apiVersion: networking.k8s.io/v1")?;
+        writeln!(output, "kind: NetworkPolicy")?;
+        writeln!(output, "metadata:")?;
+        writeln!(output, "  name: {}", options.acl_name)?;
+        writeln!(output, "  namespace: {}", options.namespace)?;
+        writeln!(output, "spec:")?;
+        writeln!(output, "  podSelector: {{}}")?;
+        writeln!(output, "  policyTypes:")?;
+        writeln!(output, "  - {policy_type}")?;
+        // A rule without peers would allow all traffic, so no results deny all traffic instead
+        if prefixes.is_empty() {
+            writeln!(output, "  {rule}: []")?;
+            return Ok(());
+        }
+        writeln!(output, "  {rule}:")?;
+        writeln!(output, "  - {peer}:")?;
+        for prefix in prefixes {
+            writeln!(output, "    - ipBlock:")?;
+            writeln!(output, "        cidr: {prefix}")?;
+        }
+        Ok(())
+    }
+}
+
+/// GCP limits the number of source or destination ranges of a single firewall rule
+const GCP_RANGES_PER_RULE: usize = 5_000;
+
+#[derive(Debug)]
+pub struct GcloudRenderer;
+
+impl Renderer for GcloudRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let (direction, ranges_flag) = match options.direction {
+            Direction::Ingress => ("INGRESS", "--source-ranges"),
+            Direction::Egress => ("EGRESS", "--destination-ranges"),
+        };
+        write_provenance(output, options, "#")?;
+        // A firewall rule may not mix IPv4 and IPv6 ranges
+        let (v4_prefixes, v6_prefixes): (Vec<&IpNet>, Vec<&IpNet>) = prefixes
+            .iter()
+            .partition(|prefix| matches!(prefix, IpNet::V4(_)));
+        for (family, family_prefixes) in [("v4", v4_prefixes), ("v6", v6_prefixes)] {
+            for (index, chunk) in family_prefixes.chunks(GCP_RANGES_PER_RULE).enumerate() {
+                let ranges: Vec<String> = chunk.iter().map(ToString::to_string).collect();
+                writeln!(
+                    output,
+                    "gcloud compute firewall-rules create {}-{family}-{} --network={} --direction={direction} --action=ALLOW --rules=all {ranges_flag}={}",
+                    options.acl_name,
+                    index + 1,
+                    options.gcp_network,
+                    ranges.join(",")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FlowspecRenderer;
+
+impl Renderer for FlowspecRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        // Fragment to include in an ExaBGP neighbor block
+        writeln!(output, "# ACL {} generated by bgp-scout", options.acl_name)?;
+        write_provenance(output, options, "#")?;
+        let component = match options.direction {
+            Direction::Ingress => "source",
+            Direction::Egress => "destination",
+        };
+        let action = match options.flowspec_rate_limit {
+            Some(rate) => format!("rate-limit {rate}"),
+            None => "discard".to_string(),
+        };
+        writeln!(output, "flow {{")?;
+        for (index, prefix) in prefixes.iter().enumerate() {
+            // ExaBGP takes IPv6 prefixes with the offset into the address to match from
+            let prefix = match prefix {
+                IpNet::V4(_) => prefix.to_string(),
+                IpNet::V6(_) => format!("{prefix}/0"),
+            };
+            writeln!(output, "    route {}-{} {{", options.acl_name, index + 1)?;
+            writeln!(output, "        match {{")?;
+            writeln!(output, "            {component} {prefix};")?;
+            writeln!(output, "        }}")?;
+            writeln!(output, "        then {{")?;
+            writeln!(output, "            {action};")?;
+            writeln!(output, "        }}")?;
+            writeln!(output, "    }}")?;
+        }
+        writeln!(output, "}}")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RtbhRenderer;
+
+impl Renderer for RtbhRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let tag = options.rtbh_tag;
+        let comment = match options.rtbh_platform {
+            RtbhPlatform::Ios | RtbhPlatform::Frr => "!",
+            RtbhPlatform::Junos => "#",
+        };
+        writeln!(
+            output,
+            "{comment} Blackhole routes {} generated by bgp-scout",
+            options.acl_name
+        )?;
+        write_provenance(output, options, comment)?;
+        for prefix in prefixes {
+            match (options.rtbh_platform, prefix) {
+                // IOS takes IPv4 routes with a netmask
+                (RtbhPlatform::Ios, IpNet::V4(v4)) => writeln!(
+                    output,
+                    "ip route {} {} Null0 tag {tag}",
+                    v4.network(),
+                    v4.netmask()
+                )?,
+                (RtbhPlatform::Ios, IpNet::V6(_)) => {
+                    writeln!(output, "ipv6 route {prefix} Null0 tag {tag}")?;
+                }
+                (RtbhPlatform::Junos, IpNet::V4(_)) => writeln!(
+                    output,
+                    "set routing-options static route {prefix} discard tag {tag}"
+                )?,
+                (RtbhPlatform::Junos, IpNet::V6(_)) => writeln!(
+                    output,
+                    "set routing-options rib inet6.0 static route {prefix} discard tag {tag}"
+                )?,
+                (RtbhPlatform::Frr, IpNet::V4(_)) => {
+                    writeln!(output, "ip route {prefix} blackhole tag {tag}")?;
+                }
+                (RtbhPlatform::Frr, IpNet::V6(_)) => {
+                    writeln!(output, "ipv6 route {prefix} blackhole tag {tag}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the ExaBGP API command announcing or withdrawing a prefix, e.g.
+/// `announce route 192.0.2.0/24 next-hop self community [65535:666]`.
+pub fn exabgp_command(action: &str, prefix: &IpNet, options: &RenderOptions<'_>) -> String {
+    let mut command = format!(
+        "{action} route {prefix} next-hop {}",
+        options.exabgp_next_hop
+    );
+    if !options.exabgp_communities.is_empty() {
+        command.push_str(&format!(
+            " community [{}]",
+            options.exabgp_communities.join(" ")
+        ));
+    }
+    command
+}
+
+#[derive(Debug)]
+pub struct ExabgpRenderer;
+
+impl Renderer for ExabgpRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        // Read by ExaBGP as API commands, which have no comment syntax, so no provenance
+        for prefix in prefixes {
+            writeln!(output, "{}", exabgp_command("announce", prefix, options))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FrrVtyshRenderer;
+
+impl Renderer for FrrVtyshRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let name = options.acl_name;
+        writeln!(output, "#!/bin/sh")?;
+        writeln!(output, "# Prefix lists {name} generated by bgp-scout")?;
+        write_provenance(output, options, "#")?;
+        // Replaces both lists in one vtysh session; use --apply frr to only change what differs
+        writeln!(output, "vtysh \\")?;
+        writeln!(output, "    -c 'configure terminal' \\")?;
+        for keyword in ["ip", "ipv6"] {
+            writeln!(output, "    -c 'no {keyword} prefix-list {name}' \\")?;
+            let family_prefixes = prefixes
+                .iter()
+                .filter(|prefix| frr::prefix_list_keyword(prefix) == keyword);
+            for (index, prefix) in family_prefixes.enumerate() {
+                let seq = (index as u32 + 1) * frr::SEQ_STEP;
+                writeln!(
+                    output,
+                    "    -c '{keyword} prefix-list {name} seq {seq} permit {prefix}' \\"
+                )?;
+            }
+        }
+        writeln!(output, "    -c 'end'")?;
+        Ok(())
+    }
+}
+
+/// Names the networks originating a result prefix. Aggregation may merge announcements from
+/// several origins, so every announced prefix overlapping the result contributes its origins.
+fn as_names(
+    prefix: &IpNet,
+    networks: &[peeringdb::Network],
+    origins: Option<&HashMap<IpNet, HashSet<u32>>>,
+) -> String {
+    let Some(origins) = origins else {
+        return "-".to_string();
+    };
+    let mut asns: Vec<u32> = origins
+        .iter()
+        .filter(|(announced, _)| announced.contains(prefix) || prefix.contains(*announced))
+        .flat_map(|(_, asns)| asns.iter().copied())
+        .collect();
+    asns.sort_unstable();
+    asns.dedup();
+
+    let names: Vec<&str> = asns
+        .iter()
+        .filter_map(|asn| networks.iter().find(|network| network.asn == *asn))
+        .map(|network| network.name.as_str())
+        .collect();
+    if names.is_empty() {
+        "-".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Writes rows as left-aligned columns separated by two spaces, padding each column to its
+/// widest cell.
+pub fn write_table<T: AsRef<str>>(
+    output: &mut dyn Write,
+    header: &[&str],
+    rows: &[Vec<T>],
+) -> io::Result<()> {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.as_ref().chars().count());
+        }
+    }
+
+    let mut write_row = |cells: Vec<&str>| -> io::Result<()> {
+        let mut line = String::new();
+        for (column, (cell, width)) in cells.iter().zip(&widths).enumerate() {
+            if column + 1 == cells.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{cell:<width$}  "));
+            }
+        }
+        writeln!(output, "{line}")
+    };
+    write_row(header.to_vec())?;
+    for row in rows {
+        write_row(row.iter().map(AsRef::as_ref).collect())?;
+    }
+    Ok(())
+}
+
+/// Returns the number of addresses in a prefix, saturating for the IPv6 default route.
+pub fn address_count(prefix: &IpNet) -> u128 {
+    let host_bits = u32::from(prefix.max_prefix_len() - prefix.prefix_len());
+    1_u128.checked_shl(host_bits).unwrap_or(u128::MAX)
+}
+
+/// Counts the address space covered by a set of possibly overlapping prefixes, as the number of
+/// IPv4 addresses and of IPv6 /64 subnets.
+pub fn address_space(prefixes: &[IpNet]) -> (u128, u128) {
+    let mut ipv4_addresses: u128 = 0;
+    let mut ipv6_subnets: u128 = 0;
+    let mut long_ipv6_subnets = HashSet::new();
+    for prefix in IpNet::aggregate(&prefixes.to_vec()) {
+        match prefix {
+            IpNet::V4(_) => ipv4_addresses += address_count(&prefix),
+            // Prefixes longer than /64 count once per /64 they fall in
+            IpNet::V6(v6) if v6.prefix_len() > 64 => {
+                long_ipv6_subnets.insert(u128::from(v6.network()) >> 64);
+            }
+            IpNet::V6(v6) => {
+                let subnets = 1_u128.checked_shl(u32::from(64 - v6.prefix_len()));
+                ipv6_subnets = ipv6_subnets.saturating_add(subnets.unwrap_or(u128::MAX));
+            }
+        }
+    }
+    ipv6_subnets = ipv6_subnets.saturating_add(long_ipv6_subnets.len() as u128);
+    (ipv4_addresses, ipv6_subnets)
+}
+
+pub fn format_prefix(prefix: &IpNet, ranges: bool) -> String {
+    if ranges {
+        format!("{}-{}", prefix.network(), prefix.broadcast())
+    } else {
+        prefix.to_string()
+    }
+}
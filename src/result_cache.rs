@@ -0,0 +1,105 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::{cache, download, index};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Identifies a query whose results can be reused: the data it was answered from, the ASNs
+/// asked for and the filters applied while looking them up.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    /// The MRT file with its size and modification time, or the name of the online backend
+    pub source: String,
+    pub origin_asns: BTreeSet<u32>,
+    pub ipv4_only: bool,
+    pub ipv6_only: bool,
+    /// Collector peers the routes were restricted to, every peer when both are empty
+    pub peer_asns: BTreeSet<u32>,
+    pub peer_ips: BTreeSet<IpAddr>,
+    /// Share of the largest feed peers were required to send with --full-feed-only
+    pub full_feed_threshold: Option<u8>,
+    /// RPKI validity kept, the ROA source it was validated against and the SHA-256 digest of
+    /// the ROAs, as sources such as validator exports keep their URL while their content changes
+    pub rpki_filter: Option<(String, String, String)>,
+    /// Whether prefixes other ASNs originate as well were dropped
+    pub exclusive: bool,
+    /// ASNs whose prefixes were dropped when originated by them too
+    pub excluded_origins: BTreeSet<u32>,
+}
+
+/// A cached result file holds the key it answers followed by the prefixes and their origins
+type CachedResult = (QueryKey, HashMap<IpNet, HashSet<u32>>);
+
+/// Describes an MRT file by its path, size and modification time, so results are not reused
+/// once the file is replaced.
+pub fn mrt_source(mrt_file: &str) -> io::Result<String> {
+    let (len, secs, nanos) = index::source_stamp(mrt_file)?;
+    Ok(format!("{mrt_file} {len} {secs}.{nanos:09}"))
+}
+
+fn result_path(key: &QueryKey) -> String {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    format!(
+        "{}/{:x}.json",
+        cache::results_dir().display(),
+        hasher.finish()
+    )
+}
+
+/// Returns the cached results of a query made less than `ttl` ago.
+pub fn load(key: &QueryKey, ttl: Duration) -> Option<HashMap<IpNet, HashSet<u32>>> {
+    let path = result_path(key);
+    let age = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?
+        .elapsed()
+        .unwrap_or_default();
+    if age >= ttl {
+        debug!("Ignoring expired results {path}");
+        return None;
+    }
+    let (cached_key, prefix_origins): CachedResult = match File::open(&path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|file| Ok(serde_json::from_reader(BufReader::new(file))?))
+    {
+        Ok(cached) => cached,
+        Err(e) => {
+            debug!("Ignoring unreadable results {path}: {e}");
+            return None;
+        }
+    };
+    // Guard against hash collisions between different queries
+    if &cached_key != key {
+        return None;
+    }
+    info!("Using results cached {}s ago", age.as_secs());
+    Some(prefix_origins)
+}
+
+/// Caches the results of a query, only warning when they cannot be written.
+pub fn store(key: &QueryKey, prefix_origins: &HashMap<IpNet, HashSet<u32>>) {
+    let path = result_path(key);
+    let result = fs::create_dir_all(cache::results_dir())
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|()| {
+            let temp_path = download::temp_path(&path);
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            serde_json::to_writer(&mut writer, &(key, prefix_origins))?;
+            writer.flush()?;
+            fs::rename(&temp_path, &path)?;
+            Ok(())
+        });
+    match result {
+        Ok(()) => debug!("Cached results in {path}"),
+        Err(e) => warn!("Could not cache results in {path}: {e}"),
+    }
+}
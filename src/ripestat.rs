@@ -0,0 +1,63 @@
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+const RIPESTAT_API: &str = "https://stat.ripe.net/data";
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefixesResponse {
+    data: AnnouncedPrefixesData,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefixesData {
+    prefixes: Vec<AnnouncedPrefix>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefix {
+    prefix: String,
+}
+
+/// Looks up the prefixes announced by the given ASNs using the RIPEstat announced-prefixes API
+/// instead of scanning an MRT file.
+pub fn announced_prefixes(
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+    verify_cache_interval: Duration,
+) -> Result<HashMap<IpNet, HashSet<u32>>, Box<dyn Error>> {
+    let mut prefixes: HashMap<IpNet, HashSet<u32>> = HashMap::new();
+
+    for asn in origin_asns {
+        let url = format!("{RIPESTAT_API}/announced-prefixes/data.json?resource=AS{asn}");
+        debug!("Querying RIPEstat {url}");
+        let path = source::fetch_file(&url, verify_cache_interval)?;
+        let response: AnnouncedPrefixesResponse = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        for announced in response.data.prefixes {
+            let Ok(prefix) = IpNet::from_str(&announced.prefix) else {
+                warn!("RIPEstat returned invalid prefix {}", announced.prefix);
+                continue;
+            };
+            let wanted = match prefix {
+                IpNet::V4(_) => !ipv6_only,
+                IpNet::V6(_) => !ipv4_only,
+            };
+            if wanted && prefixes.entry(prefix).or_default().insert(*asn) {
+                trace!("Found new matching prefix {prefix} for AS{asn}");
+            }
+        }
+    }
+
+    debug!("RIPEstat returned {} prefixes", prefixes.len());
+    Ok(prefixes)
+}
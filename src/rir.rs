@@ -0,0 +1,276 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::source;
+use ipnet::IpNet;
+use serde::Serialize;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Delegated-extended statistics files published by each RIR
+pub const DELEGATED_EXTENDED_URLS: [(&str, &str); 5] = [
+    (
+        "afrinic",
+        "https://ftp.afrinic.net/pub/stats/afrinic/delegated-afrinic-extended-latest",
+    ),
+    (
+        "apnic",
+        "https://ftp.apnic.net/stats/apnic/delegated-apnic-extended-latest",
+    ),
+    (
+        "arin",
+        "https://ftp.arin.net/pub/stats/arin/delegated-arin-extended-latest",
+    ),
+    (
+        "lacnic",
+        "https://ftp.lacnic.net/pub/stats/lacnic/delegated-lacnic-extended-latest",
+    ),
+    (
+        "ripencc",
+        "https://ftp.ripe.net/pub/stats/ripencc/delegated-ripencc-extended-latest",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Asn { first: u32, count: u32 },
+    Ipv4 { start: Ipv4Addr, count: u32 },
+    Ipv6 { start: Ipv6Addr, prefix_len: u8 },
+}
+
+/// A single allocation or assignment record from a delegated-extended file
+#[derive(Debug, Clone)]
+pub struct Delegation {
+    pub registry: String,
+    pub country: String,
+    pub resource: Resource,
+    pub date: String,
+    pub status: String,
+    pub opaque_id: String,
+}
+
+impl Delegation {
+    pub fn is_delegated(&self) -> bool {
+        self.status == "allocated" || self.status == "assigned"
+    }
+
+    /// Returns the inclusive address range of an IP delegation as integers, with a flag that is
+    /// set for IPv6.
+    fn address_range(&self) -> Option<(bool, u128, u128)> {
+        match self.resource {
+            Resource::Asn { .. } => None,
+            Resource::Ipv4 { start, count } => {
+                let first = u128::from(u32::from(start));
+                Some((false, first, first + u128::from(count.max(1)) - 1))
+            }
+            Resource::Ipv6 { start, prefix_len } => {
+                let first = u128::from(start);
+                let host_bits = 128 - u32::from(prefix_len.min(128));
+                let last = first | u128::MAX.checked_shr(128 - host_bits).unwrap_or_default();
+                Some((true, first, last))
+            }
+        }
+    }
+}
+
+/// The registry allocation covering a result prefix
+#[derive(Debug, Serialize)]
+pub struct RirAnnotation {
+    pub prefix: String,
+    pub registry: Option<String>,
+    pub country: Option<String>,
+    pub allocation_date: Option<String>,
+    pub org_handle: Option<String>,
+    pub allocated: bool,
+}
+
+/// Parses the records of a delegated-extended file, skipping the version, summary and comment
+/// lines.
+pub fn parse_delegated(contents: &str) -> Vec<Delegation> {
+    let mut delegations = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('|').collect();
+        // Summary lines use '*' for the country and end in "summary"
+        if fields.len() < 7 || fields[1] == "*" {
+            continue;
+        }
+
+        let resource = match fields[2] {
+            "asn" => u32::from_str(fields[3])
+                .ok()
+                .zip(u32::from_str(fields[4]).ok())
+                .map(|(first, count)| Resource::Asn { first, count }),
+            "ipv4" => Ipv4Addr::from_str(fields[3])
+                .ok()
+                .zip(u32::from_str(fields[4]).ok())
+                .map(|(start, count)| Resource::Ipv4 { start, count }),
+            "ipv6" => Ipv6Addr::from_str(fields[3])
+                .ok()
+                .zip(u8::from_str(fields[4]).ok())
+                .map(|(start, prefix_len)| Resource::Ipv6 { start, prefix_len }),
+            _ => None,
+        };
+        let Some(resource) = resource else {
+            trace!("Skipping unparseable delegation line {line}");
+            continue;
+        };
+
+        delegations.push(Delegation {
+            registry: fields[0].to_string(),
+            country: fields[1].to_string(),
+            resource,
+            date: fields[5].to_string(),
+            status: fields[6].to_string(),
+            opaque_id: fields.get(7).copied().unwrap_or_default().to_string(),
+        });
+    }
+    delegations
+}
+
+/// Downloads (or reuses cached copies of) the delegated-extended files of every RIR and parses
+/// them.
+pub fn fetch_delegations(
+    verify_cache_interval: Duration,
+) -> Result<Vec<Delegation>, Box<dyn Error>> {
+    let mut delegations = Vec::new();
+    for (registry, url) in DELEGATED_EXTENDED_URLS {
+        debug!("Loading {registry} delegations from {url}");
+        let path = source::fetch_file(url, verify_cache_interval)?;
+        let contents = fs::read_to_string(&path)?;
+        let parsed = parse_delegated(&contents);
+        debug!("Parsed {} {registry} delegation records", parsed.len());
+        delegations.extend(parsed);
+    }
+    Ok(delegations)
+}
+
+/// Returns every ASN delegated to the given ISO 3166 country code.
+pub fn country_asns(delegations: &[Delegation], country: &str) -> Vec<u32> {
+    let mut asns = Vec::new();
+    for delegation in delegations {
+        if !delegation.is_delegated() || !delegation.country.eq_ignore_ascii_case(country) {
+            continue;
+        }
+        if let Resource::Asn { first, count } = delegation.resource {
+            asns.extend((0..count).filter_map(|offset| first.checked_add(offset)));
+        }
+    }
+    asns.sort_unstable();
+    asns.dedup();
+    asns
+}
+
+fn prefix_range(prefix: &IpNet) -> (bool, u128, u128) {
+    match (prefix.network(), prefix.broadcast()) {
+        (IpAddr::V4(first), IpAddr::V4(last)) => (
+            false,
+            u128::from(u32::from(first)),
+            u128::from(u32::from(last)),
+        ),
+        (IpAddr::V6(first), IpAddr::V6(last)) => (true, u128::from(first), u128::from(last)),
+        _ => (false, 0, 0),
+    }
+}
+
+/// Formats the `YYYYMMDD` date of a delegation record as `YYYY-MM-DD`.
+fn format_date(date: &str) -> Option<String> {
+    if date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()) {
+        Some(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+    } else {
+        None
+    }
+}
+
+/// Annotates each prefix with the delegation covering its network address.
+///
+/// A prefix is only considered allocated when every one of its addresses falls within allocated
+/// or assigned space, so announcements that spill into unallocated space are flagged.
+pub fn annotate(
+    prefixes: &[IpNet],
+    delegations: &[Delegation],
+    ranges: bool,
+) -> Vec<RirAnnotation> {
+    let mut delegated: Vec<(bool, u128, u128, &Delegation)> = delegations
+        .iter()
+        .filter(|delegation| delegation.is_delegated())
+        .filter_map(|delegation| {
+            delegation
+                .address_range()
+                .map(|(is_v6, first, last)| (is_v6, first, last, delegation))
+        })
+        .collect();
+    delegated.sort_by_key(|&(is_v6, first, last, _)| (is_v6, first, last));
+
+    let mut annotations = Vec::new();
+    for prefix in prefixes {
+        let (is_v6, first, last) = prefix_range(prefix);
+        let overlapping: Vec<&(bool, u128, u128, &Delegation)> = delegated
+            .iter()
+            .filter(|(d_is_v6, d_first, d_last, _)| {
+                *d_is_v6 == is_v6 && *d_first <= last && *d_last >= first
+            })
+            .collect();
+
+        // Walk the sorted overlapping delegations to check they cover the prefix without gaps
+        let mut next_uncovered = Some(first);
+        for (_, d_first, d_last, _) in &overlapping {
+            if let Some(uncovered) = next_uncovered {
+                if *d_first <= uncovered && *d_last >= uncovered {
+                    next_uncovered = d_last.checked_add(1).filter(|next| *next <= last);
+                }
+            }
+        }
+        let allocated = !overlapping.is_empty() && next_uncovered.is_none();
+
+        let covering = overlapping
+            .iter()
+            .find(|(_, d_first, d_last, _)| *d_first <= first && *d_last >= first)
+            .map(|(_, _, _, delegation)| *delegation);
+        if !allocated {
+            warn!("Prefix {prefix} is announced but not entirely allocated by an RIR");
+        }
+
+        annotations.push(RirAnnotation {
+            prefix: if ranges {
+                format!("{}-{}", prefix.network(), prefix.broadcast())
+            } else {
+                prefix.to_string()
+            },
+            registry: covering.map(|d| d.registry.clone()),
+            country: covering.map(|d| d.country.clone()),
+            allocation_date: covering.and_then(|d| format_date(&d.date)),
+            org_handle: covering
+                .map(|d| d.opaque_id.clone())
+                .filter(|id| !id.is_empty()),
+            allocated,
+        });
+    }
+    annotations
+}
+
+pub fn render_annotations(output: &mut dyn Write, annotations: &[RirAnnotation]) -> io::Result<()> {
+    for annotation in annotations {
+        let flag = if annotation.allocated {
+            ""
+        } else {
+            " UNALLOCATED"
+        };
+        writeln!(
+            output,
+            "{} {} {} {} {}{flag}",
+            annotation.prefix,
+            annotation.registry.as_deref().unwrap_or("-"),
+            annotation.country.as_deref().unwrap_or("-"),
+            annotation.allocation_date.as_deref().unwrap_or("-"),
+            annotation.org_handle.as_deref().unwrap_or("-"),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,688 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use ipnet::IpNet;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::table::OriginTable;
+use crate::{asn, cron, index, mrt, source, systemd, table, MrtSource};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// How long a status request may take to be read or written
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error answered to a request, with its JSON-RPC error code
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<Box<dyn Error>> for RpcError {
+    fn from(e: Box<dyn Error>) -> Self {
+        Self::new(SERVER_ERROR, e)
+    }
+}
+
+/// MRT source of a request, named like the command line options. Defaults to the latest bview
+/// of RRC 01.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SourceParams {
+    mrt_file: Option<String>,
+    rrc: Option<String>,
+    url: Option<String>,
+    verify_cache_seconds: Option<u64>,
+    no_index: bool,
+}
+
+/// An ASN given as a number or in any notation `parse_asn` accepts, such as "AS13335"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AsnParam {
+    Number(u32),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FindNetblocksParams {
+    asns: Vec<AsnParam>,
+    #[serde(default)]
+    source: SourceParams,
+    #[serde(default)]
+    ipv4_only: bool,
+    #[serde(default)]
+    ipv6_only: bool,
+    #[serde(default)]
+    exclude_subnets: Vec<String>,
+    #[serde(default)]
+    no_aggregate: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LookupIpParams {
+    /// Addresses or prefixes to look up
+    ips: Vec<String>,
+    #[serde(default)]
+    source: SourceParams,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContainsParams {
+    needle: String,
+    haystack: String,
+}
+
+/// An origin table, with the MRT file it was loaded from
+#[derive(Debug)]
+struct LoadedTable {
+    /// Size and modification time of the MRT file
+    stamp: (u64, u64, u32),
+    table: Arc<OriginTable>,
+    loaded_at: DateTime<Utc>,
+    /// Time of the RIB snapshot in the MRT file
+    dump_time: Option<DateTime<Utc>>,
+}
+
+/// A source used by requests, with the MRT file it last resolved to
+#[derive(Debug)]
+struct UsedSource {
+    /// The source as given in requests, for the status page
+    name: String,
+    source: MrtSource,
+    mrt_file: String,
+}
+
+/// Outcome of a scheduled refresh
+#[derive(Debug)]
+struct RefreshResult {
+    time: DateTime<Utc>,
+    /// Sources that failed to refresh, and why
+    errors: Vec<String>,
+}
+
+/// Origin tables shared by the requests and the scheduled refreshes
+#[derive(Debug, Default)]
+struct Tables {
+    /// Origin tables by MRT file
+    by_file: HashMap<String, LoadedTable>,
+    /// Sources used by requests, by their options
+    sources: HashMap<String, UsedSource>,
+    last_refresh: Option<RefreshResult>,
+}
+
+fn lock(tables: &Mutex<Tables>) -> Result<MutexGuard<'_, Tables>, Box<dyn Error>> {
+    tables
+        .lock()
+        .map_err(|_| "A refresh of the origin tables failed".into())
+}
+
+/// Returns the origin table of an MRT file, loading it unless it is loaded already and the file
+/// has not changed since. The lock is not held while loading, so requests on other tables are
+/// answered meanwhile.
+fn load_table(
+    tables: &Mutex<Tables>,
+    mrt_file: &str,
+    no_index: bool,
+) -> Result<Arc<OriginTable>, Box<dyn Error>> {
+    let stamp = index::source_stamp(mrt_file).map_err(|e| format!("{mrt_file}: {e}"))?;
+    if let Some(loaded) = lock(tables)?.by_file.get(mrt_file) {
+        if loaded.stamp == stamp {
+            return Ok(Arc::clone(&loaded.table));
+        }
+    }
+    info!("Loading origin table of {mrt_file}");
+    let table = Arc::new(if no_index {
+        table::scan_origins(mrt_file, false, false)?
+    } else {
+        index::load_or_build(mrt_file)?
+    });
+    let dump_time = mrt::dump_time(mrt_file).unwrap_or_else(|e| {
+        warn!("Could not read the dump time of {mrt_file}: {e}");
+        None
+    });
+    lock(tables)?.by_file.insert(
+        mrt_file.to_string(),
+        LoadedTable {
+            stamp,
+            table: Arc::clone(&table),
+            loaded_at: Utc::now(),
+            dump_time,
+        },
+    );
+    Ok(table)
+}
+
+/// Fetches every source used so far again and rebuilds the origin tables that changed. Requests
+/// keep being answered from the previous tables until each new one is loaded, and a source that
+/// fails to refresh keeps its previous table.
+fn refresh(tables: &Mutex<Tables>) -> Result<(), Box<dyn Error>> {
+    let sources: Vec<(String, MrtSource)> = lock(tables)?
+        .sources
+        .iter()
+        .map(|(key, used)| (key.clone(), used.source.clone()))
+        .collect();
+    info!("Refreshing {} sources", sources.len());
+    let mut errors = Vec::new();
+    for (key, source) in sources {
+        let mut refreshed = source.clone();
+        refreshed.verify_cache_seconds = 0;
+        let mrt_file = match source::resolve_mrt(&refreshed)
+            .and_then(|mrt_file| load_table(tables, &mrt_file, source.no_index).map(|_| mrt_file))
+        {
+            Ok(mrt_file) => mrt_file,
+            Err(e) => {
+                warn!("Could not refresh {key}, answering from its previous table: {e}");
+                let name = lock(tables)?
+                    .sources
+                    .get(&key)
+                    .map_or(key, |used| used.name.clone());
+                errors.push(format!("{name}: {e}"));
+                continue;
+            }
+        };
+        if let Some(used) = lock(tables)?.sources.get_mut(&key) {
+            used.mrt_file = mrt_file;
+        }
+    }
+    // Drop the tables of dumps no source resolves to any more
+    let mut tables = lock(tables)?;
+    let used: HashSet<String> = tables
+        .sources
+        .values()
+        .map(|used| used.mrt_file.clone())
+        .collect();
+    tables.by_file.retain(|mrt_file, _| used.contains(mrt_file));
+    tables.last_refresh = Some(RefreshResult {
+        time: Utc::now(),
+        errors,
+    });
+    Ok(())
+}
+
+/// Refreshes the origin tables at every run of the schedule. A refresh still running when the
+/// next run is due is never overlapped; the runs it covered are skipped instead.
+fn refresh_on_schedule(schedule: &cron::Schedule, tables: &Mutex<Tables>) {
+    loop {
+        let run = match schedule.wait() {
+            Ok(run) => run,
+            Err(e) => {
+                error!("Stopping the scheduled refreshes: {e}");
+                return;
+            }
+        };
+        let _span = info_span!("refresh", %run).entered();
+        let busy = systemd::busy();
+        let refreshed = refresh(tables);
+        drop(busy);
+        if let Err(e) = refreshed {
+            error!("Stopping the scheduled refreshes: {e}");
+            return;
+        }
+        if schedule
+            .next_after(run)
+            .is_some_and(|next| next < Utc::now())
+        {
+            warn!("Refresh scheduled at {run} took until after the next run, skipping it");
+        }
+    }
+}
+
+/// Answers requests, keeping the origin table of every MRT file used so later requests are
+/// answered from memory
+#[derive(Debug)]
+struct Server {
+    strict: bool,
+    /// Whether sources are only checked for newer dumps by scheduled refreshes
+    scheduled: bool,
+    tables: Arc<Mutex<Tables>>,
+}
+
+impl Server {
+    fn table(&mut self, params: &SourceParams) -> Result<Arc<OriginTable>, Box<dyn Error>> {
+        let mrt_source = MrtSource {
+            mrt_file: params.mrt_file.clone(),
+            rrc: params.rrc.as_deref().map(source::parse_rrc).transpose()?,
+            url: params.url.clone(),
+            verify_cache_seconds: params.verify_cache_seconds.unwrap_or(86400),
+            no_index: params.no_index,
+        };
+        let key = format!("{mrt_source:?}");
+        if self.scheduled {
+            let tables = lock(&self.tables)?;
+            if let Some(loaded) = tables
+                .sources
+                .get(&key)
+                .and_then(|used| tables.by_file.get(&used.mrt_file))
+            {
+                return Ok(Arc::clone(&loaded.table));
+            }
+        }
+        let mrt_file = source::resolve_mrt(&mrt_source)?;
+        let table = load_table(&self.tables, &mrt_file, params.no_index)?;
+        let name = match (&params.mrt_file, &params.url, &params.rrc) {
+            (Some(mrt_file), _, _) => mrt_file.clone(),
+            (None, Some(url), _) => url.clone(),
+            (None, None, rrc) => format!(
+                "rrc {}",
+                rrc.as_deref()
+                    .map_or("01", |rrc| rrc.trim_start_matches("rrc"))
+            ),
+        };
+        lock(&self.tables)?.sources.insert(
+            key,
+            UsedSource {
+                name,
+                source: mrt_source,
+                mrt_file,
+            },
+        );
+        Ok(table)
+    }
+
+    fn find_netblocks(&mut self, params: FindNetblocksParams) -> Result<Value, RpcError> {
+        if params.ipv4_only && params.ipv6_only {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "ipv4_only conflicts with ipv6_only",
+            ));
+        }
+        let origin_asns = params
+            .asns
+            .iter()
+            .map(|asn| match asn {
+                AsnParam::Number(asn) => Ok(*asn),
+                AsnParam::Text(asn) => asn::parse_asn(asn),
+            })
+            .collect::<Result<HashSet<u32>, String>>()
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+        if origin_asns.is_empty() {
+            return Err(RpcError::new(INVALID_PARAMS, "No ASNs given"));
+        }
+        asn::check_asns(&origin_asns, self.strict).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+        let excluded = params
+            .exclude_subnets
+            .iter()
+            .map(|subnet| IpNet::from_str(subnet).map_err(|e| format!("{subnet}: {e}")))
+            .collect::<Result<Vec<IpNet>, String>>()
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+
+        let table = self.table(&params.source)?;
+        let prefix_origins =
+            index::origin_prefixes(&table, &origin_asns, params.ipv4_only, params.ipv6_only);
+        let prefixes: Vec<IpNet> = prefix_origins.keys().copied().collect();
+        let prefixes = if excluded.is_empty() {
+            prefixes
+        } else {
+            crate::exclude_subnets(&prefixes, excluded)?
+        };
+        let mut prefixes = if params.no_aggregate {
+            prefixes
+        } else {
+            IpNet::aggregate(&prefixes)
+        };
+        prefixes.sort_unstable();
+        Ok(json!({ "prefixes": prefixes }))
+    }
+
+    fn lookup_ip(&mut self, params: LookupIpParams) -> Result<Value, RpcError> {
+        // index::query also answers ASNs, which are looked up with find_netblocks instead
+        if let Some(ip) = params
+            .ips
+            .iter()
+            .find(|ip| IpNet::from_str(ip).is_err() && IpAddr::from_str(ip).is_err())
+        {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("{ip} is not an IP address or prefix"),
+            ));
+        }
+        let table = self.table(&params.source)?;
+        let answers = params
+            .ips
+            .iter()
+            .map(|ip| index::query(&table, ip))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+        Ok(json!(answers))
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+            serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+        }
+        match method {
+            "find_netblocks" => self.find_netblocks(parse(params)?),
+            "lookup_ip" => self.lookup_ip(parse(params)?),
+            "contains" => {
+                let params: ContainsParams = parse(params)?;
+                let needle = IpNet::from_str(&params.needle)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("needle: {e}")))?;
+                let haystack = IpNet::from_str(&params.haystack)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("haystack: {e}")))?;
+                Ok(json!({ "contains": haystack.contains(&needle) }))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+
+    /// Answers a single request, returning `None` for notifications, which get no response.
+    fn handle(&mut self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let result = match (
+            request.get("jsonrpc").and_then(Value::as_str),
+            request.get("method").and_then(Value::as_str),
+        ) {
+            (Some("2.0"), Some(method)) => {
+                debug!("RPC call {method}");
+                let params = request.get("params").cloned().unwrap_or(json!({}));
+                self.call(method, params)
+            }
+            _ => Err(RpcError::new(INVALID_REQUEST, "Not a JSON-RPC 2.0 request")),
+        };
+        let id = match id {
+            Some(id) => id,
+            // Invalid requests are answered even without an id, as the spec requires
+            None if matches!(&result, Err(e) if e.code == INVALID_REQUEST) => Value::Null,
+            None => return None,
+        };
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => {
+                debug!("RPC error {}: {}", e.code, e.message);
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": e.code, "message": e.message},
+                })
+            }
+        })
+    }
+}
+
+fn error_response(code: i64, message: impl ToString) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": {"code": code, "message": message.to_string()},
+    })
+}
+
+/// What the status endpoints report on, besides the origin tables
+#[derive(Debug)]
+struct StatusContext {
+    started: DateTime<Utc>,
+    refresh_schedule: Option<cron::Schedule>,
+    /// Age in seconds past which a RIB being served makes the health check fail
+    health_max_age: Option<u64>,
+}
+
+/// Describes the sources being served, their dumps, origin tables and the last refresh.
+fn status_report(tables: &Tables, context: &StatusContext) -> Value {
+    let now = Utc::now();
+    let mut sources: Vec<Value> = tables
+        .sources
+        .values()
+        .map(|used| {
+            let loaded = tables.by_file.get(&used.mrt_file);
+            let prefixes = |v6: bool| {
+                loaded.map_or(0, |loaded| {
+                    loaded
+                        .table
+                        .keys()
+                        .filter(|prefix| matches!(prefix, IpNet::V6(_)) == v6)
+                        .count()
+                })
+            };
+            let index_age = fs::metadata(index::index_path(&used.mrt_file))
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map(|age| age.as_secs());
+            json!({
+                "source": used.name,
+                "mrt_file": used.mrt_file,
+                "rib_time": loaded
+                    .and_then(|loaded| loaded.dump_time)
+                    .map(|time| time.to_rfc3339()),
+                "rib_age_seconds": loaded
+                    .and_then(|loaded| loaded.dump_time)
+                    .map(|time| (now - time).num_seconds()),
+                "loaded_at": loaded.map(|loaded| loaded.loaded_at.to_rfc3339()),
+                "index_age_seconds": index_age,
+                "ipv4_prefixes": prefixes(false),
+                "ipv6_prefixes": prefixes(true),
+            })
+        })
+        .collect();
+    sources.sort_by(|a, b| a["source"].as_str().cmp(&b["source"].as_str()));
+    json!({
+        "status": if health_problem(tables, context).is_some() { "unhealthy" } else { "ok" },
+        "started": context.started.to_rfc3339(),
+        "uptime_seconds": (now - context.started).num_seconds(),
+        "refresh_schedule": context.refresh_schedule.as_ref().map(ToString::to_string),
+        "next_refresh": context
+            .refresh_schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_after(now))
+            .map(|time| time.to_rfc3339()),
+        "last_refresh": tables.last_refresh.as_ref().map(|refresh| json!({
+            "time": refresh.time.to_rfc3339(),
+            "ok": refresh.errors.is_empty(),
+            "errors": refresh.errors,
+        })),
+        "sources": sources,
+    })
+}
+
+/// Returns why the data being served is not fresh: sources failing to refresh, or a RIB older
+/// than the health check allows.
+fn health_problem(tables: &Tables, context: &StatusContext) -> Option<String> {
+    if let Some(refresh) = tables
+        .last_refresh
+        .as_ref()
+        .filter(|refresh| !refresh.errors.is_empty())
+    {
+        return Some(format!(
+            "Refresh at {} failed for {}",
+            refresh.time,
+            refresh.errors.join("; ")
+        ));
+    }
+    let max_age = TimeDelta::seconds(i64::try_from(context.health_max_age?).ok()?);
+    let now = Utc::now();
+    tables.sources.values().find_map(|used| {
+        let dump_time = tables.by_file.get(&used.mrt_file)?.dump_time?;
+        (now - dump_time > max_age).then(|| {
+            format!(
+                "RIB of {} is from {dump_time}, older than {}s",
+                used.name,
+                max_age.num_seconds()
+            )
+        })
+    })
+}
+
+/// Answers one HTTP request on the status listener: `GET /healthz` with 200 or 503 and
+/// `GET /status` with JSON.
+fn answer_status(
+    stream: TcpStream,
+    tables: &Mutex<Tables>,
+    context: &StatusContext,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(STATUS_TIMEOUT))?;
+    stream.set_write_timeout(Some(STATUS_TIMEOUT))?;
+    let mut reader = io::BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are read and ignored; only the path matters
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut fields = request_line.split_whitespace();
+    let (method, path) = (
+        fields.next(),
+        fields.next().map(|path| path.split('?').next()),
+    );
+    let (status, content_type, body) = match (method, path.flatten()) {
+        (Some("GET"), Some("/healthz")) => match health_problem(&*lock(tables)?, context) {
+            None => ("200 OK", "text/plain", "ok\n".to_string()),
+            Some(problem) => (
+                "503 Service Unavailable",
+                "text/plain",
+                format!("{problem}\n"),
+            ),
+        },
+        (Some("GET"), Some("/status")) => {
+            let report = status_report(&*lock(tables)?, context);
+            ("200 OK", "application/json", format!("{report}\n"))
+        }
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    debug!(
+        "Status request {} answered {status}",
+        request_line.trim_end()
+    );
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.0 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Answers status requests one connection at a time until the process exits.
+fn serve_status(listener: &TcpListener, tables: &Mutex<Tables>, context: &StatusContext) {
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(Into::into)
+            .and_then(|stream| answer_status(stream, tables, context));
+        if let Err(e) = result {
+            debug!("Status request failed: {e}");
+        }
+    }
+}
+
+/// Reads JSON-RPC 2.0 requests from stdin, one request or batch per line, and writes each
+/// response as a line on stdout until stdin is closed.
+///
+/// Methods:
+/// * `find_netblocks` - `{"asns": [13335, "AS15169"], "source": {...}, "ipv4_only": false,
+///   "ipv6_only": false, "exclude_subnets": [], "no_aggregate": false}`, returning
+///   `{"prefixes": [...]}`
+/// * `lookup_ip` - `{"ips": ["1.1.1.1"], "source": {...}}`, returning the covering prefix and
+///   its origins for each address
+/// * `contains` - `{"needle": "1.1.1.0/24", "haystack": "1.0.0.0/8"}`, returning
+///   `{"contains": true}`
+///
+/// `source` takes the `mrt_file`, `rrc`, `url`, `verify_cache_seconds` and `no_index` options
+/// of the command line. Origin tables stay loaded, so only the first request on a source waits
+/// for it to be downloaded and indexed.
+///
+/// With a refresh schedule, sources are only checked for newer dumps at its runs, in the
+/// background, and requests are answered from the tables already loaded in the meantime.
+///
+/// With a status address, `/healthz` and `/status` are answered over HTTP there, for load
+/// balancers and monitoring to check the data being served is fresh.
+pub fn serve(
+    strict: bool,
+    refresh_schedule: Option<&cron::Schedule>,
+    status_listen: Option<&str>,
+    health_max_age: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let mut server = Server {
+        strict,
+        scheduled: refresh_schedule.is_some(),
+        tables: Arc::new(Mutex::new(Tables::default())),
+    };
+    if let Some(address) = status_listen {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| format!("Could not listen on {address}: {e}"))?;
+        info!("Answering status requests on {}", listener.local_addr()?);
+        let context = StatusContext {
+            started: Utc::now(),
+            refresh_schedule: refresh_schedule.cloned(),
+            health_max_age,
+        };
+        let tables = Arc::clone(&server.tables);
+        thread::spawn(move || serve_status(&listener, &tables, &context));
+    }
+    if let Some(schedule) = refresh_schedule {
+        info!("Refreshing the sources on schedule {schedule}");
+        let schedule = schedule.clone();
+        let tables = Arc::clone(&server.tables);
+        thread::spawn(move || refresh_on_schedule(&schedule, &tables));
+    }
+    let stdin = io::stdin();
+    let mut stdout = io::stdout().lock();
+    info!("Reading JSON-RPC requests from stdin");
+    systemd::ready();
+    systemd::status("Answering JSON-RPC requests");
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _busy = systemd::busy();
+        let response = match serde_json::from_str::<Value>(&line) {
+            Err(e) => Some(error_response(PARSE_ERROR, e)),
+            Ok(Value::Array(requests)) if requests.is_empty() => {
+                Some(error_response(INVALID_REQUEST, "Empty batch"))
+            }
+            Ok(Value::Array(requests)) => {
+                let responses: Vec<Value> = requests
+                    .into_iter()
+                    .filter_map(|request| server.handle(request))
+                    .collect();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            Ok(request) => server.handle(request),
+        };
+        if let Some(response) = response {
+            serde_json::to_writer(&mut stdout, &response)?;
+            writeln!(stdout)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
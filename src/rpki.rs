@@ -0,0 +1,282 @@
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fs, io};
+
+use crate::render::{self, ReportFormat};
+use crate::source;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Validated ROA payloads published as JSON by Cloudflare's RPKI validator
+pub const DEFAULT_ROA_URL: &str = "https://rpki.cloudflare.com/rpki.json";
+
+/// Route origin validation state as defined by RFC 6811
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Validity {
+    Valid,
+    Invalid,
+    Unknown,
+}
+
+impl Validity {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RoaAsn {
+    Number(u32),
+    Text(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct RoaRecord {
+    prefix: String,
+    #[serde(rename = "maxLength")]
+    max_length: u8,
+    asn: RoaAsn,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoaExport {
+    roas: Vec<RoaRecord>,
+}
+
+/// Validated ROA payloads indexed by ROA prefix
+#[derive(Debug, Default)]
+pub struct Roas {
+    by_prefix: HashMap<IpNet, Vec<(u8, u32)>>,
+}
+
+impl Roas {
+    /// Parses a validator JSON export with a top level `roas` array, as produced by Cloudflare's
+    /// validator, Routinator (`jsonext`/`json`) and rpki-client. The ASN may be given either as a
+    /// number or as `AS13335`.
+    pub fn from_json(contents: &str) -> Result<Self, Box<dyn Error>> {
+        let export: RoaExport = serde_json::from_str(contents)?;
+        let mut roas = Self::default();
+        for record in export.roas {
+            let asn = match &record.asn {
+                RoaAsn::Number(asn) => Some(*asn),
+                RoaAsn::Text(text) => text.strip_prefix("AS").unwrap_or(text).parse::<u32>().ok(),
+            };
+            match (IpNet::from_str(&record.prefix), asn) {
+                (Ok(prefix), Some(asn)) => roas
+                    .by_prefix
+                    .entry(prefix.trunc())
+                    .or_default()
+                    .push((record.max_length, asn)),
+                _ => warn!("Skipping invalid ROA {record:?}"),
+            }
+        }
+        debug!("Loaded ROAs for {} prefixes", roas.by_prefix.len());
+        Ok(roas)
+    }
+
+    /// Validates a route per RFC 6811: routes without a covering ROA are unknown, routes matched
+    /// by a covering ROA for their origin within its max length are valid, all others invalid.
+    pub fn validate(&self, prefix: &IpNet, origin_asn: u32) -> Validity {
+        let mut covered = false;
+        for len in 0..=prefix.prefix_len() {
+            let Ok(supernet) = IpNet::new(prefix.addr(), len) else {
+                continue;
+            };
+            if let Some(entries) = self.by_prefix.get(&supernet.trunc()) {
+                covered = true;
+                if entries.iter().any(|&(max_length, asn)| {
+                    asn != 0 && asn == origin_asn && prefix.prefix_len() <= max_length
+                }) {
+                    return Validity::Valid;
+                }
+            }
+        }
+        if covered {
+            Validity::Invalid
+        } else {
+            Validity::Unknown
+        }
+    }
+}
+
+/// Loads ROAs from a local JSON file or from a URL, caching downloads.
+pub fn load_roas(location: &str, verify_cache_interval: Duration) -> Result<Roas, Box<dyn Error>> {
+    let path = roa_path(location, verify_cache_interval)?;
+    debug!("Loading ROAs from {path}");
+    Roas::from_json(&fs::read_to_string(path)?)
+}
+
+/// Returns the file holding the ROAs, downloading them into the cache when given a URL.
+fn roa_path(location: &str, verify_cache_interval: Duration) -> Result<String, Box<dyn Error>> {
+    if location.contains("://") {
+        source::fetch_file(location, verify_cache_interval)
+    } else {
+        Ok(location.to_string())
+    }
+}
+
+/// Returns the SHA-256 digest of the ROAs at a URL or in a file, which changes whenever the
+/// validated payloads do although the location stays the same.
+pub fn roa_digest(
+    location: &str,
+    verify_cache_interval: Duration,
+) -> Result<String, Box<dyn Error>> {
+    let path = roa_path(location, verify_cache_interval)?;
+    let contents = fs::read(&path).map_err(|e| format!("{path}: {e}"))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+/// A prefix of the AS its ROAs do not make valid
+#[derive(Debug, Serialize)]
+pub struct Uncovered {
+    pub prefix: IpNet,
+    pub validity: Validity,
+}
+
+/// Share of the prefixes and address space of an AS made RPKI valid by its ROAs
+#[derive(Debug, Serialize)]
+pub struct Coverage {
+    pub asn: u32,
+    pub prefixes: usize,
+    pub valid_prefixes: usize,
+    /// Percentage of the prefixes that are valid, none without prefixes
+    pub prefix_coverage: Option<f64>,
+    pub ipv4_addresses: u128,
+    pub valid_ipv4_addresses: u128,
+    pub ipv4_coverage: Option<f64>,
+    /// IPv6 address space, in /64 subnets
+    pub ipv6_subnets: u128,
+    pub valid_ipv6_subnets: u128,
+    pub ipv6_coverage: Option<f64>,
+    /// Announced prefixes that are invalid or not covered by any ROA
+    pub uncovered: Vec<Uncovered>,
+}
+
+/// Percentage of `total` that `part` makes up, none when there is nothing to cover.
+#[allow(clippy::cast_precision_loss)]
+fn percentage(part: u128, total: u128) -> Option<f64> {
+    (total > 0).then(|| part as f64 * 100.0 / total as f64)
+}
+
+impl Coverage {
+    /// Validates each prefix the AS announces against the ROAs. Address space is counted once
+    /// where announced prefixes overlap, as valid when any valid prefix covers it.
+    pub fn measure(asn: u32, prefixes: &[IpNet], roas: &Roas) -> Self {
+        let mut valid = Vec::new();
+        let mut uncovered = Vec::new();
+        for prefix in prefixes {
+            match roas.validate(prefix, asn) {
+                Validity::Valid => valid.push(*prefix),
+                validity => uncovered.push(Uncovered {
+                    prefix: *prefix,
+                    validity,
+                }),
+            }
+        }
+        uncovered.sort_by_key(|uncovered| uncovered.prefix);
+        let (ipv4_addresses, ipv6_subnets) = render::address_space(prefixes);
+        let (valid_ipv4_addresses, valid_ipv6_subnets) = render::address_space(&valid);
+        Self {
+            asn,
+            prefixes: prefixes.len(),
+            valid_prefixes: valid.len(),
+            prefix_coverage: percentage(valid.len() as u128, prefixes.len() as u128),
+            ipv4_addresses,
+            valid_ipv4_addresses,
+            ipv4_coverage: percentage(valid_ipv4_addresses, ipv4_addresses),
+            ipv6_subnets,
+            valid_ipv6_subnets,
+            ipv6_coverage: percentage(valid_ipv6_subnets, ipv6_subnets),
+            uncovered,
+        }
+    }
+
+    /// Rows of what was measured with how much of it is valid, as a percentage
+    fn summary(&self) -> [(&'static str, u128, u128, String); 3] {
+        let format_percentage = |share: Option<f64>| {
+            share.map_or_else(|| "-".to_string(), |share| format!("{share:.1}%"))
+        };
+        [
+            (
+                "prefixes",
+                self.valid_prefixes as u128,
+                self.prefixes as u128,
+                format_percentage(self.prefix_coverage),
+            ),
+            (
+                "IPv4 addresses",
+                self.valid_ipv4_addresses,
+                self.ipv4_addresses,
+                format_percentage(self.ipv4_coverage),
+            ),
+            (
+                "IPv6 /64s",
+                self.valid_ipv6_subnets,
+                self.ipv6_subnets,
+                format_percentage(self.ipv6_coverage),
+            ),
+        ]
+    }
+}
+
+pub fn render_coverage(coverage: &Coverage, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), coverage)?,
+        ReportFormat::Text => {
+            for (name, valid, total, share) in coverage.summary() {
+                println!(
+                    "AS{} {name}: {valid} of {total} valid ({share})",
+                    coverage.asn
+                );
+            }
+            for uncovered in &coverage.uncovered {
+                println!("{} {}", uncovered.prefix, uncovered.validity.as_str());
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = coverage
+                .summary()
+                .iter()
+                .map(|(name, valid, total, share)| {
+                    vec![
+                        name.to_string(),
+                        valid.to_string(),
+                        total.to_string(),
+                        share.clone(),
+                    ]
+                })
+                .collect();
+            render::write_table(
+                &mut io::stdout(),
+                &["MEASURE", "VALID", "TOTAL", "COVERAGE"],
+                &rows,
+            )?;
+            if !coverage.uncovered.is_empty() {
+                println!();
+                let rows: Vec<Vec<String>> = coverage
+                    .uncovered
+                    .iter()
+                    .map(|uncovered| {
+                        vec![
+                            uncovered.prefix.to_string(),
+                            uncovered.validity.as_str().to_string(),
+                        ]
+                    })
+                    .collect();
+                render::write_table(&mut io::stdout(), &["UNCOVERED PREFIX", "VALIDITY"], &rows)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use bgpkit_parser::models::{BgpElem, ElemType};
+use bgpkit_parser::BgpkitParser;
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Prefixes found with the origins each was seen with
+pub type PrefixOrigins = HashMap<IpNet, HashSet<u32>>;
+
+/// Limits that stop a scan before the end of the MRT file
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanLimits {
+    /// Stop once this many matching prefixes have been found
+    pub max_prefixes: Option<usize>,
+    /// Stop once the scan has run for this long
+    pub timeout: Option<Duration>,
+}
+
+impl ScanLimits {
+    pub const fn is_limited(&self) -> bool {
+        self.max_prefixes.is_some() || self.timeout.is_some()
+    }
+}
+
+/// Collector peers whose routes a scan keeps, identified by the ASN or address the peer index
+/// table lists them with, or by their address and the family of the routes for peers selected
+/// per address family. A route is kept when any matches; every route is when all are empty.
+#[derive(Debug, Default, Clone)]
+pub struct PeerFilter {
+    pub asns: HashSet<u32>,
+    pub ips: HashSet<IpAddr>,
+    /// Peer addresses with whether their IPv6 rather than IPv4 routes are kept
+    pub feeds: HashSet<(IpAddr, bool)>,
+}
+
+impl PeerFilter {
+    pub fn is_empty(&self) -> bool {
+        self.asns.is_empty() && self.ips.is_empty() && self.feeds.is_empty()
+    }
+
+    /// Whether the route was learned from a selected peer.
+    pub fn matches(&self, elem: &BgpElem) -> bool {
+        self.is_empty()
+            || self.asns.contains(&elem.peer_asn.to_u32())
+            || self.ips.contains(&elem.peer_ip)
+            || self
+                .feeds
+                .contains(&(elem.peer_ip, matches!(elem.prefix.prefix, IpNet::V6(_))))
+    }
+}
+
+/// Why a scan stopped before the end of the MRT file, leaving its results partial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    MaxPrefixes(usize),
+    Timeout(Duration),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxPrefixes(max) => write!(f, "stopped after finding {max} prefixes"),
+            Self::Timeout(timeout) => write!(f, "stopped after {}s", timeout.as_secs()),
+        }
+    }
+}
+
+/// Scans an MRT file for the prefixes announced by the origin ASNs, returning the origins each
+/// prefix was seen with.
+pub fn scan_prefixes(
+    file: impl Read,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+) -> Result<HashMap<IpNet, HashSet<u32>>, Box<dyn Error>> {
+    scan_prefixes_limited(
+        file,
+        origin_asns,
+        ipv4_only,
+        ipv6_only,
+        &PeerFilter::default(),
+        ScanLimits::default(),
+    )
+    .map(|(prefixes, _)| prefixes)
+}
+
+/// Scans an MRT file like [`scan_prefixes`], keeping only the routes of the selected peers and
+/// stopping early when a limit is reached, returning why alongside the prefixes found until then.
+pub fn scan_prefixes_limited(
+    file: impl Read,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+    peers: &PeerFilter,
+    limits: ScanLimits,
+) -> Result<(PrefixOrigins, Option<StopReason>), Box<dyn Error>> {
+    if limits.is_limited() {
+        let _span = info_span!("parse").entered();
+        let mut reader = BufReader::new(file);
+        let parser = BgpkitParser::from_reader(&mut reader);
+        return Ok(scan_limited(
+            parser,
+            origin_asns,
+            ipv4_only,
+            ipv6_only,
+            peers,
+            limits,
+        ));
+    }
+
+    let mut prefixes: PrefixOrigins = HashMap::new();
+    scan_matches(
+        file,
+        origin_asns,
+        ipv4_only,
+        ipv6_only,
+        peers,
+        &mut |prefix, asn| {
+            if prefixes.entry(prefix).or_default().insert(asn) {
+                trace!("Found new matching prefix {prefix} from AS{asn}");
+            }
+        },
+    )?;
+    Ok((prefixes, None))
+}
+
+/// Scans an MRT file for announcements by the origin ASNs learned from the selected peers,
+/// calling `found` with the prefix and origin of every match as it is parsed, so callers decide
+/// what to keep.
+pub fn scan_matches(
+    file: impl Read,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+    peers: &PeerFilter,
+    found: &mut dyn FnMut(IpNet, u32),
+) -> Result<(), Box<dyn Error>> {
+    let _span = info_span!("parse").entered();
+    let mut reader = BufReader::new(file);
+    let mut parser = BgpkitParser::from_reader(&mut reader);
+
+    match (ipv4_only, ipv6_only) {
+        (true, false) => {
+            debug!("Filtering for only IPv4");
+            parser = parser.add_filter("ip_version", "ipv4")?;
+        }
+        (false, true) => {
+            debug!("Filtering for only IPv6");
+            parser = parser.add_filter("ip_version", "ipv6")?;
+        }
+        _ => {}
+    }
+
+    debug!("Filtering for only announce records");
+    parser = parser.add_filter("type", "announce")?;
+
+    let before = instant::Instant::now();
+
+    debug!(
+        "Scanning MRT file for prefixes associated with AS numbers {:?}...",
+        origin_asns
+    );
+    if origin_asns.len() == 1 {
+        // There's only one AS number, use bgpkit-parser native filter as it's faster
+        debug!("Using native filtering for origin AS");
+        let origin_asn = origin_asns.iter().next().copied().unwrap_or_default();
+        parser = parser.add_filter("origin_asn", &origin_asn.to_string())?;
+        for elem in parser.into_elem_iter().filter(|elem| peers.matches(elem)) {
+            found(elem.prefix.prefix, origin_asn);
+        }
+    } else {
+        // Since bgpkit-parser doesn't support filtering on more than one origin, filter manually
+        debug!("Using standard filtering for origin AS");
+        for elem in parser.into_elem_iter().filter(|elem| peers.matches(elem)) {
+            if let Some(elem_origin_asns) = &elem.origin_asns {
+                for asn in elem_origin_asns {
+                    let asn = asn.to_u32();
+                    if origin_asns.contains(&asn) {
+                        found(elem.prefix.prefix, asn);
+                    }
+                }
+            }
+        }
+    }
+
+    let after = instant::Instant::now();
+
+    #[allow(clippy::cast_precision_loss)]
+    let elapsed_seconds = ((after - before).as_millis() as f64) / 1000.0;
+
+    debug!(
+        "Finished scanning MRT file after {} seconds",
+        elapsed_seconds
+    );
+
+    Ok(())
+}
+
+/// Scans with the filtering done here instead of in the parser, which would otherwise skip the
+/// elements that do not match without giving the limits a chance to be checked.
+fn scan_limited<R: Read>(
+    parser: BgpkitParser<R>,
+    origin_asns: &HashSet<u32>,
+    ipv4_only: bool,
+    ipv6_only: bool,
+    peers: &PeerFilter,
+    limits: ScanLimits,
+) -> (PrefixOrigins, Option<StopReason>) {
+    let started = Instant::now();
+    let mut prefixes: HashMap<IpNet, HashSet<u32>> = HashMap::new();
+    debug!("Scanning MRT file with limits {limits:?}");
+    for elem in parser.into_elem_iter() {
+        if let Some(timeout) = limits.timeout {
+            if started.elapsed() >= timeout {
+                return (prefixes, Some(StopReason::Timeout(timeout)));
+            }
+        }
+        let prefix = elem.prefix.prefix;
+        let wanted_family = match prefix {
+            IpNet::V4(_) => !ipv6_only,
+            IpNet::V6(_) => !ipv4_only,
+        };
+        if elem.elem_type != ElemType::ANNOUNCE || !wanted_family || !peers.matches(&elem) {
+            continue;
+        }
+        for asn in elem.origin_asns.iter().flatten() {
+            let asn = asn.to_u32();
+            if origin_asns.contains(&asn) && prefixes.entry(prefix).or_default().insert(asn) {
+                trace!("Found new matching prefix {prefix} from AS{asn}");
+            }
+        }
+        if let Some(max) = limits.max_prefixes {
+            if prefixes.len() >= max {
+                return (prefixes, Some(StopReason::MaxPrefixes(max)));
+            }
+        }
+    }
+    (prefixes, None)
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::MrtSource;
+use crate::{collectors, download};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, warn};
+
+pub const CACHE_DIR: &str = ".cache";
+pub const DEFAULT_RRC: u8 = 1;
+
+/// Highest RIPE RIS collector number
+const MAX_RRC: u8 = 26;
+
+/// A RIPE RIS collector selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rrc {
+    Number(u8),
+    /// Probe nearby collectors and use the best one
+    Auto,
+}
+
+/// Parses an RRC argument given as a collector number or `auto`.
+pub fn parse_rrc(value: &str) -> Result<Rrc, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(Rrc::Auto);
+    }
+    match value.trim_start_matches("rrc").parse::<u8>() {
+        Ok(rrc) if rrc <= MAX_RRC => Ok(Rrc::Number(rrc)),
+        _ => Err(format!("{value} is not an RRC number 00-{MAX_RRC} or auto")),
+    }
+}
+
+/// Parses a range of RRC numbers such as 00-25, or a single collector.
+pub fn parse_rrc_range(value: &str) -> Result<RangeInclusive<u8>, String> {
+    let number = |value: &str| match parse_rrc(value)? {
+        Rrc::Number(rrc) => Ok(rrc),
+        Rrc::Auto => Err("auto cannot be part of a range".to_string()),
+    };
+    match value.split_once('-') {
+        Some((first, last)) => {
+            let (first, last) = (number(first)?, number(last)?);
+            if first > last {
+                return Err(format!("{value} is an empty range"));
+            }
+            Ok(first..=last)
+        }
+        None => number(value).map(|rrc| rrc..=rrc),
+    }
+}
+
+/// Resolves an optional RRC selection to a collector number.
+pub fn resolve_rrc(
+    rrc: Option<Rrc>,
+    verify_cache_interval: Duration,
+) -> Result<u8, Box<dyn Error>> {
+    match rrc {
+        Some(Rrc::Number(rrc)) => Ok(rrc),
+        Some(Rrc::Auto) => collectors::nearest_rrc(verify_cache_interval),
+        None => Ok(DEFAULT_RRC),
+    }
+}
+
+pub fn ripe_bview_url(rrc: u8) -> String {
+    format!("https://data.ris.ripe.net/rrc{rrc:02}/latest-bview.gz")
+}
+
+/// Resolves the MRT source arguments to a local, decompressed MRT file path.
+///
+/// A directly specified MRT file is used as-is, otherwise the URL (or the latest bview of the
+/// RIPE RRC) is downloaded into the cache and decompressed.
+pub fn resolve_mrt(source: &MrtSource) -> Result<String, Box<dyn Error>> {
+    let Some(download_url) = mrt_url(source)? else {
+        return Ok(source.mrt_file.clone().unwrap_or_default());
+    };
+
+    debug!("Using {download_url} for MRT source");
+    fetch_mrt(
+        &download_url,
+        Duration::from_secs(source.verify_cache_seconds),
+    )
+}
+
+/// Returns the URL the MRT source arguments download from, or `None` for a local MRT file.
+pub fn mrt_url(source: &MrtSource) -> Result<Option<String>, Box<dyn Error>> {
+    if source.mrt_file.is_some() {
+        return Ok(None);
+    }
+    let verify_cache_interval = Duration::from_secs(source.verify_cache_seconds);
+    Ok(Some(match (&source.url, source.rrc) {
+        (Some(u), _) => {
+            download::authorize_url(u);
+            u.clone()
+        }
+        (None, rrc) => ripe_bview_url(resolve_rrc(rrc, verify_cache_interval)?),
+    }))
+}
+
+/// Downloads a gzipped MRT file into the cache, returning the path of the decompressed copy.
+pub fn fetch_mrt(url: &str, verify_cache_interval: Duration) -> Result<String, Box<dyn Error>> {
+    download::cached_gzip(url, verify_cache_interval)
+}
+
+/// Downloads an uncompressed file into the cache, returning the path of the cached copy.
+pub fn fetch_file(url: &str, verify_cache_interval: Duration) -> Result<String, Box<dyn Error>> {
+    let (path, _) = download::cached(url, Some(verify_cache_interval), None)?;
+    Ok(path.display().to_string())
+}
@@ -0,0 +1,188 @@
+use bgpkit_parser::BgpkitParser;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::io;
+
+use crate::mrt;
+use crate::render::{self, ReportFormat};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Width of the longest bar of the prefix length histograms
+const HISTOGRAM_WIDTH: u64 = 40;
+
+/// Statistics of the prefixes and routes of one address family
+#[derive(Debug, Default, Serialize)]
+pub struct FamilyStats {
+    pub prefixes: u64,
+    /// RIB entries, one per peer announcing a prefix
+    pub routes: u64,
+    /// Prefixes of each length
+    pub prefix_lengths: BTreeMap<u8, u64>,
+    /// AS hops of the routes on average, counting prepends and each AS set as one hop
+    pub mean_path_length: Option<f64>,
+    #[serde(skip)]
+    path_hops: u64,
+    #[serde(skip)]
+    paths: u64,
+}
+
+/// Summary of a RIB dump, for following the growth of the routing table over time
+#[derive(Debug, Default, Serialize)]
+pub struct TableStats {
+    pub file: String,
+    pub prefixes: u64,
+    pub routes: u64,
+    pub origin_asns: usize,
+    pub mean_path_length: Option<f64>,
+    pub ipv4: FamilyStats,
+    pub ipv6: FamilyStats,
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn mean(total: u64, count: u64) -> Option<f64> {
+    (count > 0).then(|| total as f64 / count as f64)
+}
+
+/// Scans the announcements of an MRT file, counting prefixes, routes, prefix lengths and path
+/// lengths per address family and the distinct origin ASNs.
+pub fn table_stats(file_name: &str) -> Result<TableStats, Box<dyn Error>> {
+    let _span = info_span!("parse", file = file_name).entered();
+    let parser = BgpkitParser::from_reader(mrt::open(file_name)?).add_filter("type", "announce")?;
+
+    debug!("Scanning {file_name} for table statistics");
+    let mut stats = TableStats {
+        file: file_name.to_string(),
+        ..TableStats::default()
+    };
+    let mut prefixes: HashSet<IpNet> = HashSet::new();
+    let mut origin_asns: HashSet<u32> = HashSet::new();
+    for elem in parser.into_elem_iter() {
+        let prefix = elem.prefix.prefix;
+        let family = match prefix {
+            IpNet::V4(_) => &mut stats.ipv4,
+            IpNet::V6(_) => &mut stats.ipv6,
+        };
+        family.routes += 1;
+        if prefixes.insert(prefix) {
+            family.prefixes += 1;
+            *family
+                .prefix_lengths
+                .entry(prefix.prefix_len())
+                .or_default() += 1;
+        }
+        if let Some(path) = &elem.as_path {
+            family.path_hops += path.route_len() as u64;
+            family.paths += 1;
+        }
+        if let Some(origins) = &elem.origin_asns {
+            origin_asns.extend(origins.iter().map(|asn| asn.to_u32()));
+        }
+    }
+
+    for family in [&mut stats.ipv4, &mut stats.ipv6] {
+        family.mean_path_length = mean(family.path_hops, family.paths);
+    }
+    stats.prefixes = stats.ipv4.prefixes + stats.ipv6.prefixes;
+    stats.routes = stats.ipv4.routes + stats.ipv6.routes;
+    stats.origin_asns = origin_asns.len();
+    stats.mean_path_length = mean(
+        stats.ipv4.path_hops + stats.ipv6.path_hops,
+        stats.ipv4.paths + stats.ipv6.paths,
+    );
+    debug!(
+        "Found {} prefixes and {} routes in {file_name}",
+        stats.prefixes, stats.routes
+    );
+    Ok(stats)
+}
+
+fn format_mean(mean: Option<f64>) -> String {
+    mean.map_or_else(|| "-".to_string(), |mean| format!("{mean:.2}"))
+}
+
+/// Writes the prefix length histogram of a family as a table with a bar per length.
+fn write_histogram(family: &FamilyStats) -> io::Result<()> {
+    let largest = family.prefix_lengths.values().copied().max().unwrap_or(0);
+    let rows: Vec<Vec<String>> = family
+        .prefix_lengths
+        .iter()
+        .map(|(length, count)| {
+            #[allow(clippy::cast_precision_loss)]
+            let share = *count as f64 * 100.0 / family.prefixes as f64;
+            let bar = (count * HISTOGRAM_WIDTH).div_ceil(largest.max(1));
+            vec![
+                format!("/{length}"),
+                count.to_string(),
+                format!("{share:.2}%"),
+                "#".repeat(usize::try_from(bar).unwrap_or_default()),
+            ]
+        })
+        .collect();
+    render::write_table(
+        &mut io::stdout(),
+        &["LENGTH", "PREFIXES", "SHARE", "DISTRIBUTION"],
+        &rows,
+    )
+}
+
+pub fn render_table_stats(stats: &TableStats, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), stats)?,
+        ReportFormat::Text => {
+            println!(
+                "prefixes={} ipv4_prefixes={} ipv6_prefixes={} routes={} origin_asns={} \
+                 mean_path_length={}",
+                stats.prefixes,
+                stats.ipv4.prefixes,
+                stats.ipv6.prefixes,
+                stats.routes,
+                stats.origin_asns,
+                format_mean(stats.mean_path_length)
+            );
+            for (name, family) in [("ipv4", &stats.ipv4), ("ipv6", &stats.ipv6)] {
+                for (length, count) in &family.prefix_lengths {
+                    println!("{name} length=/{length} prefixes={count}");
+                }
+            }
+        }
+        ReportFormat::Table => {
+            let rows = vec![
+                vec![
+                    "prefixes".to_string(),
+                    stats.ipv4.prefixes.to_string(),
+                    stats.ipv6.prefixes.to_string(),
+                    stats.prefixes.to_string(),
+                ],
+                vec![
+                    "routes".to_string(),
+                    stats.ipv4.routes.to_string(),
+                    stats.ipv6.routes.to_string(),
+                    stats.routes.to_string(),
+                ],
+                vec![
+                    "mean path length".to_string(),
+                    format_mean(stats.ipv4.mean_path_length),
+                    format_mean(stats.ipv6.mean_path_length),
+                    format_mean(stats.mean_path_length),
+                ],
+                vec![
+                    "origin ASNs".to_string(),
+                    "-".to_string(),
+                    "-".to_string(),
+                    stats.origin_asns.to_string(),
+                ],
+            ];
+            render::write_table(&mut io::stdout(), &["", "IPV4", "IPV6", "TOTAL"], &rows)?;
+            for (name, family) in [("IPv4", &stats.ipv4), ("IPv6", &stats.ipv6)] {
+                if family.prefixes > 0 {
+                    println!("\n{name} prefix lengths");
+                    write_histogram(family)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
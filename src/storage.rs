@@ -0,0 +1,71 @@
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::Method;
+use std::error::Error;
+use std::fs::{self, File};
+use std::time::Duration;
+
+use crate::{aws, download, gcs};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Time allowed for each upload request, long enough for a full table dump
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Returns whether the URL names an object in S3 or Cloud Storage rather than a local file.
+pub fn is_object_url(url: &str) -> bool {
+    url.starts_with("s3://") || url.starts_with("gs://")
+}
+
+/// Builds a request for an http(s), s3:// or gs:// URL, signed with the credentials of the object
+/// store.
+pub fn request(
+    client: &Client,
+    method: Method,
+    url: &str,
+    body: Option<Body>,
+) -> Result<RequestBuilder, Box<dyn Error>> {
+    if url.starts_with("s3://") {
+        aws::s3_request(client, method, url, &[], body)
+    } else if url.starts_with("gs://") {
+        gcs::request(client, method, url, body)
+    } else {
+        let request = client.request(method, url);
+        Ok(match body {
+            Some(body) => request.body(body),
+            None => request,
+        })
+    }
+}
+
+/// Returns the response when successful, or an error with the status and body the object store
+/// answered with.
+pub fn check_response(response: Response, url: &str) -> Result<Response, Box<dyn Error>> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let text = response.text().unwrap_or_default();
+    Err(format!("Request for {url} failed with HTTP {status}: {text}").into())
+}
+
+/// Streams a local file to an s3:// or gs:// URL. Cloud Storage takes objects up to its 5 TiB
+/// limit in one request, S3 uploads larger than 5 GiB go in parts.
+pub fn upload(local_path: &str, url: &str) -> Result<(), Box<dyn Error>> {
+    let client = download::client()?;
+    let size = fs::metadata(local_path)
+        .map_err(|e| format!("{local_path}: {e}"))?
+        .len();
+    debug!("Uploading {size} bytes to {url}");
+    if url.starts_with("s3://") {
+        aws::s3_upload(&client, local_path, url, UPLOAD_TIMEOUT)?;
+    } else {
+        let body = Body::sized(File::open(local_path)?, size);
+        let response = request(&client, Method::PUT, url, Some(body))?
+            .timeout(UPLOAD_TIMEOUT)
+            .send()
+            .map_err(|e| format!("Failed to upload {url}: {e}"))?;
+        check_response(response, url)?;
+    }
+    info!("Uploaded {url}");
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::env;
+use std::error::Error;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// The systemd notification socket, and the work in progress the watchdog keeps an eye on
+#[derive(Debug)]
+struct Notifier {
+    socket: String,
+    /// Interval within which systemd expects a keep-alive, from WatchdogSec= of the unit
+    watchdog: Option<Duration>,
+    /// Start of every update, refresh or read in progress, by id
+    busy: Mutex<Vec<(u64, Instant)>>,
+    next_id: AtomicU64,
+}
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+#[cfg(target_os = "linux")]
+fn send_to(socket: &str, state: &str) -> Result<(), Box<dyn Error>> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        // Sockets starting with @ are in the abstract namespace
+        Some(name) => {
+            datagram.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?
+        }
+        None => datagram.send_to(state.as_bytes(), socket)?,
+    };
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_to(socket: &str, state: &str) -> Result<(), Box<dyn Error>> {
+    std::os::unix::net::UnixDatagram::unbound()?.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_to(_socket: &str, _state: &str) -> Result<(), Box<dyn Error>> {
+    Err("systemd notifications need Unix sockets".into())
+}
+
+fn send(state: &str) {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    match send_to(&notifier.socket, state) {
+        Ok(()) => trace!("Notified systemd of {state}"),
+        Err(e) => warn!("Could not notify systemd of {state}: {e}"),
+    }
+}
+
+/// Sends keep-alives at half the watchdog interval for as long as no work in progress has been
+/// running for longer than the interval. Once one has, the keep-alives stop and systemd restarts
+/// the service.
+fn keep_alive(notifier: &Notifier, interval: Duration) {
+    let mut wedged = false;
+    loop {
+        thread::sleep(interval / 2);
+        let oldest = notifier
+            .busy
+            .lock()
+            .ok()
+            .and_then(|busy| busy.iter().map(|(_, started)| started.elapsed()).max());
+        match oldest {
+            Some(elapsed) if elapsed > interval => {
+                if !wedged {
+                    error!(
+                        "Work has been running for {}s, longer than the {}s watchdog interval, stopping keep-alives",
+                        elapsed.as_secs(),
+                        interval.as_secs()
+                    );
+                    wedged = true;
+                }
+            }
+            _ => {
+                wedged = false;
+                send("WATCHDOG=1");
+            }
+        }
+    }
+}
+
+/// Starts notifying systemd, for services of `Type=notify`. With `WatchdogSec=` set on the unit,
+/// keep-alives are sent until an update, refresh or stream read takes longer than it.
+pub fn init() {
+    let Ok(socket) = env::var("NOTIFY_SOCKET") else {
+        warn!("NOTIFY_SOCKET is not set, not notifying systemd");
+        return;
+    };
+    // The watchdog may be meant for another process of the service
+    let watchdog_pid = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    let watchdog = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0 && watchdog_pid.is_none_or(|pid| pid == process::id()))
+        .map(Duration::from_micros);
+    let notifier = NOTIFIER.get_or_init(|| Notifier {
+        socket,
+        watchdog,
+        busy: Mutex::new(Vec::new()),
+        next_id: AtomicU64::new(0),
+    });
+    if let Some(interval) = notifier.watchdog {
+        debug!("Sending systemd watchdog keep-alives, interval {interval:?}");
+        thread::spawn(move || keep_alive(notifier, interval));
+    }
+}
+
+/// Tells systemd the service finished starting up.
+pub fn ready() {
+    send("READY=1");
+}
+
+/// Sets the status line shown by systemctl status.
+pub fn status(status: &str) {
+    send(&format!("STATUS={status}"));
+}
+
+/// Work in progress, watched by the watchdog until dropped
+#[derive(Debug)]
+pub struct Busy {
+    id: Option<u64>,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        if let (Some(id), Some(notifier)) = (self.id, NOTIFIER.get()) {
+            if let Ok(mut busy) = notifier.busy.lock() {
+                busy.retain(|(busy_id, _)| *busy_id != id);
+            }
+        }
+    }
+}
+
+/// Marks work as in progress until the returned guard is dropped, so the watchdog can tell when
+/// it wedges.
+pub fn busy() -> Busy {
+    let Some(notifier) = NOTIFIER
+        .get()
+        .filter(|notifier| notifier.watchdog.is_some())
+    else {
+        return Busy { id: None };
+    };
+    let id = notifier.next_id.fetch_add(1, Ordering::Relaxed);
+    if let Ok(mut busy) = notifier.busy.lock() {
+        busy.push((id, Instant::now()));
+    }
+    Busy { id: Some(id) }
+}
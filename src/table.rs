@@ -0,0 +1,164 @@
+use bgpkit_parser::BgpkitParser;
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::render::{self, ReportFormat};
+use crate::{mrt, parquet};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Every announced prefix of a RIB with the origin ASNs seen announcing it
+pub type OriginTable = BTreeMap<IpNet, BTreeSet<u32>>;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// CSV with space separated origin ASNs
+    #[default]
+    Csv,
+    /// Newline-delimited JSON objects
+    Ndjson,
+    /// Parquet file with a prefix column and an origin_asns column of lists of ASNs
+    Parquet,
+}
+
+#[derive(Debug, Serialize)]
+struct TableRow<'row> {
+    prefix: &'row IpNet,
+    origin_asns: &'row BTreeSet<u32>,
+}
+
+/// Scans an MRT file once, collecting the origin ASNs of every announced prefix.
+pub fn scan_origins(
+    file_name: &str,
+    ipv4_only: bool,
+    ipv6_only: bool,
+) -> Result<OriginTable, Box<dyn Error>> {
+    let _span = info_span!("parse", file = file_name).entered();
+    let mut parser = BgpkitParser::from_reader(mrt::open(file_name)?);
+    match (ipv4_only, ipv6_only) {
+        (true, false) => parser = parser.add_filter("ip_version", "ipv4")?,
+        (false, true) => parser = parser.add_filter("ip_version", "ipv6")?,
+        _ => {}
+    }
+    parser = parser.add_filter("type", "announce")?;
+
+    debug!("Scanning {file_name} for the origins of every prefix");
+    let mut table = OriginTable::new();
+    for elem in parser.into_elem_iter() {
+        let origins = table.entry(elem.prefix.prefix).or_default();
+        if let Some(origin_asns) = &elem.origin_asns {
+            origins.extend(origin_asns.iter().map(|asn| asn.to_u32()));
+        }
+    }
+    debug!("Found {} prefixes in {file_name}", table.len());
+    Ok(table)
+}
+
+/// Writes the table in the given export format.
+pub fn write_export(
+    output: &mut dyn Write,
+    table: &OriginTable,
+    format: ExportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Parquet => parquet::write_table(output, table)?,
+        ExportFormat::Ndjson => {
+            for (prefix, origin_asns) in table {
+                serde_json::to_writer(
+                    &mut *output,
+                    &TableRow {
+                        prefix,
+                        origin_asns,
+                    },
+                )?;
+                writeln!(output)?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(output, "prefix,origin_asns")?;
+            for (prefix, origin_asns) in table {
+                let origin_asns: Vec<String> = origin_asns.iter().map(u32::to_string).collect();
+                writeln!(output, "{prefix},{}", origin_asns.join(" "))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An origin ASN announcing space inside a queried supernet
+#[derive(Debug, Serialize)]
+pub struct OriginWithin {
+    pub asn: u32,
+    /// Announced prefixes inside the supernet originated by the ASN
+    pub prefixes: usize,
+    /// Percentage of the supernet the prefixes cover, counting overlapping ones once
+    pub share: f64,
+}
+
+/// Lists the origin ASNs of the announced prefixes inside the supernet, including the supernet
+/// itself, with the most prefixes first.
+pub fn origins_within(table: &OriginTable, supernet: &IpNet) -> Vec<OriginWithin> {
+    let mut prefixes: BTreeMap<u32, Vec<IpNet>> = BTreeMap::new();
+    for (prefix, origins) in table {
+        if supernet.contains(prefix) {
+            for origin in origins {
+                prefixes.entry(*origin).or_default().push(*prefix);
+            }
+        }
+    }
+    let size = render::address_count(supernet);
+    let mut origins: Vec<OriginWithin> = prefixes
+        .into_iter()
+        .map(|(asn, prefixes)| {
+            let covered: u128 = IpNet::aggregate(&prefixes)
+                .iter()
+                .map(render::address_count)
+                .sum();
+            #[allow(clippy::cast_precision_loss)]
+            let share = covered as f64 * 100.0 / size as f64;
+            OriginWithin {
+                asn,
+                prefixes: prefixes.len(),
+                share,
+            }
+        })
+        .collect();
+    origins.sort_by(|a, b| b.prefixes.cmp(&a.prefixes).then(a.asn.cmp(&b.asn)));
+    debug!("Found {} origin ASNs inside {supernet}", origins.len());
+    origins
+}
+
+pub fn render_origins_within(
+    origins: &[OriginWithin],
+    format: ReportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => serde_json::to_writer(io::stdout(), origins)?,
+        ReportFormat::Text => {
+            for origin in origins {
+                println!(
+                    "AS{} prefixes={} share={:.2}%",
+                    origin.asn, origin.prefixes, origin.share
+                );
+            }
+        }
+        ReportFormat::Table => {
+            let rows: Vec<Vec<String>> = origins
+                .iter()
+                .map(|origin| {
+                    vec![
+                        format!("AS{}", origin.asn),
+                        origin.prefixes.to_string(),
+                        format!("{:.2}%", origin.share),
+                    ]
+                })
+                .collect();
+            render::write_table(&mut io::stdout(), &["ASN", "PREFIXES", "SHARE"], &rows)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Map, Value};
+use std::env;
+use std::error::Error;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Name of the span covering a whole run, which carries its outcome
+pub const RUN_SPAN: &str = "run";
+
+/// How long an export may take before it is abandoned, so a down collector never holds up a job
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A span that has closed, waiting to be exported
+#[derive(Debug)]
+pub struct FinishedSpan {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub fields: Map<String, Value>,
+    pub started_at: SystemTime,
+    pub ended_at: SystemTime,
+}
+
+/// Spans and metrics of this run, exported over OTLP/HTTP when it ends
+#[derive(Debug)]
+struct Telemetry {
+    endpoint: String,
+    trace_id: String,
+    started: Instant,
+    started_at: SystemTime,
+    spans: Mutex<Vec<FinishedSpan>>,
+    downloaded_bytes: AtomicU64,
+    prefixes_found: AtomicU64,
+}
+
+static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+
+/// Starts collecting spans and metrics for export to an OTLP/HTTP collector such as
+/// `http://localhost:4318`.
+pub fn init(endpoint: &str) {
+    let mut trace_id = [0_u8; 16];
+    if SystemRandom::new().fill(&mut trace_id).is_err() {
+        warn!("Could not generate a trace id, not exporting telemetry");
+        return;
+    }
+    let _ = TELEMETRY.set(Telemetry {
+        endpoint: endpoint.trim_end_matches('/').to_string(),
+        trace_id: trace_id.iter().map(|byte| format!("{byte:02x}")).collect(),
+        started: Instant::now(),
+        started_at: SystemTime::now(),
+        spans: Mutex::new(Vec::new()),
+        downloaded_bytes: AtomicU64::new(0),
+        prefixes_found: AtomicU64::new(0),
+    });
+}
+
+pub fn enabled() -> bool {
+    TELEMETRY.get().is_some()
+}
+
+pub fn record_span(span: FinishedSpan) {
+    if let Some(mut spans) = TELEMETRY
+        .get()
+        .and_then(|telemetry| telemetry.spans.lock().ok())
+    {
+        spans.push(span);
+    }
+}
+
+pub fn add_downloaded_bytes(bytes: u64) {
+    if let Some(telemetry) = TELEMETRY.get() {
+        telemetry
+            .downloaded_bytes
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+pub fn add_prefixes_found(prefixes: usize) {
+    if let Some(telemetry) = TELEMETRY.get() {
+        telemetry
+            .prefixes_found
+            .fetch_add(prefixes as u64, Ordering::Relaxed);
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Converts a field to an OTLP attribute value. Integers are strings, as OTLP/JSON encodes
+/// 64-bit numbers.
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            json!({ "intValue": number.to_string() })
+        }
+        Value::Number(number) => json!({ "doubleValue": number.as_f64() }),
+        Value::String(value) => json!({ "stringValue": value }),
+        value => json!({ "stringValue": value.to_string() }),
+    }
+}
+
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": any_value(value) }))
+        .collect()
+}
+
+fn resource() -> Value {
+    let mut fields = Map::new();
+    fields.insert("service.name".to_string(), json!("bgp-scout"));
+    fields.insert(
+        "service.version".to_string(),
+        json!(env!("CARGO_PKG_VERSION")),
+    );
+    fields.insert(
+        "process.command_line".to_string(),
+        json!(env::args().collect::<Vec<_>>().join(" ")),
+    );
+    json!({ "attributes": attributes(&fields) })
+}
+
+fn scope() -> Value {
+    json!({ "name": "bgp-scout", "version": env!("CARGO_PKG_VERSION") })
+}
+
+fn traces(telemetry: &Telemetry, spans: &[FinishedSpan], error: Option<&str>) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut otlp_span = json!({
+                "traceId": telemetry.trace_id,
+                "spanId": format!("{:016x}", span.id),
+                "name": span.name,
+                // SPAN_KIND_INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.started_at),
+                "endTimeUnixNano": unix_nanos(span.ended_at),
+                "attributes": attributes(&span.fields),
+            });
+            if let Some(parent) = span.parent {
+                otlp_span["parentSpanId"] = json!(format!("{parent:016x}"));
+            }
+            if span.parent.is_none() && span.name == RUN_SPAN {
+                otlp_span["status"] = match error {
+                    // STATUS_CODE_ERROR
+                    Some(error) => json!({ "code": 2, "message": error }),
+                    // STATUS_CODE_OK
+                    None => json!({ "code": 1 }),
+                };
+            }
+            otlp_span
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{ "scope": scope(), "spans": spans }],
+        }],
+    })
+}
+
+fn metrics(telemetry: &Telemetry, error: Option<&str>) -> Value {
+    let start = unix_nanos(telemetry.started_at);
+    let now = unix_nanos(SystemTime::now());
+    let mut fields = Map::new();
+    fields.insert(
+        "status".to_string(),
+        json!(if error.is_some() { "error" } else { "ok" }),
+    );
+    let point_attributes = attributes(&fields);
+    let counter = |name: &str, unit: &str, description: &str, value: u64| {
+        json!({
+            "name": name,
+            "unit": unit,
+            "description": description,
+            "sum": {
+                // AGGREGATION_TEMPORALITY_CUMULATIVE, starting with this run
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": [{
+                    "asInt": value.to_string(),
+                    "startTimeUnixNano": start,
+                    "timeUnixNano": now,
+                    "attributes": point_attributes,
+                }],
+            },
+        })
+    };
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{
+                "scope": scope(),
+                "metrics": [
+                    {
+                        "name": "bgp_scout.run.duration",
+                        "unit": "s",
+                        "description": "Duration of the run",
+                        "gauge": {
+                            "dataPoints": [{
+                                "asDouble": telemetry.started.elapsed().as_secs_f64(),
+                                "timeUnixNano": now,
+                                "attributes": point_attributes,
+                            }],
+                        },
+                    },
+                    counter(
+                        "bgp_scout.download.bytes",
+                        "By",
+                        "Bytes downloaded",
+                        telemetry.downloaded_bytes.load(Ordering::Relaxed),
+                    ),
+                    counter(
+                        "bgp_scout.prefixes.found",
+                        "{prefix}",
+                        "Prefixes output",
+                        telemetry.prefixes_found.load(Ordering::Relaxed),
+                    ),
+                ],
+            }],
+        }],
+    })
+}
+
+/// Parses `OTEL_EXPORTER_OTLP_HEADERS`, a comma-separated list of `key=value` pairs.
+fn export_headers() -> Vec<(String, String)> {
+    env::var("OTEL_EXPORTER_OTLP_HEADERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|header| header.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn post(client: &Client, url: &str, body: &Value) -> Result<(), Box<dyn Error>> {
+    let mut request = client
+        .post(url)
+        .timeout(EXPORT_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    for (key, value) in export_headers() {
+        request = request.header(key, value);
+    }
+    request.send()?.error_for_status()?;
+    debug!("Exported telemetry to {url}");
+    Ok(())
+}
+
+/// Sends the spans and metrics of the run to the collector, with the error the run failed with.
+/// Export failures are only logged, so telemetry never fails a job.
+pub fn export(error: Option<&str>) {
+    let Some(telemetry) = TELEMETRY.get() else {
+        return;
+    };
+    let spans = match telemetry.spans.lock() {
+        Ok(mut spans) => mem::take(&mut *spans),
+        Err(_) => Vec::new(),
+    };
+    let client = Client::new();
+    for (path, body) in [
+        ("v1/traces", traces(telemetry, &spans, error)),
+        ("v1/metrics", metrics(telemetry, error)),
+    ] {
+        let url = format!("{}/{path}", telemetry.endpoint);
+        if let Err(e) = post(&client, &url, &body) {
+            warn!("Could not export telemetry to {url}: {e}");
+        }
+    }
+}
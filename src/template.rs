@@ -0,0 +1,885 @@
+use chrono::Utc;
+use ipnet::IpNet;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+
+use crate::render::{address_count, RenderOptions, Renderer};
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Filters available in `{{ value | filter }}` expressions
+const FILTERS: [&str; 10] = [
+    "upper",
+    "lower",
+    "trim",
+    "length",
+    "first",
+    "last",
+    "join",
+    "default",
+    "replace",
+    "json_encode",
+];
+
+/// A template written in the subset of Tera syntax supported by `--template`: `{{ expression }}`
+/// with filters, `{% for %}` and `{% if %}`/`{% elif %}`/`{% else %}` blocks, `{# comments #}`
+/// and `-` whitespace control on any delimiter.
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Expression(Expression),
+    For {
+        variable: String,
+        iterable: Expression,
+        body: Vec<Node>,
+    },
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Debug)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Debug)]
+struct Filter {
+    name: String,
+    args: HashMap<String, Value>,
+}
+
+#[derive(Debug)]
+struct Expression {
+    operand: Operand,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug)]
+enum Condition {
+    Truthy(Expression),
+    Equal(Expression, Expression),
+    NotEqual(Expression, Expression),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+/// Pieces of the template source between delimiters
+#[derive(Debug)]
+enum Chunk {
+    Text(String),
+    Expression(String, usize),
+    Tag(String, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Literal(Value),
+    Pipe,
+    Comma,
+    Assign,
+    Equal,
+    NotEqual,
+    OpenParen,
+    CloseParen,
+}
+
+/// Splits the source into text, expressions and tags, applying `-` whitespace control.
+fn split_chunks(source: &str) -> Result<Vec<Chunk>, Box<dyn Error>> {
+    let mut chunks = Vec::new();
+    let mut rest = source;
+    let mut trim_next = false;
+    loop {
+        let Some(start) = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min()
+        else {
+            let text = if trim_next { rest.trim_start() } else { rest };
+            chunks.push(Chunk::Text(text.to_string()));
+            break;
+        };
+        let line = source.len() - rest.len() + start;
+        let line = source[..line].matches('\n').count() + 1;
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let open = &rest[start..start + 2];
+        let close = match open {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let mut inner = &rest[start + 2..];
+        if let Some(stripped) = inner.strip_prefix('-') {
+            text = text.trim_end();
+            inner = stripped;
+        }
+        chunks.push(Chunk::Text(text.to_string()));
+
+        let end = inner
+            .find(close)
+            .ok_or_else(|| format!("Template line {line}: unclosed {open}"))?;
+        let mut content = &inner[..end];
+        trim_next = false;
+        if let Some(stripped) = content.strip_suffix('-') {
+            content = stripped;
+            trim_next = true;
+        }
+        match open {
+            "{{" => chunks.push(Chunk::Expression(content.trim().to_string(), line)),
+            "{%" => chunks.push(Chunk::Tag(content.trim().to_string(), line)),
+            _ => {}
+        }
+        rest = &inner[end + 2..];
+    }
+    Ok(chunks)
+}
+
+fn tokenize(source: &str, line: usize) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '|' => tokens.push(Token::Pipe),
+            ',' => tokens.push(Token::Comma),
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '=' if chars.next_if(|(_, next)| *next == '=').is_some() => tokens.push(Token::Equal),
+            '=' => tokens.push(Token::Assign),
+            '!' if chars.next_if(|(_, next)| *next == '=').is_some() => {
+                tokens.push(Token::NotEqual);
+            }
+            '"' | '\'' => {
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, next)) if next == c => break,
+                        Some((_, '\\')) => {
+                            if let Some((_, escaped)) = chars.next() {
+                                literal.push(match escaped {
+                                    'n' => '\n',
+                                    't' => '\t',
+                                    other => other,
+                                });
+                            }
+                        }
+                        Some((_, next)) => literal.push(next),
+                        None => return Err(format!("Template line {line}: unclosed string").into()),
+                    }
+                }
+                tokens.push(Token::Literal(Value::String(literal)));
+            }
+            // A minus is only the sign of a number, there is no arithmetic
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.peek().is_some_and(|(_, next)| next.is_ascii_digit())) =>
+            {
+                let mut end = index + c.len_utf8();
+                while let Some((next_index, _)) =
+                    chars.next_if(|(_, next)| next.is_ascii_digit() || *next == '.')
+                {
+                    end = next_index + 1;
+                }
+                let number = &source[index..end];
+                let invalid = || format!("Template line {line}: invalid number {number}");
+                // Integers stay integers, so they render without a fraction
+                let value = if number.contains('.') {
+                    json!(number.parse::<f64>().map_err(|_| invalid())?)
+                } else {
+                    json!(number.parse::<i64>().map_err(|_| invalid())?)
+                };
+                tokens.push(Token::Literal(value));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut end = index + c.len_utf8();
+                while let Some((next_index, next)) = chars
+                    .next_if(|(_, next)| next.is_alphanumeric() || *next == '_' || *next == '.')
+                {
+                    end = next_index + next.len_utf8();
+                }
+                let word = &source[index..end];
+                tokens.push(match word {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    _ => Token::Identifier(word.to_string()),
+                });
+            }
+            other => {
+                return Err(format!("Template line {line}: unexpected character {other}").into())
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of a single expression or condition
+#[derive(Debug)]
+struct Parser<'tokens> {
+    tokens: &'tokens [Token],
+    position: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> Box<dyn Error> {
+        format!("Template line {}: {message}", self.line).into()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(word)) if word == keyword)
+    }
+
+    fn finish(&self) -> Result<(), Box<dyn Error>> {
+        match self.peek() {
+            Some(token) => Err(self.error(&format!("unexpected {token:?}"))),
+            None => Ok(()),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, Box<dyn Error>> {
+        let operand = match self.next() {
+            Some(Token::Identifier(path)) => {
+                Operand::Path(path.split('.').map(str::to_string).collect())
+            }
+            Some(Token::Literal(value)) => Operand::Literal(value),
+            _ => return Err(self.error("expected a variable or literal")),
+        };
+        let mut filters = Vec::new();
+        while self.peek() == Some(&Token::Pipe) {
+            self.position += 1;
+            let Some(Token::Identifier(name)) = self.next() else {
+                return Err(self.error("expected a filter name"));
+            };
+            if !FILTERS.contains(&name.as_str()) {
+                return Err(self.error(&format!("unknown filter {name}")));
+            }
+            let mut args = HashMap::new();
+            if self.peek() == Some(&Token::OpenParen) {
+                self.position += 1;
+                while self.peek() != Some(&Token::CloseParen) {
+                    let (
+                        Some(Token::Identifier(arg)),
+                        Some(Token::Assign),
+                        Some(Token::Literal(value)),
+                    ) = (self.next(), self.next(), self.next())
+                    else {
+                        return Err(self.error("expected filter arguments as name=\"value\""));
+                    };
+                    args.insert(arg, value);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.position += 1;
+                    }
+                }
+                self.position += 1;
+            }
+            filters.push(Filter { name, args });
+        }
+        Ok(Expression { operand, filters })
+    }
+
+    fn condition(&mut self) -> Result<Condition, Box<dyn Error>> {
+        let mut condition = self.conjunction()?;
+        while self.is_keyword("or") {
+            self.position += 1;
+            condition = Condition::Or(Box::new(condition), Box::new(self.conjunction()?));
+        }
+        Ok(condition)
+    }
+
+    fn conjunction(&mut self) -> Result<Condition, Box<dyn Error>> {
+        let mut condition = self.negation()?;
+        while self.is_keyword("and") {
+            self.position += 1;
+            condition = Condition::And(Box::new(condition), Box::new(self.negation()?));
+        }
+        Ok(condition)
+    }
+
+    fn negation(&mut self) -> Result<Condition, Box<dyn Error>> {
+        if self.is_keyword("not") {
+            self.position += 1;
+            return Ok(Condition::Not(Box::new(self.negation()?)));
+        }
+        let left = self.expression()?;
+        match self.peek() {
+            Some(Token::Equal) => {
+                self.position += 1;
+                Ok(Condition::Equal(left, self.expression()?))
+            }
+            Some(Token::NotEqual) => {
+                self.position += 1;
+                Ok(Condition::NotEqual(left, self.expression()?))
+            }
+            _ => Ok(Condition::Truthy(left)),
+        }
+    }
+}
+
+fn parse_expression(source: &str, line: usize) -> Result<Expression, Box<dyn Error>> {
+    let tokens = tokenize(source, line)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        line,
+    };
+    let expression = parser.expression()?;
+    parser.finish()?;
+    Ok(expression)
+}
+
+fn parse_condition(source: &str, line: usize) -> Result<Condition, Box<dyn Error>> {
+    let tokens = tokenize(source, line)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        line,
+    };
+    let condition = parser.condition()?;
+    parser.finish()?;
+    Ok(condition)
+}
+
+/// A tag ending a block, with its line
+type EndTag = (String, usize);
+
+/// Parses nodes until one of the terminating tags, returning the nodes and the terminating tag.
+fn parse_nodes(
+    chunks: &mut std::vec::IntoIter<Chunk>,
+    terminators: &[&str],
+) -> Result<(Vec<Node>, Option<EndTag>), Box<dyn Error>> {
+    let mut nodes = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        match chunk {
+            Chunk::Text(text) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text));
+                }
+            }
+            Chunk::Expression(source, line) => {
+                nodes.push(Node::Expression(parse_expression(&source, line)?));
+            }
+            Chunk::Tag(source, line) => {
+                let keyword = source.split_whitespace().next().unwrap_or_default();
+                if terminators.contains(&keyword) {
+                    return Ok((nodes, Some((source, line))));
+                }
+                let arguments = source[keyword.len()..].trim();
+                match keyword {
+                    "for" => {
+                        let (variable, iterable) = arguments
+                            .split_once(" in ")
+                            .ok_or_else(|| format!("Template line {line}: expected for x in y"))?;
+                        let (body, end) = parse_nodes(chunks, &["endfor"])?;
+                        if end.is_none() {
+                            return Err(format!("Template line {line}: unclosed for").into());
+                        }
+                        nodes.push(Node::For {
+                            variable: variable.trim().to_string(),
+                            iterable: parse_expression(iterable, line)?,
+                            body,
+                        });
+                    }
+                    "if" => {
+                        let mut branches = Vec::new();
+                        let mut condition = parse_condition(arguments, line)?;
+                        let mut otherwise = Vec::new();
+                        loop {
+                            let (body, end) = parse_nodes(chunks, &["elif", "else", "endif"])?;
+                            let Some((end, end_line)) = end else {
+                                return Err(format!("Template line {line}: unclosed if").into());
+                            };
+                            branches.push((condition, body));
+                            match end.split_whitespace().next().unwrap_or_default() {
+                                "elif" => {
+                                    condition = parse_condition(end[4..].trim(), end_line)?;
+                                }
+                                "else" => {
+                                    let (body, end) = parse_nodes(chunks, &["endif"])?;
+                                    if end.is_none() {
+                                        return Err(
+                                            format!("Template line {line}: unclosed if").into()
+                                        );
+                                    }
+                                    otherwise = body;
+                                    break;
+                                }
+                                _ => break,
+                            }
+                        }
+                        nodes.push(Node::If {
+                            branches,
+                            otherwise,
+                        });
+                    }
+                    _ => {
+                        return Err(format!("Template line {line}: unexpected tag {keyword}").into())
+                    }
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::String(string) => !string.is_empty(),
+        Value::Array(array) => !array.is_empty(),
+        Value::Object(object) => !object.is_empty(),
+    }
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn string_arg(filter: &Filter, name: &str) -> Result<String, Box<dyn Error>> {
+    filter
+        .args
+        .get(name)
+        .map(to_text)
+        .ok_or_else(|| format!("Filter {} requires the {name} argument", filter.name).into())
+}
+
+fn apply_filter(value: Value, filter: &Filter) -> Result<Value, Box<dyn Error>> {
+    Ok(match filter.name.as_str() {
+        "upper" => Value::String(to_text(&value).to_uppercase()),
+        "lower" => Value::String(to_text(&value).to_lowercase()),
+        "trim" => Value::String(to_text(&value).trim().to_string()),
+        "length" => json!(match &value {
+            Value::Array(array) => array.len(),
+            Value::Object(object) => object.len(),
+            other => to_text(other).chars().count(),
+        }),
+        "first" => value
+            .as_array()
+            .and_then(|array| array.first())
+            .cloned()
+            .unwrap_or_default(),
+        "last" => value
+            .as_array()
+            .and_then(|array| array.last())
+            .cloned()
+            .unwrap_or_default(),
+        "join" => {
+            let separator = string_arg(filter, "sep")?;
+            let items: Vec<String> = value
+                .as_array()
+                .map(|array| array.iter().map(to_text).collect())
+                .unwrap_or_default();
+            Value::String(items.join(&separator))
+        }
+        "default" if is_truthy(&value) => value,
+        "default" => filter.args.get("value").cloned().unwrap_or_default(),
+        "replace" => Value::String(
+            to_text(&value).replace(&string_arg(filter, "from")?, &string_arg(filter, "to")?),
+        ),
+        _ => Value::String(value.to_string()),
+    })
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut chunks = split_chunks(source)?.into_iter();
+        let (nodes, end) = parse_nodes(&mut chunks, &[])?;
+        if let Some((tag, line)) = end {
+            return Err(format!("Template line {line}: unexpected tag {tag}").into());
+        }
+        Ok(Self { nodes })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| format!("{path}: {e}").into())
+    }
+
+    /// Renders the template with the given object as its variables.
+    pub fn render_value(
+        &self,
+        output: &mut dyn Write,
+        context: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut scopes = vec![context.clone()];
+        render_nodes(output, &self.nodes, &mut scopes)
+    }
+}
+
+fn lookup(path: &[String], scopes: &[Value]) -> Value {
+    let Some((first, rest)) = path.split_first() else {
+        return Value::Null;
+    };
+    let Some(mut value) = scopes.iter().rev().find_map(|scope| scope.get(first)) else {
+        return Value::Null;
+    };
+    for segment in rest {
+        let next = match segment.parse::<usize>() {
+            Ok(index) => value.get(index),
+            Err(_) => value.get(segment),
+        };
+        match next {
+            Some(next) => value = next,
+            None => return Value::Null,
+        }
+    }
+    value.clone()
+}
+
+fn evaluate(expression: &Expression, scopes: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let mut value = match &expression.operand {
+        Operand::Path(path) => lookup(path, scopes),
+        Operand::Literal(value) => value.clone(),
+    };
+    for filter in &expression.filters {
+        value = apply_filter(value, filter)?;
+    }
+    Ok(value)
+}
+
+/// Compares values, treating numbers equal when their values match regardless of type.
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => (left - right).abs() < f64::EPSILON,
+        _ => left == right,
+    }
+}
+
+fn test(condition: &Condition, scopes: &[Value]) -> Result<bool, Box<dyn Error>> {
+    Ok(match condition {
+        Condition::Truthy(expression) => is_truthy(&evaluate(expression, scopes)?),
+        Condition::Equal(left, right) => {
+            values_equal(&evaluate(left, scopes)?, &evaluate(right, scopes)?)
+        }
+        Condition::NotEqual(left, right) => {
+            !values_equal(&evaluate(left, scopes)?, &evaluate(right, scopes)?)
+        }
+        Condition::Not(condition) => !test(condition, scopes)?,
+        Condition::And(left, right) => test(left, scopes)? && test(right, scopes)?,
+        Condition::Or(left, right) => test(left, scopes)? || test(right, scopes)?,
+    })
+}
+
+fn render_nodes(
+    output: &mut dyn Write,
+    nodes: &[Node],
+    scopes: &mut Vec<Value>,
+) -> Result<(), Box<dyn Error>> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.write_all(text.as_bytes())?,
+            Node::Expression(expression) => {
+                output.write_all(to_text(&evaluate(expression, scopes)?).as_bytes())?;
+            }
+            Node::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let items = match evaluate(iterable, scopes)? {
+                    Value::Array(items) => items,
+                    Value::Null => Vec::new(),
+                    other => return Err(format!("Cannot iterate over {other}").into()),
+                };
+                let count = items.len();
+                for (index, item) in items.into_iter().enumerate() {
+                    let mut scope = Map::new();
+                    scope.insert(variable.clone(), item);
+                    scope.insert(
+                        "loop".to_string(),
+                        json!({
+                            "index": index + 1,
+                            "index0": index,
+                            "first": index == 0,
+                            "last": index + 1 == count,
+                        }),
+                    );
+                    scopes.push(Value::Object(scope));
+                    let result = render_nodes(output, body, scopes);
+                    scopes.pop();
+                    result?;
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let mut matched = None;
+                for (condition, body) in branches {
+                    if test(condition, scopes)? {
+                        matched = Some(body);
+                        break;
+                    }
+                }
+                render_nodes(output, matched.unwrap_or(otherwise), scopes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders the results through a user supplied template
+#[derive(Debug)]
+pub struct TemplateRenderer {
+    pub template: Template,
+}
+
+/// Returns the union of the origins of the announced prefixes covered by each result prefix.
+pub fn covered_origins(
+    prefixes: &[IpNet],
+    origins: &HashMap<IpNet, HashSet<u32>>,
+) -> HashMap<IpNet, BTreeSet<u32>> {
+    let results: HashSet<IpNet> = prefixes.iter().copied().collect();
+    let mut covered: HashMap<IpNet, BTreeSet<u32>> = HashMap::new();
+    for (announced, asns) in origins {
+        // Walk the supernets of the announced prefix to find the result covering it
+        let covering = (0..=announced.prefix_len()).rev().find_map(|len| {
+            IpNet::new(announced.addr(), len)
+                .ok()
+                .map(|supernet| supernet.trunc())
+                .filter(|supernet| results.contains(supernet))
+        });
+        if let Some(covering) = covering {
+            covered.entry(covering).or_default().extend(asns);
+        }
+    }
+    covered
+}
+
+impl Renderer for TemplateRenderer {
+    fn render(
+        &self,
+        output: &mut dyn Write,
+        prefixes: &[IpNet],
+        options: &RenderOptions<'_>,
+    ) -> Result<(), Box<dyn Error>> {
+        let origins = options
+            .origins
+            .map(|origins| covered_origins(prefixes, origins))
+            .unwrap_or_default();
+        let prefix_values: Vec<Value> = prefixes
+            .iter()
+            .map(|prefix| {
+                json!({
+                    "prefix": prefix.to_string(),
+                    "network": prefix.network().to_string(),
+                    "last": prefix.broadcast().to_string(),
+                    "netmask": prefix.netmask().to_string(),
+                    "hostmask": prefix.hostmask().to_string(),
+                    "prefix_len": prefix.prefix_len(),
+                    "family": if matches!(prefix, IpNet::V4(_)) { 4 } else { 6 },
+                    "addresses": address_count(prefix).to_string(),
+                    "origin_asns": origins.get(prefix).cloned().unwrap_or_default(),
+                })
+            })
+            .collect();
+        let all_origins: BTreeSet<u32> = origins.values().flatten().copied().collect();
+        let ipv4_count = prefixes
+            .iter()
+            .filter(|prefix| matches!(prefix, IpNet::V4(_)))
+            .count();
+        let context = json!({
+            "prefixes": prefix_values,
+            "networks": options.networks.unwrap_or_default(),
+            "provenance": options.provenance,
+            "metadata": {
+                "generated_at": Utc::now().to_rfc3339(),
+                "version": env!("CARGO_PKG_VERSION"),
+                "count": prefixes.len(),
+                "ipv4_count": ipv4_count,
+                "ipv6_count": prefixes.len() - ipv4_count,
+                "origin_asns": all_origins,
+                "acl_name": options.acl_name,
+            },
+        });
+        self.template.render_value(output, &context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        let mut output = Vec::new();
+        Template::parse(source)?.render_value(&mut output, context)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    fn parse_error(source: &str) -> String {
+        Template::parse(source).map_or_else(|e| e.to_string(), |_| String::new())
+    }
+
+    #[test]
+    fn expressions() -> Result<(), Box<dyn Error>> {
+        let context = json!({"name": "AS64500", "prefix": {"len": 24}, "asns": [1, 2]});
+        assert_eq!(render("{{ name }}", &context)?, "AS64500");
+        assert_eq!(render("/{{ prefix.len }}", &context)?, "/24");
+        assert_eq!(render("{{ asns.1 }}", &context)?, "2");
+        assert_eq!(render("[{{ missing }}]", &context)?, "[]");
+        assert_eq!(render("{{ 'quoted' }}", &context)?, "quoted");
+        Ok(())
+    }
+
+    #[test]
+    fn numbers() -> Result<(), Box<dyn Error>> {
+        let context = json!({});
+        assert_eq!(render("{{ 5 }}", &context)?, "5");
+        assert_eq!(render("{{ -3 }}", &context)?, "-3");
+        assert_eq!(render("{{ 1.5 }}", &context)?, "1.5");
+        assert_eq!(
+            render("{% if 2 == 2.0 %}equal{% endif %}", &context)?,
+            "equal"
+        );
+        assert!(parse_error("{{ 1 - 2 }}").contains("unexpected character -"));
+        assert!(parse_error("{{ - }}").contains("unexpected character -"));
+        Ok(())
+    }
+
+    #[test]
+    fn for_loops() -> Result<(), Box<dyn Error>> {
+        let context = json!({"prefixes": ["192.0.2.0/24", "198.51.100.0/24"]});
+        assert_eq!(
+            render(
+                "{% for p in prefixes %}{{ loop.index }}:{{ p }}{% if not loop.last %},{% endif %}{% endfor %}",
+                &context
+            )?,
+            "1:192.0.2.0/24,2:198.51.100.0/24"
+        );
+        assert_eq!(render("{% for p in missing %}x{% endfor %}", &context)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn conditions() -> Result<(), Box<dyn Error>> {
+        let source = "{% if family == 4 %}v4{% elif family == 6 and wide %}wide v6{% elif family != 6 %}other{% else %}v6{% endif %}";
+        assert_eq!(render(source, &json!({"family": 4}))?, "v4");
+        assert_eq!(
+            render(source, &json!({"family": 6, "wide": true}))?,
+            "wide v6"
+        );
+        assert_eq!(render(source, &json!({"family": 6}))?, "v6");
+        assert_eq!(render(source, &json!({"family": 5}))?, "other");
+        assert_eq!(
+            render("{% if a or b %}yes{% endif %}", &json!({"b": [1]}))?,
+            "yes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn whitespace_control() -> Result<(), Box<dyn Error>> {
+        let context = json!({"items": [1, 2]});
+        assert_eq!(render("a  {{- 'b' -}}  c", &context)?, "abc");
+        assert_eq!(render("a  {{ 'b' -}}\n  c", &context)?, "a  bc");
+        assert_eq!(render("a\n  {{- 'b' }}  c", &context)?, "ab  c");
+        assert_eq!(
+            render(
+                "[\n{%- for i in items -%}\n  {{ i }}\n{%- endfor -%}\n]",
+                &context
+            )?,
+            "[12]"
+        );
+        assert_eq!(render("a {#- comment -#} b", &context)?, "ab");
+        Ok(())
+    }
+
+    #[test]
+    fn filters() -> Result<(), Box<dyn Error>> {
+        let context = json!({"asns": [64500, 64501], "name": " Example Net ", "empty": ""});
+        assert_eq!(
+            render("{{ asns | join(sep=\", \") }}", &context)?,
+            "64500, 64501"
+        );
+        assert_eq!(
+            render("{{ name | trim | upper }}", &context)?,
+            "EXAMPLE NET"
+        );
+        assert_eq!(
+            render("{{ name | trim | lower | length }}", &context)?,
+            "11"
+        );
+        assert_eq!(
+            render(
+                "{{ name | trim | replace(from=\" \", to=\"-\") }}",
+                &context
+            )?,
+            "Example-Net"
+        );
+        assert_eq!(
+            render("{{ empty | default(value=\"none\") }}", &context)?,
+            "none"
+        );
+        assert_eq!(
+            render("{{ asns | first }}-{{ asns | last }}", &context)?,
+            "64500-64501"
+        );
+        assert_eq!(
+            render("{{ asns | json_encode }}", &context)?,
+            "[64500,64501]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<(), Box<dyn Error>> {
+        assert_eq!(parse_error("a\n{{ name"), "Template line 2: unclosed {{");
+        assert_eq!(
+            parse_error("{{ name | shout }}"),
+            "Template line 1: unknown filter shout"
+        );
+        assert_eq!(
+            parse_error("\n\n{% for x in items %}"),
+            "Template line 3: unclosed for"
+        );
+        assert_eq!(parse_error("{% if x %}"), "Template line 1: unclosed if");
+        assert_eq!(
+            parse_error("{% endif %}"),
+            "Template line 1: unexpected tag endif"
+        );
+        assert_eq!(
+            parse_error("{% while x %}"),
+            "Template line 1: unexpected tag while"
+        );
+        assert_eq!(
+            parse_error("{{ 'open }}"),
+            "Template line 1: unclosed string"
+        );
+        assert_eq!(
+            parse_error("{{ x | join(\", \") }}"),
+            "Template line 1: expected filter arguments as name=\"value\""
+        );
+        let missing_arg = render("{{ x | join }}", &json!({"x": [1]}))
+            .map_or_else(|e| e.to_string(), |_| String::new());
+        assert_eq!(missing_arg, "Filter join requires the sep argument");
+        Ok(())
+    }
+}
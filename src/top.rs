@@ -0,0 +1,272 @@
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io;
+
+use crate::render::{self, ReportFormat};
+use crate::table::OriginTable;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Rankings the top subcommand can report
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopReport {
+    /// Shortest announced prefixes of each address family
+    LargestBlocks,
+    /// Origin ASNs announcing the most prefixes
+    MostPrefixes,
+    /// Origin ASNs announcing the most prefixes their other prefixes could be aggregated into
+    MostDeaggregated,
+    /// Prefixes originated by the most ASNs
+    Moas,
+}
+
+/// An announced prefix and its origins
+#[derive(Debug, Serialize)]
+pub struct Block {
+    pub prefix: IpNet,
+    pub origins: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AsnPrefixes {
+    pub asn: u32,
+    pub prefixes: usize,
+}
+
+/// Prefixes of an origin ASN compared with the fewest prefixes covering the same space
+#[derive(Debug, Serialize)]
+pub struct Deaggregation {
+    pub asn: u32,
+    pub prefixes: usize,
+    pub aggregated: usize,
+    /// Prefixes announced beyond the aggregated ones
+    pub extra_prefixes: usize,
+    pub factor: f64,
+}
+
+/// The rankings asked for, each holding up to the requested number of entries
+#[derive(Debug, Default, Serialize)]
+pub struct Top {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub largest_blocks: Option<Vec<Block>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub most_prefixes: Option<Vec<AsnPrefixes>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub most_deaggregated: Option<Vec<Deaggregation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moas: Option<Vec<Block>>,
+}
+
+fn block(prefix: &IpNet, origins: &BTreeSet<u32>) -> Block {
+    Block {
+        prefix: *prefix,
+        origins: origins.iter().copied().collect(),
+    }
+}
+
+/// Largest prefixes of each family, IPv4 first, as address counts of the families differ too
+/// much to rank them together.
+fn largest_blocks(table: &OriginTable, count: usize) -> Vec<Block> {
+    let mut prefixes: Vec<(&IpNet, _)> = table.iter().collect();
+    prefixes.sort_by_key(|(prefix, _)| (matches!(prefix, IpNet::V6(_)), prefix.prefix_len()));
+    let ipv4 = prefixes
+        .iter()
+        .filter(|(prefix, _)| matches!(prefix, IpNet::V4(_)))
+        .take(count);
+    let ipv6 = prefixes
+        .iter()
+        .filter(|(prefix, _)| matches!(prefix, IpNet::V6(_)))
+        .take(count);
+    ipv4.chain(ipv6)
+        .map(|(prefix, origins)| block(prefix, origins))
+        .collect()
+}
+
+fn prefixes_by_origin(table: &OriginTable) -> BTreeMap<u32, Vec<IpNet>> {
+    let mut prefixes: BTreeMap<u32, Vec<IpNet>> = BTreeMap::new();
+    for (prefix, origins) in table {
+        for origin in origins {
+            prefixes.entry(*origin).or_default().push(*prefix);
+        }
+    }
+    prefixes
+}
+
+fn most_prefixes(by_origin: &BTreeMap<u32, Vec<IpNet>>, count: usize) -> Vec<AsnPrefixes> {
+    let mut origins: Vec<AsnPrefixes> = by_origin
+        .iter()
+        .map(|(asn, prefixes)| AsnPrefixes {
+            asn: *asn,
+            prefixes: prefixes.len(),
+        })
+        .collect();
+    origins.sort_by(|a, b| b.prefixes.cmp(&a.prefixes).then(a.asn.cmp(&b.asn)));
+    origins.truncate(count);
+    origins
+}
+
+fn most_deaggregated(by_origin: &BTreeMap<u32, Vec<IpNet>>, count: usize) -> Vec<Deaggregation> {
+    let mut origins: Vec<Deaggregation> = by_origin
+        .iter()
+        .map(|(asn, prefixes)| {
+            let aggregated = IpNet::aggregate(prefixes).len();
+            #[allow(clippy::cast_precision_loss)]
+            let factor = prefixes.len() as f64 / aggregated as f64;
+            Deaggregation {
+                asn: *asn,
+                prefixes: prefixes.len(),
+                aggregated,
+                extra_prefixes: prefixes.len() - aggregated,
+                factor,
+            }
+        })
+        .filter(|origin| origin.extra_prefixes > 0)
+        .collect();
+    origins.sort_by(|a, b| {
+        b.extra_prefixes
+            .cmp(&a.extra_prefixes)
+            .then(a.asn.cmp(&b.asn))
+    });
+    origins.truncate(count);
+    origins
+}
+
+fn moas(table: &OriginTable, count: usize) -> Vec<Block> {
+    let mut prefixes: Vec<(&IpNet, _)> = table
+        .iter()
+        .filter(|(_, origins)| origins.len() > 1)
+        .collect();
+    prefixes.sort_by(|(a, a_origins), (b, b_origins)| {
+        b_origins.len().cmp(&a_origins.len()).then(a.cmp(b))
+    });
+    prefixes
+        .into_iter()
+        .take(count)
+        .map(|(prefix, origins)| block(prefix, origins))
+        .collect()
+}
+
+/// Ranks the prefixes and origins of a table for each report asked for, or for every report
+/// when none is.
+pub fn top(table: &OriginTable, reports: &[TopReport], count: usize) -> Top {
+    let wanted = |report| reports.is_empty() || reports.contains(&report);
+    let by_origin = prefixes_by_origin(table);
+    debug!(
+        "Ranking {} prefixes of {} origin ASNs",
+        table.len(),
+        by_origin.len()
+    );
+    Top {
+        largest_blocks: wanted(TopReport::LargestBlocks).then(|| largest_blocks(table, count)),
+        most_prefixes: wanted(TopReport::MostPrefixes).then(|| most_prefixes(&by_origin, count)),
+        most_deaggregated: wanted(TopReport::MostDeaggregated)
+            .then(|| most_deaggregated(&by_origin, count)),
+        moas: wanted(TopReport::Moas).then(|| moas(table, count)),
+    }
+}
+
+fn join_origins(origins: &[u32]) -> String {
+    origins
+        .iter()
+        .map(|asn| format!("AS{asn}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Writes one ranking as a titled section.
+fn render_section(
+    title: &str,
+    header: &[&str],
+    rows: Vec<Vec<String>>,
+    format: ReportFormat,
+) -> io::Result<()> {
+    println!("{title}");
+    match format {
+        ReportFormat::Table => render::write_table(&mut io::stdout(), header, &rows)?,
+        _ => {
+            for row in rows {
+                let fields: Vec<String> = header
+                    .iter()
+                    .zip(row)
+                    .map(|(name, value)| format!("{}={value}", name.to_lowercase()))
+                    .collect();
+                println!("{}", fields.join(" "));
+            }
+        }
+    }
+    println!();
+    Ok(())
+}
+
+pub fn render_top(top: &Top, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    if format == ReportFormat::Json {
+        serde_json::to_writer(io::stdout(), top)?;
+        return Ok(());
+    }
+    if let Some(blocks) = &top.largest_blocks {
+        let rows = blocks
+            .iter()
+            .map(|block| vec![block.prefix.to_string(), join_origins(&block.origins)])
+            .collect();
+        render_section(
+            "Largest announced blocks",
+            &["PREFIX", "ORIGINS"],
+            rows,
+            format,
+        )?;
+    }
+    if let Some(origins) = &top.most_prefixes {
+        let rows = origins
+            .iter()
+            .map(|origin| vec![format!("AS{}", origin.asn), origin.prefixes.to_string()])
+            .collect();
+        render_section(
+            "ASNs with the most prefixes",
+            &["ASN", "PREFIXES"],
+            rows,
+            format,
+        )?;
+    }
+    if let Some(origins) = &top.most_deaggregated {
+        let rows = origins
+            .iter()
+            .map(|origin| {
+                vec![
+                    format!("AS{}", origin.asn),
+                    origin.prefixes.to_string(),
+                    origin.aggregated.to_string(),
+                    origin.extra_prefixes.to_string(),
+                    format!("{:.2}", origin.factor),
+                ]
+            })
+            .collect();
+        render_section(
+            "ASNs with the most de-aggregation",
+            &["ASN", "PREFIXES", "AGGREGATED", "EXTRA", "FACTOR"],
+            rows,
+            format,
+        )?;
+    }
+    if let Some(blocks) = &top.moas {
+        let rows = blocks
+            .iter()
+            .map(|block| {
+                vec![
+                    block.prefix.to_string(),
+                    block.origins.len().to_string(),
+                    join_origins(&block.origins),
+                ]
+            })
+            .collect();
+        render_section(
+            "Prefixes with the most origins",
+            &["PREFIX", "COUNT", "ORIGINS"],
+            rows,
+            format,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Node of the trie, with the child for each next bit of the address and the prefix ending here.
+/// Indexes are 32 bits to keep nodes small, as a full table needs only a few million of them.
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    /// Indexes of the children, 0 for none as the roots are never children
+    children: [u32; 2],
+    value: Option<u32>,
+}
+
+/// Binary trie of prefixes answering longest-prefix matches in as many steps as the address has
+/// bits, however many prefixes it holds
+#[derive(Debug)]
+pub struct PrefixTrie<T> {
+    /// Nodes of both families, the IPv4 root first and the IPv6 root second
+    nodes: Vec<Node>,
+    values: Vec<(IpNet, T)>,
+}
+
+impl<T> Default for PrefixTrie<T> {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default(); 2],
+            values: Vec::new(),
+        }
+    }
+}
+
+/// Returns the root of the family of the address and the address as bits aligned to the left.
+fn root_and_bits(addr: IpAddr) -> (usize, u128, u8) {
+    match addr {
+        IpAddr::V4(v4) => (0, u128::from(u32::from(v4)) << 96, 32),
+        IpAddr::V6(v6) => (1, u128::from(v6), 128),
+    }
+}
+
+const fn bit(bits: u128, index: u8) -> usize {
+    ((bits >> (127 - index)) & 1) as usize
+}
+
+impl<T> PrefixTrie<T> {
+    /// Adds a prefix, replacing the value of a prefix added before.
+    pub fn insert(&mut self, prefix: IpNet, value: T) {
+        let prefix = prefix.trunc();
+        let (mut node, bits, _) = root_and_bits(prefix.network());
+        for index in 0..prefix.prefix_len() {
+            let side = bit(bits, index);
+            node = match self.nodes[node].children[side] {
+                0 => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children[side] = child as u32;
+                    child
+                }
+                child => child as usize,
+            };
+        }
+        match self.nodes[node].value {
+            Some(existing) => self.values[existing as usize] = (prefix, value),
+            None => {
+                self.nodes[node].value = Some(self.values.len() as u32);
+                self.values.push((prefix, value));
+            }
+        }
+    }
+
+    /// Returns the most specific prefix covering the address, with its value.
+    pub fn longest_match(&self, addr: IpAddr) -> Option<&(IpNet, T)> {
+        let (mut node, bits, len) = root_and_bits(addr);
+        let mut found = self.nodes[node].value;
+        for index in 0..len {
+            match self.nodes[node].children[bit(bits, index)] {
+                0 => break,
+                child => node = child as usize,
+            }
+            found = self.nodes[node].value.or(found);
+        }
+        found.map(|index| &self.values[index as usize])
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+impl<T> FromIterator<(IpNet, T)> for PrefixTrie<T> {
+    fn from_iter<I: IntoIterator<Item = (IpNet, T)>>(iter: I) -> Self {
+        let mut trie = Self::default();
+        for (prefix, value) in iter {
+            trie.insert(prefix, value);
+        }
+        debug!(
+            "Built a trie of {} prefixes in {} nodes",
+            trie.len(),
+            trie.nodes.len()
+        );
+        trie
+    }
+}
@@ -0,0 +1,188 @@
+use ipnet::IpNet;
+use std::collections::{BTreeSet, HashSet};
+use std::error::Error;
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::cron;
+use crate::notify::{self, Notification};
+use crate::render::{self, Format, RenderOptions};
+use crate::systemd;
+use crate::NetblockArgs;
+#[allow(unused_imports)]
+use tracing::{debug, error, info, info_span, trace, warn};
+
+/// Writes the ExaBGP commands turning the previous results into the current ones, withdrawing
+/// first so peers never see both an aggregate and the prefixes replacing it.
+fn write_exabgp_delta(
+    output: &mut dyn Write,
+    previous: &BTreeSet<IpNet>,
+    current: &BTreeSet<IpNet>,
+    args: &NetblockArgs,
+) -> Result<(), Box<dyn Error>> {
+    let options = RenderOptions {
+        exabgp_next_hop: &args.exabgp_next_hop,
+        exabgp_communities: &args.exabgp_community,
+        ..RenderOptions::default()
+    };
+    for prefix in previous.difference(current) {
+        writeln!(
+            output,
+            "{}",
+            render::exabgp_command("withdraw", prefix, &options)
+        )?;
+    }
+    for prefix in current.difference(previous) {
+        writeln!(
+            output,
+            "{}",
+            render::exabgp_command("announce", prefix, &options)
+        )?;
+    }
+    // ExaBGP acts on each command as it reads it, so never leave any sitting in a buffer
+    output.flush()?;
+    Ok(())
+}
+
+/// Names the query in notifications by its ASNs, or their number when there are many.
+fn query_label(origin_asns: &HashSet<u32>) -> String {
+    let mut asns: Vec<u32> = origin_asns.iter().copied().collect();
+    asns.sort_unstable();
+    if asns.len() > 5 {
+        return format!("the prefixes of {} ASNs", asns.len());
+    }
+    let asns: Vec<String> = asns.iter().map(|asn| format!("AS{asn}")).collect();
+    format!("the prefixes of {}", asns.join(", "))
+}
+
+fn notify(args: &NetblockArgs, subject: String, lines: Vec<String>) {
+    if !args.notify.is_empty() {
+        notify::send(&args.notify, &Notification { subject, lines });
+    }
+}
+
+fn notify_changes(
+    args: &NetblockArgs,
+    label: &str,
+    previous: &BTreeSet<IpNet>,
+    current: &BTreeSet<IpNet>,
+) {
+    let removed: Vec<&IpNet> = previous.difference(current).collect();
+    let added: Vec<&IpNet> = current.difference(previous).collect();
+    let mut lines = vec![format!(
+        "{} added, {} removed, {} prefixes now",
+        added.len(),
+        removed.len(),
+        current.len()
+    )];
+    lines.extend(added.iter().map(|prefix| format!("+ {prefix}")));
+    lines.extend(removed.iter().map(|prefix| format!("- {prefix}")));
+    notify(args, format!("Results for {label} changed"), lines);
+}
+
+/// When a watch looks the prefixes up again
+#[derive(Debug)]
+pub enum Trigger {
+    /// After sleeping for the interval
+    Interval(Duration),
+    /// At the runs of a cron schedule
+    Cron(cron::Schedule),
+}
+
+impl Trigger {
+    fn wait(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Interval(interval) => thread::sleep(*interval),
+            Self::Cron(schedule) => {
+                schedule.wait()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Repeats a netblock query at every trigger, for as long as the process runs.
+///
+/// The results are published again whenever they change. With `--format exabgp`, only the
+/// commands announcing new prefixes and withdrawing vanished ones are written, so the output can
+/// drive an ExaBGP process directly. A failing update is logged and retried at the next interval,
+/// keeping the last results in place; only a failure of the first one ends the watch.
+///
+/// Changes after the first results, and updates starting to fail or recovering, are also sent to
+/// the `--notify` sinks.
+pub fn run(
+    origin_asns: &HashSet<u32>,
+    args: &NetblockArgs,
+    trigger: &Trigger,
+) -> Result<(), Box<dyn Error>> {
+    // Deltas replace the full output only where it would be written to stdout
+    let exabgp_delta = args.format == Format::Exabgp
+        && args.template.is_none()
+        && args.plugin.is_none()
+        && !args.count
+        && !args.group_by_asn
+        && args.output_v4.is_none()
+        && args.output_v6.is_none()
+        && args.output_redis.is_none()
+        && args.push.is_none()
+        && args.apply.is_none()
+        && args.diff_against.is_none();
+    let label = query_label(origin_asns);
+    let mut previous: Option<BTreeSet<IpNet>> = None;
+    let mut failing = false;
+    loop {
+        let _span = info_span!("watch").entered();
+        let busy = systemd::busy();
+        let published = crate::lookup_netblocks(origin_asns, args, None).and_then(|netblocks| {
+            let current: BTreeSet<IpNet> = netblocks.prefixes.iter().copied().collect();
+            if previous.as_ref() == Some(&current) {
+                debug!("Results unchanged, {} prefixes", current.len());
+                return Ok(current);
+            }
+            info!("Publishing {} prefixes", current.len());
+            if exabgp_delta {
+                write_exabgp_delta(
+                    &mut io::stdout(),
+                    previous.as_ref().unwrap_or(&BTreeSet::new()),
+                    &current,
+                    args,
+                )?;
+            } else {
+                crate::publish_netblocks(origin_asns, args, &netblocks, &mut io::stdout())?;
+            }
+            Ok(current)
+        });
+        drop(busy);
+        match published {
+            Ok(current) => {
+                if previous.is_none() {
+                    systemd::ready();
+                }
+                systemd::status(&format!("Watching {label}, {} prefixes", current.len()));
+                if failing {
+                    failing = false;
+                    notify(args, format!("Updates of {label} recovered"), Vec::new());
+                }
+                if let Some(previous) = previous.as_ref().filter(|previous| **previous != current) {
+                    notify_changes(args, &label, previous, &current);
+                }
+                previous = Some(current);
+            }
+            Err(e) if previous.is_none() => return Err(e),
+            Err(e) => {
+                warn!("Update failed, keeping the previous results: {e}");
+                // Only the first failure is notified, not every retry
+                if !failing {
+                    failing = true;
+                    notify(
+                        args,
+                        format!("Updates of {label} failing"),
+                        vec![e.to_string()],
+                    );
+                }
+            }
+        }
+        trigger.wait()?;
+    }
+}
@@ -0,0 +1,664 @@
+use serde_json::{Map, Number, Value};
+use std::error::Error;
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// A line of a YAML document without its indentation and comment
+#[derive(Debug)]
+struct Line {
+    number: usize,
+    indent: usize,
+    content: String,
+}
+
+fn line_error(number: usize, message: &str) -> Box<dyn Error> {
+    format!("YAML line {number}: {message}").into()
+}
+
+/// Returns whether a quote at a byte position opens a quoted scalar, which it does at the start
+/// of a scalar only, as the quote in `it's` does not.
+fn opens_quote(content: &str, position: usize) -> bool {
+    content[..position]
+        .chars()
+        .next_back()
+        .is_none_or(|previous| previous.is_whitespace() || "[{,".contains(previous))
+}
+
+/// Calls `visit` with each character outside quoted scalars and its position, until it returns
+/// false.
+fn outside_quotes(text: &str, mut visit: impl FnMut(usize, char) -> bool) {
+    let mut characters = text.char_indices().peekable();
+    while let Some((position, character)) = characters.next() {
+        if !matches!(character, '"' | '\'') || !opens_quote(text, position) {
+            if visit(position, character) {
+                continue;
+            }
+            return;
+        }
+        while let Some((_, quoted)) = characters.next() {
+            match quoted {
+                '\\' if character == '"' => {
+                    characters.next();
+                }
+                // A quote is written twice in single-quoted scalars
+                '\'' if character == '\''
+                    && characters.next_if(|(_, next)| *next == '\'').is_some() => {}
+                quoted if quoted == character => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Removes a comment, a `#` at the start of the line or after whitespace outside quotes.
+fn strip_comment(content: &str) -> &str {
+    let mut end = content.len();
+    outside_quotes(content, |position, character| {
+        let comment = character == '#'
+            && content[..position]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace);
+        if comment {
+            end = position;
+        }
+        !comment
+    });
+    content[..end].trim_end()
+}
+
+/// Splits a document into its lines with content, dropping blank lines, comments and the
+/// `---` and `...` markers of a single document.
+fn lines(text: &str) -> Result<Vec<Line>, Box<dyn Error>> {
+    let mut lines: Vec<Line> = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let content = strip_comment(raw);
+        let trimmed = content.trim_start_matches(' ');
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with('\t') {
+            return Err(line_error(number, "tabs are not allowed in indentation"));
+        }
+        let indent = content.len() - trimmed.len();
+        if indent == 0 {
+            match trimmed {
+                "---" if lines.is_empty() => continue,
+                "---" => return Err(line_error(number, "multiple documents are not supported")),
+                "..." => break,
+                _ if trimmed.starts_with('%') => {
+                    return Err(line_error(number, "directives are not supported"))
+                }
+                _ => {}
+            }
+        }
+        lines.push(Line {
+            number,
+            indent,
+            content: trimmed.to_string(),
+        });
+    }
+    Ok(lines)
+}
+
+fn is_sequence_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+/// Reads a quoted scalar at the start of the text, returning it and the rest of the text.
+fn quoted(text: &str, number: usize) -> Result<(String, &str), Box<dyn Error>> {
+    let mut characters = text.char_indices();
+    let quote = characters.next().map(|(_, quote)| quote);
+    let mut value = String::new();
+    while let Some((position, character)) = characters.next() {
+        match character {
+            '\'' if quote == Some('\'') => {
+                if text[position + 1..].starts_with('\'') {
+                    characters.next();
+                    value.push('\'');
+                } else {
+                    return Ok((value, &text[position + 1..]));
+                }
+            }
+            '"' if quote == Some('"') => return Ok((value, &text[position + 1..])),
+            '\\' if quote == Some('"') => {
+                let (_, escape) = characters
+                    .next()
+                    .ok_or_else(|| line_error(number, "unterminated string"))?;
+                let digits = match escape {
+                    'x' => 2,
+                    'u' => 4,
+                    'U' => 8,
+                    _ => 0,
+                };
+                if digits == 0 {
+                    value.push(match escape {
+                        '0' => '\0',
+                        'a' => '\x07',
+                        'b' => '\x08',
+                        't' | '\t' => '\t',
+                        'n' => '\n',
+                        'v' => '\x0b',
+                        'f' => '\x0c',
+                        'r' => '\r',
+                        'e' => '\x1b',
+                        ' ' | '"' | '/' | '\\' => escape,
+                        _ => return Err(line_error(number, &format!("invalid escape \\{escape}"))),
+                    });
+                    continue;
+                }
+                let hex: String = characters.by_ref().take(digits).map(|(_, c)| c).collect();
+                let mut code = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == digits)
+                    .ok_or_else(|| {
+                        line_error(number, &format!("invalid escape \\{escape}{hex}"))
+                    })?;
+                // A UTF-16 surrogate pair, as JSON writes characters outside the BMP
+                if (0xd800..0xdc00).contains(&code) {
+                    let rest = characters.as_str();
+                    let low = rest
+                        .strip_prefix("\\u")
+                        .and_then(|low| low.get(..4))
+                        .and_then(|low| u32::from_str_radix(low, 16).ok())
+                        .filter(|low| (0xdc00..0xe000).contains(low));
+                    if let Some(low) = low {
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        characters.by_ref().take(6).for_each(drop);
+                    }
+                }
+                value.push(
+                    char::from_u32(code)
+                        .ok_or_else(|| line_error(number, &format!("invalid character {hex}")))?,
+                );
+            }
+            _ => value.push(character),
+        }
+    }
+    Err(line_error(number, "unterminated string"))
+}
+
+/// Types a plain scalar following the YAML 1.2 core schema.
+fn plain(text: &str) -> Value {
+    match text {
+        "" | "~" | "null" | "Null" | "NULL" => return Value::Null,
+        "true" | "True" | "TRUE" => return Value::Bool(true),
+        "false" | "False" | "FALSE" => return Value::Bool(false),
+        _ => {}
+    }
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    if !unsigned.is_empty() && unsigned.bytes().all(|byte| byte.is_ascii_digit()) {
+        if let Ok(integer) = text.parse::<i64>() {
+            return Value::from(integer);
+        }
+    }
+    let radix = [("0x", 16), ("0o", 8)]
+        .into_iter()
+        .find_map(|(prefix, radix)| Some((text.strip_prefix(prefix)?, radix)));
+    if let Some((digits, radix)) = radix {
+        if let Ok(integer) = i64::from_str_radix(digits, radix) {
+            return Value::from(integer);
+        }
+    }
+    let is_float = unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && unsigned.chars().any(|c| c.is_ascii_digit())
+        && unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || ".eE+-".contains(c));
+    if is_float {
+        if let Some(number) = text.parse::<f64>().ok().and_then(Number::from_f64) {
+            return Value::Number(number);
+        }
+    }
+    Value::String(text.to_string())
+}
+
+/// Reads a scalar in block context, the whole value of a line.
+fn scalar(text: &str, number: usize) -> Result<Value, Box<dyn Error>> {
+    if text.starts_with(['"', '\'']) {
+        let (value, rest) = quoted(text, number)?;
+        if !rest.trim().is_empty() {
+            return Err(line_error(
+                number,
+                &format!("unexpected {} after string", rest.trim()),
+            ));
+        }
+        return Ok(Value::String(value));
+    }
+    if let Some(indicator) = text.chars().next().filter(|c| "&*!|>@`".contains(*c)) {
+        return Err(line_error(
+            number,
+            &format!("{indicator} (anchors, aliases, tags and block scalars) is not supported"),
+        ));
+    }
+    if text.contains(": ") || text.ends_with(':') {
+        return Err(line_error(
+            number,
+            "mapping values are not allowed here, quote the value",
+        ));
+    }
+    Ok(plain(text))
+}
+
+/// Parser of a flow collection, `[...]` or `{...}`, which JSON documents are made of
+struct Flow<'text> {
+    text: &'text str,
+    number: usize,
+}
+
+impl Flow<'_> {
+    fn skip_spaces(&mut self) {
+        self.text = self.text.trim_start();
+    }
+
+    fn expect(&mut self, character: char) -> Result<(), Box<dyn Error>> {
+        self.skip_spaces();
+        self.text = self.text.strip_prefix(character).ok_or_else(|| {
+            line_error(
+                self.number,
+                &format!("expected {character} in flow collection"),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Reads a plain scalar up to a flow indicator, or also up to a `:` for keys.
+    fn plain(&mut self, key: bool) -> String {
+        let end = self
+            .text
+            .char_indices()
+            .find(|(position, character)| {
+                ",[]{}".contains(*character)
+                    || (key
+                        && *character == ':'
+                        && self.text[position + 1..]
+                            .chars()
+                            .next()
+                            .is_none_or(|next| next.is_whitespace() || ",[]{}".contains(next)))
+            })
+            .map_or(self.text.len(), |(position, _)| position);
+        let (value, rest) = self.text.split_at(end);
+        self.text = rest;
+        value.trim().to_string()
+    }
+
+    fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.skip_spaces();
+        match self.text.chars().next() {
+            Some('[') => self.sequence(),
+            Some('{') => self.mapping(),
+            Some('"' | '\'') => {
+                let (value, rest) = quoted(self.text, self.number)?;
+                self.text = rest;
+                Ok(Value::String(value))
+            }
+            _ => Ok(plain(&self.plain(false))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, Box<dyn Error>> {
+        self.skip_spaces();
+        if self.text.starts_with(['"', '\'']) {
+            let (key, rest) = quoted(self.text, self.number)?;
+            self.text = rest;
+            return Ok(key);
+        }
+        Ok(self.plain(true))
+    }
+
+    /// Reads the separator after an item of a collection, returning whether it was the last.
+    fn next_item(&mut self, close: char) -> Result<bool, Box<dyn Error>> {
+        self.skip_spaces();
+        if let Some(rest) = self.text.strip_prefix(',') {
+            self.text = rest;
+        } else if !self.text.starts_with(close) {
+            return Err(line_error(
+                self.number,
+                &format!("expected , or {close} in flow collection"),
+            ));
+        }
+        // A trailing comma is allowed before the end of the collection
+        Ok(self.close(close))
+    }
+
+    /// Reads the end of a collection, returning whether it was there.
+    fn close(&mut self, close: char) -> bool {
+        self.skip_spaces();
+        match self.text.strip_prefix(close) {
+            Some(rest) => {
+                self.text = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn sequence(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        let mut last = self.close(']');
+        while !last {
+            items.push(self.value()?);
+            last = self.next_item(']')?;
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn mapping(&mut self) -> Result<Value, Box<dyn Error>> {
+        self.expect('{')?;
+        let mut map = Map::new();
+        let mut last = self.close('}');
+        while !last {
+            let key = self.key()?;
+            self.expect(':')?;
+            let value = self.value()?;
+            if map.insert(key.clone(), value).is_some() {
+                return Err(line_error(self.number, &format!("duplicate key {key}")));
+            }
+            last = self.next_item('}')?;
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+/// Returns whether the brackets of a flow collection are closed, outside quoted strings.
+fn is_closed(text: &str) -> bool {
+    let mut depth = 0_usize;
+    outside_quotes(text, |_, character| {
+        match character {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        true
+    });
+    depth == 0
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    position: usize,
+}
+
+impl Parser {
+    fn current(&self) -> Option<&Line> {
+        self.lines.get(self.position)
+    }
+
+    /// Parses the block node starting at the current line, at its indentation.
+    fn block(&mut self, indent: usize) -> Result<Value, Box<dyn Error>> {
+        let Some(line) = self.current() else {
+            return Ok(Value::Null);
+        };
+        if is_sequence_item(&line.content) {
+            self.sequence(indent)
+        } else if split_key(&line.content, line.number)?.is_some() {
+            self.mapping(indent)
+        } else {
+            self.value()
+        }
+    }
+
+    /// Parses the node of the lines indented under a parent, or null when there are none.
+    fn nested(&mut self, parent_indent: usize) -> Result<Value, Box<dyn Error>> {
+        match self.current() {
+            Some(line) if line.indent > parent_indent => {
+                let indent = line.indent;
+                self.block(indent)
+            }
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<Value, Box<dyn Error>> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get_mut(self.position) {
+            if line.indent != indent || !is_sequence_item(&line.content) {
+                break;
+            }
+            let item = line.content[1..].trim_start().to_string();
+            if item.is_empty() {
+                self.position += 1;
+                items.push(self.nested(indent)?);
+                continue;
+            }
+            // The item continues as if the dash were indentation, which makes its mapping keys
+            // line up with those of the next lines
+            line.indent += line.content.len() - item.len();
+            line.content = item;
+            let indent = line.indent;
+            items.push(self.block(indent)?);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<Value, Box<dyn Error>> {
+        let mut map = Map::new();
+        while let Some(line) = self.lines.get_mut(self.position) {
+            if line.indent != indent {
+                break;
+            }
+            let number = line.number;
+            let (key, rest) = split_key(&line.content, number)?
+                .ok_or_else(|| line_error(number, "expected a mapping key"))?;
+            if map.contains_key(&key) {
+                return Err(line_error(number, &format!("duplicate key {key}")));
+            }
+            let value = if rest.is_empty() {
+                self.position += 1;
+                match self.current() {
+                    // A sequence may be indented as much as its key
+                    Some(next) if next.indent == indent && is_sequence_item(&next.content) => {
+                        self.sequence(indent)?
+                    }
+                    _ => self.nested(indent)?,
+                }
+            } else {
+                line.content = rest;
+                self.value()?
+            };
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    /// Parses the value written on the current line, or on the following ones too for a flow
+    /// collection spanning lines.
+    fn value(&mut self) -> Result<Value, Box<dyn Error>> {
+        let Some(line) = self.current() else {
+            return Ok(Value::Null);
+        };
+        let number = line.number;
+        let mut text = line.content.clone();
+        self.position += 1;
+        if !text.starts_with(['[', '{']) {
+            return scalar(&text, number);
+        }
+        while !is_closed(&text) {
+            let next = self
+                .current()
+                .ok_or_else(|| line_error(number, "unclosed flow collection"))?;
+            text.push(' ');
+            text.push_str(&next.content);
+            self.position += 1;
+        }
+        let mut flow = Flow {
+            text: &text,
+            number,
+        };
+        let value = flow.value()?;
+        flow.skip_spaces();
+        if !flow.text.is_empty() {
+            return Err(line_error(
+                number,
+                &format!("unexpected {} after flow collection", flow.text),
+            ));
+        }
+        Ok(value)
+    }
+}
+
+/// Splits a block mapping entry into its key and the rest of the line, or returns None when the
+/// line is not a mapping entry.
+fn split_key(content: &str, number: usize) -> Result<Option<(String, String)>, Box<dyn Error>> {
+    let (key, rest) = if content.starts_with(['"', '\'']) {
+        let (key, rest) = quoted(content, number)?;
+        let Some(rest) = rest.trim_start().strip_prefix(':') else {
+            return Ok(None);
+        };
+        (key, rest)
+    } else if content.starts_with(['[', '{']) {
+        return Ok(None);
+    } else {
+        let separator = content.char_indices().find(|(position, character)| {
+            *character == ':'
+                && content[position + 1..]
+                    .chars()
+                    .next()
+                    .is_none_or(char::is_whitespace)
+        });
+        let Some((position, _)) = separator else {
+            return Ok(None);
+        };
+        (
+            content[..position].trim_end().to_string(),
+            &content[position + 1..],
+        )
+    };
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return Ok(None);
+    }
+    Ok(Some((key, rest.trim().to_string())))
+}
+
+/// Parses a YAML document into the equivalent JSON value.
+///
+/// The subset read is what configuration files are written in: block mappings and sequences,
+/// flow collections, which makes any JSON document valid, and plain, single- and double-quoted
+/// scalars typed by the YAML 1.2 core schema. Anchors, aliases, tags, block scalars and multiple
+/// documents are rejected rather than misread.
+pub fn parse(text: &str) -> Result<Value, Box<dyn Error>> {
+    let lines = lines(text)?;
+    let Some(first) = lines.first() else {
+        return Ok(Value::Null);
+    };
+    let indent = first.indent;
+    let mut parser = Parser { lines, position: 0 };
+    let value = parser.block(indent)?;
+    if let Some(line) = parser.current() {
+        return Err(line_error(line.number, "unexpected indentation"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_block_collections() -> Result<(), Box<dyn Error>> {
+        let text = "\
+---
+# Queries
+- name: cloudflare   # the CDN
+  args: [13335, -4, --format, nginx]
+  output: 'cloudflare.conf'
+- args:
+  - '15169'
+  - \"--sort\"
+  nested:
+    key: value
+    empty:
+    list:
+      - 1.5
+      - true
+      - ~
+      - - a
+        - b
+";
+        assert_eq!(
+            parse(text)?,
+            json!([
+                {
+                    "name": "cloudflare",
+                    "args": [13335, -4, "--format", "nginx"],
+                    "output": "cloudflare.conf",
+                },
+                {
+                    "args": ["15169", "--sort"],
+                    "nested": {
+                        "key": "value",
+                        "empty": null,
+                        "list": [1.5, true, null, ["a", "b"]],
+                    },
+                },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_json() -> Result<(), Box<dyn Error>> {
+        let text = r#"[
+  {"name": "a #1", "args": ["13335",
+    "-4"], "output": null, "escaped": "\"\u00e9\ud83d\ude00\\"},
+  {}
+]"#;
+        assert_eq!(
+            parse(text)?,
+            json!([
+                {"name": "a #1", "args": ["13335", "-4"], "output": null, "escaped": "\"é😀\\"},
+                {},
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_scalars() -> Result<(), Box<dyn Error>> {
+        let text = "\
+plain: it's a string
+single: 'it''s # not a comment'
+url: http://example.net/a#b
+hex: 0x1F
+octal: 0o17
+float: -2.5e3
+version: 1.2.3
+flow: {a: 1, b: [x, 'y, z'], c: }
+";
+        assert_eq!(
+            parse(text)?,
+            json!({
+                "plain": "it's a string",
+                "single": "it's # not a comment",
+                "url": "http://example.net/a#b",
+                "hex": 31,
+                "octal": 15,
+                "float": -2500.0,
+                "version": "1.2.3",
+                "flow": {"a": 1, "b": ["x", "y, z"], "c": null},
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unsupported_syntax() {
+        for text in [
+            "a: 1\na: 2",
+            "a: &anchor 1",
+            "a: |\n  text",
+            "a: 1\n---\nb: 2",
+            "a:\n\t- 1",
+            "a: 1\n    b: 2",
+            "a: [1, 2",
+            "a: \"unterminated",
+            "a: b: c",
+        ] {
+            assert!(parse(text).is_err(), "{text}");
+        }
+    }
+}
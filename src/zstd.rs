@@ -0,0 +1,846 @@
+use std::io::{self, Write};
+
+#[allow(unused_imports)]
+use tracing::{debug, error, info, trace, warn};
+
+/// Magic number starting a Zstandard frame
+const MAGIC: u32 = 0xfd2f_b528;
+
+/// Frame header descriptor: no content size, no dictionary, with a content checksum
+const FRAME_HEADER_DESCRIPTOR: u8 = 0x04;
+
+/// Window descriptor of a 128 KiB window, as matches never reach outside their block
+const WINDOW_DESCRIPTOR: u8 = (17 - 10) << 3;
+
+/// Largest block content, which is also the window size
+const BLOCK_SIZE: usize = 128 * 1024;
+
+const BLOCK_RAW: u32 = 0;
+const BLOCK_COMPRESSED: u32 = 2;
+
+/// Shortest match worth a sequence, as it takes about as many bits as four literals
+const MIN_MATCH: usize = 4;
+
+/// Bits of the hash of four bytes indexing the match finder table
+const HASH_LOG: u32 = 15;
+
+/// Predefined distributions of the literal length, match length and offset codes, with their
+/// accuracy logs, used by compressed blocks in the Predefined_Mode instead of describing their own
+const LITERAL_LENGTH_DISTRIBUTION: (u32, [i16; 36]) = (
+    6,
+    [
+        4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1,
+        1, 1, -1, -1, -1, -1,
+    ],
+);
+const MATCH_LENGTH_DISTRIBUTION: (u32, [i16; 53]) = (
+    6,
+    [
+        1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+    ],
+);
+const OFFSET_DISTRIBUTION: (u32, [i16; 29]) = (
+    5,
+    [
+        1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+    ],
+);
+
+/// Baselines and extra bits of the literal length codes from 16, below which codes are lengths
+const LITERAL_LENGTH_CODES: [(u32, u32); 20] = [
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+
+/// Baselines and extra bits of the match length codes from 32, below which codes are lengths
+/// minus 3
+const MATCH_LENGTH_CODES: [(u32, u32); 21] = [
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+const fn high_bit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+/// Code of a field of a sequence, followed by extra bits telling the value from the others of
+/// the code
+#[derive(Debug, Clone, Copy)]
+struct Code {
+    symbol: usize,
+    bits: u32,
+    extra: u32,
+}
+
+/// Returns the code of a length, of the codes that are the length minus a bias up to `direct`.
+fn length_code(length: u32, direct: u32, bias: u32, codes: &[(u32, u32)]) -> Code {
+    if length < direct + bias {
+        return Code {
+            symbol: (length - bias) as usize,
+            bits: 0,
+            extra: 0,
+        };
+    }
+    let index = codes
+        .iter()
+        .rposition(|(baseline, _)| *baseline <= length)
+        .unwrap_or_default();
+    let (baseline, bits) = codes[index];
+    Code {
+        symbol: direct as usize + index,
+        bits,
+        extra: length - baseline,
+    }
+}
+
+/// Returns the code of an offset, which is coded plus 3 as values up to 3 stand for recently
+/// used offsets.
+fn offset_code(offset: u32) -> Code {
+    let value = offset + 3;
+    Code {
+        symbol: high_bit(value) as usize,
+        bits: high_bit(value),
+        extra: value - (1 << high_bit(value)),
+    }
+}
+
+/// A state of a finite state entropy decoding table: the symbol it decodes, and how the next
+/// state is read
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    symbol: usize,
+    bits: u32,
+    baseline: u32,
+}
+
+/// Builds the decoding table of a distribution, as decoders do.
+fn decoding_table(accuracy_log: u32, distribution: &[i16]) -> Vec<State> {
+    let size = 1_usize << accuracy_log;
+    let mut table = vec![State::default(); size];
+    // Symbols of probability "less than 1" take a cell each from the end
+    let mut high_threshold = size - 1;
+    for (symbol, count) in distribution.iter().enumerate() {
+        if *count == -1 {
+            table[high_threshold].symbol = symbol;
+            high_threshold -= 1;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut position = 0;
+    for (symbol, count) in distribution.iter().enumerate() {
+        for _ in 0..(*count).max(0) {
+            table[position].symbol = symbol;
+            position = (position + step) & (size - 1);
+            while position > high_threshold {
+                position = (position + step) & (size - 1);
+            }
+        }
+    }
+    let mut next: Vec<u32> = distribution
+        .iter()
+        .map(|count| u32::try_from(*count).unwrap_or(1))
+        .collect();
+    for state in &mut table {
+        let counter = next[state.symbol];
+        next[state.symbol] += 1;
+        state.bits = accuracy_log - high_bit(counter);
+        state.baseline = (counter << state.bits) - size as u32;
+    }
+    table
+}
+
+/// Encoder of the symbols of a distribution, which runs its decoding table backwards
+#[derive(Debug)]
+struct Coder {
+    accuracy_log: u32,
+    table: Vec<State>,
+    /// For each symbol, the state decoding it that leads to each next state
+    transitions: Vec<Vec<u16>>,
+    /// A state decoding each symbol, where encoding starts
+    initial: Vec<u16>,
+}
+
+impl Coder {
+    fn new(accuracy_log: u32, distribution: &[i16]) -> Self {
+        let table = decoding_table(accuracy_log, distribution);
+        let mut transitions = vec![vec![0; table.len()]; distribution.len()];
+        let mut initial = vec![0; distribution.len()];
+        for (index, state) in table.iter().enumerate() {
+            let start = state.baseline as usize;
+            let index = index as u16;
+            transitions[state.symbol][start..start + (1 << state.bits)].fill(index);
+            initial[state.symbol] = index;
+        }
+        Self {
+            accuracy_log,
+            table,
+            transitions,
+            initial,
+        }
+    }
+
+    /// Moves from the state the decoder reaches next to one decoding the symbol, writing the bits
+    /// the decoder reads to get there.
+    fn encode(&self, state: &mut u16, symbol: usize, bits: &mut BitWriter) {
+        let previous = self.transitions[symbol][usize::from(*state)];
+        let decoded = self.table[usize::from(previous)];
+        bits.write(
+            u64::from(*state) - u64::from(decoded.baseline),
+            decoded.bits,
+        );
+        *state = previous;
+    }
+}
+
+/// Coders of the three kinds of symbols of sequences
+#[derive(Debug)]
+struct Coders {
+    literal_lengths: Coder,
+    match_lengths: Coder,
+    offsets: Coder,
+}
+
+impl Coders {
+    fn new() -> Self {
+        let (accuracy_log, distribution) = LITERAL_LENGTH_DISTRIBUTION;
+        let literal_lengths = Coder::new(accuracy_log, &distribution);
+        let (accuracy_log, distribution) = MATCH_LENGTH_DISTRIBUTION;
+        let match_lengths = Coder::new(accuracy_log, &distribution);
+        let (accuracy_log, distribution) = OFFSET_DISTRIBUTION;
+        let offsets = Coder::new(accuracy_log, &distribution);
+        Self {
+            literal_lengths,
+            match_lengths,
+            offsets,
+        }
+    }
+}
+
+/// Writer of a bitstream read backwards, from its last bit, as sequences are
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    container: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        self.container |= (value & ((1 << bits) - 1)) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.container as u8);
+            self.container >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Ends the stream with the marker bit decoders look for in the last byte.
+    fn finish(mut self) -> Vec<u8> {
+        self.write(1, 1);
+        if self.count > 0 {
+            self.bytes.push(self.container as u8);
+        }
+        self.bytes
+    }
+}
+
+/// A match copied from earlier in the block, after the literals preceding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sequence {
+    literals: u32,
+    offset: u32,
+    length: u32,
+}
+
+/// Finds the sequences of a block with a greedy search of the last position of each hash of four
+/// bytes, returning them and the literals left after the last match.
+fn find_sequences(block: &[u8]) -> (Vec<Sequence>, Vec<u8>) {
+    let mut table = vec![0_u32; 1 << HASH_LOG];
+    let mut sequences = Vec::new();
+    let mut literals = Vec::new();
+    let hash = |position: usize| {
+        let bytes = [
+            block[position],
+            block[position + 1],
+            block[position + 2],
+            block[position + 3],
+        ];
+        (u32::from_le_bytes(bytes).wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+    };
+    let mut anchor = 0;
+    let mut position = 0;
+    while position + MIN_MATCH <= block.len() {
+        let key = hash(position);
+        // Positions are stored plus one, leaving zero for empty slots
+        let candidate = table[key] as usize;
+        table[key] = position as u32 + 1;
+        if candidate == 0
+            || block[candidate - 1..candidate - 1 + MIN_MATCH]
+                != block[position..position + MIN_MATCH]
+        {
+            position += 1;
+            continue;
+        }
+        let start = candidate - 1;
+        let length = MIN_MATCH
+            + block[position + MIN_MATCH..]
+                .iter()
+                .zip(&block[start + MIN_MATCH..])
+                .take_while(|(a, b)| a == b)
+                .count();
+        literals.extend_from_slice(&block[anchor..position]);
+        sequences.push(Sequence {
+            literals: (position - anchor) as u32,
+            offset: (position - start) as u32,
+            length: length as u32,
+        });
+        let end = position + length;
+        for inside in position + 1..end.min(block.len().saturating_sub(MIN_MATCH - 1)) {
+            table[hash(inside)] = inside as u32 + 1;
+        }
+        position = end;
+        anchor = end;
+    }
+    literals.extend_from_slice(&block[anchor..]);
+    (sequences, literals)
+}
+
+/// Writes the header of a section of raw literals, in the shortest of its three sizes.
+fn literals_header(output: &mut Vec<u8>, size: usize) {
+    let size = size as u32;
+    if size < 32 {
+        output.push((size << 3) as u8);
+    } else if size < 4096 {
+        output.extend_from_slice(&((size << 4) | 0b0100).to_le_bytes()[..2]);
+    } else {
+        output.extend_from_slice(&((size << 4) | 0b1100).to_le_bytes()[..3]);
+    }
+}
+
+fn sequence_count(output: &mut Vec<u8>, count: usize) {
+    if count < 128 {
+        output.push(count as u8);
+    } else if count < 0x7f00 {
+        output.extend_from_slice(&[((count >> 8) + 0x80) as u8, count as u8]);
+    } else {
+        let rest = (count - 0x7f00) as u16;
+        output.push(0xff);
+        output.extend_from_slice(&rest.to_le_bytes());
+    }
+}
+
+/// Compresses a block into raw literals followed by the sequences coded with the predefined
+/// distributions.
+fn compress_block(coders: &Coders, block: &[u8]) -> Vec<u8> {
+    let (sequences, literals) = find_sequences(block);
+    let mut output = Vec::with_capacity(block.len());
+    literals_header(&mut output, literals.len());
+    output.extend_from_slice(&literals);
+    sequence_count(&mut output, sequences.len());
+    if sequences.is_empty() {
+        return output;
+    }
+    // All three kinds of symbols use their predefined distribution
+    output.push(0);
+
+    let Coders {
+        literal_lengths,
+        match_lengths,
+        offsets,
+    } = coders;
+    let codes: Vec<[Code; 3]> = sequences
+        .iter()
+        .map(|sequence| {
+            [
+                length_code(sequence.literals, 16, 0, &LITERAL_LENGTH_CODES),
+                length_code(sequence.length, 32, 3, &MATCH_LENGTH_CODES),
+                offset_code(sequence.offset),
+            ]
+        })
+        .collect();
+
+    // The decoder reads the stream from its end, so the last sequence is written first, and the
+    // fields of each in the reverse of the order they are read in
+    let mut bits = BitWriter::default();
+    let mut states = None;
+    for [literal_length, match_length, offset] in codes.iter().rev() {
+        match &mut states {
+            None => {
+                states = Some((
+                    literal_lengths.initial[literal_length.symbol],
+                    match_lengths.initial[match_length.symbol],
+                    offsets.initial[offset.symbol],
+                ));
+            }
+            Some((literal_length_state, match_length_state, offset_state)) => {
+                offsets.encode(offset_state, offset.symbol, &mut bits);
+                match_lengths.encode(match_length_state, match_length.symbol, &mut bits);
+                literal_lengths.encode(literal_length_state, literal_length.symbol, &mut bits);
+            }
+        }
+        for code in [literal_length, match_length, offset] {
+            bits.write(u64::from(code.extra), code.bits);
+        }
+    }
+    let (literal_length_state, match_length_state, offset_state) = states.unwrap_or_default();
+    bits.write(u64::from(match_length_state), match_lengths.accuracy_log);
+    bits.write(u64::from(offset_state), offsets.accuracy_log);
+    bits.write(
+        u64::from(literal_length_state),
+        literal_lengths.accuracy_log,
+    );
+    output.extend_from_slice(&bits.finish());
+    output
+}
+
+/// Streaming XXH64 hash, whose low 32 bits are the content checksum of frames
+#[derive(Debug)]
+struct Xxh64 {
+    accumulators: [u64; 4],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+const PRIME64_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME64_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME64_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME64_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME64_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+const fn xxh64_round(accumulator: u64, input: u64) -> u64 {
+    accumulator
+        .wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+impl Xxh64 {
+    const fn new() -> Self {
+        Self {
+            accumulators: [
+                PRIME64_1.wrapping_add(PRIME64_2),
+                PRIME64_2,
+                0,
+                0_u64.wrapping_sub(PRIME64_1),
+            ],
+            buffer: Vec::new(),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let taken = data.len().min(32 - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..taken]);
+            data = &data[taken..];
+            if self.buffer.len() < 32 {
+                return;
+            }
+            let stripe = std::mem::take(&mut self.buffer);
+            self.stripe(&stripe);
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        self.buffer.extend_from_slice(stripes.remainder());
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, accumulator) in self.accumulators.iter_mut().enumerate() {
+            *accumulator = xxh64_round(*accumulator, read_u64(&stripe[lane * 8..]));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let [v1, v2, v3, v4] = self.accumulators;
+        let mut hash = if self.length >= 32 {
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for accumulator in self.accumulators {
+                hash = (hash ^ xxh64_round(0, accumulator))
+                    .wrapping_mul(PRIME64_1)
+                    .wrapping_add(PRIME64_4);
+            }
+            hash
+        } else {
+            PRIME64_5
+        };
+        hash = hash.wrapping_add(self.length);
+        let mut rest = self.buffer.as_slice();
+        while rest.len() >= 8 {
+            hash = (hash ^ xxh64_round(0, read_u64(rest)))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u64::from(u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]));
+            hash = (hash ^ word.wrapping_mul(PRIME64_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            hash = (hash ^ u64::from(*byte).wrapping_mul(PRIME64_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^ (hash >> 32)
+    }
+}
+
+/// Zstandard compressor of a stream, written as a single frame. Blocks are compressed with a
+/// greedy match search and the predefined entropy tables, which trades ratio for a small
+/// encoder, and stored raw when that does not make them smaller.
+#[derive(Debug)]
+pub struct ZstdEncoder<W: Write> {
+    writer: W,
+    block: Vec<u8>,
+    checksum: Xxh64,
+    coders: Coders,
+    header_written: bool,
+}
+
+impl<W: Write> ZstdEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            checksum: Xxh64::new(),
+            coders: Coders::new(),
+            header_written: false,
+        }
+    }
+
+    fn write_block(&mut self, last: bool) -> io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(&MAGIC.to_le_bytes())?;
+            self.writer
+                .write_all(&[FRAME_HEADER_DESCRIPTOR, WINDOW_DESCRIPTOR])?;
+            self.header_written = true;
+        }
+        let compressed = compress_block(&self.coders, &self.block);
+        let (block_type, content) = if compressed.len() < self.block.len() {
+            (BLOCK_COMPRESSED, compressed.as_slice())
+        } else {
+            (BLOCK_RAW, self.block.as_slice())
+        };
+        let header = u32::from(last) | block_type << 1 | (content.len() as u32) << 3;
+        self.writer.write_all(&header.to_le_bytes()[..3])?;
+        self.writer.write_all(content)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the checksum, returning the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block(true)?;
+        let checksum = self.checksum.finish() as u32;
+        self.writer.write_all(&checksum.to_le_bytes())?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ZstdEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full block is only written once more data comes, as the last one is flagged
+        if self.block.len() == BLOCK_SIZE {
+            self.write_block(false)?;
+        }
+        let taken = buf.len().min(BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..taken]);
+        self.checksum.update(&buf[..taken]);
+        Ok(taken)
+    }
+
+    /// Flushes the underlying writer only, as a block ended early would cost compression.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+/// Returns the baseline and extra bits of a length code.
+#[cfg(test)]
+fn code_length(symbol: usize, direct: u32, bias: u32, codes: &[(u32, u32)]) -> (u32, u32) {
+    match symbol.checked_sub(direct as usize) {
+        Some(index) => codes[index],
+        None => (symbol as u32 + bias, 0),
+    }
+}
+
+/// Reader of a bitstream from its end, as decoders read sequences
+#[cfg(test)]
+struct BitReader<'stream> {
+    stream: &'stream [u8],
+    position: usize,
+}
+
+#[cfg(test)]
+impl<'stream> BitReader<'stream> {
+    fn new(stream: &'stream [u8]) -> Result<Self, String> {
+        let last = stream
+            .last()
+            .filter(|last| **last != 0)
+            .ok_or("no end marker")?;
+        // The stream starts below the marker bit
+        let position = stream.len() * 8 - 1 - last.leading_zeros() as usize;
+        Ok(Self { stream, position })
+    }
+
+    fn read(&mut self, bits: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..bits {
+            self.position = self.position.checked_sub(1).ok_or("bitstream overflow")?;
+            let bit = self.stream[self.position / 8] >> (self.position % 8) & 1;
+            value = value << 1 | u32::from(bit);
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+fn decompress_block(block: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+    let truncated = || "truncated block".to_string();
+    let header = *block.first().ok_or_else(truncated)?;
+    if header & 3 != 0 {
+        return Err("only raw literals are supported".to_string());
+    }
+    let (size, header_len) = match header >> 2 & 3 {
+        0 | 2 => (usize::from(header >> 3), 1),
+        1 => (
+            usize::from(u16::from_le_bytes([block[0], block[1]]) >> 4),
+            2,
+        ),
+        _ => (
+            (u32::from_le_bytes([block[0], block[1], block[2], 0]) >> 4) as usize,
+            3,
+        ),
+    };
+    let literals = block
+        .get(header_len..header_len + size)
+        .ok_or_else(truncated)?;
+    let rest = &block[header_len + size..];
+    let (count, count_len) = match *rest.first().ok_or_else(truncated)? {
+        count @ 0..128 => (usize::from(count), 1),
+        0xff => (
+            usize::from(u16::from_le_bytes([rest[1], rest[2]])) + 0x7f00,
+            3,
+        ),
+        high => ((usize::from(high) - 0x80) << 8 | usize::from(rest[1]), 2),
+    };
+    let mut literals = literals.iter();
+    if count > 0 {
+        if rest[count_len] != 0 {
+            return Err("only the predefined distributions are supported".to_string());
+        }
+        let mut bits = BitReader::new(&rest[count_len + 1..])?;
+        let coders = Coders::new();
+        let tables = [
+            &coders.literal_lengths,
+            &coders.offsets,
+            &coders.match_lengths,
+        ];
+        let mut states = [0; 3];
+        for (state, coder) in states.iter_mut().zip(tables) {
+            *state = bits.read(coder.accuracy_log)? as usize;
+        }
+        for sequence in 0..count {
+            let [literal_length, offset, match_length] =
+                [0, 1, 2].map(|index| tables[index].table[states[index]]);
+            let offset_bits = offset.symbol as u32;
+            let offset = (1 << offset_bits) + bits.read(offset_bits)?;
+            let (baseline, extra) = code_length(match_length.symbol, 32, 3, &MATCH_LENGTH_CODES);
+            let length = baseline + bits.read(extra)?;
+            let (baseline, extra) =
+                code_length(literal_length.symbol, 16, 0, &LITERAL_LENGTH_CODES);
+            let literal_count = baseline + bits.read(extra)?;
+            output.extend(literals.by_ref().take(literal_count as usize));
+            // The encoder never refers to recently used offsets, coded 1 to 3
+            let distance = offset
+                .checked_sub(3)
+                .filter(|distance| *distance > 0)
+                .ok_or("repeat offsets are not supported")?;
+            let start = output
+                .len()
+                .checked_sub(distance as usize)
+                .ok_or("offset before the start")?;
+            for index in start..start + length as usize {
+                output.push(output[index]);
+            }
+            if sequence + 1 < count {
+                for index in [0, 2, 1] {
+                    let state = tables[index].table[states[index]];
+                    states[index] = (state.baseline + bits.read(state.bits)?) as usize;
+                }
+            }
+        }
+        if bits.position != 0 {
+            return Err("bits left in the sequences".to_string());
+        }
+    }
+    output.extend(literals);
+    Ok(())
+}
+
+/// Decompresses a frame written by [`ZstdEncoder`], whose blocks only use raw literals and the
+/// predefined distributions.
+#[cfg(test)]
+pub fn decompress(frame: &[u8]) -> Result<Vec<u8>, String> {
+    let rest = frame
+        .strip_prefix(&MAGIC.to_le_bytes())
+        .ok_or("not a zstd frame")?;
+    let Some(([FRAME_HEADER_DESCRIPTOR, WINDOW_DESCRIPTOR], mut rest)) = rest.split_first_chunk()
+    else {
+        return Err("unsupported frame header".to_string());
+    };
+    let mut output = Vec::new();
+    loop {
+        let (header, body) = rest
+            .split_first_chunk::<3>()
+            .ok_or("truncated block header")?;
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let size = (header >> 3) as usize;
+        let content = body.get(..size).ok_or("truncated block")?;
+        match header >> 1 & 3 {
+            BLOCK_RAW => output.extend_from_slice(content),
+            BLOCK_COMPRESSED => decompress_block(content, &mut output)?,
+            block_type => return Err(format!("unsupported block type {block_type}")),
+        }
+        rest = &body[size..];
+        if header & 1 == 1 {
+            break;
+        }
+    }
+    let mut checksum = Xxh64::new();
+    checksum.update(&output);
+    if rest != (checksum.finish() as u32).to_le_bytes() {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(input: &[u8], chunk_size: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut encoder = ZstdEncoder::new(Vec::new());
+        for chunk in input.chunks(chunk_size) {
+            encoder.write_all(chunk)?;
+        }
+        Ok(encoder.finish()?)
+    }
+
+    #[test]
+    fn compresses_known_frame() -> Result<(), Box<dyn std::error::Error>> {
+        let frame = compress(b"AS13335 AS13335 AS13335 AS13335 AS15169 AS15169\n", 64)?;
+        // Checked with the zstd command line tool
+        let expected = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x38, 0xad, 0x00, 0x00, 0x68, 0x41, 0x53, 0x31, 0x33,
+            0x33, 0x33, 0x35, 0x20, 0x35, 0x31, 0x36, 0x39, 0x0a, 0x02, 0x00, 0x2b, 0x6f, 0x5e,
+            0x58, 0xb8, 0xca, 0x07, 0xff, 0xc4,
+        ];
+        assert_eq!(frame, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn hashes_xxh64() {
+        for (input, expected) in [
+            (&b""[..], 0xef46_db37_51d8_e999),
+            (b"abc", 0x44bc_2cf5_ad77_0999),
+        ] {
+            let mut hash = Xxh64::new();
+            hash.update(input);
+            assert_eq!(hash.finish(), expected);
+        }
+    }
+
+    #[test]
+    fn round_trips() -> Result<(), Box<dyn std::error::Error>> {
+        let mut table = Vec::new();
+        for index in 0..100_000_u32 {
+            let line = format!(
+                "10.{}.{}.0/24,{}\n",
+                index % 256,
+                index / 7 % 256,
+                13335 + index % 50
+            );
+            table.extend_from_slice(line.as_bytes());
+        }
+        let mut seed = 1_u64;
+        let noise: Vec<u8> = (0..200_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (seed >> 56) as u8
+            })
+            .collect();
+        for input in [
+            Vec::new(),
+            b"a".to_vec(),
+            table.clone(),
+            noise,
+            vec![0; 300_000],
+        ] {
+            let frame = compress(&input, 4000)?;
+            assert_eq!(decompress(&frame)?, input);
+        }
+        assert!(compress(&table, 4000)?.len() < table.len() / 2);
+        Ok(())
+    }
+}